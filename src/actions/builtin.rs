@@ -0,0 +1,149 @@
+use async_trait::async_trait;
+use log::{ info, warn };
+use std::error::Error;
+
+use super::{ ActionContext, ActionHandler, ActionOutcome, StreamedOutcome };
+use crate::agent::{ parse_thinking_response, ProgressStage };
+use crate::config::prompt;
+use crate::models::chat::ChatMessage;
+use crate::rag::rag::{ RagQueryArgs, Retrieval, RetrievalMetadata };
+
+/// Retrieves documents for the message and answers with the RAG final prompt;
+/// count questions get the bare count.
+pub struct RagToolAction;
+
+/// Where retrieval left a RAG answer.
+enum RagStep {
+    /// A count question, already answered.
+    Answered(ActionOutcome),
+    /// The final answer prompt, the formatted documents and the retrieval details.
+    Prompt(String, String, RetrievalMetadata),
+}
+
+impl RagToolAction {
+    async fn retrieve(&self, ctx: &ActionContext<'_>) -> Result<RagStep, Box<dyn Error + Send + Sync>> {
+        ctx.report(ProgressStage::Retrieving, None);
+        let rag_args = RagQueryArgs {
+            query: ctx.message.to_string(),
+            limit: Some(ctx.rag_limit),
+            topic: None,
+            filters: Vec::new(),
+        };
+
+        let (documents, metadata, schema_json) = match ctx.rag_tool.retrieve(rag_args).await? {
+            Retrieval::Count { count, metadata } => {
+                return Ok(RagStep::Answered(ActionOutcome {
+                    thinking: String::new(),
+                    response: count.to_string(),
+                    metadata: Some(metadata),
+                }));
+            }
+            Retrieval::Documents { documents, metadata, schema_json } => (documents, metadata, schema_json),
+        };
+
+        ctx.report(
+            ProgressStage::Generating,
+            Some(format!("{} documents from {}", documents.len(), metadata.topic))
+        );
+        let docs_text = ctx.rag_tool.format_documents(&documents);
+        // An empty retrieval tells the prompt that no topic matched.
+        let retrieved_topic = if documents.is_empty() { "none" } else { metadata.topic.as_str() };
+
+        let final_prompt = prompt::get_rag_final_prompt(
+            ctx.prompt_config,
+            &schema_json,
+            retrieved_topic,
+            &docs_text,
+            ctx.message
+        )?;
+        Ok(RagStep::Prompt(ctx.answer_prompt(&final_prompt), docs_text, metadata))
+    }
+}
+
+#[async_trait]
+impl ActionHandler for RagToolAction {
+    async fn handle(
+        &self,
+        ctx: &ActionContext<'_>
+    ) -> Result<ActionOutcome, Box<dyn Error + Send + Sync>> {
+        let (final_prompt, docs_text, metadata) = match self.retrieve(ctx).await? {
+            RagStep::Answered(outcome) => return Ok(outcome),
+            RagStep::Prompt(final_prompt, docs_text, metadata) => (final_prompt, docs_text, metadata),
+        };
+
+        let resp = ctx.chat_client.complete_with_images(&final_prompt, ctx.images).await?;
+        let mut outcome = parse_thinking_response(&resp.response);
+        let mut metadata = metadata;
+        if let Some(policy) = ctx.grounding {
+            match policy.check(ctx.chat_client, ctx.prompt_config, ctx.message, &docs_text, &outcome.response).await {
+                Ok(grounding) => {
+                    if !grounding.grounded {
+                        info!("Answer not grounded in retrieved documents (confidence {:.2})", grounding.confidence);
+                        outcome.response = policy.apply(ctx.prompt_config, outcome.response);
+                    }
+                    metadata.grounding = Some(grounding);
+                }
+                Err(e) => warn!("Grounding check failed, returning unchecked answer: {}", e),
+            }
+        }
+        outcome.metadata = Some(metadata);
+        Ok(outcome)
+    }
+
+    /// Streams the final answer once retrieval is done. Answers with images, or that
+    /// must pass the grounding check before they are sent, are generated in one piece.
+    async fn handle_stream(
+        &self,
+        ctx: &ActionContext<'_>
+    ) -> Result<StreamedOutcome, Box<dyn Error + Send + Sync>> {
+        if ctx.grounding.is_some() || !ctx.images.is_empty() {
+            return Ok(StreamedOutcome::complete(self.handle(ctx).await?));
+        }
+        match self.retrieve(ctx).await? {
+            RagStep::Answered(outcome) => Ok(StreamedOutcome::complete(outcome)),
+            RagStep::Prompt(final_prompt, _, metadata) => Ok(StreamedOutcome {
+                stream: ctx.chat_client.stream_completion(&final_prompt).await?,
+                metadata: Some(metadata),
+            }),
+        }
+    }
+}
+
+/// Answers directly from the chat model with recent history as context.
+pub struct GeneralLlmAction;
+
+#[async_trait]
+impl ActionHandler for GeneralLlmAction {
+    async fn handle(
+        &self,
+        ctx: &ActionContext<'_>
+    ) -> Result<ActionOutcome, Box<dyn Error + Send + Sync>> {
+        ctx.report(ProgressStage::Generating, None);
+        // Follow-ups go out as separate turns so chat models see who said what.
+        let resp = if ctx.images.is_empty() && !ctx.history_messages.is_empty() {
+            let mut messages = ctx.history_messages.to_vec();
+            messages.push(ChatMessage::new("user", &ctx.answer_prompt(ctx.message)));
+            ctx.chat_client.complete_messages(&messages).await?
+        } else {
+            let prompt_with_history = format!("{}\n\nUser: {}", ctx.history, ctx.message);
+            ctx.chat_client.complete_with_images(&ctx.answer_prompt(&prompt_with_history), ctx.images).await?
+        };
+        Ok(parse_thinking_response(&resp.response))
+    }
+
+    /// Streams the answer to a prompt with the history flattened into it.
+    async fn handle_stream(
+        &self,
+        ctx: &ActionContext<'_>
+    ) -> Result<StreamedOutcome, Box<dyn Error + Send + Sync>> {
+        if !ctx.images.is_empty() {
+            return Ok(StreamedOutcome::complete(self.handle(ctx).await?));
+        }
+        ctx.report(ProgressStage::Generating, None);
+        let prompt_with_history = format!("{}\n\nUser: {}", ctx.history, ctx.message);
+        Ok(StreamedOutcome {
+            stream: ctx.chat_client.stream_completion(&ctx.answer_prompt(&prompt_with_history)).await?,
+            metadata: None,
+        })
+    }
+}
@@ -0,0 +1,130 @@
+mod builtin;
+
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+use crate::agent::{ report_progress, ProgressSender, ProgressStage, ResponseStream, ThinkingResponse };
+use crate::config::prompt::PromptConfig;
+use crate::llm::chat::ChatClient;
+use crate::llm::chat::image::ImageInput;
+use crate::models::chat::ChatMessage;
+use crate::rag::grounding::GroundingPolicy;
+use crate::rag::rag::{ RagEngine, RetrievalMetadata };
+
+pub use self::builtin::{ GeneralLlmAction, RagToolAction };
+
+pub const CALL_RAG_TOOL: &str = "call_rag_tool";
+pub const GENERAL_LLM_CALL: &str = "general_llm_call";
+
+pub type ActionOutcome = ThinkingResponse;
+
+/// A streamed answer, with the retrieval details known before it starts.
+pub struct StreamedOutcome {
+    pub stream: ResponseStream,
+    pub metadata: Option<RetrievalMetadata>,
+}
+
+impl StreamedOutcome {
+    /// Sends a finished answer as one chunk, its reasoning as a `<think>` block first.
+    pub fn complete(outcome: ActionOutcome) -> Self {
+        let mut chunks = Vec::new();
+        if !outcome.thinking.is_empty() {
+            chunks.push(Ok(format!("<think>{}</think>", outcome.thinking)));
+        }
+        chunks.push(Ok(outcome.response));
+        Self { stream: Box::pin(futures::stream::iter(chunks)), metadata: outcome.metadata }
+    }
+}
+
+/// Everything an action handler needs to answer a classified message.
+pub struct ActionContext<'a> {
+    pub conversation_id: &'a str,
+    pub message: &'a str,
+    pub history: &'a str,
+    /// The messages behind `history`, oldest first.
+    pub history_messages: &'a [ChatMessage],
+    pub images: &'a [ImageInput],
+    pub prompt_config: &'a PromptConfig,
+    pub chat_client: &'a dyn ChatClient,
+    pub rag_tool: &'a RagEngine,
+    /// Number of RAG results to retrieve for this request.
+    pub rag_limit: usize,
+    /// Language the final answer must be written in, if any.
+    pub language: Option<&'a str>,
+    pub progress: Option<&'a ProgressSender>,
+    /// Set when RAG answers must be checked against the retrieved documents.
+    pub grounding: Option<&'a GroundingPolicy>,
+}
+
+impl ActionContext<'_> {
+    /// Adds the response-language directive to a final answer prompt.
+    pub fn answer_prompt(&self, prompt: &str) -> String {
+        crate::config::prompt::apply_response_language(self.prompt_config, prompt, self.language)
+    }
+
+    /// Reports a pipeline stage to the client, if it asked for progress events.
+    pub fn report(&self, stage: ProgressStage, detail: Option<String>) {
+        report_progress(self.progress, stage, detail);
+    }
+}
+
+#[async_trait]
+pub trait ActionHandler: Send + Sync {
+    async fn handle(
+        &self,
+        ctx: &ActionContext<'_>
+    ) -> Result<ActionOutcome, Box<dyn Error + Send + Sync>>;
+
+    /// Streams the answer for the WebSocket and streaming HTTP routes. The default
+    /// runs `handle` and sends its answer in one piece.
+    async fn handle_stream(
+        &self,
+        ctx: &ActionContext<'_>
+    ) -> Result<StreamedOutcome, Box<dyn Error + Send + Sync>> {
+        Ok(StreamedOutcome::complete(self.handle(ctx).await?))
+    }
+}
+
+/// Maps intent `action` names from prompts.json to their handlers.
+#[derive(Clone, Default)]
+pub struct ActionRegistry {
+    handlers: HashMap<String, Arc<dyn ActionHandler>>,
+}
+
+impl ActionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register(CALL_RAG_TOOL, Arc::new(RagToolAction));
+        registry.register(GENERAL_LLM_CALL, Arc::new(GeneralLlmAction));
+        registry
+    }
+
+    pub fn register(&mut self, name: impl Into<String>, handler: Arc<dyn ActionHandler>) {
+        self.handlers.insert(name.into(), handler);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn ActionHandler>> {
+        self.handlers.get(name).cloned()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.handlers.contains_key(name)
+    }
+
+    /// Returns `(intent, action)` pairs whose action has no registered handler.
+    pub fn unknown_actions(&self, config: &PromptConfig) -> Vec<(String, String)> {
+        let mut unknown: Vec<(String, String)> = config.intents
+            .iter()
+            .filter(|(_, def)| !self.contains(&def.action))
+            .map(|(intent, def)| (intent.clone(), def.action.clone()))
+            .collect();
+        unknown.sort();
+        unknown
+    }
+}
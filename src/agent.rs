@@ -1,85 +1,443 @@
-use crate::history::{ format_history_for_prompt, initialize_history_store, HistoryStore };
-use crate::rag::rag::{ RagEngine, RagQueryArgs };
+use crate::history::{ format_history_for_prompt, initialize_history_store, HistoryStore, WindowPolicy };
+use crate::rag::rag::{ Document, RagEngine, RagEngineState, RagQueryArgs, RetrievalMetadata };
+use crate::error::AgentError;
+use crate::rag::filter::FieldFilter;
+use crate::rag::grounding::GroundingPolicy;
+use crate::rag::ingest::{ self, DocumentWriter, IngestDocument, IngestResult, PreparedDocument };
+use crate::actions::{ ActionContext, ActionHandler, ActionRegistry, StreamedOutcome };
 
-use futures::{Stream, TryStreamExt};
+use futures::{Stream, StreamExt, TryStreamExt};
 use vector_nexus::db::{
     VectorStore,
     get_store_type as get_vector_store_type,
     create_vector_store,
     VectorStoreConfig,
 };
-use vector_nexus::schema::SchemaFile;
+use vector_nexus::schema::{ IndexSchema, SchemaFile };
 
 use serde_json::Value as JsonValue;
-use serde::{ Deserialize, Serialize };
 
 use crate::cli::Args;
-use crate::config::prompt::{ self, PromptConfig };
-use crate::llm::{ parse_llm_type, LlmConfig };
-use crate::llm::chat::{ ChatClient, new_client as new_chat_client };
-use crate::llm::embedding::{ EmbeddingClient, new_client as new_embedding_client };
+use crate::config::function_schema;
+use crate::config::postprocess::Postprocessor;
+use crate::config::prompt::{ self, IntentDefinition, PromptConfig };
+use crate::llm::{ parse_llm_type, GenerationParams, LlmConfig };
+use crate::llm::chat::{ ChatClient, estimate_tokens, new_client as new_chat_client, unsupported_images_error };
+use crate::llm::chat::image::ImageInput;
+use crate::llm::embedding::{ EmbeddingClient, EmbeddingInputType, new_client as new_embedding_client, probe_dimension, requested_dimensions };
+use crate::llm::embedding::fallback::FallbackEmbeddingClient;
+use crate::llm::embedding::cache::CachingEmbeddingClient;
+use crate::llm::embedding::truncate::TruncatingEmbeddingClient;
 
-use crate::cache::{self, CacheClients};
+use crate::cache::{self, CacheClients, CacheEntry};
+use crate::cache::lru::LruCache;
+use crate::models::chat::{ Conversation, ConversationMetadata };
+use chrono::Utc;
+use uuid::Uuid;
 
 use log::{ info, warn };
+use std::collections::HashMap;
 use std::error::Error;
+use std::fmt;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{ Arc, Mutex as StdMutex };
 use std::fs;
-use std::time::SystemTime;
-use std::path::PathBuf;
-use tokio::sync::RwLock;
-
-const HISTORY_FOR_PROMPT_LEN: usize = 6;
+use std::time::{ Duration, Instant, SystemTime };
+use tokio::sync::{ mpsc, RwLock };
+use serde::{ Deserialize, Serialize };
+use sha2::{ Digest, Sha256 };
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct CachePayload {
-    normalized_prompt: String,
-    response: String,
-}
+/// Longest generated conversation title kept, in characters.
+const MAX_TITLE_CHARS: usize = 80;
 
 #[derive(Clone)]
 pub struct AIAgent {
     chat_client: Arc<dyn ChatClient>,
     embedding_client: Arc<dyn EmbeddingClient>,
     query_generation_client: Arc<dyn ChatClient>,
-    rag_tool: RagEngine,
+    /// Shared so clones of the agent see schema reloads.
+    rag_tool: Arc<RagEngine>,
     prompt_config: Arc<RwLock<Arc<PromptConfig>>>,
     vector_store: Arc<dyn VectorStore>,
     history_store: Arc<dyn HistoryStore>,
-    schema_last_reload: Option<SystemTime>,
+    schema_last_reload: Arc<StdMutex<Option<SystemTime>>>,
     rag_default_limit: usize,
+    rag_max_limit: usize,
     vector_type: String,
     enable_cache: bool,
     cache: CacheClients,
-    prompts_path: String, 
+    /// Prompts file reloaded when it changes; empty when no file is watched.
+    prompts_path: String,
+    actions: Arc<ActionRegistry>,
+    intent_routing: IntentRouting,
+    response_language: Option<String>,
+    history_store_thinking: bool,
+    /// Estimated tokens of history a prompt may include; 0 is unbounded.
+    history_token_budget: usize,
+    conversation_metadata: bool,
+    conversation_auto_title: bool,
+    /// Ceiling on one message's processing, streaming included; `None` is unbounded.
+    request_timeout: Option<Duration>,
+    document_writer: Option<Arc<dyn DocumentWriter>>,
+    ingest_batch_size: usize,
+    embed_fields: Arc<HashMap<String, Vec<String>>>,
+    grounding: Option<GroundingPolicy>,
+    intent_clients: IntentClients,
+    use_query_client_for_routing: bool,
+    /// Dedicated intent classification client from `--intent-llm-type`/`--intent-model`.
+    intent_client: Option<Arc<dyn ChatClient>>,
+    intent_cache: IntentCache,
+}
+
+/// What to do with a message whose intent confidence is below the threshold.
+#[derive(Debug, Clone, PartialEq)]
+enum LowConfidenceAction {
+    Clarify,
+    Default(String),
+}
+
+#[derive(Debug, Clone)]
+struct IntentRouting {
+    confidence_threshold: f32,
+    low_confidence: LowConfidenceAction,
+    max_output_chars: usize,
+}
+
+impl IntentRouting {
+    fn from_args(args: &Args) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let low_confidence = match args.intent_low_confidence_action.trim().to_lowercase().as_str() {
+            "clarify" => LowConfidenceAction::Clarify,
+            "default" => LowConfidenceAction::Default(args.default_intent.clone()),
+            other => {
+                return Err(
+                    format!("Invalid intent low-confidence action '{}': expected clarify or default", other).into()
+                );
+            }
+        };
+        Ok(Self {
+            confidence_threshold: args.intent_confidence_threshold.clamp(0.0, 1.0),
+            low_confidence,
+            max_output_chars: args.classifier_max_output_chars,
+        })
+    }
+}
+
+/// Classifier replies for repeated messages, so they skip the classification call.
+/// Keys hash the intent prompt built from the message with case and whitespace
+/// normalized; the prompt embeds the intent list, so a prompt config reload cannot
+/// serve a stale intent.
+#[derive(Clone)]
+struct IntentCache {
+    entries: Option<Arc<StdMutex<LruCache<IntentKey, String>>>>,
+}
+
+/// SHA-256 of the normalized intent prompt.
+type IntentKey = [u8; 32];
+
+impl IntentCache {
+    /// A capacity of 0 disables the cache.
+    fn new(capacity: usize) -> Self {
+        Self { entries: (capacity > 0).then(|| Arc::new(StdMutex::new(LruCache::new(capacity)))) }
+    }
+
+    /// `None` when the cache is disabled.
+    fn key(&self, prompt_config: &PromptConfig, message: &str) -> Result<Option<IntentKey>, prompt::PromptError> {
+        if self.entries.is_none() {
+            return Ok(None);
+        }
+        let normalized = message.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+        let intent_prompt = prompt::get_intent_prompt(prompt_config, &normalized)?;
+        Ok(Some(Sha256::digest(intent_prompt.as_bytes()).into()))
+    }
+
+    fn get(&self, key: Option<IntentKey>) -> Option<String> {
+        self.entries.as_ref()?.lock().unwrap().get(&key?)
+    }
+
+    fn insert(&self, key: Option<IntentKey>, output: &str) {
+        if let (Some(entries), Some(key)) = (&self.entries, key) {
+            entries.lock().unwrap().insert(key, output.to_string());
+        }
+    }
+}
+
+/// Chat clients for intents that override the model, created on first use and reused.
+#[derive(Clone)]
+struct IntentClients {
+    /// Chat and query-generation configs; an override borrows the base URL and API key
+    /// of the first one using the same provider.
+    base_configs: Vec<LlmConfig>,
+    keep_alive: Option<String>,
+    clients: Arc<StdMutex<HashMap<String, Arc<dyn ChatClient>>>>,
+}
+
+impl IntentClients {
+    fn from_args(args: &Args) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Ok(Self {
+            base_configs: vec![AIAgent::chat_llm_config(args)?, AIAgent::query_llm_config(args)?],
+            keep_alive: args.ollama_keep_alive.clone(),
+            clients: Arc::new(StdMutex::new(HashMap::new())),
+        })
+    }
+
+    /// The intent's override client, or `None` when it uses the global chat client.
+    fn client_for(
+        &self,
+        intent: &IntentDefinition
+    ) -> Result<Option<Arc<dyn ChatClient>>, Box<dyn Error + Send + Sync>> {
+        if intent.model.is_none() && intent.provider.is_none() {
+            return Ok(None);
+        }
+        let chat_config = &self.base_configs[0];
+        let llm_type = match intent.provider.as_deref() {
+            Some(provider) => parse_llm_type(provider)?,
+            None => chat_config.llm_type.clone(),
+        };
+        let base = self.base_configs
+            .iter()
+            .find(|c| c.llm_type == llm_type)
+            .cloned()
+            .unwrap_or_else(|| LlmConfig {
+                llm_type,
+                keep_alive: self.keep_alive.clone(),
+                request_timeout_secs: chat_config.request_timeout_secs,
+                max_retries: chat_config.max_retries,
+                retry_base_ms: chat_config.retry_base_ms,
+                ..Default::default()
+            });
+        let config = LlmConfig {
+            completion_model: intent.model.clone().or(base.completion_model.clone()),
+            system_prompt: chat_config.system_prompt.clone(),
+            generation: chat_config.generation,
+            ..base
+        };
+        let key = format!("{:?}:{}", config.llm_type, config.completion_model.as_deref().unwrap_or(""));
+
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(&key) {
+            return Ok(Some(Arc::clone(client)));
+        }
+        let client = new_chat_client(&config)?;
+        info!(
+            "Intent chat client configured: Type={:?}, Model={:?}",
+            config.llm_type,
+            config.completion_model.as_deref().unwrap_or("adapter default")
+        );
+        clients.insert(key, Arc::clone(&client));
+        Ok(Some(client))
+    }
+}
+
+/// Pipeline stage reported to clients that opted into progress events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressStage {
+    Classifying,
+    Retrieving,
+    Generating,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProgressEvent {
+    pub stage: ProgressStage,
+    pub detail: Option<String>,
+}
+
+pub type ProgressSender = mpsc::UnboundedSender<ProgressEvent>;
+
+/// Sends a progress event if a listener is attached; a closed listener is ignored.
+pub fn report_progress(progress: Option<&ProgressSender>, stage: ProgressStage, detail: Option<String>) {
+    if let Some(progress) = progress {
+        let _ = progress.send(ProgressEvent { stage, detail });
+    }
+}
+
+/// A message classified to an intent, with what its action handler needs.
+struct RoutedMessage {
+    handler: Arc<dyn ActionHandler>,
+    chat_client: Arc<dyn ChatClient>,
+    history: Conversation,
+    history_str: String,
+    prompt_config: Arc<PromptConfig>,
+}
+
+enum Routing {
+    /// Answered without running an action, e.g. with a clarification question.
+    Answered(ThinkingResponse),
+    Action(RoutedMessage),
+}
+
+/// Per-message request options beyond the text itself.
+#[derive(Debug, Clone, Default)]
+pub struct MessageOptions {
+    pub images: Vec<String>,
+    pub window_policy: WindowPolicy,
+    /// Answer language for this message, overriding `--response-language`.
+    pub language: Option<String>,
+    /// Receives pipeline progress events while the message is processed.
+    pub progress: Option<ProgressSender>,
+    /// Number of RAG results to retrieve, clamped to `--rag-max-limit`.
+    pub rag_limit: Option<usize>,
+    /// ID to store the user message under; generated when unset.
+    pub message_id: Option<String>,
+    /// Neither read nor populate the response cache (set when regenerating a reply).
+    pub bypass_cache: bool,
+}
+
+pub type ResponseStream = Pin<Box<dyn Stream<Item = Result<String, Box<dyn Error + Send + Sync>>> + Send>>;
+
+/// A message took longer than `--request-timeout-secs`; its remaining work was cancelled.
+#[derive(Debug)]
+pub struct RequestTimeout(pub Duration);
+
+impl fmt::Display for RequestTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Request exceeded the {}s processing limit", self.0.as_secs())
+    }
+}
+
+impl Error for RequestTimeout {}
+
+/// Ends `stream` with a `RequestTimeout` once `deadline` passes. The provider stream is
+/// dropped at that point, which cancels it, and the turn is not added to history.
+fn with_deadline(stream: ResponseStream, deadline: tokio::time::Instant, limit: Duration) -> ResponseStream {
+    Box::pin(futures::stream::unfold(Some(stream), move |stream| async move {
+        let mut stream = stream?;
+        match tokio::time::timeout_at(deadline, stream.next()).await {
+            Ok(Some(item)) => Some((item, Some(stream))),
+            Ok(None) => None,
+            Err(_) => {
+                warn!("Streaming response cut off after the {:?} request timeout", limit);
+                Some((Err(Box::new(RequestTimeout(limit)) as Box<dyn Error + Send + Sync>), None))
+            }
+        }
+    }))
 }
 
 pub struct ThinkingResponse {
     pub thinking: String,
-    pub response: String
+    pub response: String,
+    /// Retrieval routing details, set when the answer came from RAG.
+    pub metadata: Option<RetrievalMetadata>,
 }
  
 impl AIAgent {
-    async fn initialize_llm_clients(
-        args: &Args
-    ) -> Result<
-        (Arc<dyn ChatClient>, Arc<dyn EmbeddingClient>, Arc<dyn ChatClient>),
-        Box<dyn Error + Send + Sync>
-    > {
+    fn chat_llm_config(args: &Args) -> Result<LlmConfig, Box<dyn Error + Send + Sync>> {
         let chat_llm_type = parse_llm_type(&args.chat_llm_type)?;
         let chat_api_key = if !args.chat_api_key.is_empty() {
             Some(args.chat_api_key.clone())
         } else {
             None
         };
-        let chat_config = LlmConfig {
+        Ok(LlmConfig {
             llm_type: chat_llm_type,
             base_url: args.chat_base_url.clone(),
             api_key: chat_api_key,
             completion_model: args.chat_model.clone(),
             embedding_model: None,
+            embedding_dimensions: None,
+            keep_alive: args.ollama_keep_alive.clone(),
+            seed: args.chat_seed,
+            thinking_budget: args.max_thinking_tokens,
+            generation: GenerationParams {
+                temperature: args.chat_temperature,
+                max_tokens: args.chat_max_tokens,
+                top_p: args.chat_top_p,
+                frequency_penalty: args.chat_frequency_penalty,
+                presence_penalty: args.chat_presence_penalty,
+            },
+            system_prompt: args.chat_system_prompt.clone().filter(|p| !p.trim().is_empty()),
+            request_timeout_secs: args.chat_timeout_secs,
+            max_retries: args.llm_max_retries,
+            retry_base_ms: args.llm_retry_base_ms,
+        })
+    }
+
+    fn query_llm_config(args: &Args) -> Result<LlmConfig, Box<dyn Error + Send + Sync>> {
+        let query_llm_type_str = match &args.query_llm_type {
+            Some(s) if !s.trim().is_empty() => s.as_str(),
+            _ => &args.chat_llm_type,
         };
+        let query_llm_type = parse_llm_type(query_llm_type_str)?;
+        let query_api_key_str = args.query_api_key.as_deref().unwrap_or(&args.chat_api_key);
+        let query_api_key = {
+            let s = query_api_key_str.to_string();
+            if !s.is_empty() {
+                Some(s)
+            } else {
+                None
+            }
+        };
+        Ok(LlmConfig {
+            llm_type: query_llm_type,
+            base_url: args.query_base_url.clone().or_else(|| args.chat_base_url.clone()),
+            api_key: query_api_key,
+            completion_model: args.query_model.clone().or_else(|| args.chat_model.clone()),
+            embedding_model: None,
+            embedding_dimensions: None,
+            keep_alive: args.ollama_keep_alive.clone(),
+            seed: args.chat_seed,
+            thinking_budget: args.max_thinking_tokens,
+            // Routing, rewriting and extraction keep the provider defaults.
+            generation: GenerationParams::default(),
+            // Classification and query rewriting stay free of the chat persona.
+            system_prompt: None,
+            request_timeout_secs: args.chat_timeout_secs,
+            max_retries: args.llm_max_retries,
+            retry_base_ms: args.llm_retry_base_ms,
+        })
+    }
+
+    /// Config of the dedicated intent classification client, or `None` when neither
+    /// `--intent-llm-type` nor `--intent-model` is set.
+    fn intent_llm_config(args: &Args) -> Result<Option<LlmConfig>, Box<dyn Error + Send + Sync>> {
+        let set = |value: &Option<String>| value.as_deref().is_some_and(|v| !v.trim().is_empty());
+        if !set(&args.intent_llm_type) && !set(&args.intent_model) {
+            return Ok(None);
+        }
+        let intent_llm_type_str = match &args.intent_llm_type {
+            Some(s) if !s.trim().is_empty() => s.as_str(),
+            _ => &args.chat_llm_type,
+        };
+        let intent_api_key = Some(args.intent_api_key.as_deref().unwrap_or(&args.chat_api_key))
+            .filter(|key| !key.is_empty())
+            .map(str::to_string);
+        Ok(Some(LlmConfig {
+            llm_type: parse_llm_type(intent_llm_type_str)?,
+            base_url: args.intent_base_url.clone().or_else(|| args.chat_base_url.clone()),
+            api_key: intent_api_key,
+            completion_model: args.intent_model.clone().filter(|m| !m.trim().is_empty()).or_else(|| args.chat_model.clone()),
+            keep_alive: args.ollama_keep_alive.clone(),
+            seed: args.chat_seed,
+            request_timeout_secs: args.chat_timeout_secs,
+            max_retries: args.llm_max_retries,
+            retry_base_ms: args.llm_retry_base_ms,
+            // Classification stays free of the chat persona and sampling settings.
+            ..LlmConfig::default()
+        }))
+    }
+
+    fn initialize_intent_client(args: &Args) -> Result<Option<Arc<dyn ChatClient>>, Box<dyn Error + Send + Sync>> {
+        let Some(intent_config) = Self::intent_llm_config(args)? else {
+            return Ok(None);
+        };
+        let intent_client = new_chat_client(&intent_config)?;
+        info!(
+            "Intent classification client configured: Type={:?}, Model={:?}, BaseURL={:?}",
+            intent_config.llm_type,
+            intent_config.completion_model.as_deref().unwrap_or("adapter default"),
+            intent_config.base_url.as_deref().unwrap_or("adapter default")
+        );
+        Ok(Some(intent_client))
+    }
+
+    async fn initialize_llm_clients(
+        args: &Args
+    ) -> Result<
+        (Arc<dyn ChatClient>, Arc<dyn EmbeddingClient>, Arc<dyn ChatClient>),
+        Box<dyn Error + Send + Sync>
+    > {
+        let chat_config = Self::chat_llm_config(args)?;
         let chat_client = new_chat_client(&chat_config)?;
         info!(
             "Chat client configured: Type={}, Model={:?}, BaseURL={:?}",
@@ -95,11 +453,14 @@ impl AIAgent {
             None
         };
         let embedding_config = LlmConfig {
+            embedding_dimensions: requested_dimensions(args, &embedding_llm_type, args.embedding_model.as_deref()),
             llm_type: embedding_llm_type,
             base_url: args.embedding_base_url.clone(),
             api_key: embedding_api_key,
             embedding_model: args.embedding_model.clone(),
             completion_model: None,
+            keep_alive: args.ollama_keep_alive.clone(),
+            ..LlmConfig::default()
         };
         let embedding_client = new_embedding_client(&embedding_config)?;
         info!(
@@ -109,31 +470,11 @@ impl AIAgent {
             embedding_config.base_url.as_deref().unwrap_or("adapter default")
         );
 
-        let query_llm_type_str = match &args.query_llm_type {
-            Some(s) if !s.trim().is_empty() => s.as_str(),
-            _ => &args.chat_llm_type,
-        };
-        let query_llm_type = parse_llm_type(query_llm_type_str)?;
-        let query_api_key_str = args.query_api_key.as_deref().unwrap_or(&args.chat_api_key);
-        let query_api_key = {
-            let s = query_api_key_str.to_string();
-            if !s.is_empty() {
-                Some(s)
-            } else {
-                None
-            }
-        };
-        let query_config = LlmConfig {
-            llm_type: query_llm_type,
-            base_url: args.query_base_url.clone().or_else(|| args.chat_base_url.clone()),
-            api_key: query_api_key,
-            completion_model: args.query_model.clone().or_else(|| args.chat_model.clone()),
-            embedding_model: None,
-        };
+        let query_config = Self::query_llm_config(args)?;
         let query_generation_client = new_chat_client(&query_config)?;
         info!(
-            "Query Generation client configured: Type={}, Model={:?}, BaseURL={:?}",
-            query_llm_type_str,
+            "Query Generation client configured: Type={:?}, Model={:?}, BaseURL={:?}",
+            query_config.llm_type,
             query_config.completion_model.as_deref().unwrap_or("adapter default"),
             query_config.base_url.as_deref().unwrap_or("adapter default")
         );
@@ -158,84 +499,144 @@ impl AIAgent {
             index_name: Some(args.indexes.clone()),
             user: Some(args.user.clone()),
             pass: Some(args.pass.clone()),
-            dimension: Some(args.dimension.clone()),
+            dimension: Some(args.dimension),
             metric: Some(args.metric.clone()),
         };
         create_vector_store(vector_store_config.clone()).await
     }
 
+    /// Parses raw image entries and rejects them up front for text-only chat providers.
+    fn prepare_images(&self, images: &[String]) -> Result<Vec<ImageInput>, Box<dyn Error + Send + Sync>> {
+        if images.is_empty() {
+            return Ok(Vec::new());
+        }
+        if !self.chat_client.supports_vision() {
+            return Err(unsupported_images_error(&self.chat_client.get_model()));
+        }
+        ImageInput::parse_all(images)
+    }
+
+    async fn history_for_prompt(
+        &self,
+        conversation_id: &str,
+        policy: WindowPolicy
+    ) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let conversation = self.prompt_history(conversation_id, policy).await?;
+        Ok(format_history_for_prompt(&conversation))
+    }
+
+    /// Prior messages to include in the prompt under `policy`, oldest first.
+    async fn prompt_history(
+        &self,
+        conversation_id: &str,
+        policy: WindowPolicy
+    ) -> Result<Conversation, Box<dyn Error + Send + Sync>> {
+        let Some(limit) = policy.history_limit() else {
+            return Ok(Conversation { id: conversation_id.to_string(), messages: Vec::new() });
+        };
+        let mut conversation = self.history_store.get_conversation(conversation_id, limit).await?;
+        if self.history_token_budget > 0 {
+            let mut used = 0usize;
+            let keep = conversation.messages
+                .iter()
+                .rev()
+                .take_while(|m| {
+                    used += estimate_tokens(&m.content) as usize;
+                    used <= self.history_token_budget
+                })
+                .count();
+            conversation.messages.drain(..conversation.messages.len() - keep);
+        }
+        Ok(conversation)
+    }
+
+    /// Cache context of a turn: a digest of the history its prompt includes, or `None`
+    /// when the cache is context-free (the default) or the conversation is new.
+    async fn cache_context(
+        &self,
+        conversation_id: &str,
+        policy: WindowPolicy
+    ) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        if !self.cache.context_sensitive {
+            return Ok(None);
+        }
+        let history = self.history_for_prompt(conversation_id, policy).await?;
+        Ok(cache::context_hash(&history))
+    }
+
+    /// Streams the answer to `message`, with the retrieval metadata when the answer used
+    /// retrieval (cache hits carry none). With `--request-timeout-secs` the preparation and
+    /// the stream share one deadline; past it the work is dropped and a `RequestTimeout`
+    /// error is returned or ends the stream.
     pub async fn process_message_stream(
         &self,
         conversation_id: &str,
         message: &str,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<String, Box<dyn std::error::Error + Send + Sync>>> + Send>>, Box<dyn std::error::Error + Send + Sync>> {
+        options: &MessageOptions,
+    ) -> Result<StreamedOutcome, AgentError> {
+        let answer = self.answer_message_stream(conversation_id, message, options);
+        let Some(limit) = self.request_timeout else {
+            return Ok(answer.await?);
+        };
+        let deadline = tokio::time::Instant::now() + limit;
+        let outcome = tokio::time::timeout_at(deadline, answer).await.map_err(|_| {
+            warn!("Message in conversation {} timed out after {:?} before streaming", conversation_id, limit);
+            RequestTimeout(limit)
+        })??;
+        Ok(StreamedOutcome {
+            stream: with_deadline(outcome.stream, deadline, limit),
+            metadata: outcome.metadata,
+        })
+    }
+
+    async fn answer_message_stream(
+        &self,
+        conversation_id: &str,
+        message: &str,
+        options: &MessageOptions,
+    ) -> Result<StreamedOutcome, Box<dyn Error + Send + Sync>> {
         let normalized = message.trim().to_lowercase();
-        
+        let message_id = Self::message_id_for(options);
+        let images = self.prepare_images(&options.images)?;
+        // Cache keys are text-only, so requests with images or per-request overrides neither read nor populate the cache.
+        let cacheable = Self::is_cacheable(&images, options);
+        let cache_context = if self.enable_cache && cacheable {
+            self.cache_context(conversation_id, options.window_policy).await?
+        } else {
+            None
+        };
 
-        if self.enable_cache {
-            if let Some((cached_response, _emb)) = cache::check(&self.cache, &normalized, &*self.embedding_client).await? {
+        if self.enable_cache && cacheable {
+            if let Some((cached, _emb)) = cache::check(&self.cache, &normalized, cache_context.as_deref(), &*self.embedding_client).await? {
                 info!("✅ Cache Hit - serving from cache");
 
-                self.history_store.add_message(conversation_id, "user", message).await?;
-                self.history_store.add_message(conversation_id, "assistant", &cached_response).await?;
-                
-                if cached_response.starts_with('{') && 
-                   cached_response.contains("\"response\"") && 
-                   cached_response.contains("\"thinking\"") {
-                    
-                    if let Ok(cached_json) = serde_json::from_str::<serde_json::Value>(&cached_response) {
-                        let response = cached_json.get("response")
-                            .and_then(|v| v.as_str())
-                            .map(|s| s.to_string())
-                            .unwrap_or_else(|| cached_response.clone());
-                            
-                        let thinking = cached_json.get("thinking")
-                            .and_then(|v| v.as_str())
-                            .map(|s| s.to_string())
-                            .unwrap_or_else(|| String::new());
-                        
-                         if !thinking.is_empty() {
-                            let sequence = vec![
-                                Ok(format!("<think>{}</think>", thinking)), 
-                                Ok(response)
-                            ];
-                            
-                            let stream = futures::stream::iter(sequence);
-                            return Ok(Box::pin(stream));
-                        }
-                        
-                        let cached_stream = futures::stream::once(async move { Ok(response) });
-                        return Ok(Box::pin(cached_stream));
-                    }
-                }
-                
-                let cached_response_owned = cached_response.clone();
-                let cached_stream = futures::stream::once(async move { Ok(cached_response_owned) });
-                return Ok(Box::pin(cached_stream));
+                let reply = cached_reply(&cached);
+                self.record_exchange(conversation_id, &message_id, message, &reply).await?;
+                return Ok(StreamedOutcome::complete(reply));
             }
         }
 
         info!("ℹ️ Cache Miss - streaming from LLM");
-        
-        let conversation = self.history_store.get_conversation(
-            conversation_id,
-            HISTORY_FOR_PROMPT_LEN
-        ).await?;
-        let history_str = format_history_for_prompt(&conversation);
-        let final_prompt = format!("{}\n\nUser: {}", history_str, message);
-        let original_stream = self.chat_client.stream_completion(&final_prompt).await?;
+
+        let outcome = self.stream_llm_interaction(conversation_id, message, &images, options).await?;
+        let metadata = outcome.metadata;
+        let original_stream = outcome.stream;
         let collected_normalized = normalized.clone();
+        let collected_cache_context = cache_context.clone();
         let collected_conversation_id = conversation_id.to_string();
         let collected_message = message.to_string();
+        let collected_message_id = message_id.clone();
         let collected_self = self.clone();
         
         let stream = futures::stream::unfold(
             (original_stream, String::new()),
             move |(mut stream, mut full_response)| {
                 let collected_normalized = collected_normalized.clone();
+                let collected_cache_context = collected_cache_context.clone();
                 let collected_self = collected_self.clone();
                 let collected_conversation_id = collected_conversation_id.clone();
                 let collected_message = collected_message.clone();
+                let collected_message_id = collected_message_id.clone();
                 
                 async move {
                     match stream.try_next().await {
@@ -246,8 +647,13 @@ impl AIAgent {
                         Ok(None) => {
                             if collected_self.enable_cache {
                                 let _ = async {
-                                    match collected_self.embedding_client.embed(&collected_normalized).await {
-                                        Ok(emb) => {
+                                    let embedding = if cacheable {
+                                        Some(collected_self.embedding_client.embed_with_type(&collected_normalized, EmbeddingInputType::Query).await)
+                                    } else {
+                                        None
+                                    };
+                                    match embedding {
+                                        Some(Ok(emb)) => {
                                             let thinking_response = parse_thinking_response(&full_response);
                                             let thinking = if thinking_response.thinking.is_empty() { 
                                                 None 
@@ -258,6 +664,7 @@ impl AIAgent {
                                             if let Err(e) = cache::update_streaming(
                                                 &collected_self.cache, 
                                                 &collected_normalized, 
+                                                collected_cache_context.as_deref(),
                                                 &thinking_response.response, 
                                                 thinking,
                                                 emb.embedding
@@ -267,20 +674,17 @@ impl AIAgent {
                                                 info!("✅ Cache updated with streaming response");
                                             }
                                         }
-                                        Err(e) => warn!("Failed to generate embedding for cache: {}", e),
-                                    }
-                                    
-                                    if let Err(e) = collected_self.history_store
-                                        .add_message(&collected_conversation_id, "user", &collected_message).await {
-                                        warn!("Failed to add user message to history: {}", e);
-                                    }
-                                    
-                                    if let Err(e) = collected_self.history_store
-                                        .add_message(&collected_conversation_id, "assistant", &full_response).await {
-                                        warn!("Failed to add assistant message to history: {}", e);
+                                        Some(Err(e)) => warn!("Failed to generate embedding for cache: {}", e),
+                                        None => {}
                                     }
                                 }.await;
                             }
+
+                            let reply = parse_thinking_response(&full_response);
+                            if let Err(e) = collected_self
+                                .record_exchange(&collected_conversation_id, &collected_message_id, &collected_message, &reply).await {
+                                warn!("Failed to add messages to history: {}", e);
+                            }
                             None
                         }
                         Err(e) => Some((Err(e), (stream, full_response))),
@@ -288,8 +692,43 @@ impl AIAgent {
                 }
             },
         );
-        
-        Ok(Box::pin(stream))
+
+        Ok(StreamedOutcome { stream: Box::pin(stream), metadata })
+    }
+
+    /// Answers a user turn again without duplicating it: the turn and every later message
+    /// are removed from history and the question is re-run under the same message ID.
+    /// Returns that ID with the new response stream and its retrieval metadata.
+    pub async fn regenerate_stream(
+        &self,
+        conversation_id: &str,
+        from_message_id: Option<&str>,
+        options: &MessageOptions,
+    ) -> Result<(String, StreamedOutcome), AgentError> {
+        let turn = self.history_store
+            .rewind(conversation_id, from_message_id).await?
+            .ok_or_else(|| match from_message_id {
+                Some(id) => format!("No message '{}' to regenerate", id),
+                None => "No previous message to regenerate".to_string(),
+            })?;
+        let message_id = turn.id.unwrap_or_else(|| Uuid::new_v4().to_string());
+        info!("Regenerating reply to message {} in conversation {}", message_id, conversation_id);
+        let options = MessageOptions {
+            message_id: Some(message_id.clone()),
+            bypass_cache: true,
+            ..options.clone()
+        };
+        match self.process_message_stream(conversation_id, &turn.content, &options).await {
+            Ok(outcome) => Ok((message_id, outcome)),
+            Err(e) => {
+                // Put the question back so a failed regeneration does not lose the turn.
+                if let Err(restore_err) = self.history_store
+                    .add_message_with_id(conversation_id, &message_id, "user", &turn.content, None).await {
+                    warn!("Failed to restore message {} after regeneration error: {}", message_id, restore_err);
+                }
+                Err(e)
+            }
+        }
     }
 
     async fn load_configs_and_schemas(
@@ -297,57 +736,159 @@ impl AIAgent {
         vector_store: &Arc<dyn VectorStore>
     ) -> Result<(SchemaFile, JsonValue), Box<dyn Error + Send + Sync>> {
         let schema_path = &args.schema_path;
-        let function_schema_dir = &args.function_schema_dir;
         let schemas = vector_store.generate_schema(schema_path).await?;
         let schema_file = SchemaFile { indexes: schemas };
-        let function_schema_path = PathBuf::from(function_schema_dir).join(
-            format!("{}.json", args.vector_type)
-        );
-        let function_schema_str = fs
-            ::read_to_string(&function_schema_path)
-            .map_err(|e|
-                format!(
-                    "Failed to read function schema file {}: {}",
-                    function_schema_path.display(),
-                    e
-                )
-            )?;
-        let function_schema: JsonValue = serde_json
-            ::from_str(&function_schema_str)
-            .map_err(|e|
-                format!(
-                    "Failed to parse function schema from {}: {}",
-                    function_schema_path.display(),
-                    e
-                )
-            )?;
-        info!(
-            "Loaded function schema for type '{}' from: {}",
-            args.vector_type,
-            function_schema_path.display()
-        );
+        let function_schema = Self::load_function_schema(args)?.ok_or_else(||
+            format!(
+                "No function schema found for type '{}' in {}",
+                args.vector_type,
+                args.function_schema_dir
+            )
+        )?;
 
         Ok((schema_file, function_schema))
     }
 
+    fn load_function_schema(args: &Args) -> Result<Option<JsonValue>, Box<dyn Error + Send + Sync>> {
+        function_schema::load_function_schema(
+            &args.function_schema_dir,
+            &args.vector_type,
+            args.function_schema_files.as_deref()
+        )
+    }
+
+    async fn resolve_dimension(
+        args: &Args,
+        embedding_client: &dyn EmbeddingClient
+    ) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        if !args.auto_detect_dimension {
+            if args.verify_embedding_dim {
+                Self::verify_dimension(args, embedding_client).await?;
+            }
+            return Ok(args.dimension);
+        }
+
+        let detected = probe_dimension(embedding_client).await.map_err(|e|
+            format!("Failed to auto-detect embedding dimension: {}", e)
+        )?;
+        if detected != args.dimension {
+            warn!(
+                "Auto-detected embedding dimension {} differs from configured VECTOR_DIMENSION {}. Using {}.",
+                detected,
+                args.dimension,
+                detected
+            );
+        }
+        info!("Effective vector dimension (auto-detected): {}", detected);
+        Ok(detected)
+    }
+
+    /// Embeds a probe and fails unless the vectors have `VECTOR_DIMENSION` entries.
+    async fn verify_dimension(
+        args: &Args,
+        embedding_client: &dyn EmbeddingClient
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let model = args.embedding_model.as_deref().unwrap_or("adapter default");
+        let returned = probe_dimension(embedding_client).await.map_err(|e|
+            format!(
+                "Failed to check the dimension of embedding model '{}' ({}): {}. Set VERIFY_EMBEDDING_DIM=false to skip the check",
+                model,
+                args.embedding_llm_type,
+                e
+            )
+        )?;
+        if returned == args.dimension {
+            return Ok(());
+        }
+        let llm_type = parse_llm_type(&args.embedding_llm_type)?;
+        let hint = if requested_dimensions(args, &llm_type, args.embedding_model.as_deref()).is_some() {
+            "set EMBEDDING_DIMENSION to match VECTOR_DIMENSION or leave it unset"
+        } else {
+            "set VECTOR_DIMENSION to the model's size (the collections must use it too) or pick a model with matching output"
+        };
+        Err(Box::new(AgentError::DimensionMismatch {
+            model: format!("{} ({})", model, args.embedding_llm_type),
+            expected: args.dimension,
+            actual: returned,
+            hint: hint.to_string(),
+        }))
+    }
+
+    async fn wrap_embedding_fallback(
+        args: &Args,
+        primary: Arc<dyn EmbeddingClient>
+    ) -> Result<Arc<dyn EmbeddingClient>, Box<dyn Error + Send + Sync>> {
+        let entries: Vec<&str> = match &args.embedding_fallback {
+            Some(list) => list.split(',').map(str::trim).filter(|s| !s.is_empty()).collect(),
+            None => Vec::new(),
+        };
+        if entries.is_empty() {
+            return Ok(primary);
+        }
+
+        let api_key = if !args.embedding_fallback_api_key.is_empty() {
+            Some(args.embedding_fallback_api_key.clone())
+        } else if !args.embedding_api_key.is_empty() {
+            Some(args.embedding_api_key.clone())
+        } else {
+            None
+        };
+
+        let mut providers = vec![(args.embedding_llm_type.clone(), primary)];
+        for entry in entries {
+            let (type_str, model) = match entry.split_once(':') {
+                Some((t, m)) => (t.trim(), Some(m.trim().to_string())),
+                None => (entry, None),
+            };
+            let llm_type = parse_llm_type(type_str)?;
+            let config = LlmConfig {
+                embedding_dimensions: requested_dimensions(args, &llm_type, model.as_deref()),
+                llm_type,
+                api_key: api_key.clone(),
+                embedding_model: model,
+                keep_alive: args.ollama_keep_alive.clone(),
+                ..LlmConfig::default()
+            };
+            providers.push((entry.to_string(), new_embedding_client(&config)?));
+        }
+
+        let fallback = FallbackEmbeddingClient::new(
+            providers,
+            args.circuit_breaker_threshold,
+            Duration::from_secs(args.circuit_breaker_cooldown_secs)
+        );
+        fallback.validate_dimensions(args.dimension).await?;
+        info!("Embedding fallback chain: {}", fallback.provider_names().join(" -> "));
+        Ok(Arc::new(fallback))
+    }
+
     pub async fn new(
-        args: Args, 
+        mut args: Args, 
         shared_prompt_config: Arc<RwLock<Arc<PromptConfig>>>
-    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+    ) -> Result<Self, AgentError> {
+        let current_prompt_config = shared_prompt_config.read().await.clone();
+        let actions = Arc::new(ActionRegistry::with_builtins());
+        let intent_routing = IntentRouting::from_args(&args)?;
+        Self::check_prompt_config(&args, &actions, &intent_routing, &current_prompt_config)?;
+        let cache_threshold = if args.enable_cache { cache::similarity_threshold(&args)? } else { 0.0 };
         let (chat_client, embedding_client, query_generation_client) = Self::initialize_llm_clients(
             &args
         ).await?;
+        args.dimension = Self::resolve_dimension(&args, &*embedding_client).await?;
+        let embedding_client = Self::wrap_embedding_fallback(&args, embedding_client).await?;
+        let embedding_client = TruncatingEmbeddingClient::wrap(&args, embedding_client)?;
+        let embedding_client = CachingEmbeddingClient::wrap(&args, embedding_client);
         let vector_store = Self::initialize_vector_store(&args).await?;
         let history_store = initialize_history_store(&args)?;
         let (schema_file, function_schema) = Self::load_configs_and_schemas(
             &args,
             &vector_store
         ).await?;
-        let cache = cache::init(&args).await;
+        Self::check_index_schemas(&args, &schema_file.indexes)?;
+        let cache = cache::init(&args, cache_threshold).await;
 
-        let current_prompt_config = shared_prompt_config.read().await.clone();
 
-        let rag_tool = RagEngine::new(
+        let rag_tool = Arc::new(RagEngine::new(
             Arc::clone(&vector_store),
             Arc::clone(&chat_client),
             Arc::clone(&embedding_client),
@@ -357,8 +898,17 @@ impl AIAgent {
             function_schema,
             args.vector_type.clone(),
             args.rag_default_limit,
-            args.llm_query
-        );
+            args.rag_context_docs,
+            args.rag_concurrency,
+            args.rag_field_max_chars,
+            args.rag_topic_retries,
+            args.rag_deterministic_fallback,
+            args.llm_query,
+            args.rag_filter_extraction,
+            args.use_query_client_for_routing,
+            args.classifier_max_output_chars,
+            args.rag_min_score
+        ));
 
         Ok(Self {
             chat_client,
@@ -368,167 +918,772 @@ impl AIAgent {
             prompt_config: shared_prompt_config,
             vector_store,
             history_store,
-            schema_last_reload: Some(SystemTime::now()),
+            schema_last_reload: Arc::new(StdMutex::new(Some(SystemTime::now()))),
             rag_default_limit: args.rag_default_limit,
+            rag_max_limit: args.rag_max_limit,
             vector_type: args.vector_type.clone(),
             enable_cache: args.enable_cache,
             cache,
-            prompts_path: args.prompts_path.clone(), 
+            // Remote prompts take precedence over the local file, which is then not watched.
+            prompts_path: if args.enable_remote_prompts { String::new() } else { args.prompts_path.clone() },
+            actions,
+            intent_routing,
+            response_language: args.response_language.clone().filter(|l| !l.trim().is_empty()),
+            history_store_thinking: args.history_store_thinking,
+            history_token_budget: args.history_token_budget,
+            conversation_metadata: args.conversation_metadata,
+            conversation_auto_title: args.conversation_metadata && args.conversation_auto_title,
+            request_timeout: (args.request_timeout_secs > 0).then(|| Duration::from_secs(args.request_timeout_secs)),
+            document_writer: ingest::create_document_writer(&args)?,
+            ingest_batch_size: args.ingest_batch_size.max(1),
+            embed_fields: Arc::new(ingest::load_embed_fields(&args.ingest_schema_path)?),
+            grounding: GroundingPolicy::from_args(&args)?,
+            intent_clients: IntentClients::from_args(&args)?,
+            use_query_client_for_routing: args.use_query_client_for_routing,
+            intent_client: Self::initialize_intent_client(&args)?,
+            intent_cache: IntentCache::new(args.intent_cache_size),
+        })
+    }
+
+    /// Builds an agent around the given clients and stores without opening any
+    /// connection: the cache, document ingestion and prompt file reloads are off.
+    /// The same client handles chat, query generation and routing.
+    #[cfg(feature = "testing")]
+    pub fn for_test(
+        args: Args,
+        prompt_config: PromptConfig,
+        chat_client: Arc<dyn ChatClient>,
+        embedding_client: Arc<dyn EmbeddingClient>,
+        vector_store: Arc<dyn VectorStore>,
+        history_store: Arc<dyn HistoryStore>,
+        index_schemas: Vec<IndexSchema>
+    ) -> Result<Self, AgentError> {
+        let prompt_config = Arc::new(prompt_config);
+        let rag_tool = Arc::new(RagEngine::new(
+            Arc::clone(&vector_store),
+            Arc::clone(&chat_client),
+            Arc::clone(&embedding_client),
+            Arc::clone(&chat_client),
+            index_schemas,
+            Arc::clone(&prompt_config),
+            JsonValue::Object(serde_json::Map::new()),
+            args.vector_type.clone(),
+            args.rag_default_limit,
+            args.rag_context_docs,
+            args.rag_concurrency,
+            args.rag_field_max_chars,
+            args.rag_topic_retries,
+            args.rag_deterministic_fallback,
+            args.llm_query,
+            args.rag_filter_extraction,
+            false,
+            args.classifier_max_output_chars,
+            args.rag_min_score
+        ));
+
+        Ok(Self {
+            query_generation_client: Arc::clone(&chat_client),
+            chat_client,
+            embedding_client,
+            rag_tool,
+            prompt_config: Arc::new(RwLock::new(prompt_config)),
+            vector_store,
+            history_store,
+            schema_last_reload: Arc::new(StdMutex::new(None)),
+            rag_default_limit: args.rag_default_limit,
+            rag_max_limit: args.rag_max_limit,
+            vector_type: args.vector_type.clone(),
+            enable_cache: false,
+            cache: CacheClients {
+                redis: None,
+                qdrant: None,
+                collection: args.cache_qdrant_collection.clone(),
+                threshold: 0.0,
+                ttl: 0,
+                key_prefix: String::new(),
+                context_sensitive: false,
+                model: None,
+            },
+            prompts_path: String::new(),
+            actions: Arc::new(ActionRegistry::with_builtins()),
+            intent_routing: IntentRouting::from_args(&args)?,
+            response_language: args.response_language.clone().filter(|l| !l.trim().is_empty()),
+            history_store_thinking: args.history_store_thinking,
+            history_token_budget: args.history_token_budget,
+            conversation_metadata: args.conversation_metadata,
+            conversation_auto_title: args.conversation_metadata && args.conversation_auto_title,
+            request_timeout: (args.request_timeout_secs > 0).then(|| Duration::from_secs(args.request_timeout_secs)),
+            document_writer: None,
+            ingest_batch_size: args.ingest_batch_size.max(1),
+            embed_fields: Arc::new(HashMap::new()),
+            grounding: GroundingPolicy::from_args(&args)?,
+            intent_clients: IntentClients::from_args(&args)?,
+            use_query_client_for_routing: false,
+            intent_client: None,
+            intent_cache: IntentCache::new(args.intent_cache_size),
         })
     }
 
+    /// Turns on the exact-match response cache at `--cache-redis-url` for a test agent.
+    /// The semantic Qdrant tier stays off.
+    #[cfg(feature = "testing")]
+    pub async fn with_response_cache(mut self, args: &Args) -> Self {
+        self.cache.redis = cache::redis::init(args).await;
+        self.cache.ttl = args.cache_redis_ttl;
+        self.cache.key_prefix = args.redis_key_prefix(&args.cache_redis_prefix);
+        self.enable_cache = self.cache.redis.is_some();
+        self
+    }
+
+    /// Watches `--prompts-path` for edits, as `new` does, on a test agent.
+    #[cfg(feature = "testing")]
+    pub fn with_prompts_file(mut self, args: &Args) -> Self {
+        self.prompts_path = args.prompts_path.clone();
+        self
+    }
+
     async fn execute_llm_interaction(
         &self,
         conversation_id: &str,
-        message: &str
-    ) -> Result<ThinkingResponse, Box<dyn Error + Send + Sync>> { 
-
-        if let Ok(true) = prompt::check_local_prompt_file_changed(&self.prompts_path) {
-            info!("Local prompts file changed, reloading...");
-            if let Ok(new_config) = prompt::load_prompts_from_str(&self.prompts_path) {
-                let mut write_lock = self.prompt_config.write().await;
-                *write_lock = new_config;
-                info!("Local prompts reloaded successfully");
-            }
-        }
-        
-        let conversation = self.history_store.get_conversation(
+        message: &str,
+        images: &[ImageInput],
+        options: &MessageOptions
+    ) -> Result<ThinkingResponse, Box<dyn Error + Send + Sync>> {
+        let routed = match self.route_message(conversation_id, message, options).await? {
+            Routing::Answered(reply) => return Ok(reply),
+            Routing::Action(routed) => routed,
+        };
+        routed.handler.handle(&self.action_context(&routed, conversation_id, message, images, options)).await
+    }
+
+    /// Streaming counterpart of `execute_llm_interaction`: the same intent routing,
+    /// with the action's answer streamed.
+    async fn stream_llm_interaction(
+        &self,
+        conversation_id: &str,
+        message: &str,
+        images: &[ImageInput],
+        options: &MessageOptions
+    ) -> Result<StreamedOutcome, Box<dyn Error + Send + Sync>> {
+        let routed = match self.route_message(conversation_id, message, options).await? {
+            Routing::Answered(reply) => return Ok(StreamedOutcome::complete(reply)),
+            Routing::Action(routed) => routed,
+        };
+        routed.handler.handle_stream(&self.action_context(&routed, conversation_id, message, images, options)).await
+    }
+
+    fn action_context<'a>(
+        &'a self,
+        routed: &'a RoutedMessage,
+        conversation_id: &'a str,
+        message: &'a str,
+        images: &'a [ImageInput],
+        options: &'a MessageOptions
+    ) -> ActionContext<'a> {
+        ActionContext {
             conversation_id,
-            HISTORY_FOR_PROMPT_LEN
-        ).await?;
-        let history_str = format_history_for_prompt(&conversation);
-        let current_prompt_config = self.prompt_config.read().await;
+            message,
+            history: &routed.history_str,
+            history_messages: &routed.history.messages,
+            images,
+            prompt_config: &routed.prompt_config,
+            chat_client: &*routed.chat_client,
+            rag_tool: &self.rag_tool,
+            rag_limit: self.rag_limit_for(options.rag_limit),
+            language: self.response_language_for(options),
+            progress: options.progress.as_ref(),
+            grounding: self.grounding.as_ref(),
+        }
+    }
+
+    /// Classifies the message and picks the action handler and chat client for its intent.
+    async fn route_message(
+        &self,
+        conversation_id: &str,
+        message: &str,
+        options: &MessageOptions
+    ) -> Result<Routing, Box<dyn Error + Send + Sync>> {
+        let progress = options.progress.as_ref();
+
+        self.reload_prompts_file_if_changed().await;
+
+        let history = self.prompt_history(conversation_id, options.window_policy).await?;
+        let history_str = format_history_for_prompt(&history);
+        let current_prompt_config = self.prompt_snapshot().await;
+        report_progress(progress, ProgressStage::Classifying, None);
         let intent_prompt = prompt::get_intent_prompt(&current_prompt_config, message)?;
-        let intent_response = self.chat_client.complete(&intent_prompt).await?;
-        let intent_name = intent_response.response.trim();
-        let intent_definition = current_prompt_config.intents
-            .get(intent_name)
-            .ok_or_else(|| prompt::PromptError::IntentNotFound(intent_name.to_string()))?;
-
-        match intent_definition.action.as_str() {
-            "call_rag_tool" => {
-                let rag_args = RagQueryArgs {
-                    query: message.to_string(),
-                    limit: Some(self.rag_default_limit),
+        let intent_cache_key = self.intent_cache.key(&current_prompt_config, message)?;
+        let intent_output = match self.intent_cache.get(intent_cache_key) {
+            Some(output) => {
+                info!("Intent classification served from the intent cache");
+                output
+            }
+            None => {
+                let routing_client = if let Some(intent_client) = &self.intent_client {
+                    info!("Intent classification handled by the intent client");
+                    intent_client
+                } else if self.use_query_client_for_routing {
+                    info!("Intent classification handled by the query generation client");
+                    &self.query_generation_client
+                } else {
+                    info!("Intent classification handled by the chat client");
+                    &self.chat_client
                 };
-                
-                let (documents, topic, schema_json) = self.rag_tool.get_documents_for_query(rag_args).await?;
-                
-                let docs_text = documents.iter()
-                    .map(|doc| doc.to_string())
-                    .collect::<Vec<_>>()
-                    .join("\n");
-                
-                let final_prompt = prompt::get_rag_final_prompt(
-                    &current_prompt_config, 
-                    &schema_json,
-                    &topic,
-                    &docs_text,
-                    message
-                )?;
-                
-                drop(current_prompt_config);
-                let resp = self.chat_client.complete(&final_prompt).await?;
-                Ok(parse_thinking_response(&resp.response))
+                let intent_response = routing_client.complete(&intent_prompt).await?;
+                let output = prompt::cap_classifier_output(
+                    &intent_response.response,
+                    self.intent_routing.max_output_chars,
+                    "Intent"
+                );
+                self.intent_cache.insert(intent_cache_key, output);
+                output.to_string()
+            }
+        };
+        let classification = prompt::parse_intent_classification(&intent_output);
+        let mut intent_name = classification.intent;
+        // A missing confidence (plain intent name) is treated as certain.
+        let confidence = classification.confidence.unwrap_or(1.0);
+        if confidence < self.intent_routing.confidence_threshold {
+            match &self.intent_routing.low_confidence {
+                LowConfidenceAction::Clarify => {
+                    info!("Intent '{}' below confidence threshold ({:.2}); asking for clarification", intent_name, confidence);
+                    return Ok(Routing::Answered(ThinkingResponse {
+                        thinking: String::new(),
+                        response: prompt::get_intent_clarification(&current_prompt_config, message),
+                        metadata: None,
+                    }));
+                }
+                LowConfidenceAction::Default(default_intent) => {
+                    info!("Intent '{}' below confidence threshold ({:.2}); using '{}'", intent_name, confidence, default_intent);
+                    intent_name = default_intent.clone();
+                }
+            }
+        }
+        let intent_definition = current_prompt_config.intents
+            .get(&intent_name)
+            .ok_or_else(|| prompt::PromptError::IntentNotFound(intent_name.clone()))?;
+
+        let handler = self.actions.get(&intent_definition.action).ok_or_else(||
+            prompt::PromptError::ActionError(
+                format!("Action '{}' is not implemented.", intent_definition.action)
+            )
+        )?;
+
+        let chat_client = self.intent_clients
+            .client_for(intent_definition)?
+            .unwrap_or_else(|| Arc::clone(&self.chat_client));
+
+        Ok(Routing::Action(RoutedMessage {
+            handler,
+            chat_client,
+            history,
+            history_str,
+            prompt_config: current_prompt_config,
+        }))
+    }
+
+    /// Per-request copy of the prompt config. The lock is released before returning,
+    /// so prompt reloads never wait on in-flight LLM calls.
+    /// Picks up edits to the watched prompts file before a message is routed. A file
+    /// that fails to load is logged and the current prompts stay in use.
+    async fn reload_prompts_file_if_changed(&self) {
+        if self.prompts_path.is_empty() {
+            return;
+        }
+        let current_prompt_config = self.prompt_snapshot().await;
+        match prompt::reload_prompts_if_changed(&self.prompts_path, &current_prompt_config) {
+            Ok(Some(new_config)) => {
+                self.swap_prompt_config(new_config).await;
+                info!("Local prompts reloaded from {}", self.prompts_path);
             }
-            "general_llm_call" => {
-                let prompt_with_history = format!("{}\n\nUser: {}", history_str, message);
-                drop(current_prompt_config);
-                let resp = self.chat_client.complete(&prompt_with_history).await?;
-                Ok(parse_thinking_response(&resp.response))  
+            Ok(None) => {}
+            Err(e) => warn!("Failed to reload prompts from {}: {}", self.prompts_path, e),
+        }
+    }
+
+    /// Swaps in `new_config` together with the RAG state built on it, keeping the
+    /// current index and function schemas.
+    async fn swap_prompt_config(&self, new_config: Arc<PromptConfig>) {
+        Self::warn_unknown_actions(&self.actions, &new_config);
+        let state = self.rag_tool.state();
+        self.rag_tool.swap_state(RagEngineState {
+            index_schemas: state.index_schemas.clone(),
+            prompt_config: Arc::clone(&new_config),
+            function_schema: state.function_schema.clone(),
+        });
+        *self.prompt_config.write().await = new_config;
+    }
+
+    async fn prompt_snapshot(&self) -> Arc<PromptConfig> {
+        Arc::clone(&*self.prompt_config.read().await)
+    }
+
+    /// Answer cleanup rules from the current prompt config.
+    pub async fn response_postprocessor(&self) -> Postprocessor {
+        self.prompt_snapshot().await.response_postprocess.clone()
+    }
+
+    /// Stores a user message and the assistant reply. Reasoning is never part of the
+    /// stored content; it is kept separately only when `history_store_thinking` is set.
+    async fn record_exchange(
+        &self,
+        conversation_id: &str,
+        message_id: &str,
+        message: &str,
+        reply: &ThinkingResponse
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.history_store.add_message_with_id(conversation_id, message_id, "user", message, None).await?;
+        let thinking = Some(reply.thinking.trim())
+            .filter(|t| self.history_store_thinking && !t.is_empty());
+        self.history_store
+            .add_message_with_thinking(conversation_id, "assistant", &reply.response, thinking).await?;
+        if self.conversation_metadata {
+            if let Err(e) = self.touch_conversation(conversation_id, message).await {
+                warn!("Failed to update metadata of conversation {}: {}", conversation_id, e);
             }
-            unknown_action => {
-                Err(
-                    Box::new(
-                        prompt::PromptError::ActionError(
-                            format!("Action '{}' is not implemented.", unknown_action)
-                        )
-                    )
+        }
+        Ok(())
+    }
+
+    /// Creates or refreshes a conversation's metadata after a stored exchange. The first
+    /// exchange gets a title in the background when `conversation_auto_title` is set.
+    async fn touch_conversation(&self, conversation_id: &str, message: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let now = Utc::now().timestamp();
+        let existing = self.history_store.get_metadata(conversation_id).await?;
+        let is_new = existing.is_none();
+        let mut metadata = existing.unwrap_or_else(|| ConversationMetadata {
+            id: conversation_id.to_string(),
+            created_at: now,
+            ..Default::default()
+        });
+        metadata.last_active = now;
+        self.history_store.set_metadata(&metadata).await?;
+
+        if is_new && self.conversation_auto_title {
+            let agent = self.clone();
+            let conversation_id = conversation_id.to_string();
+            let message = message.to_string();
+            tokio::spawn(async move {
+                if let Err(e) = agent.generate_title(&conversation_id, &message).await {
+                    warn!("Failed to generate a title for conversation {}: {}", conversation_id, e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    async fn generate_title(&self, conversation_id: &str, message: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let config = self.prompt_snapshot().await;
+        let prompt = prompt::get_conversation_title_prompt(&config, message);
+        let response = self.query_generation_client.complete(&prompt).await?;
+        let reply = parse_thinking_response(&response.response);
+        let title: String = reply.response
+            .lines()
+            .map(|line| line.trim().trim_matches(|c| c == '"' || c == '\'' || c == '*'))
+            .find(|line| !line.is_empty())
+            .unwrap_or_default()
+            .chars()
+            .take(MAX_TITLE_CHARS)
+            .collect();
+        if title.is_empty() {
+            return Ok(());
+        }
+
+        // Re-read so activity and edits made while the title was generated are kept.
+        let Some(mut metadata) = self.history_store.get_metadata(conversation_id).await? else {
+            return Ok(());
+        };
+        if metadata.title.is_some() {
+            return Ok(());
+        }
+        info!("Generated title for conversation {}: {}", conversation_id, title);
+        metadata.title = Some(title);
+        self.history_store.set_metadata(&metadata).await
+    }
+
+    pub fn tracks_conversations(&self) -> bool {
+        self.conversation_metadata
+    }
+
+    /// Conversations with metadata, most recently active first.
+    pub async fn list_conversations(&self, limit: usize) -> Result<Vec<ConversationMetadata>, AgentError> {
+        self.history_store.list_conversations(limit).await.map_err(AgentError::History)
+    }
+
+    /// Replaces the given fields of a conversation's metadata; an empty title clears it.
+    /// Returns `None` when the conversation has no metadata.
+    pub async fn update_conversation(
+        &self,
+        conversation_id: &str,
+        title: Option<String>,
+        tags: Option<Vec<String>>
+    ) -> Result<Option<ConversationMetadata>, AgentError> {
+        let Some(mut metadata) = self.history_store.get_metadata(conversation_id).await.map_err(AgentError::History)? else {
+            return Ok(None);
+        };
+        if let Some(title) = title {
+            metadata.title = Some(title.trim().to_string()).filter(|t| !t.is_empty());
+        }
+        if let Some(tags) = tags {
+            metadata.tags = tags
+                .into_iter()
+                .map(|tag| tag.trim().to_string())
+                .filter(|tag| !tag.is_empty())
+                .collect();
+        }
+        self.history_store.set_metadata(&metadata).await.map_err(AgentError::History)?;
+        Ok(Some(metadata))
+    }
+
+    /// Permanently deletes a conversation's history and metadata.
+    pub async fn delete_conversation(&self, conversation_id: &str) -> Result<(), AgentError> {
+        self.history_store.delete_conversation(conversation_id).await.map_err(AgentError::History)?;
+        info!("Deleted conversation {}", conversation_id);
+        Ok(())
+    }
+
+    fn message_id_for(options: &MessageOptions) -> String {
+        options.message_id.clone().unwrap_or_else(|| Uuid::new_v4().to_string())
+    }
+
+    fn is_cacheable(images: &[ImageInput], options: &MessageOptions) -> bool {
+        images.is_empty() && options.language.is_none() && options.rag_limit.is_none() && !options.bypass_cache
+    }
+
+    fn response_language_for<'a>(&'a self, options: &'a MessageOptions) -> Option<&'a str> {
+        options.language
+            .as_deref()
+            .filter(|l| !l.trim().is_empty())
+            .or(self.response_language.as_deref())
+    }
+
+    /// Per-request RAG limit clamped to `--rag-max-limit`, or the server default.
+    fn rag_limit_for(&self, requested: Option<usize>) -> usize {
+        match requested {
+            Some(limit) if self.rag_max_limit > 0 => limit.clamp(1, self.rag_max_limit),
+            Some(limit) => limit.max(1),
+            None => self.rag_default_limit,
+        }
+    }
+
+    /// Registers (or replaces) the handler for an intent action name.
+    pub fn register_action(&mut self, name: impl Into<String>, handler: Arc<dyn ActionHandler>) {
+        Arc::make_mut(&mut self.actions).register(name, handler);
+    }
+
+    /// Startup config validation: warnings by default, a startup error with `--strict-config`.
+    fn check_prompt_config(
+        args: &Args,
+        actions: &ActionRegistry,
+        intent_routing: &IntentRouting,
+        config: &PromptConfig
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut issues: Vec<String> = actions
+            .unknown_actions(config)
+            .into_iter()
+            .map(|(intent, action)| format!("Intent '{}' references unregistered action '{}'", intent, action))
+            .collect();
+        if let LowConfidenceAction::Default(intent) = &intent_routing.low_confidence {
+            if !config.intents.contains_key(intent) {
+                issues.push(format!("Default intent '{}' is not defined in the prompt configuration", intent));
+            }
+        }
+        for (intent, def) in &config.intents {
+            if let Some(Err(e)) = def.provider.as_deref().map(parse_llm_type) {
+                issues.push(format!("Intent '{}' has an invalid provider: {}", intent, e));
+            }
+        }
+        issues.extend(prompt::config_issues(config));
+        if args.strict_config && !issues.is_empty() {
+            return Err(Box::new(AgentError::Config(
+                format!("Invalid prompt configuration (strict mode):\n- {}", issues.join("\n- ")).into()
+            )));
+        }
+        for issue in &issues {
+            warn!("{}", issue);
+        }
+        Ok(())
+    }
+
+    /// Startup check that the vector store has indexes to search and the `--rag-required-topics`
+    /// among them: warnings by default, a startup error with `--strict-config`.
+    fn check_index_schemas(args: &Args, indexes: &[IndexSchema]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let issues = Self::index_schema_issues(args, indexes);
+        if args.strict_config && !issues.is_empty() {
+            return Err(Box::new(AgentError::Config(
+                format!("Invalid vector store setup (strict mode):\n- {}", issues.join("\n- ")).into()
+            )));
+        }
+        for issue in &issues {
+            warn!("{}", issue);
+        }
+        Ok(())
+    }
+
+    fn index_schema_issues(args: &Args, indexes: &[IndexSchema]) -> Vec<String> {
+        let mut issues = Vec::new();
+        // History and cache collections can share the store but hold no documents.
+        let document_indexes = indexes
+            .iter()
+            .filter(|s| s.name != args.history_qdrant_collection && s.name != args.cache_qdrant_collection)
+            .count();
+        if document_indexes == 0 {
+            issues.push(
+                format!(
+                    "No indexes were found in the {} store (check VECTOR_HOST); every RAG question will fail until documents are ingested",
+                    args.vector_type
                 )
+            );
+        }
+        let required = args.rag_required_topics.as_deref().unwrap_or("");
+        for topic in required.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+            if !indexes.iter().any(|s| s.name == topic) {
+                issues.push(format!("Required topic '{}' does not exist in the {} store", topic, args.vector_type));
             }
         }
+        issues
+    }
+
+    fn warn_unknown_actions(actions: &ActionRegistry, config: &PromptConfig) {
+        for (intent, action) in actions.unknown_actions(config) {
+            warn!("Intent '{}' references unregistered action '{}'", intent, action);
+        }
     }
 
     pub async fn process_message(
         &self,
         conversation_id: &str,
         message: &str
+    ) -> Result<ThinkingResponse, AgentError> {
+        self.process_message_with_options(conversation_id, message, &MessageOptions::default()).await
+    }
+
+    /// Answers `message`, giving up with a `RequestTimeout` once `--request-timeout-secs`
+    /// passes. Dropping the work on timeout cancels its retrieval and LLM calls.
+    pub async fn process_message_with_options(
+        &self,
+        conversation_id: &str,
+        message: &str,
+        options: &MessageOptions
+    ) -> Result<ThinkingResponse, AgentError> {
+        let answer = self.answer_message(conversation_id, message, options);
+        let mut response = match self.request_timeout {
+            Some(limit) => tokio::time::timeout(limit, answer).await.map_err(|_| {
+                warn!("Message in conversation {} timed out after {:?}", conversation_id, limit);
+                RequestTimeout(limit)
+            })??,
+            None => answer.await?,
+        };
+        response.response = self.response_postprocessor().await.apply(&response.response, true);
+        Ok(response)
+    }
+
+    async fn answer_message(
+        &self,
+        conversation_id: &str,
+        message: &str,
+        options: &MessageOptions
     ) -> Result<ThinkingResponse, Box<dyn Error + Send + Sync>> {  
         let normalized = message.trim().to_lowercase();
-      
+        let message_id = Self::message_id_for(options);
+        let images = self.prepare_images(&options.images)?;
+        let cacheable = Self::is_cacheable(&images, options);
+        let cache_context = if self.enable_cache && cacheable {
+            self.cache_context(conversation_id, options.window_policy).await?
+        } else {
+            None
+        };
 
-        if self.enable_cache {
-            if let Some((resp, _emb)) =
-                cache::check(&self.cache, &normalized, &*self.embedding_client).await?
+        if self.enable_cache && cacheable {
+            if let Some((cached, _emb)) =
+                cache::check(&self.cache, &normalized, cache_context.as_deref(), &*self.embedding_client).await?
             {
                 info!("✅ Cache Hit");
-                self.history_store.add_message(conversation_id, "user", message).await?;
-                self.history_store.add_message(conversation_id, "assistant", &resp).await?;
-                return Ok(ThinkingResponse {
-                    thinking: String::new(),
-                    response: resp.to_string(),
-                });
+                let reply = cached_reply(&cached);
+                self.record_exchange(conversation_id, &message_id, message, &reply).await?;
+                return Ok(reply);
             }
         }
 
         info!("ℹ️ Cache Miss. Proceeding with LLM call…");
-        let thinking_response = self.execute_llm_interaction(conversation_id, message).await?;
+        let thinking_response = self.execute_llm_interaction(
+            conversation_id,
+            message,
+            &images,
+            options
+        ).await?;
 
-        if self.enable_cache {
-            let emb_to_use = self.embedding_client.embed(&normalized).await?.embedding;
-            cache::update(&self.cache, &normalized, &thinking_response.response, emb_to_use).await?;
+        if self.enable_cache && cacheable {
+            let emb_to_use = self.embedding_client.embed_with_type(&normalized, EmbeddingInputType::Query).await?.embedding;
+            let thinking = Some(thinking_response.thinking.as_str()).filter(|t| !t.is_empty());
+            cache::update(
+                &self.cache,
+                &normalized,
+                cache_context.as_deref(),
+                &thinking_response.response,
+                thinking,
+                emb_to_use
+            ).await?;
         }
 
-        self.history_store.add_message(conversation_id, "user", message).await?;
-        self.history_store.add_message(conversation_id, "assistant", &thinking_response.response).await?;
+        self.record_exchange(conversation_id, &message_id, message, &thinking_response).await?;
 
         Ok(thinking_response)
     }
 
+    /// Whether `topic` is an index in the loaded schema.
+    pub fn has_topic(&self, topic: &str) -> bool {
+        self.rag_tool.index_fields(topic).is_some()
+    }
+
+    /// Retrieval without answer synthesis: the scored documents for `query` from `topic`
+    /// (inferred when `None`) that pass `filters`, up to `limit` clamped like a chat `rag_limit`.
+    pub async fn search_documents(
+        &self,
+        query: &str,
+        topic: Option<String>,
+        limit: Option<usize>,
+        filters: Vec<FieldFilter>
+    ) -> Result<(Vec<Document>, RetrievalMetadata), AgentError> {
+        Ok(self.rag_tool.search(RagQueryArgs {
+            query: query.to_string(),
+            limit: Some(self.rag_limit_for(limit)),
+            topic,
+            filters,
+        }).await?)
+    }
+
+    /// Whether the configured vector store supports `ingest_documents`.
+    pub fn can_ingest(&self) -> bool {
+        self.document_writer.is_some()
+    }
+
+    /// Embeds and upserts documents into `topic`, reporting the outcome per document.
+    /// `text_fields` defaults to the topic's declared `embed_fields` (which every document
+    /// must contain), then to its schema fields, then to every string field.
+    pub async fn ingest_documents(
+        &self,
+        topic: &str,
+        documents: Vec<IngestDocument>,
+        text_fields: Option<Vec<String>>
+    ) -> Result<Vec<IngestResult>, AgentError> {
+        let writer = self.document_writer
+            .as_ref()
+            .ok_or_else(||
+                AgentError::Config(format!("Document ingestion is not supported for vector store '{}'", self.vector_type).into())
+            )?;
+        let declared = self.embed_fields.get(topic).filter(|_| text_fields.is_none());
+        let text_fields = text_fields
+            .or_else(|| declared.cloned())
+            .or_else(|| self.rag_tool.index_fields(topic))
+            .unwrap_or_default();
+
+        let total = documents.len();
+        let mut results: Vec<Option<IngestResult>> = (0..total).map(|_| None).collect();
+        let mut batch: Vec<(usize, PreparedDocument)> = Vec::new();
+        for (i, doc) in documents.into_iter().enumerate() {
+            let id = doc.id.filter(|id| !id.trim().is_empty()).unwrap_or_else(|| Uuid::new_v4().to_string());
+            let text = ingest::embedding_text(&doc.fields, &text_fields);
+            if let Err(e) = writer.validate_id(&id) {
+                results[i] = Some(IngestResult::failed(Some(id), e));
+            } else if let Some(Err(e)) = declared.map(|fields| ingest::check_embed_fields(&doc.fields, fields)) {
+                results[i] = Some(IngestResult::failed(Some(id), e));
+            } else if text.is_empty() {
+                results[i] = Some(IngestResult::failed(Some(id), "document has no text to embed"));
+            } else {
+                match self.embedding_client.embed_with_type(&text, EmbeddingInputType::Document).await {
+                    Ok(emb) => batch.push((i, PreparedDocument { id, vector: emb.embedding, fields: doc.fields })),
+                    Err(e) => results[i] = Some(IngestResult::failed(Some(id), format!("embedding failed: {}", e))),
+                }
+            }
+
+            if batch.len() >= self.ingest_batch_size || (i + 1 == total && !batch.is_empty()) {
+                let (positions, prepared): (Vec<usize>, Vec<PreparedDocument>) = std::mem::take(&mut batch).into_iter().unzip();
+                let ids: Vec<String> = prepared.iter().map(|d| d.id.clone()).collect();
+                let outcome = writer.upsert(topic, prepared).await.map_err(|e| format!("upsert failed: {}", e));
+                for (pos, id) in positions.into_iter().zip(ids) {
+                    results[pos] = Some(match &outcome {
+                        Ok(()) => IngestResult::ok(id),
+                        Err(message) => IngestResult::failed(Some(id), message.clone()),
+                    });
+                }
+            }
+        }
+        let results: Vec<IngestResult> = results.into_iter().flatten().collect();
+
+        let indexed = results.iter().filter(|r| r.success).count();
+        info!("Ingested {}/{} documents into '{}'", indexed, total, topic);
+        Ok(results)
+    }
+
+    /// Primes provider connections and loads local models so the first request
+    /// does not pay the cold-start cost. Failures are logged, never fatal.
+    pub async fn warm_up(&self) {
+        let started = Instant::now();
+        warm_up_step("chat model", self.chat_client.complete("Reply with OK.")).await;
+        warm_up_step(
+            "embedding model",
+            self.embedding_client.embed_with_type("warm-up", EmbeddingInputType::Query)
+        ).await;
+        warm_up_step("history store", self.history_store.get_conversation("warm-up", 1)).await;
+        if self.enable_cache {
+            warm_up_step("cache", cache::ping(&self.cache)).await;
+        }
+        info!("Warm-up finished in {:?}", started.elapsed());
+    }
+
+    /// Readiness probe for the vector store: counts the documents of the first index.
+    pub async fn ping_vector_store(&self) -> Result<(), AgentError> {
+        let state = self.rag_tool.state();
+        let index = state.index_schemas.first().ok_or_else(|| AgentError::VectorStore("No indexes are loaded".into()))?;
+        self.vector_store.count_documents(&index.name).await.map_err(AgentError::VectorStore)?;
+        Ok(())
+    }
+
+    /// Readiness probe for the response cache backends; `None` while the cache is disabled.
+    pub async fn ping_cache(&self) -> Option<Result<(), AgentError>> {
+        if !self.enable_cache {
+            return None;
+        }
+        Some(cache::ping(&self.cache).await.map_err(AgentError::Cache))
+    }
+
+    /// Readiness probe for the embedding provider.
+    pub async fn ping_embedding(&self) -> Result<(), AgentError> {
+        self.embedding_client.embed_with_type("ready", EmbeddingInputType::Query).await.map_err(AgentError::Llm)?;
+        Ok(())
+    }
+
+    /// Reloads prompts when the file changed. The new RAG state is built before it is
+    /// swapped in, so requests are never blocked and in-flight ones keep the old state.
     pub async fn reload_prompts_if_changed(
-        &mut self,
+        &self,
         args: &Args
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    ) -> Result<bool, AgentError> {
         let prompts_path = &args.prompts_path;
         let schema_path = &args.schema_path;
-        let function_schema_dir = &args.function_schema_dir;
 
-        let current_prompt_config = self.prompt_config.read().await.clone();
+        let current_prompt_config = self.prompt_snapshot().await;
         let result = prompt::reload_prompts_if_changed(prompts_path, &current_prompt_config)?;
 
         if let Some(new_config) = result {
-            let schema_text = fs::read_to_string(schema_path)?;
-            let schema_file: SchemaFile = serde_json::from_str(&schema_text)?;
-            let function_schema_path = PathBuf::from(function_schema_dir).join(
-                format!("{}.json", args.vector_type)
-            );
-            let function_schema: JsonValue = match fs::read_to_string(&function_schema_path) {
-                Ok(text) => serde_json::from_str(&text)?,
-                Err(_) => {
-                    warn!(
-                        "Function schema not found during reload at {}. Using empty schema.",
-                        function_schema_path.display()
-                    );
-                    JsonValue::Object(serde_json::Map::new())
-                }
-            };
+            let schema_text = fs::read_to_string(schema_path).map_err(|e| AgentError::Config(e.into()))?;
+            let schema_file: SchemaFile = serde_json::from_str(&schema_text).map_err(|e| AgentError::Config(e.into()))?;
+            let function_schema = Self::load_function_schema(args)?.unwrap_or_else(|| {
+                warn!(
+                    "Function schema not found during reload in {}. Using empty schema.",
+                    args.function_schema_dir
+                );
+                JsonValue::Object(serde_json::Map::new())
+            });
+            Self::warn_unknown_actions(&self.actions, &new_config);
 
-            let mut prompt_write = self.prompt_config.write().await;
-            *prompt_write = Arc::clone(&new_config);
-            drop(prompt_write);
-
-            self.rag_tool = RagEngine::new(
-                Arc::clone(&self.vector_store),
-                Arc::clone(&self.chat_client),
-                Arc::clone(&self.embedding_client),
-                Arc::clone(&self.query_generation_client),
-                schema_file.indexes.clone(),
-                new_config,
+            self.rag_tool.swap_state(RagEngineState {
+                index_schemas: schema_file.indexes,
+                prompt_config: Arc::clone(&new_config),
                 function_schema,
-                self.vector_type.clone(),
-                args.rag_default_limit,
-                args.llm_query
-            );
+            });
+            *self.prompt_config.write().await = new_config;
 
             info!("Prompts and function schema successfully reloaded");
             Ok(true)
@@ -537,61 +1692,52 @@ impl AIAgent {
         }
     }
 
+    /// Regenerates the index schema from the vector store and swaps it in without
+    /// blocking requests; in-flight ones finish against the previous schema.
     pub async fn reload_schema_if_needed(
-        &mut self,
+        &self,
         args: &Args
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    ) -> Result<bool, AgentError> {
         let schema_path = &args.schema_path;
-        let function_schema_dir = &args.function_schema_dir;
-        let schemas = self.vector_store.generate_schema(schema_path).await?;
-        let function_schema_path = PathBuf::from(function_schema_dir).join(
-            format!("{}.json", args.vector_type)
-        );
-        let function_schema: JsonValue = match fs::read_to_string(&function_schema_path) {
-            Ok(text) => serde_json::from_str(&text)?,
-            Err(_) => {
-                warn!(
-                    "Function schema not found during schema reload at {}. Using empty schema.",
-                    function_schema_path.display()
-                );
-                JsonValue::Object(serde_json::Map::new())
-            }
-        };
+        let schemas = self.vector_store.generate_schema(schema_path).await.map_err(AgentError::VectorStore)?;
+        for issue in Self::index_schema_issues(args, &schemas) {
+            warn!("{}", issue);
+        }
+        let function_schema = Self::load_function_schema(args)?.unwrap_or_else(|| {
+            warn!(
+                "Function schema not found during schema reload in {}. Using empty schema.",
+                args.function_schema_dir
+            );
+            JsonValue::Object(serde_json::Map::new())
+        });
 
-        let current_prompt_config = self.prompt_config.read().await.clone();
+        let current_prompt_config = self.prompt_snapshot().await;
 
-        self.rag_tool = RagEngine::new(
-            Arc::clone(&self.vector_store),
-            Arc::clone(&self.chat_client),
-            Arc::clone(&self.embedding_client),
-            Arc::clone(&self.query_generation_client),
-            schemas,
-            current_prompt_config,
+        self.rag_tool.swap_state(RagEngineState {
+            index_schemas: schemas,
+            prompt_config: current_prompt_config,
             function_schema,
-            self.vector_type.clone(),
-            args.rag_default_limit,
-            args.llm_query
-        );
+        });
 
-        self.schema_last_reload = Some(SystemTime::now());
+        *self.schema_last_reload.lock().unwrap() = Some(SystemTime::now());
         info!("Schema successfully reloaded");
         Ok(true)
     }
 
     pub async fn force_refresh_remote_prompts(
-        &mut self,
+        &self,
         args: &Args
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    ) -> Result<bool, AgentError> {
         if !args.enable_remote_prompts {
             return Ok(false);
         }
         
         let project_id = args.remote_prompts_project_id.as_deref().ok_or_else(|| {
-            "Missing REMOTE_PROMPTS_PROJECT_ID".to_string()
+            AgentError::Config("Missing REMOTE_PROMPTS_PROJECT_ID".into())
         })?;
         
         let sa_key_path = args.remote_prompts_sa_key_path.as_deref().ok_or_else(|| {
-            "Missing REMOTE_PROMPTS_SA_KEY_PATH".to_string()
+            AgentError::Config("Missing REMOTE_PROMPTS_SA_KEY_PATH".into())
         })?;
         
         let remote_client = crate::config::remote_config::RemoteConfigClient::new();
@@ -600,26 +1746,46 @@ impl AIAgent {
             Ok(Some(json_str)) => {  
                 match crate::config::prompt::load_prompts_from_str(&json_str) {
                     Ok(new_config) => {
-                        let mut w = self.prompt_config.write().await;
-                        *w = new_config;
-                        drop(w);
-                        
+                        self.swap_prompt_config(new_config).await;
                         info!("Remote prompts successfully refreshed via webhook");
                         Ok(true)
                     }
-                    Err(e) => Err(Box::new(e)),
+                    Err(e) => Err(e.into()),
                 }
             },
             Ok(None) => {
                 warn!("No remote prompts found for project ID: {}", project_id);
                 Ok(false)
             },
-            Err(e) => Err(Box::new(e)),
+            Err(e) => Err(AgentError::Other(Box::new(e))),
         }
     }
 }
 
-fn parse_thinking_response(full_response: &str) -> ThinkingResponse {
+async fn warm_up_step<T>(
+    name: &str,
+    fut: impl std::future::Future<Output = Result<T, Box<dyn Error + Send + Sync>>>
+) {
+    let started = Instant::now();
+    match fut.await {
+        Ok(_) => info!("Warm-up: {} ready in {:?}", name, started.elapsed()),
+        Err(e) => warn!("Warm-up: {} failed after {:?}: {}", name, started.elapsed(), e),
+    }
+}
+
+/// The reply held by a cache entry; legacy plain entries may still carry inline `<think>` tags.
+fn cached_reply(cached: &CacheEntry) -> ThinkingResponse {
+    match &cached.thinking {
+        Some(thinking) => ThinkingResponse {
+            thinking: thinking.clone(),
+            response: cached.response.clone(),
+            metadata: None,
+        },
+        None => parse_thinking_response(&cached.response),
+    }
+}
+
+pub fn parse_thinking_response(full_response: &str) -> ThinkingResponse {
     if let Some(thinking_start) = full_response.find("<think>") {
         if let Some(thinking_end) = full_response.find("</think>") {
             let thinking = &full_response[thinking_start + 7..thinking_end];
@@ -627,6 +1793,7 @@ fn parse_thinking_response(full_response: &str) -> ThinkingResponse {
             return ThinkingResponse {
                 thinking: thinking.to_string(),
                 response: response.to_string(),
+                metadata: None,
             };
         }
     }
@@ -634,5 +1801,49 @@ fn parse_thinking_response(full_response: &str) -> ThinkingResponse {
     ThinkingResponse {
         thinking: String::new(),
         response: full_response.to_string(),
+        metadata: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prompt_config() -> PromptConfig {
+        prompt::load_prompts_from_str(include_str!("../json/prompts.json")).unwrap().as_ref().clone()
+    }
+
+    #[test]
+    fn intent_cache_keys_normalize_case_and_whitespace() {
+        let config = prompt_config();
+        let cache = IntentCache::new(4);
+        let key = cache.key(&config, "What are your  skills?").unwrap();
+        cache.insert(key, "PROFILE_INFO");
+
+        let same = cache.key(&config, "  what are your skills? ").unwrap();
+        assert_eq!(same, key);
+        assert_eq!(cache.get(same).as_deref(), Some("PROFILE_INFO"));
+        assert_eq!(cache.get(cache.key(&config, "What are your hobbies?").unwrap()), None);
+    }
+
+    #[test]
+    fn intent_cache_misses_when_the_intent_prompt_changes() {
+        let config = prompt_config();
+        let cache = IntentCache::new(4);
+        cache.insert(cache.key(&config, "hi").unwrap(), "GENERAL_CHAT");
+
+        let mut reloaded = config.clone();
+        reloaded.intents.clear();
+        assert_eq!(cache.get(cache.key(&reloaded, "hi").unwrap()), None);
+    }
+
+    #[test]
+    fn zero_capacity_bypasses_the_intent_cache() {
+        let config = prompt_config();
+        let cache = IntentCache::new(0);
+        let key = cache.key(&config, "hi").unwrap();
+        assert!(key.is_none());
+        cache.insert(key, "GENERAL_CHAT");
+        assert_eq!(cache.get(key), None);
     }
 }
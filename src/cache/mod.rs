@@ -1,14 +1,33 @@
 pub mod redis;
 pub mod qdrant;
+pub mod lru;
 
 use crate::cli::Args;
-use crate::llm::embedding::EmbeddingClient;
+use crate::llm::embedding::{ EmbeddingClient, EmbeddingInputType };
+use crate::metrics::{ self, CacheTier };
 use qdrant_client::Qdrant;
 use ::redis::aio::MultiplexedConnection;
  
+use chrono::Utc;
+use log::{ info, warn };
+use serde::{ Deserialize, Serialize };
+use sha2::{ Digest, Sha256 };
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+/// Recommended semantic-cache threshold and the floor below which unrelated
+/// prompts start matching, per embedding model (matched by substring).
+/// Models with a high baseline similarity (ada-002) need stricter values.
+const MODEL_THRESHOLDS: &[(&str, f32, f32)] = &[
+    ("text-embedding-ada-002", 0.95, 0.90),
+    ("text-embedding-3", 0.85, 0.75),
+    ("nomic-embed-text", 0.85, 0.75),
+    ("mxbai-embed-large", 0.85, 0.75),
+    ("all-minilm", 0.80, 0.70),
+    ("text-embedding-004", 0.85, 0.75),
+];
+const DEFAULT_THRESHOLD: (f32, f32) = (0.85, 0.75);
+
 #[derive(Clone)]
 pub struct CacheClients {
     pub redis: Option<Arc<Mutex<MultiplexedConnection>>>,
@@ -16,72 +35,170 @@ pub struct CacheClients {
     pub collection: String,
     pub threshold: f32,
     pub ttl: usize,
+    /// Namespace for Redis keys (REDIS_NAMESPACE plus CACHE_REDIS_PREFIX).
+    pub key_prefix: String,
+    /// Key entries by recent conversation context as well as the prompt.
+    pub context_sensitive: bool,
+    /// Chat model recorded on new entries.
+    pub model: Option<String>,
+}
+
+/// A cached reply. Redis stores it as JSON; Qdrant payloads and entries written
+/// before the JSON format are plain response strings.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CacheEntry {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub normalized_prompt: String,
+    pub response: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<String>,
+    /// Unix seconds; 0 for legacy entries.
+    #[serde(default)]
+    pub created_at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+impl CacheEntry {
+    fn new(clients: &CacheClients, normalized: &str, response: &str, thinking: Option<&str>) -> Self {
+        Self {
+            normalized_prompt: normalized.to_string(),
+            response: response.to_string(),
+            thinking: thinking.filter(|t| !t.is_empty()).map(str::to_string),
+            created_at: Utc::now().timestamp(),
+            model: clients.model.clone(),
+        }
+    }
+
+    /// Parses a stored value, treating anything that is not an entry as a legacy plain response.
+    fn from_stored(value: String) -> Self {
+        serde_json::from_str(&value).unwrap_or(Self { response: value, ..Default::default() })
+    }
 }
 
-pub async fn init(args: &Args) -> CacheClients {
+pub async fn init(args: &Args, threshold: f32) -> CacheClients {
     CacheClients {
         redis: redis::init(args).await,
         qdrant: qdrant::init(args).await,
         collection: args.cache_qdrant_collection.clone(),
-        threshold: args.cache_similarity_threshold,
+        threshold,
         ttl: args.cache_redis_ttl,
+        key_prefix: args.redis_key_prefix(&args.cache_redis_prefix),
+        context_sensitive: args.cache_context_sensitive,
+        model: args.chat_model.clone(),
+    }
+}
+
+fn model_thresholds(model: &str) -> (f32, f32) {
+    let model = model.to_lowercase();
+    MODEL_THRESHOLDS
+        .iter()
+        .find(|(name, _, _)| model.contains(name))
+        .map(|(_, recommended, floor)| (*recommended, *floor))
+        .unwrap_or(DEFAULT_THRESHOLD)
+}
+
+/// Semantic-cache threshold: `--cache-similarity-threshold`, or the recommended
+/// value for the embedding model. Errors below `--cache-min-threshold`.
+pub fn similarity_threshold(args: &Args) -> Result<f32, Box<dyn std::error::Error + Send + Sync>> {
+    let model = args.embedding_model.as_deref().unwrap_or("");
+    let (recommended, floor) = model_thresholds(model);
+    let Some(threshold) = args.cache_similarity_threshold else {
+        info!("Cache similarity threshold: {} (default for embedding model '{}')", recommended, model);
+        return Ok(recommended);
+    };
+
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(format!("CACHE_SIMILARITY_THRESHOLD must be between 0.0 and 1.0, got {}", threshold).into());
+    }
+    if threshold < args.cache_min_threshold {
+        return Err(
+            format!(
+                "CACHE_SIMILARITY_THRESHOLD {} is below CACHE_MIN_THRESHOLD {}; such a loose threshold serves unrelated cached answers",
+                threshold,
+                args.cache_min_threshold
+            ).into()
+        );
+    }
+    if threshold < floor {
+        warn!(
+            "CACHE_SIMILARITY_THRESHOLD {} is below the safe floor {} for embedding model '{}' (recommended {}). \
+             Embeddings of unrelated prompts commonly score above this, so the semantic cache may return answers to different questions.",
+            threshold,
+            floor,
+            model,
+            recommended
+        );
+    }
+    Ok(threshold)
+}
+
+/// Round-trips to the enabled cache backends.
+pub async fn ping(clients: &CacheClients) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(conn) = &clients.redis {
+        let mut guard = conn.lock().await;
+        ::redis::cmd("PING").query_async::<_, String>(&mut *guard).await?;
     }
+    if let Some(client) = &clients.qdrant {
+        client.health_check().await?;
+    }
+    Ok(())
+}
+
+/// Short digest of the conversation context a prompt was asked in, or `None` for an
+/// empty context so opening questions share entries across conversations.
+pub fn context_hash(context: &str) -> Option<String> {
+    if context.trim().is_empty() {
+        return None;
+    }
+    Some(hex::encode(&Sha256::digest(context.as_bytes())[..8]))
 }
 
+/// `context` is a `context_hash`; entries only match lookups with the same context.
 pub async fn check(
     clients: &CacheClients,
     normalized: &str,
+    context: Option<&str>,
     embedding_client: &dyn EmbeddingClient,
-) -> Result<Option<(String, Vec<f32>)>, Box<dyn std::error::Error + Send + Sync>> {
-
-    if let Some(val) = redis::get(&clients.redis, normalized).await? {
-        if val.starts_with('{') && val.contains("\"response\"") {
-            if let Ok(json_val) = serde_json::from_str::<serde_json::Value>(&val) {
-                if let Some(response) = json_val.get("response").and_then(|v| v.as_str()) {
-                    return Ok(Some((response.to_string(), Vec::new())));
-                }
-            }
-        }
-        return Ok(Some((val, Vec::new())));
+) -> Result<Option<(CacheEntry, Vec<f32>)>, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(val) = redis::get(&clients.redis, &redis_key(clients, normalized, context)).await? {
+        metrics::record_cache_hit(CacheTier::Redis);
+        return Ok(Some((CacheEntry::from_stored(val), Vec::new())));
     }
-    
-    let emb = embedding_client.embed(normalized).await?.embedding;
-    if let Some(hit) = qdrant::search(&clients.qdrant, &clients.collection, emb.clone(), clients.threshold).await {
-        let (response_text, emb_vec) = hit;
-        
-        if response_text.starts_with('{') && response_text.contains("\"response\"") {
-            if let Ok(json_val) = serde_json::from_str::<serde_json::Value>(&response_text) {
-                if let Some(response) = json_val.get("response").and_then(|v| v.as_str()) {
-                    return Ok(Some((response.to_string(), emb_vec)));
-                }
-            }
-        }
-        return Ok(Some((response_text, emb_vec)));
+
+    let emb = embedding_client.embed_with_type(normalized, EmbeddingInputType::Query).await?.embedding;
+    if let Some((response_text, emb_vec)) = qdrant::search(&clients.qdrant, &clients.collection, emb.clone(), context, clients.threshold).await {
+        metrics::record_cache_hit(CacheTier::Qdrant);
+        return Ok(Some((CacheEntry::from_stored(response_text), emb_vec)));
     }
-    
+
+    metrics::record_cache_miss();
     Ok(None)
 }
 
 pub async fn update(
     clients: &CacheClients,
     normalized: &str,
+    context: Option<&str>,
     response: &str,
+    thinking: Option<&str>,
     embedding: Vec<f32>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    redis::set(&clients.redis, normalized, response, clients.ttl).await?;
-    qdrant::upsert(&clients.qdrant, &clients.collection, normalized, response, embedding).await;
+    set_redis_entry(clients, context, &CacheEntry::new(clients, normalized, response, thinking)).await?;
+    qdrant::upsert(&clients.qdrant, &clients.collection, normalized, context, response, embedding).await;
     Ok(())
 }
 
 pub async fn update_streaming(
     clients: &CacheClients,
     query: &str,
+    context: Option<&str>,
     full_response: &str,
     thinking: Option<&str>,
     embedding: Vec<f32>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
-    redis::set(&clients.redis, query, full_response, clients.ttl).await?;
+    set_redis_entry(clients, context, &CacheEntry::new(clients, query, full_response, thinking)).await?;
     
     if let Some(ref _qdrant) = clients.qdrant {
         if thinking.is_some() {
@@ -95,6 +212,7 @@ pub async fn update_streaming(
                 &clients.qdrant, 
                 &clients.collection, 
                 query, 
+                context,
                 &combined_response, 
                 embedding
             ).await;
@@ -104,6 +222,7 @@ pub async fn update_streaming(
                 &clients.qdrant,
                 &clients.collection,
                 query,
+                context,
                 full_response,
                 embedding
             ).await;
@@ -111,4 +230,20 @@ pub async fn update_streaming(
     }
     
     Ok(())
-}
\ No newline at end of file
+}
+
+async fn set_redis_entry(
+    clients: &CacheClients,
+    context: Option<&str>,
+    entry: &CacheEntry,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let value = serde_json::to_string(entry)?;
+    redis::set(&clients.redis, &redis_key(clients, &entry.normalized_prompt, context), &value, clients.ttl).await
+}
+
+fn redis_key(clients: &CacheClients, normalized: &str, context: Option<&str>) -> String {
+    match context {
+        Some(context) => format!("{}ctx:{}:{}", clients.key_prefix, context, normalized),
+        None => format!("{}{}", clients.key_prefix, normalized),
+    }
+}
@@ -1,7 +1,7 @@
 use crate::cli::Args;
 use qdrant_client::Qdrant;
 use qdrant_client::qdrant::{
-    Distance, CreateCollectionBuilder, PointStruct, SearchPointsBuilder,
+    Condition, Distance, CreateCollectionBuilder, Filter, PointStruct, SearchPointsBuilder,
     UpsertPointsBuilder, VectorParams, value::Kind, vectors_config::Config as VectorsConfig,
 };
 use serde::{Deserialize, Serialize};
@@ -43,11 +43,18 @@ pub async fn search(
     client: &Option<Arc<Qdrant>>,
     collection: &str,
     embedding: Vec<f32>,
+    context: Option<&str>,
     threshold: f32,
 ) -> Option<(String, Vec<f32>)> {
     let cli = client.as_ref()?;
+    // Context-free lookups only see entries stored without a context.
+    let filter = match context {
+        Some(context) => Filter::must([Condition::matches("context", context.to_string())]),
+        None => Filter::must([Condition::is_empty("context")]),
+    };
     let resp = cli.search_points(
             SearchPointsBuilder::new(collection, embedding.clone(), 1)
+                .filter(filter)
                 .with_payload(true)
                 .build()
         ).await.ok()?;
@@ -78,7 +85,7 @@ pub async fn check_qdrant_response(
     embedding: Vec<f32>,
     threshold: f32,
 ) -> Result<Option<(String, Vec<f32>)>, Box<dyn std::error::Error>> {
-    if let Some((response, embedding)) = search(client, collection, embedding, threshold).await {
+    if let Some((response, embedding)) = search(client, collection, embedding, None, threshold).await {
         if response.starts_with('{') && response.contains("\"response\"") {
             if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(&response) {
                 if let Some(actual_response) = parsed.get("response").and_then(|v| v.as_str()) {
@@ -96,6 +103,7 @@ pub async fn upsert(
     client: &Option<Arc<Qdrant>>,
     collection: &str,
     normalized: &str,
+    context: Option<&str>,
     response: &str,
     embedding: Vec<f32>,
 ) {
@@ -116,6 +124,14 @@ pub async fn upsert(
             kind: Some(Kind::StringValue(response.to_string())),
         },
     );
+    if let Some(context) = context {
+        payload.insert(
+            "context".to_string(),
+            qdrant_client::qdrant::Value {
+                kind: Some(Kind::StringValue(context.to_string())),
+            },
+        );
+    }
     let pt = PointStruct::new(Uuid::new_v4().to_string(), embedding, payload);
     let op = UpsertPointsBuilder::new(collection, vec![pt]).build();
     let _ = cli.upsert_points(op).await;
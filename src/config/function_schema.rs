@@ -0,0 +1,126 @@
+//! Loads the vector store function schema, optionally split across files.
+//!
+//! Sources, in order of precedence:
+//! - an explicit file list (`FUNCTION_SCHEMA_FILES`), merged in the given order;
+//! - `{dir}/{vector_type}.json` followed by every `*.json` in `{dir}/{vector_type}/`,
+//!   merged in file-name order.
+//!
+//! Objects merge recursively with later files winning. Arrays are concatenated,
+//! except that an entry whose `name` matches an earlier entry replaces it.
+
+use log::info;
+use serde_json::Value as JsonValue;
+use std::error::Error;
+use std::fs;
+use std::path::{ Path, PathBuf };
+
+/// Loads and merges the function schema. `Ok(None)` when no source file exists.
+pub fn load_function_schema(
+    dir: &str,
+    vector_type: &str,
+    files: Option<&str>
+) -> Result<Option<JsonValue>, Box<dyn Error + Send + Sync>> {
+    let paths = match files.map(str::trim).filter(|f| !f.is_empty()) {
+        Some(list) => list
+            .split(',')
+            .map(str::trim)
+            .filter(|f| !f.is_empty())
+            .map(PathBuf::from)
+            .collect(),
+        None => discover_files(dir, vector_type)?,
+    };
+    if paths.is_empty() {
+        return Ok(None);
+    }
+
+    let mut merged = JsonValue::Object(serde_json::Map::new());
+    for path in &paths {
+        merge(&mut merged, read_schema_file(path)?);
+    }
+    validate(&merged)?;
+
+    let names: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+    info!("Loaded function schema for type '{}' from: {}", vector_type, names.join(", "));
+    Ok(Some(merged))
+}
+
+fn discover_files(dir: &str, vector_type: &str) -> Result<Vec<PathBuf>, Box<dyn Error + Send + Sync>> {
+    let mut paths = Vec::new();
+    let single = Path::new(dir).join(format!("{}.json", vector_type));
+    if single.is_file() {
+        paths.push(single);
+    }
+
+    let split_dir = Path::new(dir).join(vector_type);
+    if split_dir.is_dir() {
+        let entries = fs::read_dir(&split_dir).map_err(|e|
+            format!("Failed to read function schema directory {}: {}", split_dir.display(), e)
+        )?;
+        let mut parts: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.is_file() && p.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        parts.sort();
+        paths.extend(parts);
+    }
+    Ok(paths)
+}
+
+fn read_schema_file(path: &Path) -> Result<JsonValue, Box<dyn Error + Send + Sync>> {
+    let text = fs::read_to_string(path).map_err(|e|
+        format!("Failed to read function schema file {}: {}", path.display(), e)
+    )?;
+    let value: JsonValue = serde_json::from_str(&text).map_err(|e|
+        format!("Failed to parse function schema from {}: {}", path.display(), e)
+    )?;
+    if !value.is_object() {
+        return Err(format!("Function schema {} must be a JSON object", path.display()).into());
+    }
+    Ok(value)
+}
+
+fn merge(base: &mut JsonValue, overlay: JsonValue) {
+    match (base, overlay) {
+        (JsonValue::Object(base), JsonValue::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (JsonValue::Array(base), JsonValue::Array(overlay)) => {
+            for item in overlay {
+                let existing = entry_name(&item).and_then(|name|
+                    base.iter().position(|b| entry_name(b) == Some(name))
+                );
+                match existing {
+                    Some(index) => base[index] = item,
+                    None => base.push(item),
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+fn entry_name(value: &JsonValue) -> Option<&str> {
+    value.get("name").and_then(|n| n.as_str())
+}
+
+fn validate(schema: &JsonValue) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let Some(functions) = schema.get("functions") else {
+        return Ok(());
+    };
+    let functions = functions
+        .as_array()
+        .ok_or("Merged function schema: 'functions' must be an array")?;
+    for (i, function) in functions.iter().enumerate() {
+        if entry_name(function).is_none() {
+            return Err(format!("Merged function schema: functions[{}] has no string 'name'", i).into());
+        }
+    }
+    Ok(())
+}
@@ -1,2 +1,4 @@
+pub mod function_schema;
+pub mod postprocess;
 pub mod prompt;
 pub mod remote_config;
\ No newline at end of file
@@ -0,0 +1,226 @@
+//! Cleanup rules applied to answers before they reach the client, configured in
+//! the `response_postprocess` section of the prompt config. None by default.
+
+use regex::Regex;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+struct PostprocessConfig {
+    #[serde(default)]
+    replace: Vec<ReplaceRule>,
+    /// Patterns removed when the answer starts with them.
+    #[serde(default)]
+    strip_prefixes: Vec<String>,
+    /// Characters held back from a streamed answer so that a match can complete.
+    #[serde(default = "default_max_match_chars")]
+    max_match_chars: usize,
+}
+
+fn default_max_match_chars() -> usize {
+    100
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct ReplaceRule {
+    pattern: String,
+    /// Replacement text; `$1` or `${name}` refer to capture groups.
+    #[serde(default)]
+    replacement: String,
+}
+
+/// Compiled `response_postprocess` rules. Replace rules run in order on the
+/// answer; prefix patterns only apply at the start of the answer.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(try_from = "PostprocessConfig")]
+pub struct Postprocessor {
+    replace: Vec<(Regex, String)>,
+    strip_prefixes: Vec<Regex>,
+    max_match_chars: usize,
+}
+
+impl TryFrom<PostprocessConfig> for Postprocessor {
+    type Error = String;
+
+    fn try_from(config: PostprocessConfig) -> Result<Self, Self::Error> {
+        let compile = |pattern: &str| {
+            Regex::new(pattern).map_err(|e| format!("Invalid response_postprocess pattern '{}': {}", pattern, e))
+        };
+        let replace = config.replace
+            .iter()
+            .map(|rule| Ok((compile(&rule.pattern)?, rule.replacement.clone())))
+            .collect::<Result<_, String>>()?;
+        let strip_prefixes = config.strip_prefixes
+            .iter()
+            .map(|pattern| compile(&format!("^(?:{})", pattern)))
+            .collect::<Result<_, String>>()?;
+        Ok(Self { replace, strip_prefixes, max_match_chars: config.max_match_chars })
+    }
+}
+
+impl Postprocessor {
+    /// Applies the replace rules, and the prefix patterns when `at_start`.
+    pub fn apply(&self, text: &str, at_start: bool) -> String {
+        let mut text = if at_start { self.apply_prefixes(text) } else { text.to_string() };
+        for (pattern, replacement) in &self.replace {
+            text = pattern.replace_all(&text, replacement.as_str()).into_owned();
+        }
+        text
+    }
+
+    fn apply_prefixes(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for prefix in &self.strip_prefixes {
+            if let Some(m) = prefix.find(&text) {
+                text.drain(..m.end());
+            }
+        }
+        text
+    }
+
+    fn is_empty(&self) -> bool {
+        self.replace.is_empty() && self.strip_prefixes.is_empty()
+    }
+
+    /// State for applying the rules to an answer that arrives in fragments.
+    pub fn stream(&self) -> PostprocessStream {
+        PostprocessStream { rules: self.clone(), pending: String::new(), started: false }
+    }
+}
+
+/// Applies the rules to a streamed answer. The last `max_match_chars` characters
+/// are held back, and a cut never falls inside a match, so patterns that span
+/// fragments still match.
+#[derive(Debug, Clone)]
+pub struct PostprocessStream {
+    rules: Postprocessor,
+    pending: String,
+    started: bool,
+}
+
+impl PostprocessStream {
+    /// Takes the next answer fragment and returns the processed text that can be sent.
+    pub fn push(&mut self, fragment: &str) -> String {
+        self.pending.push_str(fragment);
+        if self.rules.is_empty() {
+            return std::mem::take(&mut self.pending);
+        }
+        let window = self.rules.max_match_chars;
+        if self.pending.chars().count() <= window {
+            return String::new();
+        }
+        let at_start = !self.started;
+        self.started = true;
+        if at_start {
+            self.pending = self.rules.apply_prefixes(&self.pending);
+        }
+        let Some((mut cut, _)) = self.pending.char_indices().rev().nth(window.saturating_sub(1)) else {
+            return String::new();
+        };
+        // Move the cut back until no match crosses it.
+        loop {
+            let crossing = self.rules.replace
+                .iter()
+                .flat_map(|(pattern, _)| pattern.find_iter(&self.pending))
+                .filter(|m| m.start() < cut && cut < m.end())
+                .map(|m| m.start())
+                .min();
+            match crossing {
+                Some(start) => cut = start,
+                None => break,
+            }
+        }
+        let ready: String = self.pending.drain(..cut).collect();
+        self.rules.apply(&ready, false)
+    }
+
+    /// The processed rest of the answer once the stream has ended.
+    pub fn finish(&mut self) -> String {
+        let at_start = !self.started;
+        self.started = true;
+        self.rules.apply(&std::mem::take(&mut self.pending), at_start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn postprocessor(config: serde_json::Value) -> Result<Postprocessor, serde_json::Error> {
+        serde_json::from_value(config)
+    }
+
+    #[test]
+    fn passes_answers_through_without_rules() {
+        let text = "```\nlet x = a ** b; // \\boxed{x}\n```";
+        assert_eq!(Postprocessor::default().apply(text, true), text);
+        assert_eq!(postprocessor(json!({})).unwrap().apply(text, true), text);
+    }
+
+    #[test]
+    fn applies_replace_rules_in_order() {
+        let rules = postprocessor(json!({
+            "replace": [
+                { "pattern": "\\\\boxed\\{([^}]*)\\}", "replacement": "$1" },
+                { "pattern": "\\*\\*" },
+            ],
+        })).unwrap();
+        assert_eq!(rules.apply("The answer is **\\boxed{42}**.", false), "The answer is 42.");
+    }
+
+    #[test]
+    fn strips_prefixes_only_at_the_start() {
+        let rules = postprocessor(json!({ "strip_prefixes": ["Answer:\\s*", "Sure[,!]\\s*"] })).unwrap();
+        assert_eq!(rules.apply("Answer: Sure, here it is.", true), "here it is.");
+        assert_eq!(rules.apply("Answer: more", false), "Answer: more");
+        assert_eq!(rules.apply("The Answer: stays", true), "The Answer: stays");
+    }
+
+    /// Feeds `text` to a stream `size` bytes at a time.
+    fn streamed(rules: &Postprocessor, text: &str, size: usize) -> String {
+        let mut stream = rules.stream();
+        let mut out = String::new();
+        for chunk in text.as_bytes().chunks(size) {
+            out.push_str(&stream.push(std::str::from_utf8(chunk).unwrap()));
+        }
+        out + &stream.finish()
+    }
+
+    #[test]
+    fn streamed_answers_match_across_fragments() {
+        let rules = postprocessor(json!({
+            "replace": [{ "pattern": "\\\\boxed\\{([^}]*)\\}", "replacement": "$1" }],
+            "strip_prefixes": ["Answer:\\s*"],
+            "max_match_chars": 16,
+        })).unwrap();
+        let text = "Answer: the first is \\boxed{42} and the second is \\boxed{7}, done.";
+        let expected = "the first is 42 and the second is 7, done.";
+        assert_eq!(rules.apply(text, true), expected);
+        for size in [1, 3, 5, 8, 40] {
+            assert_eq!(streamed(&rules, text, size), expected, "fragments of {}", size);
+        }
+    }
+
+    #[test]
+    fn streams_release_text_beyond_the_window() {
+        let rules = postprocessor(json!({ "replace": [{ "pattern": "\\*\\*" }], "max_match_chars": 4 })).unwrap();
+        let mut stream = rules.stream();
+        assert_eq!(stream.push("**Bold"), "");
+        assert_eq!(stream.push(" text*"), "Bold t");
+        assert_eq!(stream.push("*"), "e");
+        assert_eq!(stream.finish(), "xt");
+
+        let mut passthrough = Postprocessor::default().stream();
+        assert_eq!(passthrough.push("As written"), "As written");
+        assert_eq!(passthrough.finish(), "");
+    }
+
+    #[test]
+    fn rejects_invalid_patterns_and_unknown_keys() {
+        let err = postprocessor(json!({ "replace": [{ "pattern": "(" }] })).unwrap_err();
+        assert!(err.to_string().contains("Invalid response_postprocess pattern"), "{}", err);
+        assert!(postprocessor(json!({ "strip": ["x"] })).is_err());
+    }
+}
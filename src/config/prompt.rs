@@ -7,9 +7,9 @@ use std::path::Path;
 use std::sync::Arc;
 use tokio::sync::RwLock; 
 use std::time::SystemTime;
-use log::info;
-use std::sync::Mutex;
+use log::{ info, warn };
 use crate::cli::Args;
+use crate::config::postprocess::Postprocessor;
 use crate::config::remote_config::RemoteConfigClient;
 
 #[derive(Debug)]
@@ -63,6 +63,12 @@ impl From<serde_json::Error> for PromptError {
 pub struct IntentDefinition {
     pub description: String,
     pub action: String,
+    /// Chat model that answers this intent instead of `CHAT_MODEL`.
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Chat provider for `model`; defaults to `CHAT_LLM_TYPE`.
+    #[serde(default)]
+    pub provider: Option<String>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -70,6 +76,12 @@ pub struct PromptConfig {
     pub intents: HashMap<String, IntentDefinition>,
     pub query_templates: HashMap<String, String>,
     pub response_templates: HashMap<String, String>,
+    /// Cleanup rules for answers sent to clients.
+    #[serde(default)]
+    pub response_postprocess: Postprocessor,
+    /// Date field per index, used to answer latest/recent questions with the newest entry.
+    #[serde(default)]
+    pub recency_fields: HashMap<String, String>,
     #[serde(skip)]
     pub last_loaded: Option<SystemTime>,
 }
@@ -103,6 +115,50 @@ impl PromptConfig {
     }
 }
 
+/// Templates the code always fills in, with every placeholder it substitutes.
+const REQUIRED_TEMPLATES: &[(&str, &str, &[&str])] = &[
+    ("query_templates", "intent_classification", &["{intent_descriptions}", "{message}"]),
+    ("query_templates", "rag_topic_inference", &["{schema_json}", "{user_question}"]),
+    ("query_templates", "rag_dynamic_query_generation", &["{fields_json}", "{user_question}"]),
+    ("query_templates", "fallback_topic_resolver", &["{schema_summary}", "{user_question}"]),
+    ("response_templates", "rag_final_answer", &["{schema}", "{topic}", "{documents}", "{user_question}"]),
+];
+
+/// Templates with a built-in default; checked only when present.
+const OPTIONAL_TEMPLATES: &[(&str, &str, &[&str])] = &[
+    ("response_templates", "response_language_directive", &["{language}"]),
+    ("response_templates", "intent_clarification", &[]),
+    ("query_templates", "grounding_check", &["{documents}", "{answer}"]),
+    ("query_templates", "conversation_title", &["{message}"]),
+    ("query_templates", "rag_filter_extraction", &["{fields_json}", "{user_question}"]),
+];
+
+/// Lists missing templates and placeholders that a template does not contain.
+/// Empty when the config covers every prompt the code builds.
+pub fn config_issues(config: &PromptConfig) -> Vec<String> {
+    let mut issues = Vec::new();
+    let templates = REQUIRED_TEMPLATES
+        .iter()
+        .map(|t| (t, true))
+        .chain(OPTIONAL_TEMPLATES.iter().map(|t| (t, false)));
+    for ((section, key, placeholders), required) in templates {
+        let map = match *section {
+            "query_templates" => &config.query_templates,
+            _ => &config.response_templates,
+        };
+        match map.get(*key) {
+            Some(template) => {
+                for placeholder in placeholders.iter().filter(|p| !template.contains(*p)) {
+                    issues.push(format!("{}:{} does not contain {}", section, key, placeholder));
+                }
+            }
+            None if required => issues.push(format!("{}:{} is missing", section, key)),
+            None => {}
+        }
+    }
+    issues
+}
+
 pub fn load_prompts_from_str(json_str: &str) -> Result<Arc<PromptConfig>, PromptError> {
     let mut config: PromptConfig = serde_json::from_str(json_str)?;
     config.last_loaded = Some(SystemTime::now());
@@ -217,6 +273,138 @@ pub fn get_intent_prompt(config: &PromptConfig, message: &str) -> Result<String,
     Ok(template.replace("{intent_descriptions}", &descriptions).replace("{message}", message))
 }
 
+const DEFAULT_INTENT_CLARIFICATION: &str =
+    "I'm not sure I understood what you're looking for. Could you rephrase or add a bit more detail?";
+
+/// Intent chosen by the classifier, with its confidence when one was reported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntentClassification {
+    pub intent: String,
+    pub confidence: Option<f32>,
+}
+
+#[derive(Deserialize)]
+struct RawIntentClassification {
+    intent: String,
+    #[serde(default, alias = "score")]
+    confidence: Option<f32>,
+}
+
+/// Cuts an intent or topic reply to `max_chars` (0 keeps it whole), warning when it was
+/// longer. Valid replies are short, so longer text is a runaway or adversarial answer.
+pub fn cap_classifier_output<'a>(raw: &'a str, max_chars: usize, kind: &str) -> &'a str {
+    if max_chars == 0 {
+        return raw;
+    }
+    match raw.char_indices().nth(max_chars) {
+        Some((cut, _)) => {
+            warn!(
+                "{} classifier returned {} characters; truncating to {}",
+                kind,
+                raw.chars().count(),
+                max_chars
+            );
+            &raw[..cut]
+        }
+        None => raw,
+    }
+}
+
+/// Parses classifier output, accepting a JSON object like
+/// `{"intent": "PROFILE_INFO", "confidence": 0.82}` or a bare intent name.
+pub fn parse_intent_classification(raw: &str) -> IntentClassification {
+    let trimmed = raw.trim();
+    if let (Some(start), Some(end)) = (trimmed.find('{'), trimmed.rfind('}')) {
+        if start < end {
+            if let Ok(parsed) = serde_json::from_str::<RawIntentClassification>(&trimmed[start..=end]) {
+                return IntentClassification {
+                    intent: parsed.intent.trim().to_string(),
+                    confidence: parsed.confidence.map(|c| c.clamp(0.0, 1.0)),
+                };
+            }
+        }
+    }
+    IntentClassification {
+        intent: trimmed.trim_matches(|c: char| c == '"' || c == '`' || c == '\'').trim().to_string(),
+        confidence: None,
+    }
+}
+
+const DEFAULT_RESPONSE_LANGUAGE_DIRECTIVE: &str = "Answer in {language}.";
+
+/// Appends the `response_templates.response_language_directive` (default
+/// "Answer in {language}.") to an answer prompt. Only used for final answers;
+/// intent and topic prompts stay in their canonical language.
+pub fn apply_response_language(config: &PromptConfig, prompt: &str, language: Option<&str>) -> String {
+    match language.map(str::trim).filter(|l| !l.is_empty()) {
+        Some(language) => {
+            let directive = get_response_template(config, "response_language_directive")
+                .unwrap_or(DEFAULT_RESPONSE_LANGUAGE_DIRECTIVE)
+                .replace("{language}", language);
+            format!("{}\n\n{}", prompt, directive)
+        }
+        None => prompt.to_string(),
+    }
+}
+
+/// Reply sent when the classifier is not confident enough to route a message.
+/// Uses `response_templates.intent_clarification` when present.
+pub fn get_intent_clarification(config: &PromptConfig, message: &str) -> String {
+    let template = get_response_template(config, "intent_clarification")
+        .unwrap_or(DEFAULT_INTENT_CLARIFICATION);
+    let descriptions = config.intents
+        .iter()
+        .map(|(name, definition)| format!("- {}: {}", name, definition.description))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    template.replace("{intent_descriptions}", &descriptions).replace("{message}", message)
+}
+
+const DEFAULT_GROUNDING_CHECK: &str = "Decide whether every claim in the answer is supported by the documents.\n\nDocuments:\n---\n{documents}\n---\n\nQuestion: {user_question}\nAnswer: {answer}\n\nRespond ONLY with a JSON object like {\"grounded\": true, \"confidence\": 0.9}.";
+const DEFAULT_GROUNDING_DISCLAIMER: &str =
+    "Note: parts of this answer may not be supported by the available documents.";
+const DEFAULT_GROUNDING_REFUSAL: &str =
+    "I couldn't find enough support in the available documents to answer that reliably.";
+
+/// Verification prompt for the grounding check (`query_templates.grounding_check`).
+pub fn get_grounding_check_prompt(config: &PromptConfig, documents: &str, answer: &str, user_question: &str) -> String {
+    get_query_template(config, "grounding_check")
+        .unwrap_or(DEFAULT_GROUNDING_CHECK)
+        .replace("{documents}", documents)
+        .replace("{user_question}", user_question)
+        .replace("{answer}", answer)
+}
+
+/// Appended to answers that fail the grounding check (`response_templates.grounding_disclaimer`).
+pub fn get_grounding_disclaimer(config: &PromptConfig) -> String {
+    get_response_template(config, "grounding_disclaimer").unwrap_or(DEFAULT_GROUNDING_DISCLAIMER).to_string()
+}
+
+/// Replaces answers that fail the grounding check (`response_templates.grounding_refusal`).
+pub fn get_grounding_refusal(config: &PromptConfig) -> String {
+    get_response_template(config, "grounding_refusal").unwrap_or(DEFAULT_GROUNDING_REFUSAL).to_string()
+}
+
+const DEFAULT_RAG_FILTER_EXTRACTION: &str = "List the constraints the question puts on document fields.\n\nFields: {fields_json}\nQuestion: {user_question}\n\nUse only the fields listed. Each constraint has a \"field\", an \"op\" (eq, ne, gt, gte, lt, lte or contains) and a \"value\". Write dates as YYYY-MM-DD; \"after 2022\" is {\"op\": \"gt\", \"value\": \"2022-12-31\"}. Respond ONLY with a JSON array such as [{\"field\": \"language\", \"op\": \"eq\", \"value\": \"Rust\"}], or [] when there are no constraints.";
+
+/// Filter extraction prompt for a question (`query_templates.rag_filter_extraction`).
+pub fn get_rag_filter_extraction_prompt(config: &PromptConfig, fields_json: &str, user_question: &str) -> String {
+    get_query_template(config, "rag_filter_extraction")
+        .unwrap_or(DEFAULT_RAG_FILTER_EXTRACTION)
+        .replace("{fields_json}", fields_json)
+        .replace("{user_question}", user_question)
+}
+
+const DEFAULT_CONVERSATION_TITLE: &str = "Write a short title (at most six words) for a conversation that starts with the message below. Reply with the title only, without quotes.\n\nMessage: {message}";
+
+/// Title generation prompt for a conversation's first message (`query_templates.conversation_title`).
+pub fn get_conversation_title_prompt(config: &PromptConfig, message: &str) -> String {
+    get_query_template(config, "conversation_title")
+        .unwrap_or(DEFAULT_CONVERSATION_TITLE)
+        .replace("{message}", message)
+}
+
 pub fn get_rag_topic_prompt(
     config: &PromptConfig,
     schema_json: &str,
@@ -226,6 +414,21 @@ pub fn get_rag_topic_prompt(
     Ok(template.replace("{schema_json}", schema_json).replace("{user_question}", user_question))
 }
 
+pub fn get_rag_field_selection_prompt(
+    config: &PromptConfig,
+    topic: &str,
+    fields_json: &str,
+    user_question: &str
+) -> Result<String, PromptError> {
+    let template = get_query_template(config, "rag_dynamic_query_generation")?;
+    Ok(
+        template
+            .replace("{topic}", topic)
+            .replace("{fields_json}", fields_json)
+            .replace("{user_question}", user_question)
+    )
+}
+
 pub fn get_rag_final_prompt(
     config: &PromptConfig,
     schema: &str,
@@ -255,29 +458,3 @@ pub fn get_fallback_topic_prompt(
         .replace("{schema_summary}", schema_summary)
         .replace("{user_question}", user_question))
 }
-
-
-pub fn check_local_prompt_file_changed(path: &str) -> Result<bool, PromptError> {
-    let metadata = fs::metadata(path)
-        .map_err(|e| PromptError::IoError(e))?;
-    
-    let modified = metadata.modified()
-        .map_err(|e| PromptError::IoError(e))?;
-    
-    static LAST_MODIFIED: Mutex<Option<SystemTime>> = Mutex::new(None);
-    let mut last_mod = LAST_MODIFIED.lock().unwrap();
-    
-    let changed = match *last_mod {
-        Some(time) if modified > time => {
-            *last_mod = Some(modified);
-            true
-        }
-        None => {
-            *last_mod = Some(modified);
-            true
-        }
-        _ => false
-    };
-    
-    Ok(changed)
-}
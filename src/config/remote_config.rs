@@ -65,6 +65,12 @@ pub struct RemoteConfigClient {
     etag: Mutex<Option<String>>,
 }
 
+impl Default for RemoteConfigClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl RemoteConfigClient {
     pub fn new() -> Self {
         RemoteConfigClient {
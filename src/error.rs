@@ -0,0 +1,79 @@
+//! Error type returned by the public `AIAgent` API and `run`.
+//!
+//! Internals still pass `Box<dyn Error + Send + Sync>` around; `?` converts it at the
+//! API boundary, recovering the variant when the boxed error is one this module knows.
+
+use std::error::Error;
+use thiserror::Error;
+
+use crate::agent::RequestTimeout;
+use crate::config::prompt::PromptError;
+
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+#[derive(Debug, Error)]
+pub enum AgentError {
+    /// A chat or embedding provider call failed.
+    #[error("LLM error: {0}")]
+    Llm(#[source] BoxError),
+    #[error("Vector store error: {0}")]
+    VectorStore(#[source] BoxError),
+    #[error("History store error: {0}")]
+    History(#[source] BoxError),
+    #[error("Cache error: {0}")]
+    Cache(#[source] BoxError),
+    /// Invalid arguments or prompt configuration.
+    #[error("Configuration error: {0}")]
+    Config(#[source] BoxError),
+    /// The embedding model's vectors do not have `VECTOR_DIMENSION` entries.
+    #[error(
+        "Embedding model '{model}' returns {actual}-dimensional vectors but VECTOR_DIMENSION is {expected}; {hint}"
+    )]
+    DimensionMismatch {
+        model: String,
+        expected: usize,
+        actual: usize,
+        hint: String,
+    },
+    /// The message exceeded `--request-timeout-secs`.
+    #[error("{0}")]
+    Timeout(#[from] RequestTimeout),
+    /// Anything not classified above; the source is the original error.
+    #[error("{0}")]
+    Other(#[source] BoxError),
+}
+
+impl From<BoxError> for AgentError {
+    fn from(err: BoxError) -> Self {
+        let err = match err.downcast::<AgentError>() {
+            Ok(err) => return *err,
+            Err(err) => err,
+        };
+        let err = match err.downcast::<RequestTimeout>() {
+            Ok(timeout) => return AgentError::Timeout(*timeout),
+            Err(err) => err,
+        };
+        if err.is::<PromptError>() {
+            return AgentError::Config(err);
+        }
+        AgentError::Other(err)
+    }
+}
+
+impl From<PromptError> for AgentError {
+    fn from(err: PromptError) -> Self {
+        AgentError::Config(Box::new(err))
+    }
+}
+
+impl From<String> for AgentError {
+    fn from(message: String) -> Self {
+        AgentError::Other(message.into())
+    }
+}
+
+impl From<&str> for AgentError {
+    fn from(message: &str) -> Self {
+        AgentError::Other(message.into())
+    }
+}
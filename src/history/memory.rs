@@ -0,0 +1,130 @@
+use async_trait::async_trait;
+use crate::models::chat::{ ChatMessage, Conversation, ConversationMetadata };
+use crate::history::{ rewind_position, HistoryStore };
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Mutex;
+use chrono::Utc;
+
+/// In-process history, lost on restart. Suited to local runs and tests.
+#[derive(Default)]
+pub struct InMemoryHistoryStore {
+    /// Messages per conversation, newest first like the Redis list.
+    conversations: Mutex<HashMap<String, Vec<ChatMessage>>>,
+    metadata: Mutex<HashMap<String, ConversationMetadata>>,
+    max_messages: usize,
+}
+
+impl InMemoryHistoryStore {
+    /// `max_messages` of 0 keeps every message.
+    pub fn new(max_messages: usize) -> Self {
+        Self { conversations: Mutex::new(HashMap::new()), metadata: Mutex::new(HashMap::new()), max_messages }
+    }
+}
+
+#[async_trait]
+impl HistoryStore for InMemoryHistoryStore {
+    async fn add_message_with_id(
+        &self,
+        conversation_id: &str,
+        id: &str,
+        role: &str,
+        content: &str,
+        thinking: Option<&str>
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let message = ChatMessage {
+            id: Some(id.to_string()),
+            role: role.to_string(),
+            content: content.to_string(),
+            timestamp: Utc::now().timestamp(),
+            thinking: thinking.map(str::to_string),
+        };
+        let mut conversations = self.conversations.lock().unwrap();
+        let messages = conversations.entry(conversation_id.to_string()).or_default();
+        messages.insert(0, message);
+        if self.max_messages > 0 {
+            messages.truncate(self.max_messages);
+        }
+        Ok(())
+    }
+
+    async fn get_conversation(
+        &self,
+        conversation_id: &str,
+        limit: usize
+    ) -> Result<Conversation, Box<dyn Error + Send + Sync>> {
+        let conversations = self.conversations.lock().unwrap();
+        let mut messages: Vec<ChatMessage> = conversations
+            .get(conversation_id)
+            .map(|messages| messages.iter().take(limit).cloned().collect())
+            .unwrap_or_default();
+        messages.reverse();
+
+        Ok(Conversation {
+            id: conversation_id.to_string(),
+            messages,
+        })
+    }
+
+    async fn get_conversation_range(
+        &self,
+        conversation_id: &str,
+        offset: usize,
+        limit: usize
+    ) -> Result<Conversation, Box<dyn Error + Send + Sync>> {
+        let conversations = self.conversations.lock().unwrap();
+        let mut messages: Vec<ChatMessage> = conversations
+            .get(conversation_id)
+            .map(|messages| messages.iter().skip(offset).take(limit).cloned().collect())
+            .unwrap_or_default();
+        messages.reverse();
+
+        Ok(Conversation {
+            id: conversation_id.to_string(),
+            messages,
+        })
+    }
+
+    async fn rewind(
+        &self,
+        conversation_id: &str,
+        from_message_id: Option<&str>
+    ) -> Result<Option<ChatMessage>, Box<dyn Error + Send + Sync>> {
+        let mut conversations = self.conversations.lock().unwrap();
+        let Some(messages) = conversations.get_mut(conversation_id) else {
+            return Ok(None);
+        };
+        let Some(pos) = rewind_position(messages, from_message_id) else {
+            return Ok(None);
+        };
+        let rewound = messages.drain(..=pos).next_back();
+        Ok(rewound)
+    }
+
+    async fn set_metadata(&self, metadata: &ConversationMetadata) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.metadata.lock().unwrap().insert(metadata.id.clone(), metadata.clone());
+        Ok(())
+    }
+
+    async fn get_metadata(
+        &self,
+        conversation_id: &str
+    ) -> Result<Option<ConversationMetadata>, Box<dyn Error + Send + Sync>> {
+        Ok(self.metadata.lock().unwrap().get(conversation_id).cloned())
+    }
+
+    async fn list_conversations(
+        &self,
+        limit: usize
+    ) -> Result<Vec<ConversationMetadata>, Box<dyn Error + Send + Sync>> {
+        let mut conversations: Vec<ConversationMetadata> = self.metadata.lock().unwrap().values().cloned().collect();
+        conversations.sort_by_key(|m| std::cmp::Reverse(m.last_active));
+        conversations.truncate(limit);
+        Ok(conversations)
+    }
+    async fn delete_conversation(&self, conversation_id: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.conversations.lock().unwrap().remove(conversation_id);
+        self.metadata.lock().unwrap().remove(conversation_id);
+        Ok(())
+    }
+}
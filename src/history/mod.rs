@@ -1,13 +1,99 @@
+mod memory;
 mod qdrant;
 mod redis;
 use async_trait::async_trait;
-use log::info;
+use log::{ info, warn };
 use std::error::Error;
 use crate::cli::Args;
 use std::sync::Arc;
-use crate::models::chat::Conversation;
-use crate::llm::embedding::new_client as new_embedding_client;
-use crate::llm::LlmConfig;
+use crate::models::chat::{ ChatMessage, Conversation, ConversationMetadata };
+use crate::llm::embedding::{ new_client as new_embedding_client, requested_dimensions };
+use crate::llm::embedding::cache::CachingEmbeddingClient;
+use crate::llm::embedding::truncate::TruncatingEmbeddingClient;
+use crate::llm::{ LlmConfig, LlmType };
+use serde::{ Deserialize, Serialize };
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+
+pub use memory::InMemoryHistoryStore;
+
+pub const DEFAULT_HISTORY_WINDOW: usize = 6;
+const FULL_HISTORY_LIMIT: usize = 500;
+
+/// How much prior conversation is included in the prompt.
+/// Parsed from `full`, `none`, `last_n`, or `last_n:<count>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum WindowPolicy {
+    Full,
+    LastN(usize),
+    None,
+}
+
+impl Default for WindowPolicy {
+    fn default() -> Self {
+        WindowPolicy::LastN(DEFAULT_HISTORY_WINDOW)
+    }
+}
+
+impl WindowPolicy {
+    /// Number of messages to fetch, or `None` when history is skipped entirely.
+    pub fn history_limit(&self) -> Option<usize> {
+        match self {
+            WindowPolicy::Full => Some(FULL_HISTORY_LIMIT),
+            WindowPolicy::LastN(0) | WindowPolicy::None => None,
+            WindowPolicy::LastN(n) => Some(*n),
+        }
+    }
+}
+
+impl FromStr for WindowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        match s.as_str() {
+            "full" => Ok(WindowPolicy::Full),
+            "none" => Ok(WindowPolicy::None),
+            "last_n" => Ok(WindowPolicy::default()),
+            _ => {
+                let n = s
+                    .strip_prefix("last_n:")
+                    .and_then(|n| n.trim().parse::<usize>().ok())
+                    .ok_or_else(|| format!(
+                        "Invalid window policy '{}': expected full, none, last_n or last_n:<count>",
+                        s
+                    ))?;
+                Ok(WindowPolicy::LastN(n))
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for WindowPolicy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for WindowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WindowPolicy::Full => write!(f, "full"),
+            WindowPolicy::LastN(n) => write!(f, "last_n:{}", n),
+            WindowPolicy::None => write!(f, "none"),
+        }
+    }
+}
+
+impl From<WindowPolicy> for String {
+    fn from(policy: WindowPolicy) -> Self {
+        policy.to_string()
+    }
+}
 
 #[async_trait]
 pub trait HistoryStore: Send + Sync {
@@ -16,6 +102,30 @@ pub trait HistoryStore: Send + Sync {
         conversation_id: &str,
         role: &str,
         content: &str
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.add_message_with_thinking(conversation_id, role, content, None).await
+    }
+
+    /// Stores a message with the assistant's reasoning kept apart from its content.
+    async fn add_message_with_thinking(
+        &self,
+        conversation_id: &str,
+        role: &str,
+        content: &str,
+        thinking: Option<&str>
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let id = Uuid::new_v4().to_string();
+        self.add_message_with_id(conversation_id, &id, role, content, thinking).await
+    }
+
+    /// Stores a message under a caller-chosen ID (a UUID string).
+    async fn add_message_with_id(
+        &self,
+        conversation_id: &str,
+        id: &str,
+        role: &str,
+        content: &str,
+        thinking: Option<&str>
     ) -> Result<(), Box<dyn Error + Send + Sync>>;
 
     async fn get_conversation(
@@ -23,6 +133,58 @@ pub trait HistoryStore: Send + Sync {
         conversation_id: &str,
         limit: usize
     ) -> Result<Conversation, Box<dyn Error + Send + Sync>>;
+
+    /// A page of a conversation for scrollback: up to `limit` messages after skipping the
+    /// `offset` most recent, oldest first. Unlike `get_conversation` this is always a plain
+    /// recency slice.
+    async fn get_conversation_range(
+        &self,
+        conversation_id: &str,
+        offset: usize,
+        limit: usize
+    ) -> Result<Conversation, Box<dyn Error + Send + Sync>>;
+
+    /// Removes a user turn and every later message, returning the removed user message.
+    /// `from_message_id` may name the user message or a reply to it; `None` picks the
+    /// most recent user message. Returns `None` when there is no matching turn.
+    async fn rewind(
+        &self,
+        conversation_id: &str,
+        from_message_id: Option<&str>
+    ) -> Result<Option<ChatMessage>, Box<dyn Error + Send + Sync>>;
+
+    /// Stores a conversation's metadata, replacing any previous value.
+    async fn set_metadata(&self, metadata: &ConversationMetadata) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    async fn get_metadata(
+        &self,
+        conversation_id: &str
+    ) -> Result<Option<ConversationMetadata>, Box<dyn Error + Send + Sync>>;
+
+    /// Conversations with metadata, most recently active first. Expired ones are left out.
+    async fn list_conversations(
+        &self,
+        limit: usize
+    ) -> Result<Vec<ConversationMetadata>, Box<dyn Error + Send + Sync>>;
+
+    /// Permanently removes a conversation's messages and metadata. Deleting an unknown
+    /// conversation is not an error.
+    async fn delete_conversation(&self, conversation_id: &str) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+/// Whether a client-supplied conversation ID is acceptable: 1 to 128 ASCII letters,
+/// digits, `-` or `_`, which covers the UUIDs the server generates.
+pub fn is_valid_conversation_id(id: &str) -> bool {
+    (1..=128).contains(&id.len()) && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Index of the user turn to rewind to in `messages` (ordered newest first).
+pub(crate) fn rewind_position(messages: &[ChatMessage], from_message_id: Option<&str>) -> Option<usize> {
+    let start = match from_message_id {
+        Some(id) => messages.iter().position(|m| m.id.as_deref() == Some(id))?,
+        None => 0,
+    };
+    messages[start..].iter().position(|m| m.role == "user").map(|i| start + i)
 }
 
 pub fn create_history_store(
@@ -33,20 +195,34 @@ pub fn create_history_store(
             let store = redis::RedisHistoryStore::new(args.clone())?;
             Ok(Arc::new(store))
         }
+        "memory" => Ok(Arc::new(InMemoryHistoryStore::new(args.history_max_messages))),
         "qdrant" => {
+            if args.history_qdrant_collection == args.indexes || args.history_qdrant_collection == args.cache_qdrant_collection {
+                warn!(
+                    "HISTORY_QDRANT_COLLECTION '{}' is shared with the document index or cache collection; history points will mix with them",
+                    args.history_qdrant_collection
+                );
+            }
+            let llm_type: LlmType = args.embedding_llm_type
+                .parse()
+                .map_err(|e| format!("Invalid embedding LLM type: {}", e))?;
             let embedding_config = LlmConfig {
-                llm_type: args.embedding_llm_type
-                    .parse()
-                    .map_err(|e| format!("Invalid embedding LLM type: {}", e))?,
+                embedding_dimensions: requested_dimensions(args, &llm_type, args.embedding_model.as_deref()),
+                llm_type,
                 base_url: args.embedding_base_url.clone(),
                 api_key: Some(args.embedding_api_key.clone()).filter(|k| !k.is_empty()),
                 completion_model: None,
                 embedding_model: args.embedding_model.clone(),
+                keep_alive: args.ollama_keep_alive.clone(),
+                ..LlmConfig::default()
             };
-            let embedding_client = new_embedding_client(&embedding_config)?;
-            let store = qdrant::QdrantHistoryStore::new(args.clone(), embedding_client)?;
+            // Long messages are embedded shortened; the payload keeps the full content.
+            let embedding_client = TruncatingEmbeddingClient::wrap(args, new_embedding_client(&embedding_config)?)?;
+            let embedding_client = CachingEmbeddingClient::wrap(args, embedding_client);
+            let store = Arc::new(qdrant::QdrantHistoryStore::new(args.clone(), embedding_client)?);
+            store.spawn_expiry_sweep();
 
-            Ok(Arc::new(store))
+            Ok(store)
         }
         _ =>
             Err(
@@ -64,7 +240,7 @@ pub fn initialize_history_store(
     args: &Args
 ) -> Result<Arc<dyn HistoryStore>, Box<dyn Error + Send + Sync>> {
     info!("Chat history will be stored in: {} at {}", args.history_type, args.history_host);
-    create_history_store(&args)
+    create_history_store(args)
 }
 
 pub fn format_history_for_prompt(conversation: &Conversation) -> String {
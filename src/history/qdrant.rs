@@ -1,17 +1,18 @@
 use async_trait::async_trait;
-use log::info;
-use crate::models::chat::{ ChatMessage, Conversation };
-use crate::history::HistoryStore;
+use log::{ info, warn };
+use crate::models::chat::{ ChatMessage, Conversation, ConversationMetadata };
+use crate::history::{ rewind_position, HistoryStore };
 use crate::cli::Args;
-use crate::llm::embedding::EmbeddingClient;
+use crate::llm::embedding::{ EmbeddingClient, EmbeddingInputType };
 use std::error::Error;
 use chrono::Utc;
 use std::collections::{ HashMap, HashSet };
-use uuid::Uuid;
 use qdrant_client::qdrant::Value as QdrantValue;
-use std::sync::Arc;
+use std::sync::{ Arc, Mutex };
+use std::time::Duration;
+use uuid::Uuid;
 
-use qdrant_client::Qdrant;
+use qdrant_client::{ Payload, Qdrant };
 use qdrant_client::qdrant::{
     CreateCollection,
     Distance,
@@ -30,13 +31,41 @@ use qdrant_client::qdrant::{
     OrderBy,
     Direction,
     UpsertPoints,
+    DeletePointsBuilder,
+    CountPointsBuilder,
+    PointsIdsList,
+    Range,
+    GetPointsBuilder,
 };
 
+const EXPIRY_SWEEP_INTERVAL_SECS: u64 = 600;
+const MAX_CACHED_EMBEDDINGS: usize = 1024;
+const REWIND_SCROLL_PAGE: u32 = 256;
+
+/// Keeps points whose `field` timestamp is at or after `cutoff`.
+fn unexpired(field: &str, cutoff: i64) -> Condition {
+    Condition::range(field, Range { gte: Some(cutoff as f64), ..Default::default() })
+}
+
+/// Points whose `field` timestamp is before `cutoff`, for the expiry sweep.
+fn expired(field: &str, cutoff: i64) -> Filter {
+    Filter::must([Condition::range(field, Range { lt: Some(cutoff as f64), ..Default::default() })])
+}
+
 pub struct QdrantHistoryStore {
     client: Qdrant,
     collection_name: String,
+    /// One point per conversation holding its metadata, with a constant one-element vector.
+    metadata_collection: String,
     embedding_client: Arc<dyn EmbeddingClient>,
     vector_dim: u64,
+    ttl_secs: u64,
+    max_messages: usize,
+    semantic_recall: bool,
+    /// Roles whose messages get a real embedding; others are stored with a zero vector.
+    embed_roles: HashSet<String>,
+    /// Last stored message embedding per conversation, reused as the recall query vector.
+    last_embeddings: Mutex<HashMap<String, (String, Vec<f32>)>>,
 }
 
 impl QdrantHistoryStore {
@@ -49,9 +78,19 @@ impl QdrantHistoryStore {
 
         let store = Self {
             client,
-            collection_name: args.indexes.clone(),
+            collection_name: args.history_qdrant_collection.clone(),
+            metadata_collection: args.history_qdrant_metadata_collection.clone(),
             embedding_client,
             vector_dim,
+            ttl_secs: args.history_ttl_secs,
+            max_messages: args.history_max_messages,
+            semantic_recall: args.history_semantic_recall,
+            embed_roles: args.history_embed_roles
+                .split(',')
+                .map(|role| role.trim().to_lowercase())
+                .filter(|role| !role.is_empty())
+                .collect(),
+            last_embeddings: Mutex::new(HashMap::new()),
         };
 
         Ok(store)
@@ -93,16 +132,153 @@ impl QdrantHistoryStore {
         Ok(())
     }
 
+    async fn ensure_metadata_collection_exists(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if !self.client.collection_exists(&self.metadata_collection).await? {
+            self.client.create_collection(CreateCollection {
+                collection_name: self.metadata_collection.clone(),
+                vectors_config: Some(
+                    VectorsConfig::from(VectorParams {
+                        size: 1,
+                        distance: Distance::Dot.into(),
+                        ..Default::default()
+                    })
+                ),
+                ..Default::default()
+            }).await?;
+            info!("Created Qdrant conversation metadata collection: {}", self.metadata_collection);
+
+            self.client.create_field_index(CreateFieldIndexCollection {
+                collection_name: self.metadata_collection.clone(),
+                field_name: "last_active".to_string(),
+                field_type: Some(FieldType::Integer.into()),
+                wait: Some(true),
+                ..Default::default()
+            }).await?;
+            info!("Created 'last_active' index in {}", self.metadata_collection);
+        }
+        Ok(())
+    }
+
+    /// Conversation IDs are arbitrary strings, so metadata points use a UUID derived from them.
+    fn metadata_point_id(conversation_id: &str) -> PointId {
+        Self::string_to_point_id(&Uuid::new_v5(&Uuid::NAMESPACE_OID, conversation_id.as_bytes()).to_string())
+    }
+
+    fn payload_to_metadata(payload: HashMap<String, QdrantValue>) -> Option<ConversationMetadata> {
+        serde_json::from_value(Payload::from(payload).into()).ok()
+    }
+
+    fn expiry_cutoff(&self) -> Option<i64> {
+        (self.ttl_secs > 0).then(|| Utc::now().timestamp() - self.ttl_secs as i64)
+    }
+
     fn create_conversation_filter(&self, conversation_id: &str) -> Filter {
-        Filter::must([Condition::matches("conversation_id", conversation_id.to_string())])
+        let mut conditions = vec![Condition::matches("conversation_id", conversation_id.to_string())];
+        if let Some(cutoff) = self.expiry_cutoff() {
+            // Hide expired messages that the periodic sweep has not removed yet.
+            conditions.push(unexpired("timestamp", cutoff));
+        }
+        Filter::must(conditions)
+    }
+
+    pub async fn delete_expired(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let Some(cutoff) = self.expiry_cutoff() else {
+            return Ok(());
+        };
+        if !self.client.collection_exists(&self.collection_name).await? {
+            return Ok(());
+        }
+        self.client.delete_points(
+            DeletePointsBuilder::new(&self.collection_name).points(expired("timestamp", cutoff)).wait(true)
+        ).await?;
+        if self.client.collection_exists(&self.metadata_collection).await? {
+            self.client.delete_points(
+                DeletePointsBuilder::new(&self.metadata_collection).points(expired("last_active", cutoff)).wait(true)
+            ).await?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the oldest points of a conversation beyond `max_messages`.
+    async fn trim_conversation(&self, conversation_id: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.max_messages == 0 {
+            return Ok(());
+        }
+        let filter = Filter::must([Condition::matches("conversation_id", conversation_id.to_string())]);
+        let count = self.client
+            .count(CountPointsBuilder::new(&self.collection_name).filter(filter.clone()).exact(true))
+            .await?
+            .result
+            .map(|r| r.count as usize)
+            .unwrap_or(0);
+        if count <= self.max_messages {
+            return Ok(());
+        }
+
+        let oldest = self.client.scroll(ScrollPoints {
+            collection_name: self.collection_name.clone(),
+            filter: Some(filter),
+            limit: Some((count - self.max_messages) as u32),
+            with_payload: Some(WithPayloadSelector {
+                selector_options: Some(WithPayloadOptions::Enable(false)),
+            }),
+            order_by: Some(OrderBy {
+                key: "timestamp".to_string(),
+                direction: Some(Direction::Asc.into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }).await?;
+        let ids: Vec<PointId> = oldest.result.into_iter().filter_map(|p| p.id).collect();
+        if !ids.is_empty() {
+            self.client.delete_points(
+                DeletePointsBuilder::new(&self.collection_name)
+                    .points(PointsIdsList::from(ids))
+                    .wait(true)
+            ).await?;
+        }
+        Ok(())
+    }
+
+    /// Periodically deletes history points older than the TTL. No-op when TTL is 0.
+    pub fn spawn_expiry_sweep(self: &Arc<Self>) {
+        if self.ttl_secs == 0 {
+            return;
+        }
+        let store = Arc::clone(self);
+        let interval = Duration::from_secs(EXPIRY_SWEEP_INTERVAL_SECS.min(store.ttl_secs));
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = store.delete_expired().await {
+                    warn!("Qdrant history expiry sweep failed: {}", e);
+                }
+            }
+        });
+        info!("Qdrant history expiry sweep enabled (TTL {}s, every {:?})", self.ttl_secs, interval);
     }
 
-    fn payload_to_chat_message(payload: HashMap<String, QdrantValue>) -> Option<ChatMessage> {
+    fn payload_to_chat_message(id: Option<&PointId>, payload: HashMap<String, QdrantValue>) -> Option<ChatMessage> {
         let role = payload.get("role")?.as_str()?.to_string();
         let content = payload.get("content")?.as_str()?.to_string();
         let timestamp = payload.get("timestamp")?.as_integer()?;
+        let thinking = payload
+            .get("thinking")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string());
+
+        let id = id.map(Self::point_id_to_string).filter(|id| !id.is_empty());
+
+        Some(ChatMessage { id, role, content, timestamp, thinking })
+    }
 
-        Some(ChatMessage { role, content, timestamp })
+    fn point_id_to_string(point_id: &PointId) -> String {
+        match &point_id.point_id_options {
+            Some(qdrant_client::qdrant::point_id::PointIdOptions::Uuid(uuid)) => uuid.clone(),
+            Some(qdrant_client::qdrant::point_id::PointIdOptions::Num(num)) => num.to_string(),
+            None => String::new(),
+        }
     }
 
     fn string_to_point_id(s: &str) -> PointId {
@@ -122,17 +298,23 @@ impl QdrantHistoryStore {
 
 #[async_trait]
 impl HistoryStore for QdrantHistoryStore {
-    async fn add_message(
+    async fn add_message_with_id(
         &self,
         conversation_id: &str,
+        id: &str,
         role: &str,
-        content: &str
+        content: &str,
+        thinking: Option<&str>
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
         self.ensure_collection_exists().await?;
 
         let timestamp = Utc::now().timestamp();
-        let embedding_response = self.embedding_client.embed(content).await?;
-        let vector = embedding_response.embedding;
+        let embedded = self.embed_roles.contains(&role.to_lowercase());
+        let vector = if embedded {
+            self.embedding_client.embed_with_type(content, EmbeddingInputType::Document).await?.embedding
+        } else {
+            vec![0.0; self.vector_dim as usize]
+        };
 
         if (vector.len() as u64) != self.vector_dim {
             return Err(
@@ -144,14 +326,27 @@ impl HistoryStore for QdrantHistoryStore {
             );
         }
 
+        if self.semantic_recall && embedded {
+            let mut cache = self.last_embeddings.lock().unwrap();
+            if cache.len() >= MAX_CACHED_EMBEDDINGS && !cache.contains_key(conversation_id) {
+                cache.clear();
+            }
+            cache.insert(conversation_id.to_string(), (content.to_string(), vector.clone()));
+        }
+
         let mut payload = HashMap::new();
         payload.insert("conversation_id".to_string(), conversation_id.to_string().into());
         payload.insert("role".to_string(), role.to_string().into());
         payload.insert("content".to_string(), content.to_string().into());
         payload.insert("timestamp".to_string(), timestamp.into());
+        if !embedded {
+            payload.insert("embedded".to_string(), false.into());
+        }
+        if let Some(thinking) = thinking {
+            payload.insert("thinking".to_string(), thinking.to_string().into());
+        }
 
-        let point_id = Uuid::new_v4().to_string();
-        let point = PointStruct::new(point_id, vector, payload);
+        let point = PointStruct::new(Self::string_to_point_id(id), vector, payload);
         let upsert_request = UpsertPoints {
             collection_name: self.collection_name.clone(),
             wait: Some(true),
@@ -160,6 +355,7 @@ impl HistoryStore for QdrantHistoryStore {
             shard_key_selector: None,
         };
         self.client.upsert_points(upsert_request).await?;
+        self.trim_conversation(conversation_id).await?;
 
         Ok(())
     }
@@ -174,7 +370,7 @@ impl HistoryStore for QdrantHistoryStore {
         let conversation_filter = self.create_conversation_filter(conversation_id);
         let mut combined_messages: HashMap<String, ChatMessage> = HashMap::new();
         let mut retrieved_ids_str: HashSet<String> = HashSet::new();
-        let recency_limit = (limit / 2).max(1);
+        let recency_limit = if self.semantic_recall { (limit / 2).max(1) } else { limit };
 
         let recency_scroll = ScrollPoints {
             collection_name: self.collection_name.clone(),
@@ -195,34 +391,34 @@ impl HistoryStore for QdrantHistoryStore {
         let mut last_message_content: Option<String> = None;
 
         for point in recency_response.result {
-            if let Some(message) = Self::payload_to_chat_message(point.payload) {
+            if let Some(message) = Self::payload_to_chat_message(point.id.as_ref(), point.payload) {
                 if last_message_content.is_none() {
                     last_message_content = Some(message.content.clone());
                 }
 
-                let point_id_str = match &point.id {
-                    Some(point_id) =>
-                        match &point_id.point_id_options {
-                            Some(qdrant_client::qdrant::point_id::PointIdOptions::Uuid(uuid)) =>
-                                uuid.clone(),
-                            Some(qdrant_client::qdrant::point_id::PointIdOptions::Num(num)) =>
-                                num.to_string(),
-                            _ => String::new(),
-                        }
-                    None => String::new(),
-                };
-
-                if !point_id_str.is_empty() {
+                if let Some(point_id_str) = message.id.clone() {
                     retrieved_ids_str.insert(point_id_str.clone());
                     combined_messages.insert(point_id_str, message);
                 }
             }
         }
 
-        let semantic_limit = limit - combined_messages.len();
-        if semantic_limit > 0 && last_message_content.is_some() {
-            let query_text = last_message_content.unwrap();
-            let query_embedding = self.embedding_client.embed(&query_text).await?.embedding;
+        let semantic_limit = if self.semantic_recall {
+            limit.saturating_sub(combined_messages.len())
+        } else {
+            0
+        };
+        if let (true, Some(query_text)) = (semantic_limit > 0, last_message_content) {
+            let cached_embedding = self.last_embeddings
+                .lock()
+                .unwrap()
+                .get(conversation_id)
+                .filter(|(content, _)| *content == query_text)
+                .map(|(_, embedding)| embedding.clone());
+            let query_embedding = match cached_embedding {
+                Some(embedding) => embedding,
+                None => self.embedding_client.embed_with_type(&query_text, EmbeddingInputType::Query).await?.embedding,
+            };
 
             let mut semantic_filter = conversation_filter.clone();
             if !retrieved_ids_str.is_empty() {
@@ -263,6 +459,9 @@ impl HistoryStore for QdrantHistoryStore {
                 }
             }
 
+            // Placeholder vectors carry no meaning, so only embedded messages are searched.
+            semantic_filter.must_not.push(Condition::matches("embedded", false));
+
             let semantic_search = SearchPoints {
                 collection_name: self.collection_name.clone(),
                 vector: query_embedding,
@@ -277,21 +476,9 @@ impl HistoryStore for QdrantHistoryStore {
             let semantic_response = self.client.search_points(semantic_search).await?;
 
             for scored_point in semantic_response.result {
-                let point_id_str = match &scored_point.id {
-                    Some(point_id) =>
-                        match &point_id.point_id_options {
-                            Some(qdrant_client::qdrant::point_id::PointIdOptions::Uuid(uuid)) =>
-                                uuid.clone(),
-                            Some(qdrant_client::qdrant::point_id::PointIdOptions::Num(num)) =>
-                                num.to_string(),
-                            _ => String::new(),
-                        }
-                    None => String::new(),
-                };
-
-                if !point_id_str.is_empty() && !combined_messages.contains_key(&point_id_str) {
-                    if let Some(message) = Self::payload_to_chat_message(scored_point.payload) {
-                        combined_messages.insert(point_id_str, message);
+                if let Some(message) = Self::payload_to_chat_message(scored_point.id.as_ref(), scored_point.payload) {
+                    if let Some(point_id_str) = message.id.clone() {
+                        combined_messages.entry(point_id_str).or_insert(message);
                     }
                 }
             }
@@ -309,4 +496,216 @@ impl HistoryStore for QdrantHistoryStore {
             messages: final_messages,
         })
     }
+
+    async fn get_conversation_range(
+        &self,
+        conversation_id: &str,
+        offset: usize,
+        limit: usize
+    ) -> Result<Conversation, Box<dyn Error + Send + Sync>> {
+        self.ensure_collection_exists().await?;
+        if limit == 0 {
+            return Ok(Conversation { id: conversation_id.to_string(), messages: Vec::new() });
+        }
+
+        // Ordered scrolls cannot page with an offset, so the skipped messages are fetched too.
+        let response = self.client.scroll(ScrollPoints {
+            collection_name: self.collection_name.clone(),
+            filter: Some(self.create_conversation_filter(conversation_id)),
+            limit: Some(offset.saturating_add(limit).min(u32::MAX as usize) as u32),
+            with_payload: Some(WithPayloadSelector {
+                selector_options: Some(WithPayloadOptions::Enable(true)),
+            }),
+            order_by: Some(OrderBy {
+                key: "timestamp".to_string(),
+                direction: Some(Direction::Desc.into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }).await?;
+        let mut messages: Vec<ChatMessage> = response.result
+            .into_iter()
+            .filter_map(|p| Self::payload_to_chat_message(p.id.as_ref(), p.payload))
+            .collect();
+        // Newest first; within the same second a reply sorts after its question.
+        messages.sort_by_key(|m| std::cmp::Reverse((m.timestamp, m.role != "user")));
+        let mut messages: Vec<ChatMessage> = messages.into_iter().skip(offset).take(limit).collect();
+        messages.reverse();
+
+        Ok(Conversation {
+            id: conversation_id.to_string(),
+            messages,
+        })
+    }
+
+    async fn rewind(
+        &self,
+        conversation_id: &str,
+        from_message_id: Option<&str>
+    ) -> Result<Option<ChatMessage>, Box<dyn Error + Send + Sync>> {
+        self.ensure_collection_exists().await?;
+
+        let mut messages = Vec::new();
+        let mut offset = None;
+        loop {
+            let page = self.client.scroll(ScrollPoints {
+                collection_name: self.collection_name.clone(),
+                filter: Some(self.create_conversation_filter(conversation_id)),
+                limit: Some(REWIND_SCROLL_PAGE),
+                offset,
+                with_payload: Some(WithPayloadSelector {
+                    selector_options: Some(WithPayloadOptions::Enable(true)),
+                }),
+                ..Default::default()
+            }).await?;
+            messages.extend(
+                page.result.into_iter().filter_map(|p| Self::payload_to_chat_message(p.id.as_ref(), p.payload))
+            );
+            offset = page.next_page_offset;
+            if offset.is_none() {
+                break;
+            }
+        }
+        // Newest first; within the same second a reply sorts after its question.
+        messages.sort_by_key(|m| std::cmp::Reverse((m.timestamp, m.role != "user")));
+
+        let Some(pos) = rewind_position(&messages, from_message_id) else {
+            return Ok(None);
+        };
+        let ids: Vec<PointId> = messages[..=pos]
+            .iter()
+            .filter_map(|m| m.id.as_deref())
+            .map(Self::string_to_point_id)
+            .collect();
+        self.client.delete_points(
+            DeletePointsBuilder::new(&self.collection_name)
+                .points(PointsIdsList::from(ids))
+                .wait(true)
+        ).await?;
+        self.last_embeddings.lock().unwrap().remove(conversation_id);
+        Ok(messages.into_iter().nth(pos))
+    }
+
+    async fn set_metadata(&self, metadata: &ConversationMetadata) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.ensure_metadata_collection_exists().await?;
+        let payload = Payload::try_from(serde_json::to_value(metadata)?)?;
+        let point = PointStruct::new(Self::metadata_point_id(&metadata.id), vec![1.0], payload);
+        self.client.upsert_points(UpsertPoints {
+            collection_name: self.metadata_collection.clone(),
+            wait: Some(true),
+            points: vec![point],
+            ordering: None,
+            shard_key_selector: None,
+        }).await?;
+        Ok(())
+    }
+
+    async fn get_metadata(
+        &self,
+        conversation_id: &str
+    ) -> Result<Option<ConversationMetadata>, Box<dyn Error + Send + Sync>> {
+        self.ensure_metadata_collection_exists().await?;
+        let response = self.client.get_points(
+            GetPointsBuilder::new(&self.metadata_collection, vec![Self::metadata_point_id(conversation_id)])
+                .with_payload(true)
+        ).await?;
+        let cutoff = self.expiry_cutoff();
+        Ok(
+            response.result
+                .into_iter()
+                .find_map(|point| Self::payload_to_metadata(point.payload))
+                .filter(|metadata| cutoff.is_none_or(|cutoff| metadata.last_active >= cutoff))
+        )
+    }
+
+    async fn list_conversations(
+        &self,
+        limit: usize
+    ) -> Result<Vec<ConversationMetadata>, Box<dyn Error + Send + Sync>> {
+        self.ensure_metadata_collection_exists().await?;
+        // Hide conversations that the periodic sweep has not removed yet.
+        let filter = self.expiry_cutoff().map(|cutoff| Filter::must([unexpired("last_active", cutoff)]));
+        let response = self.client.scroll(ScrollPoints {
+            collection_name: self.metadata_collection.clone(),
+            filter,
+            limit: Some(limit as u32),
+            with_payload: Some(WithPayloadSelector {
+                selector_options: Some(WithPayloadOptions::Enable(true)),
+            }),
+            order_by: Some(OrderBy {
+                key: "last_active".to_string(),
+                direction: Some(Direction::Desc.into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }).await?;
+        Ok(response.result.into_iter().filter_map(|point| Self::payload_to_metadata(point.payload)).collect())
+    }
+
+    async fn delete_conversation(&self, conversation_id: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.client.collection_exists(&self.collection_name).await? {
+            // Unlike the read filter, this also catches expired messages not yet swept.
+            let filter = Filter::must([Condition::matches("conversation_id", conversation_id.to_string())]);
+            self.client.delete_points(
+                DeletePointsBuilder::new(&self.collection_name).points(filter).wait(true)
+            ).await?;
+        }
+        if self.client.collection_exists(&self.metadata_collection).await? {
+            self.client.delete_points(
+                DeletePointsBuilder::new(&self.metadata_collection)
+                    .points(PointsIdsList::from(vec![Self::metadata_point_id(conversation_id)]))
+                    .wait(true)
+            ).await?;
+        }
+        self.last_embeddings.lock().unwrap().remove(conversation_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockEmbeddingClient;
+    use clap::Parser;
+    use qdrant_client::qdrant::condition::ConditionOneOf;
+
+    fn store(ttl_secs: &str) -> QdrantHistoryStore {
+        let args = Args::parse_from(["dynamic-agent", "--history-ttl-secs", ttl_secs]);
+        QdrantHistoryStore::new(args, Arc::new(MockEmbeddingClient::new(4))).unwrap()
+    }
+
+    /// Field and range of a range condition.
+    fn range_of(condition: &Condition) -> (&str, &Range) {
+        match &condition.condition_one_of {
+            Some(ConditionOneOf::Field(field)) => (field.key.as_str(), field.range.as_ref().expect("range condition")),
+            other => panic!("not a field condition: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_skip_messages_older_than_the_ttl() {
+        let filter = store("3600").create_conversation_filter("conv-1");
+        assert_eq!(filter.must.len(), 2);
+        let (field, range) = range_of(&filter.must[1]);
+        assert_eq!(field, "timestamp");
+        let cutoff = range.gte.expect("lower bound");
+        let expected = (Utc::now().timestamp() - 3600) as f64;
+        assert!((expected - cutoff).abs() <= 1.0, "cutoff {} vs {}", cutoff, expected);
+    }
+
+    #[test]
+    fn without_ttl_reads_keep_every_message() {
+        let store = store("0");
+        assert_eq!(store.create_conversation_filter("conv-1").must.len(), 1);
+        assert!(store.expiry_cutoff().is_none());
+    }
+
+    #[test]
+    fn sweep_deletes_points_before_the_cutoff() {
+        let filter = expired("last_active", 1_000);
+        let (field, range) = range_of(&filter.must[0]);
+        assert_eq!(field, "last_active");
+        assert_eq!(range.lt, Some(1_000.0));
+        assert_eq!(range.gte, None);
+    }
 }
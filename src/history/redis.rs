@@ -1,32 +1,54 @@
 use async_trait::async_trait;
-use crate::models::chat::{ ChatMessage, Conversation };
-use crate::history::HistoryStore;
+use crate::models::chat::{ ChatMessage, Conversation, ConversationMetadata };
+use crate::history::{ rewind_position, HistoryStore };
 use crate::cli::Args;
+use std::collections::HashMap;
 use std::error::Error;
 use chrono::Utc;
 use log::error;
 use redis::{ Client, AsyncCommands };
 use serde::{ Serialize, Deserialize };
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 struct StoredMessage {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
     role: String,
     content: String,
     timestamp: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    thinking: Option<String>,
+}
+
+impl From<StoredMessage> for ChatMessage {
+    fn from(msg: StoredMessage) -> Self {
+        ChatMessage {
+            id: msg.id,
+            role: msg.role,
+            content: msg.content,
+            timestamp: msg.timestamp,
+            thinking: msg.thinking,
+        }
+    }
 }
 
 pub struct RedisHistoryStore {
     client: Client,
     key_prefix: String,
-    _scan_count: usize,
+    /// Hash holding every conversation's metadata as JSON, keyed by conversation ID.
+    metadata_key: String,
+    ttl_secs: u64,
+    max_messages: usize,
 }
 
 impl RedisHistoryStore {
     pub fn new(args: Args) -> Result<Self, Box<dyn Error + Send + Sync>> {
         Ok(Self {
             client: Client::open(args.history_host.as_str())?,
-            key_prefix: args.history_redis_prefix,
-            _scan_count: args.history_redis_scan_count,
+            key_prefix: args.redis_key_prefix(&args.history_redis_prefix),
+            metadata_key: args.redis_key_prefix(&args.history_redis_metadata_key),
+            ttl_secs: args.history_ttl_secs,
+            max_messages: args.history_max_messages,
         })
     }
 
@@ -37,23 +59,33 @@ impl RedisHistoryStore {
 
 #[async_trait]
 impl HistoryStore for RedisHistoryStore {
-    async fn add_message(
+    async fn add_message_with_id(
         &self,
         conversation_id: &str,
+        id: &str,
         role: &str,
-        content: &str
+        content: &str,
+        thinking: Option<&str>
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
         let mut conn = self.get_connection().await?;
         let key = format!("{}{}", self.key_prefix, conversation_id);
 
         let message = StoredMessage {
+            id: Some(id.to_string()),
             role: role.to_string(),
             content: content.to_string(),
             timestamp: Utc::now().timestamp(),
+            thinking: thinking.map(str::to_string),
         };
 
         let json_msg = serde_json::to_string(&message)?;
         let _: i64 = conn.lpush(&key, &json_msg).await?;
+        if self.max_messages > 0 {
+            conn.ltrim::<_, ()>(&key, 0, (self.max_messages as isize) - 1).await?;
+        }
+        if self.ttl_secs > 0 {
+            conn.expire::<_, ()>(&key, self.ttl_secs as i64).await?;
+        }
         Ok(())
     }
 
@@ -62,20 +94,28 @@ impl HistoryStore for RedisHistoryStore {
         conversation_id: &str,
         limit: usize
     ) -> Result<Conversation, Box<dyn Error + Send + Sync>> {
+        self.get_conversation_range(conversation_id, 0, limit).await
+    }
+
+    async fn get_conversation_range(
+        &self,
+        conversation_id: &str,
+        offset: usize,
+        limit: usize
+    ) -> Result<Conversation, Box<dyn Error + Send + Sync>> {
+        if limit == 0 {
+            // LRANGE with stop = start - 1 would still return one entry at offset 0.
+            return Ok(Conversation { id: conversation_id.to_string(), messages: Vec::new() });
+        }
         let mut conn = self.get_connection().await?;
         let key = format!("{}{}", self.key_prefix, conversation_id);
-        let json_entries: Vec<String> = conn.lrange(&key, 0, (limit as isize) - 1).await?;
+        let start = offset as isize;
+        let json_entries: Vec<String> = conn.lrange(&key, start, start + (limit as isize) - 1).await?;
         let mut messages = Vec::new();
 
         for json_entry in &json_entries {
             match serde_json::from_str::<StoredMessage>(json_entry) {
-                Ok(msg) => {
-                    messages.push(ChatMessage {
-                        role: msg.role,
-                        content: msg.content,
-                        timestamp: msg.timestamp,
-                    });
-                }
+                Ok(msg) => messages.push(msg.into()),
                 Err(e) => {
                     error!("Error parsing history entry: {}", e);
                 }
@@ -88,4 +128,74 @@ impl HistoryStore for RedisHistoryStore {
             messages,
         })
     }
+
+    async fn rewind(
+        &self,
+        conversation_id: &str,
+        from_message_id: Option<&str>
+    ) -> Result<Option<ChatMessage>, Box<dyn Error + Send + Sync>> {
+        let mut conn = self.get_connection().await?;
+        let key = format!("{}{}", self.key_prefix, conversation_id);
+        // The list is newest first, so the turn and everything after it is a prefix.
+        // Unparseable entries stay as empty placeholders to keep positions aligned.
+        let json_entries: Vec<String> = conn.lrange(&key, 0, -1).await?;
+        let messages: Vec<ChatMessage> = json_entries
+            .iter()
+            .map(|entry| serde_json::from_str::<StoredMessage>(entry).unwrap_or_default().into())
+            .collect();
+        let Some(pos) = rewind_position(&messages, from_message_id) else {
+            return Ok(None);
+        };
+        conn.ltrim::<_, ()>(&key, (pos as isize) + 1, -1).await?;
+        Ok(messages.into_iter().nth(pos))
+    }
+
+    async fn set_metadata(&self, metadata: &ConversationMetadata) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut conn = self.get_connection().await?;
+        let json = serde_json::to_string(metadata)?;
+        conn.hset::<_, _, _, ()>(&self.metadata_key, &metadata.id, json).await?;
+        Ok(())
+    }
+
+    async fn get_metadata(
+        &self,
+        conversation_id: &str
+    ) -> Result<Option<ConversationMetadata>, Box<dyn Error + Send + Sync>> {
+        let mut conn = self.get_connection().await?;
+        let json: Option<String> = conn.hget(&self.metadata_key, conversation_id).await?;
+        Ok(json.map(|json| serde_json::from_str(&json)).transpose()?)
+    }
+
+    async fn list_conversations(
+        &self,
+        limit: usize
+    ) -> Result<Vec<ConversationMetadata>, Box<dyn Error + Send + Sync>> {
+        let mut conn = self.get_connection().await?;
+        let entries: HashMap<String, String> = conn.hgetall(&self.metadata_key).await?;
+        // Hash fields do not expire with the history lists, so expired entries are dropped here.
+        let cutoff = (self.ttl_secs > 0).then(|| Utc::now().timestamp() - self.ttl_secs as i64);
+        let mut expired = Vec::new();
+        let mut conversations = Vec::new();
+        for (id, json) in entries {
+            match serde_json::from_str::<ConversationMetadata>(&json) {
+                Ok(metadata) if cutoff.is_some_and(|cutoff| metadata.last_active < cutoff) => expired.push(id),
+                Ok(metadata) => conversations.push(metadata),
+                Err(e) => error!("Error parsing conversation metadata for {}: {}", id, e),
+            }
+        }
+        if !expired.is_empty() {
+            conn.hdel::<_, _, ()>(&self.metadata_key, expired).await?;
+        }
+        conversations.sort_by_key(|m| std::cmp::Reverse(m.last_active));
+        conversations.truncate(limit);
+        Ok(conversations)
+    }
+
+    async fn delete_conversation(&self, conversation_id: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut conn = self.get_connection().await?;
+        let key = format!("{}{}", self.key_prefix, conversation_id);
+        conn.del::<_, ()>(&key).await?;
+        conn.hdel::<_, _, ()>(&self.metadata_key, conversation_id).await?;
+        Ok(())
+    }
 }
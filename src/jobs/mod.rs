@@ -0,0 +1,180 @@
+use crate::agent::AIAgent;
+use crate::cli::Args;
+use crate::models::job::{ Job, JobStatus };
+use crate::server::streams::ConversationStreams;
+use chrono::Utc;
+use log::{ error, info, warn };
+use redis::{ AsyncCommands, Client };
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+const JOB_KEY_PREFIX: &str = "job:";
+const QUEUE_KEY: &str = "jobs:queue";
+const PROCESSING_KEY: &str = "jobs:processing";
+const QUEUE_POLL_SECS: f64 = 5.0;
+const WORKER_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Redis-backed job store. Pending ids live in a queue list and move to a
+/// processing list while running, so a restart can requeue interrupted jobs.
+pub struct JobStore {
+    client: Client,
+    result_ttl: u64,
+    // Keys are under the REDIS_NAMESPACE.
+    job_key_prefix: String,
+    queue_key: String,
+    processing_key: String,
+}
+
+impl JobStore {
+    pub fn new(args: &Args) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Ok(Self {
+            client: Client::open(args.job_redis_url.as_str())?,
+            result_ttl: args.job_result_ttl,
+            job_key_prefix: args.redis_key_prefix(JOB_KEY_PREFIX),
+            queue_key: args.redis_key_prefix(QUEUE_KEY),
+            processing_key: args.redis_key_prefix(PROCESSING_KEY),
+        })
+    }
+
+    async fn get_connection(&self) -> Result<redis::aio::MultiplexedConnection, redis::RedisError> {
+        self.client.get_multiplexed_async_connection().await
+    }
+
+    fn job_key(&self, id: &str) -> String {
+        format!("{}{}", self.job_key_prefix, id)
+    }
+
+    async fn save(&self, job: &Job) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut conn = self.get_connection().await?;
+        let payload = serde_json::to_string(job)?;
+        match job.status {
+            JobStatus::Done | JobStatus::Error if self.result_ttl > 0 => {
+                conn.set_ex::<_, _, ()>(self.job_key(&job.id), payload, self.result_ttl).await?;
+            }
+            _ => {
+                conn.set::<_, _, ()>(self.job_key(&job.id), payload).await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn enqueue(
+        &self,
+        conversation_id: String,
+        content: String
+    ) -> Result<Job, Box<dyn Error + Send + Sync>> {
+        let now = Utc::now().timestamp();
+        let job = Job {
+            id: Uuid::new_v4().to_string(),
+            status: JobStatus::Pending,
+            conversation_id,
+            content,
+            result: None,
+            thinking: None,
+            metadata: None,
+            error: None,
+            created_at: now,
+            updated_at: now,
+        };
+        self.save(&job).await?;
+        let mut conn = self.get_connection().await?;
+        conn.lpush::<_, _, ()>(&self.queue_key, &job.id).await?;
+        Ok(job)
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<Job>, Box<dyn Error + Send + Sync>> {
+        let mut conn = self.get_connection().await?;
+        let payload: Option<String> = conn.get(self.job_key(id)).await?;
+        match payload {
+            Some(p) => Ok(Some(serde_json::from_str(&p)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn requeue_interrupted(&self) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        let mut conn = self.get_connection().await?;
+        let mut count = 0;
+        while let Some(_id) = conn.rpoplpush::<_, _, Option<String>>(&self.processing_key, &self.queue_key).await? {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Runs the oldest queued job, waiting up to `QUEUE_POLL_SECS` for one. Like a chat
+    /// message, the job claims its conversation in `streams` first, so it never runs
+    /// alongside a reply streaming on the same conversation.
+    pub async fn process_next(
+        &self,
+        agent: &Arc<Mutex<AIAgent>>,
+        streams: &ConversationStreams
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Dedicated connection: the blocking pop must not stall other commands.
+        let mut queue_conn = self.get_connection().await?;
+        let id: Option<String> = queue_conn.brpoplpush(&self.queue_key, &self.processing_key, QUEUE_POLL_SECS).await?;
+        let Some(id) = id else {
+            return Ok(());
+        };
+
+        let Some(mut job) = self.get(&id).await? else {
+            warn!("Job {} dequeued but no record found; dropping", id);
+            queue_conn.lrem::<_, _, ()>(&self.processing_key, 1, &id).await?;
+            return Ok(());
+        };
+
+        job.status = JobStatus::Running;
+        job.updated_at = Utc::now().timestamp();
+        self.save(&job).await?;
+        info!("Processing job {} (conversation {})", job.id, job.conversation_id);
+
+        let outcome = match streams.acquire(&job.conversation_id).await {
+            Ok(guard) => {
+                // Work on a clone so a long job does not hold the agent lock from other clients.
+                let agent = agent.lock().await.clone();
+                tokio::select! {
+                    outcome = agent.process_message(&job.conversation_id, &job.content) => outcome.map_err(|e| e.to_string()),
+                    _ = guard.cancelled() => Err("cancelled by a newer message".to_string()),
+                }
+            }
+            Err(_) => Err("conversation busy".to_string()),
+        };
+        match outcome {
+            Ok(response) => {
+                job.status = JobStatus::Done;
+                job.result = Some(response.response);
+                if !response.thinking.is_empty() {
+                    job.thinking = Some(response.thinking);
+                }
+                job.metadata = response.metadata;
+            }
+            Err(e) => {
+                error!("Job {} failed: {}", job.id, e);
+                job.status = JobStatus::Error;
+                job.error = Some(e);
+            }
+        }
+        job.updated_at = Utc::now().timestamp();
+        self.save(&job).await?;
+        queue_conn.lrem::<_, _, ()>(&self.processing_key, 1, &id).await?;
+        Ok(())
+    }
+
+    pub fn spawn_worker(self: Arc<Self>, agent: Arc<Mutex<AIAgent>>, streams: Arc<ConversationStreams>) {
+        tokio::spawn(async move {
+            match self.requeue_interrupted().await {
+                Ok(0) => {}
+                Ok(n) => info!("Requeued {} interrupted job(s)", n),
+                Err(e) => warn!("Failed to requeue interrupted jobs: {}", e),
+            }
+            info!("Job worker started");
+            loop {
+                if let Err(e) = self.process_next(&agent, &streams).await {
+                    error!("Job worker error: {}", e);
+                    tokio::time::sleep(WORKER_RETRY_DELAY).await;
+                }
+            }
+        });
+    }
+}
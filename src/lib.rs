@@ -1,4 +1,5 @@
 pub mod agent;
+pub mod error;
 pub mod models;
 pub mod server; 
 pub mod config;
@@ -7,23 +8,29 @@ pub mod cli;
 pub mod history;
 pub mod rag;
 pub mod cache;
+pub mod jobs;
+pub mod actions;
+pub mod metrics;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 use agent::AIAgent;
 use cli::Args;
 use config::prompt::initialize_prompt_configuration;
+use error::AgentError;
 use log::info;
 use server::Server;
-use std::error::Error;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
 
 
-pub async fn run(args: Args) -> Result<(), Box<dyn Error + Send + Sync>> {
+pub async fn run(args: Args) -> Result<(), AgentError> {
     info!("--- Core Configuration ---");
     info!("Server Address: {}", args.server_addr);
     info!("Vector Store Type: {}", args.vector_type);
     info!("Vector Store Host: {}", args.host);
+    info!("Vector Dimension: {} (auto-detect: {})", args.dimension, args.auto_detect_dimension);
     info!("Chat LLM Type: {}", args.chat_llm_type);
     info!("Embedding LLM Type: {}", args.embedding_llm_type);
     info!("History Store Type: {}", args.history_type);
@@ -48,17 +55,27 @@ pub async fn run(args: Args) -> Result<(), Box<dyn Error + Send + Sync>> {
     }
     
     info!("-------------------------");
+
+    llm::http::configure(llm::http::HttpPoolConfig::from_args(&args));
     
     let shared_prompt_config = match initialize_prompt_configuration(&args).await {
         Ok(config) => config,
         Err(e) => {
             eprintln!("Failed to initialize prompt configuration: {e}");
-            return Err(Box::new(e));
+            return Err(AgentError::Config(Box::new(e)));
         }
     };
 
     let agent_args = args.clone();
-    let agent = Arc::new(Mutex::new(AIAgent::new(agent_args, Arc::clone(&shared_prompt_config)).await?));
+    let agent = AIAgent::new(agent_args, Arc::clone(&shared_prompt_config)).await?;
+    if args.check {
+        info!("Configuration check passed; exiting without starting the servers (--check)");
+        return Ok(());
+    }
+    if args.warmup {
+        agent.warm_up().await;
+    }
+    let agent = Arc::new(Mutex::new(agent));
     
     let addr = args.server_addr.clone();
     info!("Starting WebSocket server on: {addr}" );
@@ -1,18 +1,101 @@
 use async_trait::async_trait;
 use std::error::Error as StdError;
-use super::{ ChatClient, CompletionResponse };
+use std::time::Duration;
+use super::{ http_stream_generate, with_retries, ChatClient, ChatStream, CompletionResponse, RetryPolicy, estimate_tokens };
+use super::image::ImageInput;
+use crate::error::AgentError;
 use crate::llm::LlmConfig;
-use rllm::{
-    builder::{ LLMBackend, LLMBuilder },
-    chat::{ ChatMessage, ChatRole, MessageType },
-    LLMProvider,
-};
+use crate::llm::endpoint::endpoint_url;
+use crate::llm::http::with_timeout;
+use log::warn;
+use rllm::builder::LLMBackend;
+use serde::Deserialize;
 
+const ANTHROPIC_DEFAULT_BASE_URL: &str = "https://api.anthropic.com";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+const ANTHROPIC_DEFAULT_MAX_TOKENS: u32 = 2048;
+
+#[derive(Deserialize)]
+struct AnthropicMessagesResponse {
+    content: Vec<AnthropicContentBlock>,
+    #[serde(default)]
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Deserialize)]
+struct AnthropicContentBlock {
+    #[serde(default)]
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AnthropicUsage {
+    input_tokens: Option<u32>,
+    output_tokens: Option<u32>,
+}
+
+/// One server-sent event of a streaming Messages API response.
+#[derive(Deserialize)]
+struct AnthropicStreamEvent {
+    #[serde(rename = "type")]
+    event_type: String,
+    #[serde(default)]
+    delta: Option<AnthropicDelta>,
+    #[serde(default)]
+    error: Option<AnthropicStreamError>,
+}
+
+#[derive(Deserialize)]
+struct AnthropicStreamError {
+    #[serde(rename = "type", default)]
+    error_type: String,
+    #[serde(default)]
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct AnthropicDelta {
+    #[serde(default)]
+    text: Option<String>,
+}
+
+/// Text carried by a `content_block_delta` event. The `event:` lines and the other
+/// events (`message_start`, `ping`, `message_stop`, ...) yield nothing; an `error`
+/// event (e.g. `overloaded_error`) fails the stream.
+fn parse_anthropic_line(line: &str) -> Result<Option<String>, Box<dyn StdError + Send + Sync>> {
+    let Some(data) = line.trim().strip_prefix("data:").map(str::trim) else {
+        return Ok(None);
+    };
+    let Ok(event) = serde_json::from_str::<AnthropicStreamEvent>(data) else {
+        return Ok(None);
+    };
+    match event.event_type.as_str() {
+        "content_block_delta" => Ok(event.delta.and_then(|d| d.text).filter(|text| !text.is_empty())),
+        "error" => {
+            warn!("Anthropic stream error: {}", data);
+            let error = event.error.map_or_else(
+                || data.to_string(),
+                |e| format!("{}: {}", e.error_type, e.message)
+            );
+            Err(Box::new(AgentError::Llm(format!("Anthropic stream error: {}", error).into())))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Calls the Messages API directly (rather than through rllm) so usage metadata is preserved.
 pub struct AnthropicChatClient {
-    llm: Box<dyn LLMProvider + Send + Sync>,
+    http: reqwest::Client,
     api_key: String,
     model: String,
     base_url: Option<String>,
+    max_tokens: u32,
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    /// Sent as the top-level `system` field.
+    system_prompt: Option<String>,
+    request_timeout: Option<Duration>,
+    retry: RetryPolicy,
 }
 
 impl AnthropicChatClient {
@@ -25,28 +108,17 @@ impl AnthropicChatClient {
     ) -> Result<Self, Box<dyn StdError + Send + Sync>> {
         let chat_model = model.unwrap_or_else(|| "claude-3-haiku-20240307".to_string());
 
-        let mut builder = LLMBuilder::new()
-            .backend(LLMBackend::Anthropic)
-            .api_key(api_key.clone())  
-            .model(&chat_model)
-            .stream(false); 
-        if let Some(url) = &base_url {  
-            builder = builder.base_url(url);
-        }
-        if let Some(tokens) = max_tokens {
-            builder = builder.max_tokens(tokens);
-        }
-        if let Some(temp) = temperature {
-            builder = builder.temperature(temp);
-        }
-
-        let llm_provider = builder.build()?;
-
         Ok(Self { 
-            llm: llm_provider,
+            http: crate::llm::http::shared_client(),
             api_key,
             model: chat_model,
             base_url,
+            max_tokens: max_tokens.unwrap_or(ANTHROPIC_DEFAULT_MAX_TOKENS),
+            temperature,
+            top_p: None,
+            system_prompt: None,
+            request_timeout: None,
+            retry: RetryPolicy::default(),
         })
     }
 
@@ -56,10 +128,76 @@ impl AnthropicChatClient {
             .ok_or_else(|| "Anthropic API key is required for AnthropicChatClient".to_string())?;
         let model = config.completion_model.clone();
         let base_url = config.base_url.clone();
-        let max_tokens = None;
-        let temperature = None;
+        // The Messages API has no frequency or presence penalty.
+        let max_tokens = config.generation.max_tokens;
+        let temperature = config.generation.temperature;
 
-        Self::new(api_key, model, base_url, max_tokens, temperature)
+        Ok(Self {
+            top_p: config.generation.top_p,
+            system_prompt: config.system_prompt.clone(),
+            request_timeout: config.request_timeout(),
+            retry: config.retry_policy(),
+            ..Self::new(api_key, model, base_url, max_tokens, temperature)?
+        })
+    }
+
+    fn messages_url(&self) -> String {
+        endpoint_url(self.base_url.as_deref(), ANTHROPIC_DEFAULT_BASE_URL, "/v1/messages")
+    }
+
+    fn message_payload(&self, content: Vec<serde_json::Value>) -> serde_json::Value {
+        let mut payload = serde_json::json!({
+            "model": self.model,
+            "max_tokens": self.max_tokens,
+            "messages": [{ "role": "user", "content": content }],
+        });
+        if let Some(temp) = self.temperature {
+            payload["temperature"] = serde_json::json!(temp);
+        }
+        if let Some(top_p) = self.top_p {
+            payload["top_p"] = serde_json::json!(top_p);
+        }
+        if let Some(system) = &self.system_prompt {
+            payload["system"] = serde_json::json!(system);
+        }
+        payload
+    }
+
+    async fn send_message(
+        &self,
+        prompt: &str,
+        content: Vec<serde_json::Value>
+    ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
+        let payload = self.message_payload(content);
+        let url = self.messages_url();
+        let (url, payload) = (&url, &payload);
+
+        let resp = with_retries(self.retry, || async move {
+            let request = self.http
+                .post(url)
+                .header("x-api-key", &self.api_key)
+                .header("anthropic-version", ANTHROPIC_VERSION)
+                .json(payload);
+            Ok(
+                with_timeout(request, self.request_timeout)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<AnthropicMessagesResponse>()
+                    .await?
+            )
+        }).await?;
+
+        let text = resp.content
+            .into_iter()
+            .filter_map(|block| block.text)
+            .collect::<String>();
+        let usage = resp.usage.as_ref();
+        Ok(CompletionResponse {
+            prompt_tokens: usage.and_then(|u| u.input_tokens).or_else(|| Some(estimate_tokens(prompt))),
+            completion_tokens: usage.and_then(|u| u.output_tokens).or_else(|| Some(estimate_tokens(&text))),
+            response: text,
+        })
     }
 }
 
@@ -69,17 +207,63 @@ impl ChatClient for AnthropicChatClient {
         &self,
         prompt: &str
     ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
-        let messages = vec![ChatMessage {
-            role: ChatRole::User,
-            content: prompt.to_string(),
-            message_type: MessageType::Text,
-        }];
+        let content = vec![serde_json::json!({ "type": "text", "text": prompt })];
+        self.send_message(prompt, content).await
+    }
 
-        let response_text = self.llm.chat(&messages).await?;
+    async fn complete_with_images(
+        &self,
+        prompt: &str,
+        images: &[ImageInput]
+    ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
+        if images.is_empty() {
+            return self.complete(prompt).await;
+        }
 
-        Ok(CompletionResponse { response: response_text.to_string() })
+        let mut content: Vec<serde_json::Value> = images
+            .iter()
+            .map(|image| match image {
+                ImageInput::Url(url) => serde_json::json!({
+                    "type": "image",
+                    "source": { "type": "url", "url": url }
+                }),
+                ImageInput::Base64 { mime_type, data } => serde_json::json!({
+                    "type": "image",
+                    "source": { "type": "base64", "media_type": mime_type, "data": data }
+                }),
+            })
+            .collect();
+        content.push(serde_json::json!({ "type": "text", "text": prompt }));
+
+        self.send_message(prompt, content).await
     }
-    
+
+    async fn stream_completion(
+        &self,
+        prompt: &str
+    ) -> Result<ChatStream, Box<dyn StdError + Send + Sync>> {
+        let mut payload = self.message_payload(vec![serde_json::json!({ "type": "text", "text": prompt })]);
+        payload["stream"] = serde_json::json!(true);
+        let headers = vec![
+            ("x-api-key".to_string(), self.api_key.clone()),
+            ("anthropic-version".to_string(), ANTHROPIC_VERSION.to_string()),
+        ];
+
+        http_stream_generate(
+            self.http.clone(),
+            self.messages_url(),
+            "",
+            payload,
+            parse_anthropic_line,
+            Some(headers),
+            self.request_timeout,
+        ).await
+    }
+
+    fn supports_vision(&self) -> bool {
+        true
+    }
+
     fn get_api_key(&self) -> String {
         self.api_key.clone()
     }
@@ -95,8 +279,34 @@ impl ChatClient for AnthropicChatClient {
     fn get_llm_backend(&self) -> LLMBackend {
         LLMBackend::Anthropic
     }
-    
+
     fn supports_native_streaming(&self) -> bool {
-        false
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_anthropic_line;
+
+    fn text(line: &str) -> Option<String> {
+        parse_anthropic_line(line).unwrap()
+    }
+
+    #[test]
+    fn parses_only_text_deltas() {
+        let delta = r#"data: {"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hel"}}"#;
+        assert_eq!(text(delta), Some("Hel".to_string()));
+        assert_eq!(text("event: content_block_delta"), None);
+        assert_eq!(text(r#"data: {"type":"message_start","message":{}}"#), None);
+        assert_eq!(text(r#"data: {"type":"ping"}"#), None);
+        assert_eq!(text(r#"data: {"type":"content_block_delta","delta":{"type":"text_delta","text":""}}"#), None);
+    }
+
+    #[test]
+    fn error_events_fail_the_stream() {
+        let error = parse_anthropic_line(r#"data: {"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "LLM error: Anthropic stream error: overloaded_error: Overloaded");
     }
 }
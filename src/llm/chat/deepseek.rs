@@ -1,18 +1,87 @@
 use async_trait::async_trait;
+use futures::StreamExt;
+use log::info;
+use reqwest::Client as HttpClient;
+use reqwest::header::AUTHORIZATION;
+use serde::{ Deserialize, Serialize };
 use std::error::Error as StdError;
-use super::{ ChatClient, CompletionResponse };
-use crate::llm::LlmConfig;
-use rllm::{
-    builder::{ LLMBackend, LLMBuilder },
-    chat::{ ChatMessage, ChatRole, MessageType },
-    LLMProvider,
-};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use super::{ abort_on_drop, ChatClient, ChatStream, CompletionResponse, RetryPolicy, with_retries };
+use crate::llm::{ GenerationParams, LlmConfig };
+use crate::llm::endpoint::endpoint_url;
+use crate::llm::http::{ send_streaming, with_timeout };
+use rllm::builder::LLMBackend;
+
+const DEEPSEEK_DEFAULT_BASE_URL: &str = "https://api.deepseek.com";
 
 pub struct DeepSeekChatClient {
-    llm: Box<dyn LLMProvider + Send + Sync>,
+    /// Requests go to the OpenAI-compatible endpoint directly; rllm's DeepSeek
+    /// backend sends neither `base_url` nor sampling settings beyond temperature.
+    http: HttpClient,
     api_key: String,
     model: String,
     base_url: Option<String>,
+    generation: GenerationParams,
+    request_timeout: Option<Duration>,
+    system_prompt: Option<String>,
+    retry: RetryPolicy,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DeepSeekMessage {
+    role: String,
+    content: String,
+}
+
+#[derive(Serialize)]
+struct DeepSeekRequest {
+    model: String,
+    messages: Vec<DeepSeekMessage>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+}
+
+#[derive(Deserialize)]
+struct DeepSeekResponse {
+    choices: Vec<DeepSeekChoice>,
+    #[serde(default)]
+    usage: Option<DeepSeekUsage>,
+}
+
+#[derive(Deserialize)]
+struct DeepSeekChoice {
+    message: DeepSeekMessage,
+}
+
+#[derive(Deserialize)]
+struct DeepSeekUsage {
+    prompt_tokens: Option<u32>,
+    completion_tokens: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct DeepSeekStreamResponse {
+    choices: Vec<DeepSeekStreamChoice>,
+}
+
+#[derive(Deserialize)]
+struct DeepSeekStreamChoice {
+    delta: DeepSeekDelta,
+}
+
+#[derive(Deserialize)]
+struct DeepSeekDelta {
+    content: Option<String>,
 }
 
 impl DeepSeekChatClient {
@@ -23,31 +92,15 @@ impl DeepSeekChatClient {
         max_tokens: Option<u32>,
         temperature: Option<f32>
     ) -> Result<Self, Box<dyn StdError + Send + Sync>> {
-        let chat_model = model.unwrap_or_else(|| "deepseek-chat".to_string());
-
-        let mut builder = LLMBuilder::new()
-            .backend(LLMBackend::DeepSeek)
-            .api_key(api_key.clone()) 
-            .model(&chat_model)
-            .stream(false);
-
-        if let Some(url) = &base_url {  
-            builder = builder.base_url(url);
-        }
-        if let Some(tokens) = max_tokens {
-            builder = builder.max_tokens(tokens);
-        }
-        if let Some(temp) = temperature {
-            builder = builder.temperature(temp);
-        }
-
-        let llm_provider = builder.build()?;
-
-        Ok(Self { 
-            llm: llm_provider,
+        Ok(Self {
+            http: crate::llm::http::shared_client(),
             api_key,
-            model: chat_model,
-            base_url
+            model: model.unwrap_or_else(|| "deepseek-chat".to_string()),
+            base_url,
+            generation: GenerationParams { max_tokens, temperature, ..Default::default() },
+            request_timeout: None,
+            system_prompt: None,
+            retry: RetryPolicy::default(),
         })
     }
 
@@ -55,12 +108,58 @@ impl DeepSeekChatClient {
         let api_key = config.api_key
             .clone()
             .ok_or_else(|| "DeepSeek API key is required for DeepSeekChatClient".to_string())?;
-        let model = config.completion_model.clone();
-        let base_url = config.base_url.clone();
-        let max_tokens = None;
-        let temperature = None;
 
-        Self::new(api_key, model, base_url, max_tokens, temperature)
+        Ok(Self {
+            generation: config.generation,
+            request_timeout: config.request_timeout(),
+            system_prompt: config.system_prompt.clone(),
+            retry: config.retry_policy(),
+            ..Self::new(api_key, config.completion_model.clone(), config.base_url.clone(), None, None)?
+        })
+    }
+
+    fn chat_url(&self) -> String {
+        endpoint_url(self.base_url.as_deref(), DEEPSEEK_DEFAULT_BASE_URL, "/chat/completions")
+    }
+
+    fn chat_request(&self, prompt: &str, stream: bool) -> DeepSeekRequest {
+        let mut messages = Vec::new();
+        if let Some(system) = &self.system_prompt {
+            messages.push(DeepSeekMessage { role: "system".to_string(), content: system.clone() });
+        }
+        messages.push(DeepSeekMessage { role: "user".to_string(), content: prompt.to_string() });
+        DeepSeekRequest {
+            model: self.model.clone(),
+            messages,
+            stream,
+            max_tokens: self.generation.max_tokens,
+            temperature: self.generation.temperature,
+            top_p: self.generation.top_p,
+            frequency_penalty: self.generation.frequency_penalty,
+            presence_penalty: self.generation.presence_penalty,
+        }
+    }
+}
+
+/// Content of one `data: ` line of the SSE body; `None` for other lines, `[DONE]`
+/// and deltas without content.
+fn parse_stream_line(line: &str) -> Option<String> {
+    let data = line.trim_end_matches('\r').strip_prefix("data:")?.trim_start();
+    if data == "[DONE]" {
+        return None;
+    }
+    match serde_json::from_str::<DeepSeekStreamResponse>(data) {
+        Ok(response) => {
+            let content: String = response.choices
+                .into_iter()
+                .filter_map(|choice| choice.delta.content)
+                .collect();
+            Some(content).filter(|c| !c.is_empty())
+        }
+        Err(e) => {
+            info!("Failed to parse DeepSeek chunk: {}, error: {}", data, e);
+            None
+        }
     }
 }
 
@@ -70,17 +169,94 @@ impl ChatClient for DeepSeekChatClient {
         &self,
         prompt: &str
     ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
-        let messages = vec![ChatMessage {
-            role: ChatRole::User,
-            content: prompt.to_string(),
-            message_type: MessageType::Text,
-        }];
+        let url = self.chat_url();
+        let req = self.chat_request(prompt, false);
+        let (url, req) = (&url, &req);
 
-        let response_text = self.llm.chat(&messages).await?;
+        let resp = with_retries(self.retry, || async move {
+            let request = self.http
+                .post(url)
+                .header(AUTHORIZATION, format!("Bearer {}", self.api_key))
+                .json(req);
+            Ok(
+                with_timeout(request, self.request_timeout)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<DeepSeekResponse>()
+                    .await?
+            )
+        }).await?;
 
-        Ok(CompletionResponse { response: response_text.to_string() })
+        let response = resp.choices
+            .into_iter()
+            .next()
+            .ok_or_else(|| "No response from DeepSeek API".to_string())?
+            .message.content;
+        let usage = resp.usage.as_ref();
+        Ok(CompletionResponse {
+            prompt_tokens: usage.and_then(|u| u.prompt_tokens),
+            completion_tokens: usage.and_then(|u| u.completion_tokens),
+            response,
+        })
     }
     
+    async fn stream_completion(&self, prompt: &str) -> Result<ChatStream, Box<dyn StdError + Send + Sync>> {
+        let url = self.chat_url();
+        let req = self.chat_request(prompt, true);
+        let (tx, rx) = mpsc::channel(32);
+        let request = self.http
+            .post(&url)
+            .header(AUTHORIZATION, format!("Bearer {}", self.api_key))
+            .json(&req);
+        let request_timeout = self.request_timeout;
+
+        info!("Starting DeepSeek stream request to {}", url);
+
+        let task = tokio::spawn(async move {
+            let resp = match send_streaming(request, request_timeout).await {
+                Ok(resp) => resp,
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
+                    return;
+                }
+            };
+            if let Err(e) = resp.error_for_status_ref() {
+                let _ = tx.send(Err(Box::new(e) as _)).await;
+                return;
+            }
+
+            // Network chunks do not follow line boundaries, so partial lines wait for the rest.
+            let mut pending: Vec<u8> = Vec::new();
+            let mut stream = resp.bytes_stream();
+            while let Some(chunk) = stream.next().await {
+                let chunk = match chunk {
+                    Ok(chunk) => chunk,
+                    Err(e) => {
+                        let _ = tx.send(Err(Box::new(e) as _)).await;
+                        return;
+                    }
+                };
+                pending.extend_from_slice(&chunk);
+                while let Some(end) = pending.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = pending.drain(..=end).collect();
+                    let Some(content) = parse_stream_line(&String::from_utf8_lossy(&line[..end])) else {
+                        continue;
+                    };
+                    if tx.send(Ok(content)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(abort_on_drop(rx, task))
+    }
+
+    fn supports_native_streaming(&self) -> bool {
+        true
+    }
+
     fn get_api_key(&self) -> String {
         self.api_key.clone()
     }
@@ -96,4 +272,31 @@ impl ChatClient for DeepSeekChatClient {
     fn get_llm_backend(&self) -> LLMBackend {
         LLMBackend::DeepSeek
     }
+
+    fn get_request_timeout(&self) -> Option<Duration> {
+        self.request_timeout
+    }
+
+    fn get_system_prompt(&self) -> Option<String> {
+        self.system_prompt.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_stream_line;
+
+    #[test]
+    fn parses_content_deltas_only() {
+        assert_eq!(
+            parse_stream_line("data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\r"),
+            Some("Hi".to_string())
+        );
+        assert_eq!(parse_stream_line("data:{\"choices\":[{\"delta\":{\"content\":\"!\"}}]}"), Some("!".to_string()));
+        assert_eq!(parse_stream_line("data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}"), None);
+        assert_eq!(parse_stream_line("data: {\"choices\":[{\"delta\":{\"content\":\"\"}}]}"), None);
+        assert_eq!(parse_stream_line("data: [DONE]"), None);
+        assert_eq!(parse_stream_line(": keep-alive"), None);
+        assert_eq!(parse_stream_line("data: not json"), None);
+    }
 }
@@ -1,21 +1,26 @@
 use async_trait::async_trait;
-use std::{error::Error as StdError, pin::Pin };
-use futures::Stream;
-use tokio::sync::mpsc;
-use tokio_stream::wrappers::ReceiverStream;
+use std::{error::Error as StdError, pin::Pin, time::Duration };
+use futures::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
-use log::info;
+use log::{info, warn};
 
-use super::{ChatClient, CompletionResponse, http_stream_generate};
-use crate::llm::LlmConfig; 
-use rllm::chat::{ChatMessage, ChatRole, MessageType};
-use rllm::builder::{LLMBackend, LLMBuilder};
-use rllm::LLMProvider;
+use super::{ChatClient, CompletionResponse, RetryPolicy, estimate_tokens, http_stream_generate, with_retries};
+use super::image::ImageInput;
+use crate::llm::{ GenerationParams, LlmConfig }; 
+use crate::llm::endpoint::endpoint_url;
+use crate::llm::http::with_timeout;
+use rllm::builder::LLMBackend;
+
+const GEMINI_DEFAULT_BASE_URL: &str = "https://generativelanguage.googleapis.com";
 
  
 #[derive(Serialize)]
 struct GeminiStreamRequest {
     contents: Vec<GeminiContent>,
+    #[serde(rename = "generationConfig", skip_serializing_if = "Option::is_none")]
+    generation_config: Option<serde_json::Value>,
+    #[serde(rename = "systemInstruction", skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<GeminiContent>,
 }
 
 #[derive(Serialize)]
@@ -48,6 +53,22 @@ struct GooglePart {
     text: String,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiGenerateResponse {
+    #[serde(default)]
+    candidates: Vec<GoogleCandidate>,
+    #[serde(default)]
+    usage_metadata: Option<GeminiUsageMetadata>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiUsageMetadata {
+    prompt_token_count: Option<u32>,
+    candidates_token_count: Option<u32>,
+}
+
 fn parse_gemini_line(line: &str) -> Option<String> {
     let line = line.trim();
     if line.is_empty() || line == "[" || line == "]" || line == "," {
@@ -90,11 +111,18 @@ fn parse_gemini_line(line: &str) -> Option<String> {
     None
 }
 
+/// Calls generateContent directly (rather than through rllm) so `usageMetadata` is preserved.
 pub struct GeminiChatClient {
-    llm: Box<dyn LLMProvider + Send + Sync>,
+    http: reqwest::Client,
     api_key: String,
     model: String,
     base_url: Option<String>,
+    generation: GenerationParams,
+    thinking_budget: Option<u32>,
+    /// Sent as `systemInstruction`.
+    system_prompt: Option<String>,
+    request_timeout: Option<Duration>,
+    retry: RetryPolicy,
 }
 
 impl GeminiChatClient {
@@ -106,31 +134,62 @@ impl GeminiChatClient {
         temperature: Option<f32>
     ) -> Result<Self, Box<dyn StdError + Send + Sync>> {
         let chat_model = model.unwrap_or_else(|| "gemini-1.5-flash-latest".to_string());
+        let client = Self { 
+            http: crate::llm::http::shared_client(),
+            api_key,
+            model: chat_model,
+            base_url,
+            generation: GenerationParams { max_tokens, temperature, ..Default::default() },
+            thinking_budget: None,
+            system_prompt: None,
+            request_timeout: None,
+            retry: RetryPolicy::default(),
+        };
+        client.check_base_url()?;
+        info!("Gemini endpoint for model {}: {}", client.model, client.model_endpoint());
+
+        Ok(client)
+    }
 
-        let mut builder = LLMBuilder::new()
-            .backend(LLMBackend::Google)
-            .api_key(api_key.clone())
-            .model(&chat_model)
-            .stream(false);
+    /// Rejects base URLs `model_endpoint` cannot turn into a model endpoint, so a bad
+    /// setting fails at startup instead of on every request.
+    fn check_base_url(&self) -> Result<(), Box<dyn StdError + Send + Sync>> {
+        let Some(raw) = self.base_url.as_deref().map(str::trim).filter(|u| !u.is_empty()) else {
+            return Ok(());
+        };
+        let invalid = |reason: &str| -> Box<dyn StdError + Send + Sync> {
+            format!(
+                "Invalid Gemini base URL '{}': {}. Use the API root ({}) or a model endpoint ending in /models/<model>",
+                raw,
+                reason,
+                GEMINI_DEFAULT_BASE_URL
+            ).into()
+        };
 
-        if let Some(url) = &base_url {
-            builder = builder.base_url(url);
+        let url = url::Url::parse(raw).map_err(|e| invalid(&e.to_string()))?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(invalid("the scheme must be http or https"));
         }
-        if let Some(tokens) = max_tokens {
-            builder = builder.max_tokens(tokens);
+        if url.query().is_some() {
+            return Err(invalid("it must not contain a query string; the API key is added per request"));
         }
-        if let Some(temp) = temperature {
-            builder = builder.temperature(temp);
+        let path = url.path().trim_end_matches('/');
+        if path.contains(':') {
+            return Err(invalid("it must not name a method such as :generateContent"));
         }
-
-        let llm_provider = builder.build()?;
-
-        Ok(Self { 
-            llm: llm_provider,
-            api_key,
-            model: chat_model,
-            base_url
-        })
+        if path.ends_with("/models") {
+            return Err(invalid("it ends at /models without a model name"));
+        }
+        if let Some((_, url_model)) = path.split_once("/models/") {
+            if url_model != self.model {
+                warn!(
+                    "Gemini base URL names model '{}', which is used instead of the configured model '{}'",
+                    url_model,
+                    self.model
+                );
+            }
+        }
+        Ok(())
     }
 
     pub fn from_config(config: &LlmConfig) -> Result<Self, Box<dyn StdError + Send + Sync>> {
@@ -139,10 +198,90 @@ impl GeminiChatClient {
             .ok_or_else(|| "Google API key is required for GeminiChatClient".to_string())?;
         let model = config.completion_model.clone();
         let base_url = config.base_url.clone();
-        let max_tokens = None;
-        let temperature = None;
 
-        Self::new(api_key, model, base_url, max_tokens, temperature)
+        Ok(Self {
+            generation: config.generation,
+            thinking_budget: config.thinking_budget,
+            system_prompt: config.system_prompt.clone(),
+            request_timeout: config.request_timeout(),
+            retry: config.retry_policy(),
+            ..Self::new(api_key, model, base_url, None, None)?
+        })
+    }
+
+    fn generation_config(&self) -> Option<serde_json::Value> {
+        let mut generation_config = serde_json::Map::new();
+        let params = &self.generation;
+        if let Some(tokens) = params.max_tokens {
+            generation_config.insert("maxOutputTokens".into(), tokens.into());
+        }
+        if let Some(temp) = params.temperature {
+            generation_config.insert("temperature".into(), temp.into());
+        }
+        if let Some(top_p) = params.top_p {
+            generation_config.insert("topP".into(), top_p.into());
+        }
+        if let Some(penalty) = params.frequency_penalty {
+            generation_config.insert("frequencyPenalty".into(), penalty.into());
+        }
+        if let Some(penalty) = params.presence_penalty {
+            generation_config.insert("presencePenalty".into(), penalty.into());
+        }
+        if let Some(budget) = self.thinking_budget {
+            generation_config.insert("thinkingConfig".into(), serde_json::json!({ "thinkingBudget": budget }));
+        }
+        (!generation_config.is_empty()).then_some(serde_json::Value::Object(generation_config))
+    }
+
+    fn system_instruction(&self) -> Option<GeminiContent> {
+        self.system_prompt.as_ref().map(|system| GeminiContent { parts: vec![GeminiPart { text: system.clone() }] })
+    }
+
+    /// Model URL that `:generateContent` / `:streamGenerateContent` is appended to.
+    /// A base URL already naming a model (`.../models/<model>`) is used as-is.
+    fn model_endpoint(&self) -> String {
+        match self.base_url.as_deref().map(|url| url.trim().trim_end_matches('/')) {
+            Some(url) if url.contains("/models/") => url.to_string(),
+            base => endpoint_url(base, GEMINI_DEFAULT_BASE_URL, &format!("/v1beta/models/{}", self.model)),
+        }
+    }
+
+    async fn generate_content(
+        &self,
+        prompt: &str,
+        parts: Vec<serde_json::Value>
+    ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
+        let url = format!("{}:generateContent?key={}", self.model_endpoint(), self.api_key);
+        let mut payload = serde_json::json!({ "contents": [{ "role": "user", "parts": parts }] });
+        if let Some(generation_config) = self.generation_config() {
+            payload["generationConfig"] = generation_config;
+        }
+        if let Some(system_instruction) = self.system_instruction() {
+            payload["systemInstruction"] = serde_json::to_value(system_instruction)?;
+        }
+
+        let (url, payload) = (&url, &payload);
+        let resp = with_retries(self.retry, || async move {
+            Ok(
+                with_timeout(self.http.post(url).json(payload), self.request_timeout)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<GeminiGenerateResponse>()
+                    .await?
+            )
+        }).await?;
+
+        let text = resp.candidates
+            .first()
+            .map(|c| c.content.parts.iter().map(|p| p.text.as_str()).collect::<String>())
+            .ok_or("No response from Gemini API")?;
+        let usage = resp.usage_metadata.as_ref();
+        Ok(CompletionResponse {
+            prompt_tokens: usage.and_then(|u| u.prompt_token_count).or_else(|| Some(estimate_tokens(prompt))),
+            completion_tokens: usage.and_then(|u| u.candidates_token_count).or_else(|| Some(estimate_tokens(&text))),
+            response: text,
+        })
     }
 }
 
@@ -152,22 +291,41 @@ impl ChatClient for GeminiChatClient {
         &self,
         prompt: &str
     ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
-        let messages = vec![ChatMessage {
-            role: ChatRole::User,
-            content: prompt.to_string(),
-            message_type: MessageType::Text,
-        }];
         info!(
             "GeminiChatClient::complete() → model={} base_url={:?}",
             self.model,
             self.base_url
         );
-        let resp = self.llm.chat(&messages).await?;
-        let text = resp
-            .text()
-            .map(|s| s.to_string())
-            .unwrap_or_else(|| resp.to_string());
-        Ok(CompletionResponse { response: text })
+        self.generate_content(prompt, vec![serde_json::json!({ "text": prompt })]).await
+    }
+
+    async fn complete_with_images(
+        &self,
+        prompt: &str,
+        images: &[ImageInput]
+    ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
+        if images.is_empty() {
+            return self.complete(prompt).await;
+        }
+
+        let mut parts = vec![serde_json::json!({ "text": prompt })];
+        for image in images {
+            match image {
+                ImageInput::Base64 { mime_type, data } => parts.push(serde_json::json!({
+                    "inline_data": { "mime_type": mime_type, "data": data }
+                })),
+                ImageInput::Url(_) => {
+                    return Err("Gemini only accepts inline base64 images; send the image as base64 or a data URI instead of a URL".into());
+                }
+            }
+        }
+
+        info!("GeminiChatClient::complete_with_images() → model={} images={}", self.model, images.len());
+        self.generate_content(prompt, parts).await
+    }
+
+    fn supports_vision(&self) -> bool {
+        true
     }
 
     async fn complete_stream(
@@ -191,41 +349,47 @@ impl ChatClient for GeminiChatClient {
         
         let payload = GeminiStreamRequest {
             contents: vec![content],
+            generation_config: self.generation_config(),
+            system_instruction: self.system_instruction(),
         };
 
-        let model_specific_base_url = self.base_url.clone().ok_or_else(|| {
-            Box::<dyn StdError + Send + Sync>::from(
-                "Gemini base_url (CHAT_BASE_URL) is not configured or is empty. It should point to the specific model endpoint.",
-            )
-        })?;
+        let model_specific_base_url = self.model_endpoint();
 
         let route_suffix = format!(":streamGenerateContent?key={}", self.api_key);
-        info!("Attempting to stream from URL: {}{}", model_specific_base_url, route_suffix);
+        info!("Attempting to stream from URL: {}:streamGenerateContent", model_specific_base_url);
 
         let headers = vec![
             ("Content-Type".to_string(), "application/json".to_string())
         ];
         
-        match http_stream_generate(
-            model_specific_base_url,
+        let streamed = http_stream_generate(
+            self.http.clone(),
+            model_specific_base_url.clone(),
             &route_suffix,
             payload,
-            parse_gemini_line,
-            Some(headers),  
+            |line| Ok(parse_gemini_line(line)),
+            Some(headers),
+            self.request_timeout,
         )
-        .await
-        {
-            Ok(stream) => Ok(stream),
-            Err(_e) => { 
-                let resp = self.complete(prompt).await?;
-                let text = resp.response;
-                let (tx, rx) = mpsc::channel(1);
-                tokio::spawn(async move {
-                    let _ = tx.send(Ok(text)).await;
-                });
-                Ok(Box::pin(ReceiverStream::new(rx)))
-            }
-        }
+        .await;
+        // The request runs inside the stream, so a failed call shows up as its first item.
+        let error = match streamed {
+            Ok(mut stream) => match stream.next().await {
+                Some(Err(e)) => e,
+                first => return Ok(Box::pin(futures::stream::iter(first).chain(stream))),
+            },
+            Err(e) => e,
+        };
+
+        warn!(
+            "Gemini streaming request to {}:streamGenerateContent failed ({}); falling back to a non-streaming generateContent call",
+            model_specific_base_url,
+            error
+        );
+        let resp = self.complete(prompt).await.map_err(|fallback_error| {
+            format!("Gemini streaming failed ({}) and the non-streaming fallback failed too: {}", error, fallback_error)
+        })?;
+        Ok(Box::pin(futures::stream::once(async move { Ok(resp.response) })))
     }
 
     async fn stream_completion(
@@ -261,3 +425,30 @@ impl ChatClient for GeminiChatClient {
         LLMBackend::Google
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(base_url: Option<&str>) -> String {
+        let client = GeminiChatClient::new(
+            "key".to_string(),
+            Some("gemini-2.0-flash".to_string()),
+            base_url.map(str::to_string),
+            None,
+            None
+        ).unwrap();
+        client.model_endpoint()
+    }
+
+    #[test]
+    fn model_endpoints_carry_the_model_in_the_path() {
+        let expected = "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.0-flash";
+        assert_eq!(endpoint(None), expected);
+        assert_eq!(endpoint(Some("https://generativelanguage.googleapis.com/")), expected);
+        assert_eq!(endpoint(Some("https://generativelanguage.googleapis.com/v1beta")), expected);
+        assert_eq!(endpoint(Some("https://proxy.local/gemini/")), "https://proxy.local/gemini/v1beta/models/gemini-2.0-flash");
+        // A base naming a model is used as-is, even for another model.
+        assert_eq!(endpoint(Some("https://proxy.local/v1/models/gemini-pro/")), "https://proxy.local/v1/models/gemini-pro");
+    }
+}
@@ -5,18 +5,27 @@ use reqwest::{Client as HttpClient, header::{HeaderMap, HeaderValue, CONTENT_TYP
 use serde::{Deserialize, Serialize};
 use std::error::Error as StdError;
 use std::pin::Pin;
+use std::time::Duration;
 use tokio::sync::mpsc;
-use tokio_stream::wrappers::ReceiverStream;
 
-use super::{ChatClient, CompletionResponse};
-use crate::llm::LlmConfig;
+use super::{abort_on_drop, with_retries, ChatClient, CompletionResponse, RetryPolicy};
+use crate::llm::{ GenerationParams, LlmConfig };
+use crate::models::chat::ChatMessage;
+use crate::llm::endpoint::join_endpoint;
+use crate::llm::http::{ send_streaming, with_timeout };
 use rllm::builder::LLMBackend;
 
+const GROQ_DEFAULT_BASE_URL: &str = "https://api.groq.com";
+
 pub struct GroqChatClient {
     http: HttpClient,
     api_key: String,
     model: String,
     base_url: String,
+    system_prompt: Option<String>,
+    generation: GenerationParams,
+    request_timeout: Option<Duration>,
+    retry: RetryPolicy,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -33,6 +42,12 @@ struct GroqRequest {
     #[serde(rename = "max_tokens")]
     max_tokens: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     stream: Option<bool>,
 }
 
@@ -70,7 +85,7 @@ impl GroqChatClient {
         base_url: Option<String>,
     ) -> Result<Self, Box<dyn StdError + Send + Sync>> {
         let chat_model = model.unwrap_or_else(|| "llama-3.1-8b-instruct".to_string());
-        let api_url = base_url.unwrap_or_else(|| "https://api.groq.com".to_string());
+        let api_url = base_url.unwrap_or_else(|| GROQ_DEFAULT_BASE_URL.to_string());
         
         let mut headers = HeaderMap::new();
         headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
@@ -80,7 +95,7 @@ impl GroqChatClient {
                 .map_err(|e| format!("Invalid API key format: {}", e))?
         );
         
-        let http = HttpClient::builder()
+        let http = crate::llm::http::client_builder()
             .default_headers(headers)
             .build()
             .map_err(|e| Box::new(e) as Box<dyn StdError + Send + Sync>)?;
@@ -90,6 +105,10 @@ impl GroqChatClient {
             api_key,
             model: chat_model,
             base_url: api_url,
+            system_prompt: None,
+            generation: GenerationParams::default(),
+            request_timeout: None,
+            retry: RetryPolicy::default(),
         })
     }
 
@@ -98,55 +117,93 @@ impl GroqChatClient {
             .clone()
             .ok_or_else(|| "Groq API key is required".to_string())?;
         
-        Self::new(
-            api_key,
-            config.completion_model.clone(),
-            config.base_url.clone(),
-        )
+        Ok(Self {
+            system_prompt: config.system_prompt.clone(),
+            generation: config.generation,
+            request_timeout: config.request_timeout(),
+            retry: config.retry_policy(),
+            ..Self::new(
+                api_key,
+                config.completion_model.clone(),
+                config.base_url.clone(),
+            )?
+        })
     }
-}
 
-#[async_trait]
-impl ChatClient for GroqChatClient {
-    async fn complete(
+    fn with_system_prompt(&self, mut messages: Vec<GroqMessage>) -> Vec<GroqMessage> {
+        if let Some(system) = &self.system_prompt {
+            messages.insert(0, GroqMessage { role: "system".to_string(), content: system.clone() });
+        }
+        messages
+    }
+
+    /// Non-streaming completion for a list of chat messages.
+    async fn complete_chat(
         &self,
-        prompt: &str
+        messages: Vec<GroqMessage>
     ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
-        let url = format!("{}", self.base_url.trim_end_matches('/'));
-        
-        let messages = vec![GroqMessage {
-            role: "user".to_string(),
-            content: prompt.to_string(),
-        }];
+        let url = join_endpoint(&self.base_url, "/openai/v1/chat/completions");
         
         let req = GroqRequest {
-            messages,
+            messages: self.with_system_prompt(messages),
             model: self.model.clone(),
-            temperature: 0.7,
-            max_tokens: 1024,
+            temperature: self.generation.temperature.unwrap_or(0.7),
+            max_tokens: self.generation.max_tokens.unwrap_or(1024),
+            top_p: self.generation.top_p,
+            frequency_penalty: self.generation.frequency_penalty,
+            presence_penalty: self.generation.presence_penalty,
             stream: None,
         };
         
-        let resp = self.http.post(&url)
-            .json(&req)
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<GroqResponse>()
-            .await?;
+        let (url, req) = (&url, &req);
+        let resp = with_retries(self.retry, || async move {
+            Ok(
+                with_timeout(self.http.post(url).json(req), self.request_timeout)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<GroqResponse>()
+                    .await?
+            )
+        }).await?;
         
         let content = resp.choices.first()
             .ok_or_else(|| "No response from Groq API".to_string())?
             .message.content.clone();
         
-        Ok(CompletionResponse { response: content })
+        Ok(CompletionResponse { response: content, ..Default::default() })
+    }
+}
+
+#[async_trait]
+impl ChatClient for GroqChatClient {
+    async fn complete(
+        &self,
+        prompt: &str
+    ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
+        let messages = vec![GroqMessage {
+            role: "user".to_string(),
+            content: prompt.to_string(),
+        }];
+        self.complete_chat(messages).await
+    }
+
+    async fn complete_messages(
+        &self,
+        messages: &[ChatMessage]
+    ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
+        let messages = messages
+            .iter()
+            .map(|m| GroqMessage { role: m.role.clone(), content: m.content.clone() })
+            .collect();
+        self.complete_chat(messages).await
     }
     
     async fn stream_completion(
         &self,
         prompt: &str
     ) -> Result<Pin<Box<dyn Stream<Item = Result<String, Box<dyn StdError + Send + Sync>>> + Send>>, Box<dyn StdError + Send + Sync>> {
-        let url = format!("{}", self.base_url.trim_end_matches('/'));
+        let url = join_endpoint(&self.base_url, "/openai/v1/chat/completions");
         
         let messages = vec![GroqMessage {
             role: "user".to_string(),
@@ -154,25 +211,29 @@ impl ChatClient for GroqChatClient {
         }];
         
         let req = GroqRequest {
-            messages,
+            messages: self.with_system_prompt(messages),
             model: self.model.clone(),
-            temperature: 0.7,
-            max_tokens: 1024,
+            temperature: self.generation.temperature.unwrap_or(0.7),
+            max_tokens: self.generation.max_tokens.unwrap_or(1024),
+            top_p: self.generation.top_p,
+            frequency_penalty: self.generation.frequency_penalty,
+            presence_penalty: self.generation.presence_penalty,
             stream: Some(true),
         };
         
         let (tx, rx) = mpsc::channel(32);
         let client = self.http.clone();
+        let request_timeout = self.request_timeout;
         
         info!("Starting Groq stream request to {}", url);
         
-        tokio::spawn(async move {
-            match client.post(&url).json(&req).send().await {
+        let task = tokio::spawn(async move {
+            match send_streaming(client.post(&url).json(&req), request_timeout).await {
                 Ok(resp) => {
                     if let Err(e) = resp.error_for_status_ref() {
                         let err_msg = format!("Groq API error: {}", e);
                   
-                        let _ = tx.send(Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, err_msg)) as _)).await;
+                        let _ = tx.send(Err(Box::new(std::io::Error::other(err_msg)) as _)).await;
                         return;
                     }
                     
@@ -194,11 +255,10 @@ impl ChatClient for GroqChatClient {
                                                 Ok(stream_resp) => {
                                                     for choice in stream_resp.choices {
                                                         if let Some(content) = choice.delta.content {
-                                                            if !content.is_empty() {
-                                                                if tx.send(Ok(content)).await.is_err() {
+                                                            if !content.is_empty()
+                                                                && tx.send(Ok(content)).await.is_err() {
                                                                     return;
                                                                 }
-                                                            }
                                                         }
                                                         
                                                         if let Some(reason) = choice.finish_reason {
@@ -226,12 +286,12 @@ impl ChatClient for GroqChatClient {
                 Err(e) => {
                     let err_msg = format!("Groq request error: {}", e);
                     info!("{}", err_msg);
-                    let _ = tx.send(Err(Box::new(e) as _)).await;
+                    let _ = tx.send(Err(e)).await;
                 }
             }
         });
         
-        Ok(Box::pin(ReceiverStream::new(rx)))
+        Ok(abort_on_drop(rx, task))
     }
     
     fn supports_native_streaming(&self) -> bool {
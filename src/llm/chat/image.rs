@@ -0,0 +1,51 @@
+use std::error::Error as StdError;
+
+const DEFAULT_IMAGE_MIME: &str = "image/png";
+
+/// An image attached to a chat message, given either as a URL or base64 data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageInput {
+    Url(String),
+    Base64 { mime_type: String, data: String },
+}
+
+impl ImageInput {
+    /// Accepts `http(s)://` URLs, `data:<mime>;base64,<data>` URIs, or bare base64 (assumed PNG).
+    pub fn parse(raw: &str) -> Result<Self, Box<dyn StdError + Send + Sync>> {
+        let raw = raw.trim();
+        if raw.is_empty() {
+            return Err("Image entry is empty".into());
+        }
+        if raw.starts_with("http://") || raw.starts_with("https://") {
+            return Ok(ImageInput::Url(raw.to_string()));
+        }
+        if let Some(rest) = raw.strip_prefix("data:") {
+            let (meta, data) = rest
+                .split_once(',')
+                .ok_or("Malformed data URI image: missing ',' separator")?;
+            let mime_type = meta
+                .strip_suffix(";base64")
+                .ok_or("Only base64-encoded data URI images are supported")?;
+            return Ok(ImageInput::Base64 {
+                mime_type: mime_type.to_string(),
+                data: data.to_string(),
+            });
+        }
+        Ok(ImageInput::Base64 {
+            mime_type: DEFAULT_IMAGE_MIME.to_string(),
+            data: raw.to_string(),
+        })
+    }
+
+    pub fn parse_all(raw: &[String]) -> Result<Vec<Self>, Box<dyn StdError + Send + Sync>> {
+        raw.iter().map(|s| Self::parse(s)).collect()
+    }
+
+    /// URL form accepted by OpenAI-style `image_url` parts (plain URL or data URI).
+    pub fn to_url(&self) -> String {
+        match self {
+            ImageInput::Url(url) => url.clone(),
+            ImageInput::Base64 { mime_type, data } => format!("data:{};base64,{}", mime_type, data),
+        }
+    }
+}
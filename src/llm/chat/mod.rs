@@ -5,6 +5,8 @@ pub mod anthropic;
 pub mod deepseek;
 pub mod groq;
 pub mod xai;
+pub mod image;
+pub mod timed;
 
 use async_trait::async_trait;
 use futures::{Stream, StreamExt, Future}; 
@@ -12,7 +14,13 @@ use serde::Deserialize;
 use std::error::Error as StdError;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 use super::{ LlmConfig, LlmType };
+use super::http::{ send_streaming, ProviderTimeout };
+use super::is_retryable_error;
+use log::warn;
+use std::collections::hash_map::RandomState;
+use std::hash::{ BuildHasher, Hasher };
 use self::ollama::OllamaClient;
 use self::openai::OpenAIChatClient;
 use self::gemini::GeminiChatClient;
@@ -20,7 +28,12 @@ use self::anthropic::AnthropicChatClient;
 use self::deepseek::DeepSeekChatClient;
 use self::groq::GroqChatClient;
 use self::xai::XAIChatClient;
+use self::image::ImageInput;
+use crate::history::format_history_for_prompt;
+use crate::models::chat::{ ChatMessage as ConversationMessage, Conversation };
+use std::task::{ Context, Poll };
 use tokio::sync::mpsc;
+use tokio::task::{ AbortHandle, JoinHandle };
 use tokio_stream::wrappers::ReceiverStream;
 use rllm::{
     builder::{ LLMBackend, LLMBuilder },
@@ -28,9 +41,20 @@ use rllm::{
 };
 use reqwest;
 
-#[derive(Deserialize, Debug, Clone)]
+pub type ChatStream = Pin<Box<dyn Stream<Item = Result<String, Box<dyn StdError + Send + Sync>>> + Send>>;
+
+#[derive(Deserialize, Debug, Clone, Default)]
 pub struct CompletionResponse {
     pub response: String,
+    #[serde(default)]
+    pub prompt_tokens: Option<u32>,
+    #[serde(default)]
+    pub completion_tokens: Option<u32>,
+}
+
+/// Rough token count (~4 chars per token) for providers that omit usage metadata.
+pub fn estimate_tokens(text: &str) -> u32 {
+    text.chars().count().div_ceil(4) as u32
 }
 
 #[async_trait]
@@ -63,15 +87,135 @@ pub trait ChatClient: Send + Sync {
         stream_chat_for_provider(self, prompt).await
     }
     
+    /// Completion for a conversation given as `system`, `user` and `assistant` messages,
+    /// oldest first. The default flattens them into one prompt with `flatten_messages`;
+    /// providers with a chat API override it to keep the roles.
+    async fn complete_messages(
+        &self,
+        messages: &[ConversationMessage]
+    ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
+        self.complete(&flatten_messages(messages)).await
+    }
+
+    /// Completion for a prompt with attached images. Providers without vision support
+    /// keep this default, which rejects any images.
+    async fn complete_with_images(
+        &self,
+        prompt: &str,
+        images: &[ImageInput]
+    ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
+        if images.is_empty() {
+            return self.complete(prompt).await;
+        }
+        Err(unsupported_images_error(&self.get_model()))
+    }
+
+    fn supports_vision(&self) -> bool {
+        false
+    }
+
     fn get_api_key(&self) -> String;
     fn get_model(&self) -> String;
     fn get_base_url(&self) -> Option<String>;
     fn get_llm_backend(&self) -> LLMBackend;
+    /// Timeout applied to calls made through rllm for this client.
+    fn get_request_timeout(&self) -> Option<Duration> {
+        None
+    }
+    /// System prompt applied to calls made through rllm for this client.
+    fn get_system_prompt(&self) -> Option<String> {
+        None
+    }
     fn supports_native_streaming(&self) -> bool {
         false  
     }
 }
 
+/// Single-prompt form of a message list: system messages first, then earlier turns as
+/// in `format_history_for_prompt`, then the final user message as `User: ...`.
+pub fn flatten_messages(messages: &[ConversationMessage]) -> String {
+    let (system, turns): (Vec<_>, Vec<_>) = messages.iter().cloned().partition(|m| m.role == "system");
+    let (history, question) = match turns.split_last() {
+        Some((last, earlier)) if last.role == "user" => (earlier.to_vec(), Some(last.content.as_str())),
+        _ => (turns.clone(), None),
+    };
+    let mut prompt = format_history_for_prompt(&Conversation { id: String::new(), messages: history });
+    if let Some(question) = question {
+        prompt = format!("{}\n\nUser: {}", prompt, question);
+    }
+    if system.is_empty() {
+        return prompt;
+    }
+    let instructions = system.iter().map(|m| m.content.as_str()).collect::<Vec<_>>().join("\n\n");
+    format!("{}\n\n{}", instructions, prompt)
+}
+
+/// Retries for transient failures of non-streaming provider calls.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetryPolicy {
+    /// Extra attempts after the first; 0 disables retrying.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each later one.
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Backoff before retry number `retry` (from 0), with jitter between half and all of it.
+    fn delay(&self, retry: u32) -> Duration {
+        let backoff = self.base_delay.saturating_mul(2u32.saturating_pow(retry));
+        let jitter = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        backoff.mul_f64(0.5 + jitter / 2.0)
+    }
+}
+
+/// Throttling (429), server errors and connection failures. Client errors such as
+/// 400 or 401 fail fast, and timeouts are not retried since the wait already ran out.
+fn is_transient_error(err: &(dyn StdError + 'static)) -> bool {
+    if err.is::<ProviderTimeout>() {
+        return false;
+    }
+    if let Some(e) = err.downcast_ref::<reqwest::Error>() {
+        if e.is_timeout() {
+            return false;
+        }
+    }
+    is_retryable_error(err)
+}
+
+/// Runs `call` until it succeeds, fails with a non-transient error, or `policy`
+/// runs out of retries, backing off exponentially between attempts.
+pub async fn with_retries<T, F, Fut>(policy: RetryPolicy, mut call: F) -> Result<T, Box<dyn StdError + Send + Sync>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Box<dyn StdError + Send + Sync>>>,
+{
+    let mut retry = 0;
+    loop {
+        match call().await {
+            Err(e) if retry < policy.max_retries && is_transient_error(e.as_ref()) => {
+                let delay = policy.delay(retry);
+                retry += 1;
+                warn!(
+                    "Provider call failed ({}); retrying in {:?} (retry {}/{})",
+                    e,
+                    delay,
+                    retry,
+                    policy.max_retries
+                );
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+pub fn unsupported_images_error(model: &str) -> Box<dyn StdError + Send + Sync> {
+    format!(
+        "Model '{}' does not accept image input. Remove the images or configure a vision-capable chat provider (openai, gemini, anthropic).",
+        model
+    ).into()
+}
+
 pub async fn stream_chat_for_provider<T: ChatClient + ?Sized>(
     client: &T,
     prompt: &str
@@ -81,6 +225,8 @@ pub async fn stream_chat_for_provider<T: ChatClient + ?Sized>(
     let base_url = client.get_base_url();
     let backend = client.get_llm_backend();
     let supports_streaming = client.supports_native_streaming();
+    let request_timeout = client.get_request_timeout();
+    let system_prompt = client.get_system_prompt();
 
     if supports_streaming {
         return client.stream_completion(prompt).await;
@@ -101,6 +247,12 @@ pub async fn stream_chat_for_provider<T: ChatClient + ?Sized>(
         if let Some(url) = base_url_clone {
             builder = builder.base_url(url);
         }
+        if let Some(timeout) = request_timeout {
+            builder = builder.timeout_seconds(timeout.as_secs());
+        }
+        if let Some(system) = system_prompt {
+            builder = builder.system(system);
+        }
         
         let provider = builder.build()?;
         
@@ -122,25 +274,54 @@ pub async fn stream_chat_for_provider<T: ChatClient + ?Sized>(
 
 
 
+/// Channel-backed stream that aborts its producer task when dropped, so a cancelled
+/// or timed-out request stops reading from the provider right away.
+struct TaskStream {
+    inner: ReceiverStream<Result<String, Box<dyn StdError + Send + Sync>>>,
+    task: AbortHandle,
+}
+
+impl Stream for TaskStream {
+    type Item = Result<String, Box<dyn StdError + Send + Sync>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+impl Drop for TaskStream {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Wraps the receiving end of a streaming task; dropping the stream aborts `task`.
+pub fn abort_on_drop(
+    rx: mpsc::Receiver<Result<String, Box<dyn StdError + Send + Sync>>>,
+    task: JoinHandle<()>
+) -> ChatStream {
+    Box::pin(TaskStream { inner: ReceiverStream::new(rx), task: task.abort_handle() })
+}
+
 pub fn create_streaming_response<F, Fut>(
     response_fn: F
-) -> Result<Pin<Box<dyn Stream<Item = Result<String, Box<dyn StdError + Send + Sync>>> + Send>>, Box<dyn StdError + Send + Sync>>
+) -> Result<ChatStream, Box<dyn StdError + Send + Sync>>
 where
     F: FnOnce(mpsc::Sender<Result<String, Box<dyn StdError + Send + Sync>>>) -> Fut + Send + 'static,
     Fut: Future<Output = ()> + Send + 'static,
 {
     let (tx, rx) = mpsc::channel(32);
     
-    tokio::spawn(async move {
+    let task = tokio::spawn(async move {
         response_fn(tx).await;
     });
     
-    Ok(Box::pin(ReceiverStream::new(rx)))
+    Ok(abort_on_drop(rx, task))
 }
 
 pub fn full_response_as_stream<F, Fut>(
     response_fn: F
-) -> Result<Pin<Box<dyn Stream<Item = Result<String, Box<dyn StdError + Send + Sync>>> + Send>>, Box<dyn StdError + Send + Sync>>
+) -> Result<ChatStream, Box<dyn StdError + Send + Sync>>
 where
     F: FnOnce() -> Fut + Send + 'static,
     Fut: Future<Output = Result<String, Box<dyn StdError + Send + Sync>>> + Send + 'static,
@@ -190,24 +371,32 @@ pub fn new_client(
             Arc::new(specific_client)
         }
     };
-    Ok(client)
+    Ok(timed::TimedChatClient::wrap(client))
 }
 
+/// Turns one line of a streamed response into text to forward (`Ok(None)` for lines
+/// without any), or an error the provider reported mid-stream, which ends the stream.
+pub type LineParser = fn(&str) -> Result<Option<String>, Box<dyn StdError + Send + Sync>>;
+
+/// Streams a POST response line by line through `line_parser`. Pass the caller's
+/// long-lived client so connections are reused across calls. `request_timeout`
+/// bounds the wait for the response to start.
 pub async fn http_stream_generate(
+    client: reqwest::Client,
     base_url: String,
     route: &str,           
     payload: impl serde::Serialize + Send + 'static,
-    line_parser: fn(&str) -> Option<String>,
+    line_parser: LineParser,
     headers: Option<Vec<(String, String)>>,
+    request_timeout: Option<Duration>,
 ) -> Result<
     Pin<Box<dyn Stream<Item = Result<String, Box<dyn StdError + Send + Sync>>> + Send>>,
     Box<dyn StdError + Send + Sync>
 > {
     let url = format!("{}{}", base_url.trim_end_matches('/'), route);
     let (tx, rx) = mpsc::channel(32);
-    let client = reqwest::Client::new();
     
-    tokio::spawn(async move {
+    let task = tokio::spawn(async move {
         let mut req = client.post(&url).json(&payload);
         
         if let Some(header_list) = headers {
@@ -216,39 +405,51 @@ pub async fn http_stream_generate(
             }
         }
         
-        match req.send().await {
+        match send_streaming(req, request_timeout).await {
             Ok(resp) => {
                 if let Err(e) = resp.error_for_status_ref() {
                     let _ = tx.send(Err(Box::new(e) as _)).await;
                     return;
                 }
+                // Network chunks do not follow line (or UTF-8 character) boundaries,
+                // so partial lines wait for the rest.
+                let mut pending: Vec<u8> = Vec::new();
                 let mut bytes = resp.bytes_stream();
-                while let Some(chunk) = bytes.next().await {
-                    match chunk {
-                        Ok(buf) => {
-                            if let Ok(text) = String::from_utf8(buf.to_vec()) {
-                            
-                                for line in text.lines() {
-                                    if let Some(tok) = line_parser(line) {
-                                        if tx.send(Ok(tok)).await.is_err() {
-                                            return;
-                                        }
-                                    }
-                                }
+                loop {
+                    let line = match pending.iter().position(|b| *b == b'\n') {
+                        Some(end) => pending.drain(..=end).collect::<Vec<u8>>(),
+                        None => match bytes.next().await {
+                            Some(Ok(buf)) => {
+                                pending.extend_from_slice(&buf);
+                                continue;
+                            }
+                            Some(Err(e)) => {
+                                let _ = tx.send(Err(Box::new(e) as _)).await;
+                                return;
+                            }
+                            None if pending.is_empty() => return,
+                            None => std::mem::take(&mut pending),
+                        },
+                    };
+                    match line_parser(&String::from_utf8_lossy(&line)) {
+                        Ok(None) => {}
+                        Ok(Some(tok)) => {
+                            if tx.send(Ok(tok)).await.is_err() {
+                                return;
                             }
                         }
                         Err(e) => {
-                            let _ = tx.send(Err(Box::new(e) as _)).await;
+                            let _ = tx.send(Err(e)).await;
                             return;
                         }
                     }
                 }
             }
             Err(e) => {
-                let _ = tx.send(Err(Box::new(e) as _)).await;
+                let _ = tx.send(Err(e)).await;
             }
         }
     });
     
-    Ok(Box::pin(ReceiverStream::new(rx)))
+    Ok(abort_on_drop(rx, task))
 }
@@ -3,11 +3,14 @@ use serde::{ Deserialize, Serialize };
 use std::error::Error;
 use async_trait::async_trait;
 use std::error::Error as StdError;
-use super::{ ChatClient, CompletionResponse };
-use crate::llm::LlmConfig;
+use super::{ abort_on_drop, with_retries, ChatClient, CompletionResponse, RetryPolicy };
+use crate::llm::{ GenerationParams, LlmConfig };
+use crate::llm::endpoint::join_endpoint;
+use crate::llm::http::{ send_streaming, shared_client, with_timeout };
+use crate::llm::ollama::{ keep_alive_value, OLLAMA_DEFAULT_BASE_URL };
 use futures::{Stream, StreamExt};
 use std::pin::Pin;
-use tokio_stream::wrappers::ReceiverStream;
+use std::time::Duration;
 use tokio::sync::mpsc;
 use log::info;
 use rllm::builder::LLMBackend;
@@ -17,6 +20,13 @@ pub struct OllamaClient {
     http: HttpClient,
     base_url: String,
     completion_model: String,
+    keep_alive: Option<serde_json::Value>,
+    seed: Option<u64>,
+    generation: GenerationParams,
+    /// Put in front of each prompt; `/api/generate` takes a single prompt string.
+    system_prompt: Option<String>,
+    request_timeout: Option<Duration>,
+    retry: RetryPolicy,
 }
 
 #[derive(Serialize)]
@@ -24,6 +34,26 @@ struct GenerateRequest {
     model: String,
     prompt: String,
     stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<GenerateOptions>,
+}
+
+#[derive(Serialize)]
+struct GenerateOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_predict: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
 }
 
 #[derive(Deserialize)]
@@ -38,14 +68,20 @@ struct StreamResponse {
 }
 
 impl OllamaClient {
-    pub fn new(base_url: Option<String>, completion_model: Option<String>) -> Self {
+    pub fn new(base_url: Option<String>, completion_model: Option<String>, keep_alive: Option<&str>) -> Self {
         let model = completion_model.unwrap_or_else(|| "cogito:3b".to_string());
-        let url = base_url.unwrap_or_else(|| "http://localhost:11434".into());
+        let url = base_url.unwrap_or_else(|| OLLAMA_DEFAULT_BASE_URL.into());
 
         Self {
-            http: HttpClient::new(),
+            http: shared_client(),
             base_url: url,
             completion_model: model,
+            keep_alive: keep_alive_value(keep_alive),
+            seed: None,
+            generation: GenerationParams::default(),
+            system_prompt: None,
+            request_timeout: None,
+            retry: RetryPolicy::default(),
         }
     }
 
@@ -54,20 +90,51 @@ impl OllamaClient {
             return Err("Invalid config type for OllamaClient".into());
         }
 
-        Ok(Self::new(config.base_url.clone(), config.completion_model.clone()))
+        Ok(Self {
+            seed: config.seed,
+            generation: config.generation,
+            system_prompt: config.system_prompt.clone(),
+            request_timeout: config.request_timeout(),
+            retry: config.retry_policy(),
+            ..Self::new(config.base_url.clone(), config.completion_model.clone(), config.keep_alive.as_deref())
+        })
+    }
+
+    /// Model options for the request; `None` when every setting is left to the model file.
+    fn options(&self) -> Option<GenerateOptions> {
+        let params = self.generation;
+        (self.seed.is_some() || params != GenerationParams::default()).then_some(GenerateOptions {
+            seed: self.seed,
+            temperature: params.temperature,
+            num_predict: params.max_tokens,
+            top_p: params.top_p,
+            frequency_penalty: params.frequency_penalty,
+            presence_penalty: params.presence_penalty,
+        })
+    }
+
+    fn prompt_with_system(&self, prompt: &str) -> String {
+        match &self.system_prompt {
+            Some(system) => format!("{}\n\n{}", system, prompt),
+            None => prompt.to_string(),
+        }
     }
 
     pub async fn generate(
         &self,
         prompt: &str
     ) -> Result<GenerateResponse, Box<dyn Error + Send + Sync>> {
-        let url = format!("{}/api/generate", self.base_url);
+        let url = join_endpoint(&self.base_url, "/api/generate");
         let req = GenerateRequest {
             model: self.completion_model.clone(),
-            prompt: prompt.to_string(),
+            prompt: self.prompt_with_system(prompt),
             stream: false,
+            keep_alive: self.keep_alive.clone(),
+            options: self.options(),
         };
-        let resp = self.http.post(&url).json(&req).send().await?.error_for_status()?;
+        let resp = with_timeout(self.http.post(&url).json(&req), self.request_timeout)
+            .send().await?
+            .error_for_status()?;
         let data = resp.json::<GenerateResponse>().await?;
         Ok(data)
     }
@@ -76,22 +143,25 @@ impl OllamaClient {
         &self,
         prompt: &str
     ) -> Result<Pin<Box<dyn Stream<Item = Result<String, Box<dyn StdError + Send + Sync>>> + Send>>, Box<dyn StdError + Send + Sync>> {
-        let url = format!("{}/api/generate", self.base_url);
+        let url = join_endpoint(&self.base_url, "/api/generate");
         let req = GenerateRequest {
             model: self.completion_model.clone(),
-            prompt: prompt.to_string(),
-            stream: true, 
+            prompt: self.prompt_with_system(prompt),
+            stream: true,
+            keep_alive: self.keep_alive.clone(),
+            options: self.options(),
         };
         
         let (tx, rx) = mpsc::channel(32);
         let client = self.http.clone();
+        let request_timeout = self.request_timeout;
 
-        tokio::spawn(async move {
-            match client.post(&url).json(&req).send().await {
+        let task = tokio::spawn(async move {
+            match send_streaming(client.post(&url).json(&req), request_timeout).await {
                 Ok(response) => {
                     if !response.status().is_success() {
                         let err_msg = format!("HTTP error: {}", response.status());
-                        let _ = tx.send(Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, err_msg)) as _)).await;
+                        let _ = tx.send(Err(Box::new(std::io::Error::other(err_msg)) as _)).await;
                         return;
                     }
                     let mut stream = response.bytes_stream();
@@ -108,11 +178,10 @@ impl OllamaClient {
                                         
                                         match serde_json::from_str::<StreamResponse>(line) {
                                             Ok(stream_resp) => {
-                                                if !stream_resp.response.is_empty() {
-                                                    if tx.send(Ok(stream_resp.response)).await.is_err() {
+                                                if !stream_resp.response.is_empty()
+                                                    && tx.send(Ok(stream_resp.response)).await.is_err() {
                                                         break;
                                                     }
-                                                }
                                                 
                                                 if stream_resp.done {
                                                     break;
@@ -134,13 +203,12 @@ impl OllamaClient {
                     }
                 },
                 Err(e) => {
-                    let _ = tx.send(Err(Box::new(e) as Box<dyn StdError + Send + Sync>)).await;
+                    let _ = tx.send(Err(e)).await;
                 }
             }
         });
         
-        let stream = ReceiverStream::new(rx);
-        Ok(Box::pin(stream))
+        Ok(abort_on_drop(rx, task))
     }
 }
 
@@ -150,8 +218,8 @@ impl ChatClient for OllamaClient {
         &self,
         prompt: &str
     ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
-        let gen_resp = self.generate(prompt).await?;
-        Ok(CompletionResponse { response: gen_resp.response })
+        let gen_resp = with_retries(self.retry, || self.generate(prompt)).await?;
+        Ok(CompletionResponse { response: gen_resp.response, ..Default::default() })
     }
     
     async fn stream_completion(
@@ -5,19 +5,33 @@ use reqwest::{Client as HttpClient, header::{HeaderMap, HeaderValue, CONTENT_TYP
 use serde::{Deserialize, Serialize};
 use std::error::Error as StdError;
 use std::pin::Pin;
+use std::time::Duration;
 use tokio::sync::mpsc;
-use tokio_stream::wrappers::ReceiverStream;
 
-use super::{ChatClient, CompletionResponse};
-use crate::llm::LlmConfig;
+use super::{abort_on_drop, with_retries, ChatClient, CompletionResponse, RetryPolicy};
+use crate::models::chat::ChatMessage;
+use super::image::ImageInput;
+use crate::llm::{ GenerationParams, LlmConfig };
+use crate::llm::endpoint::join_endpoint;
+use crate::llm::http::{ send_streaming, with_timeout };
 use rllm::builder::LLMBackend;
 
+const OPENAI_DEFAULT_BASE_URL: &str = "https://api.openai.com";
+
 pub struct OpenAIChatClient {
     http: HttpClient,
     api_key: String,
     model: String,
     base_url: String,
     use_responses_endpoint: bool,
+    /// Not sent to the /responses endpoint, which has no seed parameter.
+    seed: Option<u64>,
+    /// The /responses endpoint takes no frequency or presence penalty.
+    generation: GenerationParams,
+    /// Sent as a leading `system` message, or as `instructions` on the /responses endpoint.
+    system_prompt: Option<String>,
+    request_timeout: Option<Duration>,
+    retry: RetryPolicy,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -47,6 +61,8 @@ struct OpenAIChatRequest {
     stream: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     store: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
 }
 
 #[derive(Serialize)]
@@ -68,6 +84,8 @@ struct OpenAIResponsesRequest {
     store: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instructions: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -108,11 +126,6 @@ struct OpenAIDelta {
     content: Option<String>,
 }
 
-#[derive(Deserialize)]
-struct OpenAIResponsesResponse {
-    _result: String,
-}
-
 #[derive(Deserialize)]
 struct OpenAIResponsesStreamResponse {
     delta: Option<String>,
@@ -127,7 +140,7 @@ impl OpenAIChatClient {
         use_responses_endpoint: bool,
     ) -> Result<Self, Box<dyn StdError + Send + Sync>> {
         let chat_model = model.unwrap_or_else(|| "gpt-4o".to_string());
-        let api_url = base_url.unwrap_or_else(|| "https://api.openai.com/v1/chat/completions".to_string());
+        let api_url = base_url.unwrap_or_else(|| OPENAI_DEFAULT_BASE_URL.to_string());
         let mut headers = HeaderMap::new();
         headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
         headers.insert(
@@ -136,7 +149,7 @@ impl OpenAIChatClient {
                 .map_err(|e| format!("Invalid API key format: {}", e))?
         );
         
-        let http = HttpClient::builder()
+        let http = crate::llm::http::client_builder()
             .default_headers(headers)
             .build()
             .map_err(|e| Box::new(e) as Box<dyn StdError + Send + Sync>)?;
@@ -147,6 +160,11 @@ impl OpenAIChatClient {
             model: chat_model,
             base_url: api_url,
             use_responses_endpoint,
+            seed: None,
+            generation: GenerationParams::default(),
+            system_prompt: None,
+            request_timeout: None,
+            retry: RetryPolicy::default(),
         })
     }
 
@@ -160,14 +178,83 @@ impl OpenAIChatClient {
             .map(|url| url.contains("/responses"))
             .unwrap_or(false);
         
-        Self::new(
-            api_key,
-            config.completion_model.clone(),
-            config.base_url.clone(),
-            use_responses_endpoint,
-        )
+        Ok(Self {
+            seed: config.seed,
+            generation: config.generation,
+            system_prompt: config.system_prompt.clone(),
+            request_timeout: config.request_timeout(),
+            retry: config.retry_policy(),
+            ..Self::new(
+                api_key,
+                config.completion_model.clone(),
+                config.base_url.clone(),
+                use_responses_endpoint,
+            )?
+        })
     }
     
+    fn chat_completions_url(&self) -> String {
+        join_endpoint(&self.base_url, "/v1/chat/completions")
+    }
+
+    fn responses_url(&self) -> String {
+        join_endpoint(&self.base_url, "/v1/responses")
+    }
+
+    fn with_system_prompt(&self, mut messages: Vec<OpenAIMessage>) -> Vec<OpenAIMessage> {
+        if let Some(system) = &self.system_prompt {
+            messages.insert(0, OpenAIMessage { role: "system".to_string(), content: system.clone() });
+        }
+        messages
+    }
+
+    /// Non-streaming completion for a list of chat messages.
+    async fn complete_chat(
+        &self,
+        messages: Vec<OpenAIMessage>
+    ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
+        let req = OpenAIChatRequest {
+            model: self.model.clone(),
+            messages: self.with_system_prompt(messages),
+            temperature: self.generation.temperature.unwrap_or(1.0),
+            response_format: Some(ResponseFormat { format_type: "text".to_string() }),
+            max_completion_tokens: Some(self.generation.max_tokens.unwrap_or(2048)),
+            max_tokens: None,
+            top_p: Some(self.generation.top_p.unwrap_or(1.0)),
+            frequency_penalty: Some(self.generation.frequency_penalty.unwrap_or(0.0)),
+            presence_penalty: Some(self.generation.presence_penalty.unwrap_or(0.0)),
+            stream: None,
+            store: Some(false),
+            seed: self.seed,
+        };
+        
+        let resp = self.send_chat(&req).await?;
+        
+        let content = resp.choices.first()
+            .ok_or_else(|| "No response from OpenAI API".to_string())?
+            .message.content.clone();
+        
+        Ok(CompletionResponse { response: content, ..Default::default() })
+    }
+
+    /// Non-streaming chat completion call, retried on transient failures.
+    async fn send_chat<T: Serialize + Sync>(&self, req: &T) -> Result<OpenAIResponse, Box<dyn StdError + Send + Sync>> {
+        let url = &self.chat_completions_url();
+        with_retries(self.retry, || async move {
+            let request = self.http.post(url)
+                .header(AUTHORIZATION, format!("Bearer {}", self.api_key))
+                .json(req);
+            Ok(
+                with_timeout(request, self.request_timeout)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<OpenAIResponse>()
+                    .await?
+            )
+        }).await
+    }
+
     async fn generate_stream(
         &self,
         prompt: &str
@@ -183,7 +270,7 @@ impl OpenAIChatClient {
         &self,
         prompt: &str
     ) -> Result<Pin<Box<dyn Stream<Item = Result<String, Box<dyn StdError + Send + Sync>>> + Send>>, Box<dyn StdError + Send + Sync>> {
-        let url = self.base_url.trim_end_matches('/').to_string();
+        let url = self.chat_completions_url();
         
         let messages = vec![OpenAIMessage {
             role: "user".to_string(),
@@ -192,34 +279,35 @@ impl OpenAIChatClient {
         
         let req = OpenAIChatRequest {
             model: self.model.clone(),
-            messages,
-            temperature: 0.7,
-            max_tokens: Some(2048),
+            messages: self.with_system_prompt(messages),
+            temperature: self.generation.temperature.unwrap_or(0.7),
+            max_tokens: Some(self.generation.max_tokens.unwrap_or(2048)),
             response_format: None,
             max_completion_tokens: None,
-            top_p: None,
-            frequency_penalty: None,
-            presence_penalty: None,
+            top_p: self.generation.top_p,
+            frequency_penalty: self.generation.frequency_penalty,
+            presence_penalty: self.generation.presence_penalty,
             stream: Some(true),
             store: None,
+            seed: self.seed,
         };
         
         let (tx, rx) = mpsc::channel(32);
         let client = self.http.clone();
         let auth_header = format!("Bearer {}", self.api_key);
+        let request_timeout = self.request_timeout;
         
-        tokio::spawn(async move {
-            let resp = match client.post(&url)
+        let task = tokio::spawn(async move {
+            let request = client.post(&url)
                 .header(AUTHORIZATION, auth_header)
-                .json(&req)
-                .send()
-                .await {
-                    Ok(r) => r,
-                    Err(e) => {
-                        let _ = tx.send(Err(Box::new(e) as _)).await;
-                        return;
-                    }
-                };
+                .json(&req);
+            let resp = match send_streaming(request, request_timeout).await {
+                Ok(r) => r,
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
+                    return;
+                }
+            };
                 
             if let Err(e) = resp.error_for_status_ref() {
                 let _ = tx.send(Err(Box::new(e) as _)).await;
@@ -244,11 +332,10 @@ impl OpenAIChatClient {
                                         Ok(stream_resp) => {
                                             for choice in stream_resp.choices {
                                                 if let Some(content) = choice.delta.content {
-                                                    if !content.is_empty() {
-                                                        if tx.send(Ok(content)).await.is_err() {
+                                                    if !content.is_empty()
+                                                        && tx.send(Ok(content)).await.is_err() {
                                                             return;
                                                         }
-                                                    }
                                                 }
                                                 
                                                 if let Some(reason) = &choice.finish_reason {
@@ -274,22 +361,19 @@ impl OpenAIChatClient {
             }
         });
         
-        Ok(Box::pin(ReceiverStream::new(rx)))
+        Ok(abort_on_drop(rx, task))
     }
     
     async fn generate_stream_responses(
         &self,
         prompt: &str
     ) -> Result<Pin<Box<dyn Stream<Item = Result<String, Box<dyn StdError + Send + Sync>>> + Send>>, Box<dyn StdError + Send + Sync>> {
-        let url = if self.base_url.ends_with("/v1/responses") {
-            self.base_url.clone()
-        } else {
-            format!("{}/v1/responses", self.base_url.trim_end_matches('/'))
-        };
+        let url = self.responses_url();
         
         let req = OpenAIResponsesRequest {
             model: self.model.clone(),
             input: vec![prompt.to_string()],
+            instructions: self.system_prompt.clone(),
             text: OpenAITextFormat {
                 format: OpenAIFormat {
                     format_type: "text".to_string(),
@@ -297,9 +381,9 @@ impl OpenAIChatClient {
             },
             reasoning: serde_json::json!({}),
             tools: Vec::new(),
-            temperature: 1.0,
-            max_output_tokens: 2048,
-            top_p: 1.0,
+            temperature: self.generation.temperature.unwrap_or(1.0),
+            max_output_tokens: self.generation.max_tokens.unwrap_or(2048),
+            top_p: self.generation.top_p.unwrap_or(1.0),
             store: true,
             stream: Some(true),
         };
@@ -307,19 +391,19 @@ impl OpenAIChatClient {
         let (tx, rx) = mpsc::channel(32);
         let client = self.http.clone();
         let auth_header = format!("Bearer {}", self.api_key);
+        let request_timeout = self.request_timeout;
         
-        tokio::spawn(async move {
-            let resp = match client.post(&url)
+        let task = tokio::spawn(async move {
+            let request = client.post(&url)
                 .header(AUTHORIZATION, auth_header)
-                .json(&req)
-                .send()
-                .await {
-                    Ok(r) => r,
-                    Err(e) => {
-                        let _ = tx.send(Err(Box::new(e) as _)).await;
-                        return;
-                    }
-                };
+                .json(&req);
+            let resp = match send_streaming(request, request_timeout).await {
+                Ok(r) => r,
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
+                    return;
+                }
+            };
                 
             if let Err(e) = resp.error_for_status_ref() {
                 let _ = tx.send(Err(Box::new(e) as _)).await;
@@ -343,11 +427,10 @@ impl OpenAIChatClient {
                                     match serde_json::from_str::<OpenAIResponsesStreamResponse>(data) {
                                         Ok(stream_resp) => {
                                             if let Some(delta) = stream_resp.delta {
-                                                if !delta.is_empty() {
-                                                    if tx.send(Ok(delta)).await.is_err() {
+                                                if !delta.is_empty()
+                                                    && tx.send(Ok(delta)).await.is_err() {
                                                         return;
                                                     }
-                                                }
                                             }
                                             
                                             if let Some(done) = stream_resp.done {
@@ -372,7 +455,7 @@ impl OpenAIChatClient {
             }
         });
         
-        Ok(Box::pin(ReceiverStream::new(rx)))
+        Ok(abort_on_drop(rx, task))
     }
 }
 
@@ -382,43 +465,79 @@ impl ChatClient for OpenAIChatClient {
         &self,
         prompt: &str
     ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
-        let url = format!("{}/v1/chat/completions", self.base_url.trim_end_matches('/'));
-        
         let messages = vec![OpenAIMessage {
             role: "user".to_string(),
             content: prompt.to_string(),
         }];
-        
-        let req = OpenAIChatRequest {
-            model: self.model.clone(),
-            messages,
-            temperature: 1.0,
-            response_format: Some(ResponseFormat { format_type: "text".to_string() }),
-            max_completion_tokens: Some(2048),
-            max_tokens: None,
-            top_p: Some(1.0),
-            frequency_penalty: Some(0.0),
-            presence_penalty: Some(0.0),
-            stream: None,
-            store: Some(false),
-        };
-        
-        let resp = self.http.post(&url)
-            .header(AUTHORIZATION, format!("Bearer {}", self.api_key))
-            .json(&req)
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<OpenAIResponse>()
-            .await?;
-        
+        self.complete_chat(messages).await
+    }
+
+    async fn complete_messages(
+        &self,
+        messages: &[ChatMessage]
+    ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
+        let messages = messages
+            .iter()
+            .map(|m| OpenAIMessage { role: m.role.clone(), content: m.content.clone() })
+            .collect();
+        self.complete_chat(messages).await
+    }
+    
+    async fn complete_with_images(
+        &self,
+        prompt: &str,
+        images: &[ImageInput]
+    ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
+        if images.is_empty() {
+            return self.complete(prompt).await;
+        }
+
+        let mut content = vec![serde_json::json!({ "type": "text", "text": prompt })];
+        content.extend(images.iter().map(|image| serde_json::json!({
+            "type": "image_url",
+            "image_url": { "url": image.to_url() }
+        })));
+
+        let mut messages = Vec::new();
+        if let Some(system) = &self.system_prompt {
+            messages.push(serde_json::json!({ "role": "system", "content": system }));
+        }
+        messages.push(serde_json::json!({ "role": "user", "content": content }));
+
+        let mut req = serde_json::json!({
+            "model": self.model,
+            "messages": messages,
+            "max_completion_tokens": self.generation.max_tokens.unwrap_or(2048),
+            "store": false,
+        });
+        let params = &self.generation;
+        for (key, value) in [
+            ("temperature", params.temperature),
+            ("top_p", params.top_p),
+            ("frequency_penalty", params.frequency_penalty),
+            ("presence_penalty", params.presence_penalty),
+        ] {
+            if let Some(value) = value {
+                req[key] = value.into();
+            }
+        }
+        if let Some(seed) = self.seed {
+            req["seed"] = seed.into();
+        }
+
+        let resp = self.send_chat(&req).await?;
+
         let content = resp.choices.first()
             .ok_or_else(|| "No response from OpenAI API".to_string())?
             .message.content.clone();
-        
-        Ok(CompletionResponse { response: content })
+
+        Ok(CompletionResponse { response: content, ..Default::default() })
     }
-    
+
+    fn supports_vision(&self) -> bool {
+        true
+    }
+
     async fn stream_completion(
         &self,
         prompt: &str
@@ -0,0 +1,97 @@
+use async_trait::async_trait;
+use futures::StreamExt;
+use rllm::builder::LLMBackend;
+use std::error::Error as StdError;
+use std::sync::Arc;
+use std::time::{ Duration, Instant };
+
+use super::{ image::ImageInput, ChatClient, ChatStream, CompletionResponse };
+use crate::metrics;
+use crate::models::chat::ChatMessage;
+
+/// Records every call's duration in `llm_request_duration_seconds`. A stream is
+/// timed until it ends or is dropped.
+pub struct TimedChatClient {
+    inner: Arc<dyn ChatClient>,
+}
+
+impl TimedChatClient {
+    pub fn wrap(inner: Arc<dyn ChatClient>) -> Arc<dyn ChatClient> {
+        Arc::new(Self { inner })
+    }
+}
+
+/// Observes the elapsed time when dropped.
+struct CallTimer(Instant);
+
+impl Drop for CallTimer {
+    fn drop(&mut self) {
+        metrics::observe_llm_request(self.0.elapsed());
+    }
+}
+
+#[async_trait]
+impl ChatClient for TimedChatClient {
+    async fn complete(&self, prompt: &str) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
+        let _timer = CallTimer(Instant::now());
+        self.inner.complete(prompt).await
+    }
+
+    async fn stream_completion(&self, prompt: &str) -> Result<ChatStream, Box<dyn StdError + Send + Sync>> {
+        let timer = CallTimer(Instant::now());
+        let stream = self.inner.stream_completion(prompt).await?;
+        Ok(Box::pin(stream.map(move |item| {
+            let _ = &timer;
+            item
+        })))
+    }
+
+    async fn complete_messages(
+        &self,
+        messages: &[ChatMessage]
+    ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
+        let _timer = CallTimer(Instant::now());
+        self.inner.complete_messages(messages).await
+    }
+
+    async fn complete_with_images(
+        &self,
+        prompt: &str,
+        images: &[ImageInput]
+    ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
+        let _timer = CallTimer(Instant::now());
+        self.inner.complete_with_images(prompt, images).await
+    }
+
+    fn supports_vision(&self) -> bool {
+        self.inner.supports_vision()
+    }
+
+    fn get_api_key(&self) -> String {
+        self.inner.get_api_key()
+    }
+
+    fn get_model(&self) -> String {
+        self.inner.get_model()
+    }
+
+    fn get_base_url(&self) -> Option<String> {
+        self.inner.get_base_url()
+    }
+
+    fn get_llm_backend(&self) -> LLMBackend {
+        self.inner.get_llm_backend()
+    }
+
+    fn get_request_timeout(&self) -> Option<Duration> {
+        self.inner.get_request_timeout()
+    }
+
+    fn get_system_prompt(&self) -> Option<String> {
+        self.inner.get_system_prompt()
+    }
+
+    fn supports_native_streaming(&self) -> bool {
+        self.inner.supports_native_streaming()
+    }
+}
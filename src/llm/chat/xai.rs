@@ -1,24 +1,33 @@
 use async_trait::async_trait;
 use std::error::Error as StdError;
 use std::pin::Pin;
+use std::time::Duration;
 use futures::{Stream, StreamExt};
 use tokio::sync::mpsc;
-use tokio_stream::wrappers::ReceiverStream;
 use serde::{Deserialize, Serialize};
 use log::info;
 use reqwest::Client as HttpClient;
 use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE, AUTHORIZATION};
 
-use super::{ChatClient, CompletionResponse };
-use crate::llm::LlmConfig;
+use super::{abort_on_drop, with_retries, ChatClient, CompletionResponse, RetryPolicy };
+use crate::llm::{ GenerationParams, LlmConfig };
+use crate::models::chat::ChatMessage;
+use crate::llm::endpoint::endpoint_url;
+use crate::llm::http::{ send_streaming, with_timeout };
 use rllm::builder::LLMBackend;
 
+const XAI_DEFAULT_BASE_URL: &str = "https://api.x.ai";
+
 #[derive(Debug)]
 pub struct XAIChatClient {
     http: HttpClient,
     api_key: String,
     model: String,
     base_url: Option<String>,
+    system_prompt: Option<String>,
+    generation: GenerationParams,
+    request_timeout: Option<Duration>,
+    retry: RetryPolicy,
 }
 
 #[derive(Serialize)]
@@ -28,6 +37,14 @@ struct XAIRequest {
     stream: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -62,7 +79,7 @@ impl XAIChatClient {
         let mut headers = HeaderMap::new();
         headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
         
-        let http_client = HttpClient::builder()
+        let http_client = crate::llm::http::client_builder()
             .default_headers(headers)
             .build()
             .map_err(|e| Box::new(e) as Box<dyn StdError + Send + Sync>)?;
@@ -71,7 +88,11 @@ impl XAIChatClient {
             http: http_client,
             api_key,
             model: chat_model,
-            base_url
+            base_url,
+            system_prompt: None,
+            generation: GenerationParams::default(),
+            request_timeout: None,
+            retry: RetryPolicy::default(),
         })
     }
 
@@ -83,14 +104,20 @@ impl XAIChatClient {
         let model = config.completion_model.clone();
         let base_url = config.base_url.clone();
 
-        Self::new(api_key, model, base_url)
+        Ok(Self {
+            system_prompt: config.system_prompt.clone(),
+            generation: config.generation,
+            request_timeout: config.request_timeout(),
+            retry: config.retry_policy(),
+            ..Self::new(api_key, model, base_url)?
+        })
     }
     
     async fn generate_stream(
         &self,
         prompt: &str
     ) -> Result<Pin<Box<dyn Stream<Item = Result<String, Box<dyn StdError + Send + Sync>>> + Send>>, Box<dyn StdError + Send + Sync>> {
-        let url = self.base_url.clone().unwrap_or_else(|| "https://api.x.ai/v1/chat/completions".to_string());
+        let url = endpoint_url(self.base_url.as_deref(), XAI_DEFAULT_BASE_URL, "/v1/chat/completions");
         
         let messages = vec![XAIMessage {
             role: "user".to_string(),
@@ -99,21 +126,26 @@ impl XAIChatClient {
         
         let req = XAIRequest {
             model: self.model.clone(),
-            messages,
+            messages: self.with_system_prompt(messages),
             stream: true,
-            temperature: Some(0.7), 
+            temperature: Some(self.generation.temperature.unwrap_or(0.7)),
+            max_tokens: self.generation.max_tokens,
+            top_p: self.generation.top_p,
+            frequency_penalty: self.generation.frequency_penalty,
+            presence_penalty: self.generation.presence_penalty,
         };
         
         let (tx, rx) = mpsc::channel(32);
         
         let client = self.http.clone();
         let auth_header = format!("Bearer {}", self.api_key);
+        let request_timeout = self.request_timeout;
         
-        tokio::spawn(async move {
+        let task = tokio::spawn(async move {
             let mut builder = client.post(&url).json(&req);
             builder = builder.header(AUTHORIZATION, auth_header);
             
-            match builder.send().await {
+            match send_streaming(builder, request_timeout).await {
                 Ok(resp) => {
                     if let Err(e) = resp.error_for_status_ref() {
                         info!("XAI API error: {}", e);
@@ -139,11 +171,10 @@ impl XAIChatClient {
                                                 Ok(stream_resp) => {
                                                     for choice in stream_resp.choices {
                                                         if let Some(content) = choice.delta.content {
-                                                            if !content.is_empty() {
-                                                                if tx.send(Ok(content)).await.is_err() {
+                                                            if !content.is_empty()
+                                                                && tx.send(Ok(content)).await.is_err() {
                                                                     return;
                                                                 }
-                                                            }
                                                         }
                                                     }
                                                 },
@@ -163,44 +194,48 @@ impl XAIChatClient {
                     }
                 },
                 Err(e) => {
-                    let _ = tx.send(Err(Box::new(e) as _)).await;
+                    let _ = tx.send(Err(e)).await;
                 }
             }
         });
         
-        Ok(Box::pin(ReceiverStream::new(rx)))
+        Ok(abort_on_drop(rx, task))
     }
-}
 
-#[async_trait]
-impl ChatClient for XAIChatClient {
-    async fn complete(
+    fn with_system_prompt(&self, mut messages: Vec<XAIMessage>) -> Vec<XAIMessage> {
+        if let Some(system) = &self.system_prompt {
+            messages.insert(0, XAIMessage { role: "system".to_string(), content: system.clone() });
+        }
+        messages
+    }
+
+    /// Non-streaming completion for a list of chat messages.
+    async fn complete_chat(
         &self,
-        prompt: &str
+        messages: Vec<XAIMessage>
     ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
-        let url = self.base_url.clone().unwrap_or_else(|| "https://api.x.ai/v1/chat/completions".to_string());
-        
-        let messages = vec![XAIMessage {
-            role: "user".to_string(),
-            content: prompt.to_string(),
-        }];
+        let url = endpoint_url(self.base_url.as_deref(), XAI_DEFAULT_BASE_URL, "/v1/chat/completions");
         
         let req = XAIRequest {
             model: self.model.clone(),
-            messages,
+            messages: self.with_system_prompt(messages),
             stream: false,
-            temperature: Some(0.7),
+            temperature: Some(self.generation.temperature.unwrap_or(0.7)),
+            max_tokens: self.generation.max_tokens,
+            top_p: self.generation.top_p,
+            frequency_penalty: self.generation.frequency_penalty,
+            presence_penalty: self.generation.presence_penalty,
         };
         
-        let client = self.http.clone();
         let auth_header = format!("Bearer {}", self.api_key);
+        let (url, req, auth_header) = (&url, &req, &auth_header);
         
-        let resp = client.post(&url)
-            .header(AUTHORIZATION, auth_header)
-            .json(&req)
-            .send()
-            .await?
-            .error_for_status()?;
+        let resp = with_retries(self.retry, || async move {
+            let request = self.http.post(url)
+                .header(AUTHORIZATION, auth_header)
+                .json(req);
+            Ok(with_timeout(request, self.request_timeout).send().await?.error_for_status()?)
+        }).await?;
         
         #[derive(Deserialize)]
         struct XAIResponse {
@@ -217,7 +252,32 @@ impl ChatClient for XAIChatClient {
             .ok_or_else(|| "No response from XAI API".to_string())?
             .message.content.clone();
         
-        Ok(CompletionResponse { response: content })
+        Ok(CompletionResponse { response: content, ..Default::default() })
+    }
+}
+
+#[async_trait]
+impl ChatClient for XAIChatClient {
+    async fn complete(
+        &self,
+        prompt: &str
+    ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
+        let messages = vec![XAIMessage {
+            role: "user".to_string(),
+            content: prompt.to_string(),
+        }];
+        self.complete_chat(messages).await
+    }
+
+    async fn complete_messages(
+        &self,
+        messages: &[ChatMessage]
+    ) -> Result<CompletionResponse, Box<dyn StdError + Send + Sync>> {
+        let messages = messages
+            .iter()
+            .map(|m| XAIMessage { role: m.role.clone(), content: m.content.clone() })
+            .collect();
+        self.complete_chat(messages).await
     }
     
     async fn stream_completion(
@@ -0,0 +1,228 @@
+use lazy_static::lazy_static;
+use log::{ info, warn };
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
+
+lazy_static! {
+    static ref BREAKERS: Mutex<Vec<Arc<CircuitBreaker>>> = Mutex::new(Vec::new());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl BreakerState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BreakerState::Closed => "closed",
+            BreakerState::Open => "open",
+            BreakerState::HalfOpen => "half_open",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BreakerSnapshot {
+    pub name: String,
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+}
+
+#[derive(Default)]
+struct BreakerInner {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    probe_in_flight: bool,
+}
+
+/// Per-provider circuit breaker. After `threshold` consecutive failures the
+/// provider is skipped for `cooldown`, then a single probe request is let through.
+pub struct CircuitBreaker {
+    name: String,
+    threshold: u32,
+    cooldown: Duration,
+    inner: Mutex<BreakerInner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: impl Into<String>, threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            name: name.into(),
+            threshold,
+            cooldown,
+            inner: Mutex::new(BreakerInner::default()),
+        }
+    }
+
+    /// Creates a breaker and registers it so its state shows up in `snapshot()`.
+    pub fn register(name: impl Into<String>, threshold: u32, cooldown: Duration) -> Arc<Self> {
+        let breaker = Arc::new(Self::new(name, threshold, cooldown));
+        BREAKERS.lock().unwrap().push(Arc::clone(&breaker));
+        breaker
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn state_of(&self, inner: &BreakerInner) -> BreakerState {
+        match inner.opened_at {
+            None => BreakerState::Closed,
+            Some(at) if at.elapsed() < self.cooldown => BreakerState::Open,
+            Some(_) => BreakerState::HalfOpen,
+        }
+    }
+
+    pub fn state(&self) -> BreakerState {
+        let inner = self.inner.lock().unwrap();
+        self.state_of(&inner)
+    }
+
+    /// Admits a request unless the circuit is open; in half-open state only one probe
+    /// is admitted at a time. Report the outcome through the returned permit.
+    pub fn try_acquire(&self) -> Option<BreakerPermit<'_>> {
+        if self.threshold == 0 {
+            return Some(BreakerPermit { breaker: self, probe: false });
+        }
+        let mut inner = self.inner.lock().unwrap();
+        match self.state_of(&inner) {
+            BreakerState::Closed => Some(BreakerPermit { breaker: self, probe: false }),
+            BreakerState::Open => None,
+            BreakerState::HalfOpen => {
+                if inner.probe_in_flight {
+                    None
+                } else {
+                    inner.probe_in_flight = true;
+                    Some(BreakerPermit { breaker: self, probe: true })
+                }
+            }
+        }
+    }
+
+    fn record_success(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.opened_at.is_some() {
+            info!("Circuit for provider '{}' closed after successful probe", self.name);
+        }
+        *inner = BreakerInner::default();
+    }
+
+    fn record_failure(&self) {
+        if self.threshold == 0 {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.consecutive_failures += 1;
+        inner.probe_in_flight = false;
+        if inner.consecutive_failures >= self.threshold {
+            if inner.opened_at.is_none() {
+                warn!(
+                    "Circuit for provider '{}' opened after {} consecutive failures; skipping for {:?}",
+                    self.name,
+                    inner.consecutive_failures,
+                    self.cooldown
+                );
+            }
+            inner.opened_at = Some(Instant::now());
+        }
+    }
+
+    pub fn snapshot(&self) -> BreakerSnapshot {
+        let inner = self.inner.lock().unwrap();
+        BreakerSnapshot {
+            name: self.name.clone(),
+            state: self.state_of(&inner),
+            consecutive_failures: inner.consecutive_failures,
+        }
+    }
+}
+
+/// One admitted request. Dropping it without an outcome, e.g. when the request
+/// future is cancelled, frees the half-open probe slot so the next request can probe.
+pub struct BreakerPermit<'a> {
+    breaker: &'a CircuitBreaker,
+    probe: bool,
+}
+
+impl BreakerPermit<'_> {
+    pub fn record_success(mut self) {
+        self.probe = false;
+        self.breaker.record_success();
+    }
+
+    pub fn record_failure(mut self) {
+        self.probe = false;
+        self.breaker.record_failure();
+    }
+}
+
+impl Drop for BreakerPermit<'_> {
+    fn drop(&mut self) {
+        if self.probe {
+            self.breaker.inner.lock().unwrap().probe_in_flight = false;
+        }
+    }
+}
+
+/// State of every registered breaker, for metrics/diagnostics.
+pub fn snapshot() -> Vec<BreakerSnapshot> {
+    BREAKERS.lock()
+        .unwrap()
+        .iter()
+        .map(|b| b.snapshot())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COOLDOWN: Duration = Duration::from_millis(20);
+
+    fn open_breaker() -> CircuitBreaker {
+        let breaker = CircuitBreaker::new("test", 2, COOLDOWN);
+        breaker.try_acquire().unwrap().record_failure();
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        breaker.try_acquire().unwrap().record_failure();
+        breaker
+    }
+
+    #[test]
+    fn opens_then_half_opens_then_closes_after_a_successful_probe() {
+        let breaker = open_breaker();
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(breaker.try_acquire().is_none());
+
+        std::thread::sleep(COOLDOWN);
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        let probe = breaker.try_acquire().expect("probe admitted");
+        assert!(breaker.try_acquire().is_none(), "only one probe at a time");
+
+        probe.record_success();
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert_eq!(breaker.snapshot().consecutive_failures, 0);
+        assert!(breaker.try_acquire().is_some());
+    }
+
+    #[test]
+    fn failed_probe_reopens_the_circuit() {
+        let breaker = open_breaker();
+        std::thread::sleep(COOLDOWN);
+        breaker.try_acquire().expect("probe admitted").record_failure();
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(breaker.try_acquire().is_none());
+    }
+
+    #[test]
+    fn dropped_probe_frees_the_probe_slot() {
+        let breaker = open_breaker();
+        std::thread::sleep(COOLDOWN);
+        drop(breaker.try_acquire().expect("probe admitted"));
+
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        assert!(breaker.try_acquire().is_some(), "a new probe is admitted");
+    }
+}
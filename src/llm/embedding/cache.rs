@@ -0,0 +1,163 @@
+use async_trait::async_trait;
+use sha2::{ Digest, Sha256 };
+use std::error::Error as StdError;
+use std::sync::{ Arc, Mutex };
+
+use super::{ EmbeddingClient, EmbeddingInputType, EmbeddingResponse };
+use crate::cache::lru::LruCache;
+use crate::cli::Args;
+
+/// SHA-256 of the input type and text, so long texts are not kept as keys.
+type CacheKey = [u8; 32];
+
+/// Remembers recent embeddings so repeated text (a prompt asked again, a history
+/// message re-embedded) does not cost another provider call.
+pub struct CachingEmbeddingClient {
+    inner: Arc<dyn EmbeddingClient>,
+    cache: Mutex<LruCache<CacheKey, Vec<f32>>>,
+}
+
+impl CachingEmbeddingClient {
+    /// Wraps `client` per `--embedding-cache-size`; returns it unchanged when the size is 0.
+    pub fn wrap(args: &Args, client: Arc<dyn EmbeddingClient>) -> Arc<dyn EmbeddingClient> {
+        if args.embedding_cache_size == 0 {
+            return client;
+        }
+        Arc::new(Self { inner: client, cache: Mutex::new(LruCache::new(args.embedding_cache_size)) })
+    }
+
+    fn key(text: &str, input_type: Option<EmbeddingInputType>) -> CacheKey {
+        let tag: &[u8] = match input_type {
+            None => b"-",
+            Some(EmbeddingInputType::Query) => b"q",
+            Some(EmbeddingInputType::Document) => b"d",
+        };
+        Sha256::new().chain_update(tag).chain_update(text.as_bytes()).finalize().into()
+    }
+
+    async fn embed_cached(
+        &self,
+        text: &str,
+        input_type: Option<EmbeddingInputType>
+    ) -> Result<EmbeddingResponse, Box<dyn StdError + Send + Sync>> {
+        let key = Self::key(text, input_type);
+        if let Some(embedding) = self.cache.lock().unwrap().get(&key) {
+            return Ok(EmbeddingResponse { embedding });
+        }
+        let response = match input_type {
+            Some(input_type) => self.inner.embed_with_type(text, input_type).await?,
+            None => self.inner.embed(text).await?,
+        };
+        self.cache.lock().unwrap().insert(key, response.embedding.clone());
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl EmbeddingClient for CachingEmbeddingClient {
+    async fn embed(&self, text: &str) -> Result<EmbeddingResponse, Box<dyn StdError + Send + Sync>> {
+        self.embed_cached(text, None).await
+    }
+
+    async fn embed_with_type(
+        &self,
+        text: &str,
+        input_type: EmbeddingInputType
+    ) -> Result<EmbeddingResponse, Box<dyn StdError + Send + Sync>> {
+        self.embed_cached(text, Some(input_type)).await
+    }
+
+    /// Only the texts missing from the cache are sent, as one batch.
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn StdError + Send + Sync>> {
+        let keys: Vec<CacheKey> = texts.iter().map(|text| Self::key(text, None)).collect();
+        let mut embeddings: Vec<Option<Vec<f32>>> = {
+            let mut cache = self.cache.lock().unwrap();
+            keys.iter().map(|key| cache.get(key)).collect()
+        };
+        let missing: Vec<usize> = (0..texts.len()).filter(|&i| embeddings[i].is_none()).collect();
+        if !missing.is_empty() {
+            let batch: Vec<String> = missing.iter().map(|&i| texts[i].clone()).collect();
+            let fetched = super::check_batch_len("Embedding provider", self.inner.embed_batch(&batch).await?, batch.len())?;
+            let mut cache = self.cache.lock().unwrap();
+            for (i, embedding) in missing.into_iter().zip(fetched) {
+                cache.insert(keys[i], embedding.clone());
+                embeddings[i] = Some(embedding);
+            }
+        }
+        Ok(embeddings.into_iter().flatten().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockEmbeddingClient;
+    use clap::Parser;
+
+    /// Records every text that reaches the provider.
+    #[derive(Default)]
+    struct Recording {
+        calls: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl EmbeddingClient for Recording {
+        async fn embed(&self, text: &str) -> Result<EmbeddingResponse, Box<dyn StdError + Send + Sync>> {
+            self.calls.lock().unwrap().push(text.to_string());
+            MockEmbeddingClient::new(4).embed(text).await
+        }
+    }
+
+    fn cached(size: &str) -> (Arc<dyn EmbeddingClient>, Arc<Recording>) {
+        let args = Args::parse_from(["dynamic-agent", "--embedding-cache-size", size]);
+        let inner = Arc::new(Recording::default());
+        (CachingEmbeddingClient::wrap(&args, inner.clone()), inner)
+    }
+
+    fn calls(inner: &Recording) -> Vec<String> {
+        inner.calls.lock().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn repeated_text_is_served_from_memory() {
+        let (client, inner) = cached("8");
+        let first = client.embed("hello").await.unwrap().embedding;
+        assert_eq!(client.embed("hello").await.unwrap().embedding, first);
+        assert_eq!(calls(&inner), ["hello"]);
+
+        // The input type is part of the key.
+        client.embed_with_type("hello", EmbeddingInputType::Query).await.unwrap();
+        client.embed_with_type("hello", EmbeddingInputType::Query).await.unwrap();
+        assert_eq!(calls(&inner), ["hello", "hello"]);
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_entry_is_evicted() {
+        let (client, inner) = cached("2");
+        for text in ["a", "b", "a", "c", "a", "b"] {
+            client.embed(text).await.unwrap();
+        }
+        assert_eq!(calls(&inner), ["a", "b", "c", "b"]);
+    }
+
+    #[tokio::test]
+    async fn batches_send_only_the_misses() {
+        let (client, inner) = cached("8");
+        client.embed("b").await.unwrap();
+        let texts: Vec<String> = ["a", "b", "c"].iter().map(|s| s.to_string()).collect();
+        let embeddings = client.embed_batch(&texts).await.unwrap();
+        assert_eq!(calls(&inner), ["b", "a", "c"]);
+        assert_eq!(embeddings, MockEmbeddingClient::new(4).embed_batch(&texts).await.unwrap());
+
+        client.embed_batch(&texts).await.unwrap();
+        assert_eq!(calls(&inner).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn zero_size_disables_the_cache() {
+        let (client, inner) = cached("0");
+        client.embed("hello").await.unwrap();
+        client.embed("hello").await.unwrap();
+        assert_eq!(calls(&inner), ["hello", "hello"]);
+    }
+}
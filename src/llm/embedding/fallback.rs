@@ -0,0 +1,131 @@
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use log::warn;
+use std::error::Error as StdError;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{ probe_dimension, EmbeddingClient, EmbeddingInputType, EmbeddingResponse };
+use crate::llm::circuit_breaker::CircuitBreaker;
+use crate::llm::is_retryable_error;
+
+struct Provider {
+    name: String,
+    client: Arc<dyn EmbeddingClient>,
+    breaker: Arc<CircuitBreaker>,
+}
+
+pub struct FallbackEmbeddingClient {
+    providers: Vec<Provider>,
+}
+
+impl FallbackEmbeddingClient {
+    pub fn new(
+        providers: Vec<(String, Arc<dyn EmbeddingClient>)>,
+        breaker_threshold: u32,
+        breaker_cooldown: Duration
+    ) -> Self {
+        let providers = providers
+            .into_iter()
+            .map(|(name, client)| {
+                let breaker = CircuitBreaker::register(
+                    format!("embedding:{}", name),
+                    breaker_threshold,
+                    breaker_cooldown
+                );
+                Provider { name, client, breaker }
+            })
+            .collect();
+        Self { providers }
+    }
+
+    pub fn provider_names(&self) -> Vec<&str> {
+        self.providers.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    /// Probes every provider and fails unless all of them produce `expected`-sized vectors.
+    pub async fn validate_dimensions(
+        &self,
+        expected: usize
+    ) -> Result<(), Box<dyn StdError + Send + Sync>> {
+        for Provider { name, client, .. } in &self.providers {
+            let dimension = probe_dimension(client.as_ref()).await.map_err(|e|
+                format!("Failed to probe embedding provider '{}' for its dimension: {}", name, e)
+            )?;
+            if dimension != expected {
+                return Err(
+                    format!(
+                        "Embedding provider '{}' produces {}-dimensional vectors, but the vector dimension is {}. \
+                         All providers in EMBEDDING_FALLBACK must share the same dimension: mixing vector sizes \
+                         in one collection corrupts similarity search. Pick models with matching output sizes \
+                         or remove '{}' from the fallback list.",
+                        name,
+                        dimension,
+                        expected,
+                        name
+                    ).into()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EmbeddingClient for FallbackEmbeddingClient {
+    async fn embed(
+        &self,
+        text: &str
+    ) -> Result<EmbeddingResponse, Box<dyn StdError + Send + Sync>> {
+        self.with_failover(|client| client.embed(text)).await
+    }
+
+    async fn embed_with_type(
+        &self,
+        text: &str,
+        input_type: EmbeddingInputType
+    ) -> Result<EmbeddingResponse, Box<dyn StdError + Send + Sync>> {
+        self.with_failover(|client| client.embed_with_type(text, input_type)).await
+    }
+
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn StdError + Send + Sync>> {
+        self.with_failover(|client| client.embed_batch(texts)).await
+    }
+}
+
+impl FallbackEmbeddingClient {
+    /// Runs `call` against each provider in order until one succeeds or fails with a
+    /// non-retryable error.
+    async fn with_failover<'a, T>(
+        &'a self,
+        call: impl Fn(&'a dyn EmbeddingClient) -> BoxFuture<'a, Result<T, Box<dyn StdError + Send + Sync>>>
+    ) -> Result<T, Box<dyn StdError + Send + Sync>> {
+        let mut last_error: Option<Box<dyn StdError + Send + Sync>> = None;
+
+        for provider in &self.providers {
+            let Some(permit) = provider.breaker.try_acquire() else {
+                warn!("Skipping embedding provider '{}': circuit open", provider.name);
+                continue;
+            };
+
+            match call(provider.client.as_ref()).await {
+                Ok(response) => {
+                    permit.record_success();
+                    return Ok(response);
+                }
+                Err(e) if is_retryable_error(e.as_ref()) => {
+                    permit.record_failure();
+                    warn!("Embedding provider '{}' failed ({}), trying next provider", provider.name, e);
+                    last_error = Some(e);
+                }
+                Err(e) => {
+                    // The provider answered; the request itself was bad, so fail over is pointless.
+                    permit.record_success();
+                    return Err(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| "All embedding providers are unavailable (circuits open)".into()))
+    }
+}
@@ -5,6 +5,9 @@ pub mod anthropic;
 pub mod deepseek;
 pub mod xai;
 pub mod groq;
+pub mod fallback;
+pub mod truncate;
+pub mod cache;
 
 use async_trait::async_trait;
 use std::error::Error as StdError;
@@ -12,6 +15,7 @@ use std::sync::Arc;
 use log::warn;
 
 use super::{ LlmConfig, LlmType };
+use crate::cli::Args;
 use self::ollama::OllamaEmbeddingClient;
 use self::openai::OpenAIEmbeddingClient;
 use self::gemini::GoogleEmbeddingClient as GeminiEmbeddingClient;
@@ -25,9 +29,71 @@ pub struct EmbeddingResponse {
     pub embedding: Vec<f32>,
 }
 
+/// Whether text is a search query or content being stored, for models that embed them differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmbeddingInputType {
+    Query,
+    Document,
+}
+
 #[async_trait]
 pub trait EmbeddingClient: Send + Sync {
     async fn embed(&self, text: &str) -> Result<EmbeddingResponse, Box<dyn StdError + Send + Sync>>;
+
+    /// Embeds `text` with an input-type hint. Providers without hint support ignore it.
+    async fn embed_with_type(
+        &self,
+        text: &str,
+        _input_type: EmbeddingInputType
+    ) -> Result<EmbeddingResponse, Box<dyn StdError + Send + Sync>> {
+        self.embed(text).await
+    }
+
+    /// Embeds several texts, returning one vector per text in input order. Providers
+    /// with a batch API send them in one request; others embed them one at a time.
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn StdError + Send + Sync>> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for text in texts {
+            embeddings.push(self.embed(text).await?.embedding);
+        }
+        Ok(embeddings)
+    }
+}
+
+/// Checks that a batch response has one vector per input.
+fn check_batch_len(
+    provider: &str,
+    embeddings: Vec<Vec<f32>>,
+    expected: usize
+) -> Result<Vec<Vec<f32>>, Box<dyn StdError + Send + Sync>> {
+    if embeddings.len() != expected {
+        return Err(format!("{} returned {} embeddings for {} inputs", provider, embeddings.len(), expected).into());
+    }
+    Ok(embeddings)
+}
+
+const DIMENSION_PROBE_TEXT: &str = "dimension probe";
+
+/// Vector length to request from an embedding model: `EMBEDDING_DIMENSION`, or else
+/// `VECTOR_DIMENSION` for OpenAI `text-embedding-3-*` models, which can shorten their
+/// output. `None` leaves the model's native length, as with `AUTO_DETECT_DIMENSION`.
+pub fn requested_dimensions(args: &Args, llm_type: &LlmType, model: Option<&str>) -> Option<u32> {
+    if args.embedding_dimension.is_some() {
+        return args.embedding_dimension;
+    }
+    let shortenable = *llm_type == LlmType::OpenAI
+        && model.unwrap_or(openai::DEFAULT_MODEL).starts_with("text-embedding-3");
+    (shortenable && !args.auto_detect_dimension).then_some(args.dimension as u32)
+}
+
+pub async fn probe_dimension(
+    client: &dyn EmbeddingClient
+) -> Result<usize, Box<dyn StdError + Send + Sync>> {
+    let response = client.embed(DIMENSION_PROBE_TEXT).await?;
+    if response.embedding.is_empty() {
+        return Err("Embedding probe returned an empty vector".into());
+    }
+    Ok(response.embedding.len())
 }
 
 pub fn new_client(
@@ -1,37 +1,63 @@
 use async_trait::async_trait;
+use reqwest::Client as HttpClient;
+use serde::{ Deserialize, Serialize };
 use std::error::Error as StdError;
-use super::{ EmbeddingClient, EmbeddingResponse };
+use super::{ check_batch_len, EmbeddingClient, EmbeddingResponse };
 use super::super::LlmConfig;
-use rllm::{ builder::{ LLMBackend, LLMBuilder }, LLMProvider };
+use crate::llm::endpoint::join_endpoint;
+use crate::llm::http::shared_client;
+use crate::llm::ollama::{ keep_alive_value, OLLAMA_DEFAULT_BASE_URL };
 
 pub struct OllamaEmbeddingClient {
-    llm: Box<dyn LLMProvider + Send + Sync>,
+    http: HttpClient,
+    base_url: String,
+    model: String,
+    keep_alive: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct EmbedRequest<'a> {
+    model: &'a str,
+    input: Vec<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<&'a serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct EmbedResponse {
+    embeddings: Vec<Vec<f32>>,
 }
 
 impl OllamaEmbeddingClient {
     pub fn new(
         base_url: Option<String>,
-        model: Option<String>
+        model: Option<String>,
+        keep_alive: Option<&str>
     ) -> Result<Self, Box<dyn StdError + Send + Sync>> {
-        let url = base_url.unwrap_or_else(|| "http://localhost:11434".to_string());
-        let embed_model = model.unwrap_or_else(|| "nomic-embed-text".to_string());
-
-        let builder = LLMBuilder::new()
-            .backend(LLMBackend::Ollama)
-            .base_url(url)
-            .model(embed_model)
-            .stream(false);
-
-        let llm_provider = builder.build()?;
-
         Ok(Self {
-            llm: llm_provider,
+            http: shared_client(),
+            base_url: base_url.unwrap_or_else(|| OLLAMA_DEFAULT_BASE_URL.to_string()),
+            model: model.unwrap_or_else(|| "nomic-embed-text".to_string()),
+            keep_alive: keep_alive_value(keep_alive),
         })
     }
 
     pub fn from_config(config: &LlmConfig) -> Result<Self, Box<dyn StdError + Send + Sync>> {
         let model = config.embedding_model.clone();
-        Self::new(config.base_url.clone(), model)
+        Self::new(config.base_url.clone(), model, config.keep_alive.as_deref())
+    }
+}
+
+impl OllamaEmbeddingClient {
+    async fn embed_inputs(&self, input: Vec<&str>) -> Result<Vec<Vec<f32>>, Box<dyn StdError + Send + Sync>> {
+        let url = join_endpoint(&self.base_url, "/api/embed");
+        let req = EmbedRequest {
+            model: &self.model,
+            input,
+            keep_alive: self.keep_alive.as_ref(),
+        };
+        let resp = self.http.post(&url).json(&req).send().await?.error_for_status()?;
+        Ok(resp.json::<EmbedResponse>().await?.embeddings)
     }
 }
 
@@ -41,11 +67,18 @@ impl EmbeddingClient for OllamaEmbeddingClient {
         &self,
         text: &str
     ) -> Result<EmbeddingResponse, Box<dyn StdError + Send + Sync>> {
-        let mut embeddings = self.llm.embed(vec![text.to_string()]).await?;
-        let embedding = embeddings
+        let embedding = self.embed_inputs(vec![text]).await?
             .pop()
             .ok_or_else(|| "Ollama embedding generation returned no results".to_string())?;
 
         Ok(EmbeddingResponse { embedding })
     }
+
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn StdError + Send + Sync>> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+        let embeddings = self.embed_inputs(texts.iter().map(String::as_str).collect()).await?;
+        check_batch_len("Ollama embedding generation", embeddings, texts.len())
+    }
 }
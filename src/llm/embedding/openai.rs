@@ -2,7 +2,9 @@ use async_trait::async_trait;
 use rllm::{ builder::{ LLMBackend, LLMBuilder }, LLMProvider };
 use std::error::Error as StdError;
 use super::super::LlmConfig;
-use super::{ EmbeddingClient, EmbeddingResponse };
+use super::{ check_batch_len, EmbeddingClient, EmbeddingResponse };
+
+pub(crate) const DEFAULT_MODEL: &str = "text-embedding-3-small";
 
 pub struct OpenAIEmbeddingClient {
     llm: Box<dyn LLMProvider + Send + Sync>,
@@ -15,7 +17,7 @@ impl OpenAIEmbeddingClient {
         base_url: Option<String>,
         dimensions: Option<u32>
     ) -> Result<Self, Box<dyn StdError + Send + Sync>> {
-        let model_name = model.unwrap_or_else(|| "text-embedding-3-small".to_string());
+        let model_name = model.unwrap_or_else(|| DEFAULT_MODEL.to_string());
 
         let mut builder = LLMBuilder::new()
             .backend(LLMBackend::OpenAI)
@@ -40,9 +42,7 @@ impl OpenAIEmbeddingClient {
             .ok_or_else(|| "OpenAI API key is required for OpenAIEmbeddingClient".to_string())?;
         let model = config.embedding_model.clone();
         let base_url = config.base_url.clone();
-        let dimensions = None;
-
-        Self::new(api_key, model, base_url, dimensions)
+        Self::new(api_key, model, base_url, config.embedding_dimensions)
     }
 }
 
@@ -59,4 +59,12 @@ impl EmbeddingClient for OpenAIEmbeddingClient {
 
         Ok(EmbeddingResponse { embedding })
     }
+
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn StdError + Send + Sync>> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+        let embeddings = self.llm.embed(texts.to_vec()).await?;
+        check_batch_len("OpenAI embedding generation", embeddings, texts.len())
+    }
 }
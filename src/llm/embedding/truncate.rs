@@ -0,0 +1,142 @@
+use async_trait::async_trait;
+use log::info;
+use std::error::Error as StdError;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use super::{ EmbeddingClient, EmbeddingInputType, EmbeddingResponse };
+use crate::cli::Args;
+
+/// What to do with text longer than the embedding input limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowStrategy {
+    /// Embed only the first `max_chars` characters.
+    Truncate,
+    /// Embed every `max_chars` chunk and average the vectors.
+    Chunk,
+}
+
+impl FromStr for OverflowStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "truncate" => Ok(OverflowStrategy::Truncate),
+            "chunk" => Ok(OverflowStrategy::Chunk),
+            other => Err(format!("Invalid embedding overflow strategy '{}': expected truncate or chunk", other)),
+        }
+    }
+}
+
+/// Keeps text within the provider's input limit before it is embedded.
+pub struct TruncatingEmbeddingClient {
+    inner: Arc<dyn EmbeddingClient>,
+    max_chars: usize,
+    strategy: OverflowStrategy,
+}
+
+impl TruncatingEmbeddingClient {
+    /// Wraps `client` per `--embedding-max-input-chars`; returns it unchanged when the limit is 0.
+    pub fn wrap(
+        args: &Args,
+        client: Arc<dyn EmbeddingClient>
+    ) -> Result<Arc<dyn EmbeddingClient>, Box<dyn StdError + Send + Sync>> {
+        if args.embedding_max_input_chars == 0 {
+            return Ok(client);
+        }
+        Ok(
+            Arc::new(Self {
+                inner: client,
+                max_chars: args.embedding_max_input_chars,
+                strategy: args.embedding_overflow.parse()?,
+            })
+        )
+    }
+
+    async fn embed_limited(
+        &self,
+        text: &str,
+        input_type: Option<EmbeddingInputType>
+    ) -> Result<EmbeddingResponse, Box<dyn StdError + Send + Sync>> {
+        let chunks = split_chars(text, self.max_chars);
+        if chunks.len() <= 1 {
+            return self.embed_one(text, input_type).await;
+        }
+
+        let total = text.chars().count();
+        match self.strategy {
+            OverflowStrategy::Truncate => {
+                info!("Truncating embedding input from {} to {} characters", total, self.max_chars);
+                self.embed_one(chunks[0], input_type).await
+            }
+            OverflowStrategy::Chunk => {
+                info!("Embedding {}-character input as {} chunks", total, chunks.len());
+                let mut sum: Vec<f32> = Vec::new();
+                for chunk in &chunks {
+                    let embedding = self.embed_one(chunk, input_type).await?.embedding;
+                    if sum.is_empty() {
+                        sum = embedding;
+                    } else if embedding.len() != sum.len() {
+                        return Err(
+                            format!("Chunk embeddings differ in dimension ({} vs {})", sum.len(), embedding.len()).into()
+                        );
+                    } else {
+                        sum.iter_mut().zip(embedding).for_each(|(s, v)| *s += v);
+                    }
+                }
+                let count = chunks.len() as f32;
+                Ok(EmbeddingResponse { embedding: sum.into_iter().map(|v| v / count).collect() })
+            }
+        }
+    }
+
+    async fn embed_one(
+        &self,
+        text: &str,
+        input_type: Option<EmbeddingInputType>
+    ) -> Result<EmbeddingResponse, Box<dyn StdError + Send + Sync>> {
+        match input_type {
+            Some(input_type) => self.inner.embed_with_type(text, input_type).await,
+            None => self.inner.embed(text).await,
+        }
+    }
+}
+
+#[async_trait]
+impl EmbeddingClient for TruncatingEmbeddingClient {
+    async fn embed(&self, text: &str) -> Result<EmbeddingResponse, Box<dyn StdError + Send + Sync>> {
+        self.embed_limited(text, None).await
+    }
+
+    async fn embed_with_type(
+        &self,
+        text: &str,
+        input_type: EmbeddingInputType
+    ) -> Result<EmbeddingResponse, Box<dyn StdError + Send + Sync>> {
+        self.embed_limited(text, Some(input_type)).await
+    }
+
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn StdError + Send + Sync>> {
+        if texts.iter().all(|text| text.chars().count() <= self.max_chars) {
+            return self.inner.embed_batch(texts).await;
+        }
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for text in texts {
+            embeddings.push(self.embed_limited(text, None).await?.embedding);
+        }
+        Ok(embeddings)
+    }
+}
+
+/// Splits `text` into pieces of at most `max_chars` characters, on char boundaries.
+fn split_chars(text: &str, max_chars: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let end = rest.char_indices().nth(max_chars).map(|(i, _)| i).unwrap_or(rest.len());
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
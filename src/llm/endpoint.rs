@@ -0,0 +1,127 @@
+//! Builds provider endpoints from user-supplied base URLs.
+//!
+//! A base URL may be a bare host (`https://api.openai.com`), a host plus part
+//! of the provider path (`https://api.openai.com/v1`), or a full endpoint
+//! (`https://api.openai.com/v1/chat/completions`). Trailing slashes are ignored.
+//!
+//! - A base already ending with the last two segments of the provider path
+//!   (e.g. `/chat/completions`) is treated as a full endpoint and used as-is.
+//! - Otherwise the provider path is appended, skipping whatever leading part
+//!   of it the base already ends with, so each segment appears exactly once.
+
+/// Joins `path` onto `base_url` following the module rules.
+pub fn join_endpoint(base_url: &str, path: &str) -> String {
+    let base = base_url.trim().trim_end_matches('/');
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    if segments.is_empty() {
+        return base.to_string();
+    }
+
+    let tail = segments[segments.len() - segments.len().min(2)..].join("/");
+    if ends_with_segments(base, &tail) {
+        return base.to_string();
+    }
+
+    for n in (1..segments.len()).rev() {
+        if ends_with_segments(base, &segments[..n].join("/")) {
+            return format!("{}/{}", base, segments[n..].join("/"));
+        }
+    }
+    format!("{}/{}", base, segments.join("/"))
+}
+
+/// Like [`join_endpoint`], using `default_base` when no (non-empty) base URL is configured.
+pub fn endpoint_url(base_url: Option<&str>, default_base: &str, path: &str) -> String {
+    let base = base_url
+        .map(str::trim)
+        .filter(|b| !b.is_empty())
+        .unwrap_or(default_base);
+    join_endpoint(base, path)
+}
+
+fn ends_with_segments(base: &str, segments: &str) -> bool {
+    base.strip_suffix(segments).is_some_and(|rest| rest.ends_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_each_path_segment_exactly_once() {
+        let cases = [
+            ("https://api.openai.com", "/v1/chat/completions", "https://api.openai.com/v1/chat/completions"),
+            ("https://api.openai.com/", "/v1/chat/completions", "https://api.openai.com/v1/chat/completions"),
+            ("https://api.openai.com/v1", "/v1/chat/completions", "https://api.openai.com/v1/chat/completions"),
+            ("https://api.openai.com/v1/", "v1/chat/completions", "https://api.openai.com/v1/chat/completions"),
+            ("https://api.openai.com/v1/chat/completions", "/v1/chat/completions", "https://api.openai.com/v1/chat/completions"),
+            ("https://proxy.local/openai/v1", "/v1/chat/completions", "https://proxy.local/openai/v1/chat/completions"),
+            // Gateways with a different version prefix keep their own path.
+            ("https://gateway.local/api/chat/completions", "/v1/chat/completions", "https://gateway.local/api/chat/completions"),
+            ("http://localhost:11434", "/api/chat", "http://localhost:11434/api/chat"),
+            ("http://localhost:11434/api", "/api/chat", "http://localhost:11434/api/chat"),
+            ("http://localhost:11434/api/chat", "/api/chat", "http://localhost:11434/api/chat"),
+            ("http://localhost:11434 ", "", "http://localhost:11434"),
+        ];
+        for (base, path, expected) in cases {
+            assert_eq!(join_endpoint(base, path), expected, "{} + {}", base, path);
+        }
+    }
+
+    #[test]
+    fn builds_groq_endpoints() {
+        let path = "/openai/v1/chat/completions";
+        let expected = "https://api.groq.com/openai/v1/chat/completions";
+        assert_eq!(join_endpoint("https://api.groq.com", path), expected);
+        assert_eq!(join_endpoint("https://api.groq.com/", path), expected);
+        assert_eq!(join_endpoint("https://api.groq.com/openai/v1/", path), expected);
+        assert_eq!(join_endpoint(expected, path), expected);
+        assert_eq!(join_endpoint("https://proxy.local/groq", path), "https://proxy.local/groq/openai/v1/chat/completions");
+    }
+
+    #[test]
+    fn builds_xai_endpoints() {
+        let (default, path) = ("https://api.x.ai", "/v1/chat/completions");
+        let expected = "https://api.x.ai/v1/chat/completions";
+        assert_eq!(endpoint_url(None, default, path), expected);
+        assert_eq!(endpoint_url(Some("https://api.x.ai/"), default, path), expected);
+        assert_eq!(endpoint_url(Some("https://api.x.ai/v1/"), default, path), expected);
+        assert_eq!(endpoint_url(Some("https://proxy.local/xai"), default, path), "https://proxy.local/xai/v1/chat/completions");
+    }
+
+    #[test]
+    fn builds_deepseek_endpoints() {
+        let (default, path) = ("https://api.deepseek.com", "/chat/completions");
+        let expected = "https://api.deepseek.com/chat/completions";
+        assert_eq!(endpoint_url(None, default, path), expected);
+        assert_eq!(endpoint_url(Some("https://api.deepseek.com/"), default, path), expected);
+        assert_eq!(endpoint_url(Some(expected), default, path), expected);
+        // DeepSeek also serves the OpenAI-style `/v1` prefix.
+        assert_eq!(endpoint_url(Some("https://api.deepseek.com/v1/"), default, path), "https://api.deepseek.com/v1/chat/completions");
+        assert_eq!(endpoint_url(Some("https://proxy.local/deepseek"), default, path), "https://proxy.local/deepseek/chat/completions");
+    }
+
+    #[test]
+    fn builds_gemini_model_endpoints() {
+        let (default, path) = ("https://generativelanguage.googleapis.com", "/v1beta/models/gemini-2.0-flash");
+        let expected = "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.0-flash";
+        assert_eq!(endpoint_url(None, default, path), expected);
+        assert_eq!(endpoint_url(Some("https://generativelanguage.googleapis.com/"), default, path), expected);
+        assert_eq!(endpoint_url(Some("https://generativelanguage.googleapis.com/v1beta/"), default, path), expected);
+        assert_eq!(endpoint_url(Some("https://proxy.local/gemini"), default, path), "https://proxy.local/gemini/v1beta/models/gemini-2.0-flash");
+    }
+
+    #[test]
+    fn segment_matches_need_a_path_boundary() {
+        // `/myv1` ends with the text `v1` but not with the segment.
+        assert_eq!(join_endpoint("https://host/myv1", "/v1/messages"), "https://host/myv1/v1/messages");
+    }
+
+    #[test]
+    fn falls_back_to_the_default_base() {
+        let default = "https://api.anthropic.com";
+        assert_eq!(endpoint_url(None, default, "/v1/messages"), "https://api.anthropic.com/v1/messages");
+        assert_eq!(endpoint_url(Some("  "), default, "/v1/messages"), "https://api.anthropic.com/v1/messages");
+        assert_eq!(endpoint_url(Some("https://proxy/v1"), default, "/v1/messages"), "https://proxy/v1/messages");
+    }
+}
@@ -0,0 +1,106 @@
+//! Connection pooling shared by the provider HTTP clients.
+//!
+//! Every provider client is built once and reuses connections through these
+//! settings. `configure` must run before the first client is built; clients
+//! created earlier keep the defaults.
+
+use crate::cli::Args;
+use log::warn;
+use once_cell::sync::{ Lazy, OnceCell };
+use reqwest::{ Client as HttpClient, ClientBuilder, RequestBuilder, Response };
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+static POOL_CONFIG: OnceCell<HttpPoolConfig> = OnceCell::new();
+
+// One pool for every client that needs no default headers of its own.
+static SHARED_HTTP: Lazy<HttpClient> = Lazy::new(|| client_builder().build().unwrap_or_default());
+
+#[derive(Debug, Clone, Copy)]
+pub struct HttpPoolConfig {
+    /// Idle connections kept open per host.
+    pub max_idle_per_host: usize,
+    /// How long an unused connection stays in the pool.
+    pub idle_timeout: Duration,
+    /// TCP keep-alive probe interval; `None` disables probes.
+    pub tcp_keepalive: Option<Duration>,
+}
+
+impl Default for HttpPoolConfig {
+    fn default() -> Self {
+        Self {
+            max_idle_per_host: 32,
+            idle_timeout: Duration::from_secs(90),
+            tcp_keepalive: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+impl HttpPoolConfig {
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            max_idle_per_host: args.llm_http_pool_max_idle,
+            idle_timeout: Duration::from_secs(args.llm_http_pool_idle_timeout_secs),
+            tcp_keepalive: Some(args.llm_http_tcp_keepalive_secs)
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+        }
+    }
+}
+
+/// Sets the pool settings for the process. Only the first call takes effect.
+pub fn configure(config: HttpPoolConfig) {
+    if POOL_CONFIG.set(config).is_err() {
+        warn!("LLM HTTP pool already configured; ignoring {:?}", config);
+    }
+}
+
+/// Builder carrying the pool settings, for clients that need their own default headers.
+pub fn client_builder() -> ClientBuilder {
+    let config = POOL_CONFIG.get().copied().unwrap_or_default();
+    HttpClient::builder()
+        .pool_max_idle_per_host(config.max_idle_per_host)
+        .pool_idle_timeout(config.idle_timeout)
+        .tcp_keepalive(config.tcp_keepalive)
+}
+
+/// Handle to the shared connection pool; clones reuse the same connections.
+pub fn shared_client() -> HttpClient {
+    SHARED_HTTP.clone()
+}
+
+/// A provider sent no response headers within its `CHAT_TIMEOUT_SECS` window.
+#[derive(Debug)]
+pub struct ProviderTimeout(pub Duration);
+
+impl fmt::Display for ProviderTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LLM provider request timed out after {}s", self.0.as_secs())
+    }
+}
+
+impl Error for ProviderTimeout {}
+
+/// Bounds a non-streaming call, response body included. `None` leaves it unbounded.
+pub fn with_timeout(request: RequestBuilder, timeout: Option<Duration>) -> RequestBuilder {
+    match timeout {
+        Some(timeout) => request.timeout(timeout),
+        None => request,
+    }
+}
+
+/// Sends a streaming request, bounding only the wait for the response headers so long
+/// generations are not cut off; stalls mid-stream are left to the stream timeouts.
+pub async fn send_streaming(
+    request: RequestBuilder,
+    timeout: Option<Duration>
+) -> Result<Response, Box<dyn Error + Send + Sync>> {
+    let Some(timeout) = timeout else {
+        return Ok(request.send().await?);
+    };
+    match tokio::time::timeout(timeout, request.send()).await {
+        Ok(response) => Ok(response?),
+        Err(_) => Err(Box::new(ProviderTimeout(timeout))),
+    }
+}
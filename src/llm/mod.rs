@@ -1,8 +1,19 @@
 pub mod chat;
 pub mod embedding;
+pub mod circuit_breaker;
+pub mod endpoint;
+pub mod http;
+pub mod ollama;
 use serde::{ Deserialize, Serialize };
 use std::str::FromStr;
 use std::fmt;
+use std::time::Duration;
+
+/// Default chat request timeout, matching the `CHAT_TIMEOUT_SECS` default.
+pub const DEFAULT_CHAT_TIMEOUT_SECS: u64 = 60;
+/// Defaults matching `LLM_MAX_RETRIES` and `LLM_RETRY_BASE_MS`.
+pub const DEFAULT_LLM_MAX_RETRIES: u32 = 3;
+pub const DEFAULT_LLM_RETRY_BASE_MS: u64 = 200;
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
@@ -48,13 +59,58 @@ impl FromStr for LlmType {
     }
 }
 
+/// Sampling settings for chat requests. An unset field keeps the provider
+/// client's built-in default; providers without a matching parameter ignore it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GenerationParams {
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub top_p: Option<f32>,
+    /// Sent by OpenAI, DeepSeek, xAI, Groq, Gemini and Ollama.
+    pub frequency_penalty: Option<f32>,
+    /// Sent by OpenAI, DeepSeek, xAI, Groq, Gemini and Ollama.
+    pub presence_penalty: Option<f32>,
+}
+
 #[derive(Debug, Clone)]
 pub struct LlmConfig {
     pub llm_type: LlmType,
     pub api_key: Option<String>,
     pub completion_model: Option<String>,
     pub embedding_model: Option<String>,
+    /// Embedding vector length to request; only OpenAI sends it (`dimensions`).
+    pub embedding_dimensions: Option<u32>,
     pub base_url: Option<String>,
+    /// Ollama `keep_alive` sent with each request; ignored by other providers.
+    pub keep_alive: Option<String>,
+    /// Sampling seed for reproducible completions; only OpenAI and Ollama send it.
+    pub seed: Option<u64>,
+    /// Reasoning token budget; only Gemini sends it (`thinkingConfig.thinkingBudget`).
+    pub thinking_budget: Option<u32>,
+    /// Temperature, token limit and other sampling settings for chat requests.
+    pub generation: GenerationParams,
+    /// Instructions sent ahead of every chat prompt, as a system message where the provider has one.
+    pub system_prompt: Option<String>,
+    /// Chat request timeout in seconds; 0 disables it. Streams are bounded only until
+    /// their response starts. Embedding clients ignore it.
+    pub request_timeout_secs: u64,
+    /// Retries of non-streaming chat calls after transient failures. Embedding clients ignore it.
+    pub max_retries: u32,
+    /// Backoff before the first retry in milliseconds, doubled for each later one.
+    pub retry_base_ms: u64,
+}
+
+impl LlmConfig {
+    pub fn request_timeout(&self) -> Option<Duration> {
+        (self.request_timeout_secs > 0).then(|| Duration::from_secs(self.request_timeout_secs))
+    }
+
+    pub fn retry_policy(&self) -> chat::RetryPolicy {
+        chat::RetryPolicy {
+            max_retries: self.max_retries,
+            base_delay: Duration::from_millis(self.retry_base_ms),
+        }
+    }
 }
 
 impl Default for LlmConfig {
@@ -64,7 +120,16 @@ impl Default for LlmConfig {
             api_key: None,
             completion_model: None,
             embedding_model: None,
+            embedding_dimensions: None,
             base_url: None,
+            keep_alive: None,
+            seed: None,
+            thinking_budget: None,
+            generation: GenerationParams::default(),
+            system_prompt: None,
+            request_timeout_secs: DEFAULT_CHAT_TIMEOUT_SECS,
+            max_retries: DEFAULT_LLM_MAX_RETRIES,
+            retry_base_ms: DEFAULT_LLM_RETRY_BASE_MS,
         }
     }
 }
@@ -81,3 +146,135 @@ pub fn parse_llm_type(type_str: &str) -> Result<LlmType, String> {
         _ => Err(format!("Unsupported LLM type: {}", type_str)),
     }
 }
+
+/// Phrases of transient failures in providers' stringified errors. Bare status codes
+/// and words like "timeout" are left out: they also turn up in unrelated text.
+const RETRYABLE_ERROR_MARKERS: &[&str] = &[
+    "rate limit",
+    "too many requests",
+    "quota exceeded",
+    "timed out",
+    "deadline has elapsed",
+    "error sending request",
+    "connection refused",
+    "connection reset",
+    "connection closed",
+    "service unavailable",
+    "temporarily unavailable",
+    "overloaded",
+];
+
+fn is_retryable_status(status: u16) -> bool {
+    status == 429 || (500..600).contains(&status)
+}
+
+/// HTTP status named in a stringified error: `status 503`, `status: 503`,
+/// `status code 503`, or the code with its reason phrase (`503 Service Unavailable`).
+fn status_in_message(message: &str) -> Option<u16> {
+    let words: Vec<&str> = message
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+    words.iter().enumerate().find_map(|(i, word)| {
+        let code = word.parse::<u16>().ok().filter(|_| word.len() == 3)?;
+        let after_status = match i.checked_sub(1).map(|j| words[j]) {
+            Some("status") => true,
+            Some("code") => i >= 2 && words[i - 2] == "status",
+            _ => false,
+        };
+        let reason = reqwest::StatusCode::from_u16(code).ok()?.canonical_reason()?.to_lowercase();
+        let with_reason = words[i + 1..].join(" ").starts_with(&reason.replace('-', " "));
+        (after_status || with_reason).then_some(code)
+    })
+}
+
+/// Whether `err`, or any error it wraps, is worth retrying elsewhere: provider timeouts,
+/// throttling (429), server errors (5xx) and network failures.
+pub fn is_retryable_error(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut current = Some(err);
+    while let Some(e) = current {
+        if e.is::<http::ProviderTimeout>() || e.is::<tokio::time::error::Elapsed>() {
+            return true;
+        }
+        if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+            if e.is_timeout() || e.is_connect() {
+                return true;
+            }
+            if let Some(status) = e.status() {
+                return is_retryable_status(status.as_u16());
+            }
+        }
+        if let Some(e) = e.downcast_ref::<std::io::Error>() {
+            use std::io::ErrorKind::*;
+            if matches!(e.kind(), TimedOut | ConnectionRefused | ConnectionReset | ConnectionAborted | BrokenPipe) {
+                return true;
+            }
+        }
+        current = e.source();
+    }
+    let message = err.to_string().to_lowercase();
+    if let Some(status) = status_in_message(&message) {
+        return is_retryable_status(status);
+    }
+    RETRYABLE_ERROR_MARKERS.iter().any(|m| message.contains(m))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[derive(Debug)]
+    struct Wrapped(std::io::Error);
+
+    impl fmt::Display for Wrapped {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "request failed")
+        }
+    }
+
+    impl Error for Wrapped {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[test]
+    fn classifies_stringified_provider_errors() {
+        let cases = [
+            ("HTTP Error: HTTP status server error (500 Internal Server Error) for url (http://x)", true),
+            ("HTTP Error: HTTP status client error (429 Too Many Requests) for url (http://x)", true),
+            ("HTTP error: 503 Service Unavailable", true),
+            ("Provider Error: status 502", true),
+            ("upstream returned status code 504", true),
+            ("HTTP status client error (404 Not Found) for url (http://x)", false),
+            ("HTTP status client error (401 Unauthorized) for url (http://x)", false),
+            ("Rate limit reached for requests", true),
+            ("overloaded_error: Overloaded", true),
+            ("error sending request for url (http://127.0.0.1:11434/api/chat)", true),
+            ("operation timed out", true),
+            // Codes and keywords that are not about the transport.
+            ("Invalid Request: max_tokens must be at most 500", false),
+            ("Provider Error: the connection field is required", false),
+            ("Invalid Request: timeout must be positive", false),
+            ("JSON Parse Error: expected value at line 1 column 502", false),
+            ("model 'llama3:500b' not found", false),
+        ];
+        for (message, expected) in cases {
+            let err: Box<dyn Error + Send + Sync> = message.into();
+            assert_eq!(is_retryable_error(err.as_ref()), expected, "{}", message);
+        }
+    }
+
+    #[test]
+    fn classifies_typed_errors_through_the_source_chain() {
+        let timeout = http::ProviderTimeout(Duration::from_secs(5));
+        assert!(is_retryable_error(&timeout));
+
+        let refused = Wrapped(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused"));
+        assert!(is_retryable_error(&refused));
+
+        let invalid = Wrapped(std::io::Error::new(std::io::ErrorKind::InvalidData, "bad"));
+        assert!(!is_retryable_error(&invalid));
+    }
+}
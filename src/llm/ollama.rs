@@ -0,0 +1,15 @@
+//! HTTP plumbing shared by the Ollama chat and embedding clients.
+
+use serde_json::Value as JsonValue;
+
+pub const OLLAMA_DEFAULT_BASE_URL: &str = "http://localhost:11434";
+
+/// Ollama `keep_alive` request value: integers are sent as seconds (`-1` keeps
+/// the model loaded indefinitely), anything else as a duration string like `10m`.
+pub fn keep_alive_value(keep_alive: Option<&str>) -> Option<JsonValue> {
+    let value = keep_alive.map(str::trim).filter(|v| !v.is_empty())?;
+    Some(match value.parse::<i64>() {
+        Ok(secs) => JsonValue::from(secs),
+        Err(_) => JsonValue::from(value),
+    })
+}
@@ -1,5 +1,5 @@
-use clap::Parser;
-use dynamic_agent::cli::Args;
+use clap::{ CommandFactory, FromArgMatches };
+use dynamic_agent::cli::{ self, Args };
 use dotenv::dotenv;
 use rustls::crypto::ring;
 use std::error::Error;
@@ -17,6 +17,10 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     )
     .init();
 
-    let args = Args::parse();
-    dynamic_agent::run(args).await
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if args.print_config {
+        println!("{}", serde_json::to_string_pretty(&cli::config_report(&args, &matches))?);
+    }
+    Ok(dynamic_agent::run(args).await?)
 }
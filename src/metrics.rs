@@ -0,0 +1,73 @@
+//! Process-wide counters for the response cache and LLM calls, served by `GET /metrics`
+//! in the Prometheus text exposition format.
+
+use std::fmt::Write;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::time::Duration;
+
+/// Upper bounds of the `llm_request_duration_seconds` buckets.
+const LLM_DURATION_BUCKETS: [f64; 10] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+static CACHE_HITS_REDIS: AtomicU64 = AtomicU64::new(0);
+static CACHE_HITS_QDRANT: AtomicU64 = AtomicU64::new(0);
+static CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+static LLM_DURATION_BUCKET_COUNTS: [AtomicU64; LLM_DURATION_BUCKETS.len()] =
+    [const { AtomicU64::new(0) }; LLM_DURATION_BUCKETS.len()];
+static LLM_DURATION_COUNT: AtomicU64 = AtomicU64::new(0);
+static LLM_DURATION_SUM_MICROS: AtomicU64 = AtomicU64::new(0);
+
+/// Where a cached answer was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheTier {
+    /// Exact match on the normalized prompt.
+    Redis,
+    /// Semantic match above the similarity threshold.
+    Qdrant,
+}
+
+pub fn record_cache_hit(tier: CacheTier) {
+    let counter = match tier {
+        CacheTier::Redis => &CACHE_HITS_REDIS,
+        CacheTier::Qdrant => &CACHE_HITS_QDRANT,
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_cache_miss() {
+    CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records one chat model call; streamed calls count until the stream ends or is dropped.
+pub fn observe_llm_request(duration: Duration) {
+    let secs = duration.as_secs_f64();
+    // Buckets hold only their own range; `render` accumulates them as Prometheus expects.
+    if let Some(i) = LLM_DURATION_BUCKETS.iter().position(|bound| secs <= *bound) {
+        LLM_DURATION_BUCKET_COUNTS[i].fetch_add(1, Ordering::Relaxed);
+    }
+    LLM_DURATION_COUNT.fetch_add(1, Ordering::Relaxed);
+    LLM_DURATION_SUM_MICROS.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+}
+
+/// Appends the cache and LLM metrics to `body`.
+pub fn render(body: &mut String) {
+    body.push_str("# HELP cache_hits_total Answers served from the response cache, by tier.\n");
+    body.push_str("# TYPE cache_hits_total counter\n");
+    let _ = writeln!(body, "cache_hits_total{{tier=\"redis\"}} {}", CACHE_HITS_REDIS.load(Ordering::Relaxed));
+    let _ = writeln!(body, "cache_hits_total{{tier=\"qdrant\"}} {}", CACHE_HITS_QDRANT.load(Ordering::Relaxed));
+    body.push_str("# HELP cache_misses_total Cache lookups that found no answer.\n");
+    body.push_str("# TYPE cache_misses_total counter\n");
+    let _ = writeln!(body, "cache_misses_total {}", CACHE_MISSES.load(Ordering::Relaxed));
+
+    body.push_str("# HELP llm_request_duration_seconds Duration of chat model calls, streams included.\n");
+    body.push_str("# TYPE llm_request_duration_seconds histogram\n");
+    let mut cumulative = 0;
+    for (bound, count) in LLM_DURATION_BUCKETS.iter().zip(&LLM_DURATION_BUCKET_COUNTS) {
+        cumulative += count.load(Ordering::Relaxed);
+        let _ = writeln!(body, "llm_request_duration_seconds_bucket{{le=\"{}\"}} {}", bound, cumulative);
+    }
+    let total = LLM_DURATION_COUNT.load(Ordering::Relaxed);
+    let _ = writeln!(body, "llm_request_duration_seconds_bucket{{le=\"+Inf\"}} {}", total);
+    let sum = LLM_DURATION_SUM_MICROS.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+    let _ = writeln!(body, "llm_request_duration_seconds_sum {}", sum);
+    let _ = writeln!(body, "llm_request_duration_seconds_count {}", total);
+}
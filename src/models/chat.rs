@@ -2,9 +2,28 @@ use serde::{ Serialize, Deserialize };
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ChatMessage {
+    /// Stable message ID; absent for entries stored before IDs were introduced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     pub role: String,
     pub content: String,
     pub timestamp: i64,
+    /// Assistant reasoning, kept only when `HISTORY_STORE_THINKING` is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<String>,
+}
+
+impl ChatMessage {
+    /// Message built for a prompt rather than loaded from history.
+    pub fn new(role: &str, content: &str) -> Self {
+        Self {
+            id: None,
+            role: role.to_string(),
+            content: content.to_string(),
+            timestamp: chrono::Utc::now().timestamp(),
+            thinking: None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -12,3 +31,17 @@ pub struct Conversation {
     pub id: String,
     pub messages: Vec<ChatMessage>,
 }
+
+/// Conversation-level details for conversation lists, stored apart from the messages.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ConversationMetadata {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Unix seconds of the first stored exchange.
+    pub created_at: i64,
+    /// Unix seconds of the latest stored exchange.
+    pub last_active: i64,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
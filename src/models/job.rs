@@ -0,0 +1,30 @@
+use serde::{ Serialize, Deserialize };
+use crate::rag::rag::RetrievalMetadata;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Done,
+    Error,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+    pub status: JobStatus,
+    pub conversation_id: String,
+    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Topic, searched fields and hit count when the answer used retrieval.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<RetrievalMetadata>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
@@ -1,4 +1,78 @@
 use serde::{ Serialize, Deserialize };
+use crate::history::WindowPolicy;
+use crate::agent::ProgressStage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolVersion {
+    V1,
+    /// v1 plus a machine-readable `code` on `error` messages.
+    V2,
+}
+
+impl ProtocolVersion {
+    pub const SUPPORTED: &'static [ProtocolVersion] = &[ProtocolVersion::V1, ProtocolVersion::V2];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProtocolVersion::V1 => "dynamic-agent.v1",
+            ProtocolVersion::V2 => "dynamic-agent.v2",
+        }
+    }
+
+    /// Whether `error` messages carry a structured `code`.
+    pub fn supports_error_codes(&self) -> bool {
+        matches!(self, ProtocolVersion::V2)
+    }
+
+    pub fn from_subprotocol(name: &str) -> Option<Self> {
+        Self::SUPPORTED.iter().copied().find(|v| v.as_str() == name.trim())
+    }
+
+    /// Picks the first supported entry of a `Sec-WebSocket-Protocol` header.
+    /// `Ok(None)` means the client requested no subprotocol (legacy clients get v1).
+    pub fn negotiate(header: Option<&str>) -> Result<Option<Self>, String> {
+        let requested = match header {
+            Some(h) if !h.trim().is_empty() => h,
+            _ => return Ok(None),
+        };
+        requested
+            .split(',')
+            .find_map(Self::from_subprotocol)
+            .map(Some)
+            .ok_or_else(|| requested.to_string())
+    }
+
+    pub fn supported_list() -> String {
+        Self::SUPPORTED.iter().map(|v| v.as_str()).collect::<Vec<_>>().join(", ")
+    }
+
+    pub fn parse_client_message(&self, text: &str) -> Result<ClientMessage, serde_json::Error> {
+        match self {
+            ProtocolVersion::V1 | ProtocolVersion::V2 => serde_json::from_str::<ClientMessage>(text),
+        }
+    }
+}
+
+/// Stable error categories for `error` messages; clients should branch on these
+/// rather than on the human-readable `message`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Too many messages; see `retry_after`.
+    RateLimited,
+    /// Another message is still being answered on this conversation.
+    Busy,
+    /// The stream was superseded by a newer message on the connection.
+    Cancelled,
+    /// The model did not answer in time.
+    Timeout,
+    Auth,
+    /// The message was malformed, too large or otherwise rejected.
+    BadRequest,
+    /// The LLM provider failed or returned an error.
+    ProviderError,
+    Internal,
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
@@ -6,15 +80,64 @@ pub enum ClientMessage {
     #[serde(rename = "chat")]
     Chat { 
         content: String,
+        /// Optional images as URLs, data URIs, or bare base64 (vision-capable providers only).
+        #[serde(default)]
+        images: Vec<String>,
+        /// History window for this and later messages on the connection: full, none, last_n or last_n:<count>.
+        #[serde(default)]
+        window_policy: Option<WindowPolicy>,
+        /// Answer language for this message, overriding the server default.
+        #[serde(default)]
+        language: Option<String>,
+        /// Number of RAG results to retrieve, clamped to the server's maximum.
+        #[serde(default)]
+        rag_limit: Option<usize>,
+        #[serde(default)]
+        capabilities: Option<ClientCapabilities>,
+        /// Continues this conversation, for this and later messages on the connection.
+        #[serde(default)]
+        conversation_id: Option<String>
+    },
+    /// Switches the connection to an earlier conversation, e.g. after a reconnect.
+    #[serde(rename = "resume")]
+    Resume { conversation_id: String },
+    /// Answers a previous user message again, replacing its reply and everything after it.
+    #[serde(rename = "regenerate")]
+    Regenerate {
+        /// User message (or reply) to regenerate from; defaults to the last user message.
+        #[serde(default)]
+        from_message_id: Option<String>,
+        #[serde(default)]
+        window_policy: Option<WindowPolicy>,
+        #[serde(default)]
+        language: Option<String>,
+        #[serde(default)]
+        rag_limit: Option<usize>,
         #[serde(default)]
         capabilities: Option<ClientCapabilities>
     },
+    /// Stops the answer being streamed; the connection stays open for further messages.
+    #[serde(rename = "cancel")]
+    Cancel {},
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ClientCapabilities {
     #[serde(default)]
     pub supports_thinking: bool,
+    /// Receive `progress` events while the request is being prepared.
+    #[serde(default)]
+    pub supports_progress: bool,
+    /// Receive `typing` and thinking-start status messages. Defaults to true.
+    #[serde(default = "default_true")]
+    pub wants_status_events: bool,
+    /// Receive a `metadata` message with the retrieval topic, fields and hit count before answers that used retrieval.
+    #[serde(default)]
+    pub supports_metadata: bool,
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[derive(Serialize, Deserialize)]
@@ -33,11 +156,48 @@ pub enum ServerMessage {
     ThinkingFragment { content: String },
     
     #[serde(rename = "error")]
-    Error { message: String },
+    Error {
+        message: String,
+        /// Machine-readable category, sent only to `dynamic-agent.v2` clients.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<ErrorCode>,
+        /// Seconds until the client may retry (set for rate limiting).
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        retry_after: Option<u64>,
+    },
     
     #[serde(rename = "typing")]
     Typing,
+
+    /// Where retrieval looked for the answer that follows.
+    #[serde(rename = "metadata")]
+    Metadata {
+        topic: String,
+        fields: Vec<String>,
+        hit_count: usize,
+    },
+
+    #[serde(rename = "progress")]
+    Progress {
+        stage: ProgressStage,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        detail: Option<String>,
+    },
     
     #[serde(rename = "done")]
-    Done { timestamp: i64 },
+    Done {
+        timestamp: i64,
+        /// ID of the user message this turn answered; pass it as `from_message_id` to regenerate.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        message_id: Option<String>,
+        /// Conversation the turn belongs to; send it back in `resume` to continue later.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        conversation_id: Option<String>,
+        /// Set when reasoning beyond `MAX_THINKING_TOKENS` was not forwarded.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        thinking_truncated: Option<bool>,
+        /// Set when the client cancelled the answer before it finished.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cancelled: Option<bool>,
+    },
 }
@@ -0,0 +1,181 @@
+//! Field constraints on retrieval ("projects after 2022", "language is Rust").
+//!
+//! `vector-nexus` has no filter parameter on its searches, so filters are applied
+//! to the hits a search returns; see `RagEngine::retrieve_documents`.
+
+use log::{ info, warn };
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+use std::cmp::Ordering;
+
+use super::rag::normalize_schema_name;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterOp {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    /// Case-insensitive substring match.
+    Contains,
+}
+
+/// One constraint on a document field.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldFilter {
+    pub field: String,
+    pub op: FilterOp,
+    pub value: Value,
+}
+
+impl FieldFilter {
+    /// Whether `document` satisfies the filter. Numbers (or numeric strings) compare
+    /// as numbers, anything else as case-insensitive strings, so ISO dates order
+    /// correctly. An array field matches when any element does; a missing field never matches.
+    pub fn matches(&self, document: &Value) -> bool {
+        match document.get(&self.field) {
+            Some(Value::Array(items)) => items.iter().any(|item| self.matches_value(item)),
+            Some(value) => self.matches_value(value),
+            None => false,
+        }
+    }
+
+    fn matches_value(&self, actual: &Value) -> bool {
+        if actual.is_null() {
+            return false;
+        }
+        let (actual_text, wanted_text) = (text(actual), text(&self.value));
+        let ordering = match (number(actual), number(&self.value)) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => Some(actual_text.cmp(&wanted_text)),
+        };
+        match self.op {
+            FilterOp::Contains => actual_text.contains(&wanted_text),
+            FilterOp::Eq => ordering == Some(Ordering::Equal),
+            FilterOp::Ne => ordering.is_some_and(|o| o != Ordering::Equal),
+            FilterOp::Gt => ordering == Some(Ordering::Greater),
+            FilterOp::Gte => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+            FilterOp::Lt => ordering == Some(Ordering::Less),
+            FilterOp::Lte => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        }
+    }
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.trim().to_lowercase(),
+        other => other.to_string().to_lowercase(),
+    }
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Keeps the filters whose field is one of `fields`, renamed to the schema's
+/// spelling. Filters on other fields are dropped with a warning.
+pub fn known_fields(filters: &[FieldFilter], fields: &[String]) -> Vec<FieldFilter> {
+    filters
+        .iter()
+        .filter_map(|filter| {
+            let wanted = normalize_schema_name(&filter.field);
+            match fields.iter().find(|f| normalize_schema_name(f) == wanted) {
+                Some(field) => Some(FieldFilter { field: field.clone(), ..filter.clone() }),
+                None => {
+                    warn!("Ignoring filter on unknown field '{}'", filter.field);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Filters from a filter extraction reply: a JSON array of `{"field", "op", "value"}`
+/// objects, optionally wrapped as `{"filters": [...]}`. Entries that do not parse are skipped.
+pub fn parse_filters(reply: &str) -> Vec<FieldFilter> {
+    let Some(start) = reply.find(['{', '[']) else {
+        return Vec::new();
+    };
+    // A streaming read tolerates text after the JSON, such as a closing code fence.
+    let Some(Ok(value)) = serde_json::Deserializer::from_str(&reply[start..]).into_iter::<Value>().next() else {
+        return Vec::new();
+    };
+    let items = match value.get("filters").unwrap_or(&value) {
+        Value::Array(items) => items.clone(),
+        _ => return Vec::new(),
+    };
+    items
+        .into_iter()
+        .filter_map(|item| match serde_json::from_value::<FieldFilter>(item.clone()) {
+            Ok(filter) => Some(filter),
+            Err(e) => {
+                info!("Skipping unparseable filter {}: {}", item, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Drops the hits that fail any filter.
+pub fn apply<T>(filters: &[FieldFilter], hits: &mut Vec<(f32, T, Value)>) {
+    if filters.is_empty() {
+        return;
+    }
+    let before = hits.len();
+    hits.retain(|(_, _, document)| filters.iter().all(|filter| filter.matches(document)));
+    info!("Filters {:?} kept {} of {} hits", filters, hits.len(), before);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn filter(field: &str, op: FilterOp, value: Value) -> FieldFilter {
+        FieldFilter { field: field.to_string(), op, value }
+    }
+
+    #[test]
+    fn numeric_equality_compares_numbers_and_numeric_strings() {
+        let stars = filter("stars", FilterOp::Eq, json!(42));
+        assert!(stars.matches(&json!({ "stars": 42 })));
+        assert!(stars.matches(&json!({ "stars": "42.0" })));
+        assert!(!stars.matches(&json!({ "stars": 41 })));
+        assert!(!stars.matches(&json!({ "name": "agent" })));
+        assert!(filter("year", FilterOp::Gt, json!("2022")).matches(&json!({ "year": 2023 })));
+    }
+
+    #[test]
+    fn string_equality_ignores_case_and_matches_any_array_element() {
+        let language = filter("language", FilterOp::Eq, json!("rust"));
+        assert!(language.matches(&json!({ "language": " Rust " })));
+        assert!(language.matches(&json!({ "language": ["Go", "RUST"] })));
+        assert!(!language.matches(&json!({ "language": "Rustacean" })));
+        assert!(!language.matches(&json!({ "language": null })));
+        assert!(filter("end_date", FilterOp::Lt, json!("2021-01-01")).matches(&json!({ "end_date": "2020-06-30" })));
+    }
+
+    #[test]
+    fn unknown_fields_are_dropped_and_known_ones_respelled() {
+        let fields = ["start_date".to_string(), "language".to_string()];
+        let kept = known_fields(
+            &[filter("Start-Date", FilterOp::Gte, json!("2022")), filter("stars", FilterOp::Eq, json!(1))],
+            &fields
+        );
+        assert_eq!(kept, [filter("start_date", FilterOp::Gte, json!("2022"))]);
+    }
+
+    #[test]
+    fn extraction_replies_parse_with_bad_entries_skipped() {
+        let reply = "```json\n{\"filters\": [{\"field\": \"language\", \"op\": \"eq\", \"value\": \"Rust\"}, {\"field\": \"x\", \"op\": \"near\", \"value\": 1}]}\n```";
+        assert_eq!(parse_filters(reply), [filter("language", FilterOp::Eq, json!("Rust"))]);
+        assert!(parse_filters("no constraints").is_empty());
+    }
+}
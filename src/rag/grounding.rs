@@ -0,0 +1,133 @@
+//! Post-answer check that a RAG answer is supported by the retrieved documents.
+
+use crate::cli::Args;
+use crate::config::prompt::{ self, PromptConfig };
+use crate::llm::chat::ChatClient;
+use serde::{ Deserialize, Serialize };
+use std::error::Error;
+use std::str::FromStr;
+
+/// Verdict of the grounding check, reported in retrieval metadata.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Grounding {
+    pub grounded: bool,
+    pub confidence: f32,
+}
+
+/// What to do with an answer whose grounding confidence is below the threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroundingAction {
+    /// Only report `grounded: false` in metadata.
+    Flag,
+    /// Append `response_templates.grounding_disclaimer` to the answer.
+    Disclaimer,
+    /// Replace the answer with `response_templates.grounding_refusal`.
+    Refuse,
+}
+
+impl FromStr for GroundingAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "flag" => Ok(GroundingAction::Flag),
+            "disclaimer" => Ok(GroundingAction::Disclaimer),
+            "refuse" => Ok(GroundingAction::Refuse),
+            other => Err(format!("Invalid grounding action '{}': expected flag, disclaimer or refuse", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GroundingPolicy {
+    pub threshold: f32,
+    pub action: GroundingAction,
+}
+
+impl GroundingPolicy {
+    /// `None` unless `--rag-grounding-check` is enabled.
+    pub fn from_args(args: &Args) -> Result<Option<Self>, Box<dyn Error + Send + Sync>> {
+        if !args.rag_grounding_check {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            threshold: args.rag_grounding_threshold.clamp(0.0, 1.0),
+            action: args.rag_grounding_action.parse()?,
+        }))
+    }
+
+    /// Runs the verification call. The answer counts as grounded only when the
+    /// model says so with at least `threshold` confidence.
+    pub async fn check(
+        &self,
+        chat_client: &dyn ChatClient,
+        config: &PromptConfig,
+        question: &str,
+        documents: &str,
+        answer: &str
+    ) -> Result<Grounding, Box<dyn Error + Send + Sync>> {
+        let check_prompt = prompt::get_grounding_check_prompt(config, documents, answer, question);
+        let resp = chat_client.complete(&check_prompt).await?;
+        let verdict = parse_grounding(&resp.response).ok_or_else(||
+            format!("Unparseable grounding check response: {}", resp.response.trim())
+        )?;
+        Ok(Grounding {
+            grounded: verdict.grounded && verdict.confidence >= self.threshold,
+            confidence: verdict.confidence,
+        })
+    }
+
+    /// Applies the configured action to an answer that failed the check.
+    pub fn apply(&self, config: &PromptConfig, answer: String) -> String {
+        match self.action {
+            GroundingAction::Flag => answer,
+            GroundingAction::Disclaimer => format!("{}\n\n{}", answer, prompt::get_grounding_disclaimer(config)),
+            GroundingAction::Refuse => prompt::get_grounding_refusal(config),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RawGrounding {
+    grounded: bool,
+    #[serde(default, alias = "score")]
+    confidence: Option<f32>,
+}
+
+/// Parses `{"grounded": true, "confidence": 0.9}`, tolerating surrounding text.
+fn parse_grounding(raw: &str) -> Option<Grounding> {
+    let trimmed = raw.trim();
+    let start = trimmed.find('{')?;
+    let end = trimmed.rfind('}')?;
+    if start >= end {
+        return None;
+    }
+    let parsed: RawGrounding = serde_json::from_str(&trimmed[start..=end]).ok()?;
+    let confidence = parsed.confidence
+        .unwrap_or(if parsed.grounded { 1.0 } else { 0.0 })
+        .clamp(0.0, 1.0);
+    Some(Grounding { grounded: parsed.grounded, confidence })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_verdicts_with_surrounding_text() {
+        let verdict = parse_grounding("Sure: {\"grounded\": false, \"score\": 0.3} done").unwrap();
+        assert!(!verdict.grounded);
+        assert_eq!(verdict.confidence, 0.3);
+
+        let verdict = parse_grounding("{\"grounded\": true, \"confidence\": 7}").unwrap();
+        assert_eq!(verdict.confidence, 1.0);
+        assert_eq!(parse_grounding("{\"grounded\": true}").unwrap().confidence, 1.0);
+        assert!(parse_grounding("grounded").is_none());
+    }
+
+    #[test]
+    fn actions_parse_case_insensitively() {
+        assert_eq!(" Refuse ".parse::<GroundingAction>(), Ok(GroundingAction::Refuse));
+        assert!("ignore".parse::<GroundingAction>().is_err());
+    }
+}
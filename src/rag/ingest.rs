@@ -0,0 +1,222 @@
+//! Document ingestion into the vector store (`POST /api/documents`).
+//!
+//! `vector_nexus::VectorStore` is read-only, so writes go through a
+//! [`DocumentWriter`] that stores points in the layout the store reads back.
+//! Only Qdrant is supported today.
+
+use crate::cli::Args;
+use async_trait::async_trait;
+use log::{ info, warn };
+use qdrant_client::Qdrant;
+use qdrant_client::qdrant::{ CreateCollection, Distance, PointId, PointStruct, UpsertPoints, VectorParams, VectorsConfig };
+use serde::{ Deserialize, Serialize };
+use serde_json::{ Map, Value as JsonValue };
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use uuid::Uuid;
+
+#[derive(Deserialize, Debug)]
+pub struct IngestDocument {
+    /// Stable id; re-ingesting the same id replaces the document. Generated when absent.
+    pub id: Option<String>,
+    pub fields: Map<String, JsonValue>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct IngestResult {
+    pub id: Option<String>,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl IngestResult {
+    pub fn ok(id: String) -> Self {
+        Self { id: Some(id), success: true, error: None }
+    }
+
+    pub fn failed(id: Option<String>, error: impl Into<String>) -> Self {
+        Self { id, success: false, error: Some(error.into()) }
+    }
+}
+
+/// An embedded document ready to be stored.
+pub struct PreparedDocument {
+    pub id: String,
+    pub vector: Vec<f32>,
+    pub fields: Map<String, JsonValue>,
+}
+
+#[async_trait]
+pub trait DocumentWriter: Send + Sync {
+    /// Rejects ids the store cannot address, before any embedding work.
+    fn validate_id(&self, id: &str) -> Result<(), String>;
+
+    /// Inserts or replaces a batch of documents in the topic's index.
+    async fn upsert(
+        &self,
+        topic: &str,
+        documents: Vec<PreparedDocument>
+    ) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+/// Writer for the configured vector store, or `None` when its type has no writer.
+pub fn create_document_writer(
+    args: &Args
+) -> Result<Option<Arc<dyn DocumentWriter>>, Box<dyn Error + Send + Sync>> {
+    match args.vector_type.to_lowercase().as_str() {
+        "qdrant" => Ok(Some(Arc::new(QdrantDocumentWriter::new(args)?))),
+        _ => Ok(None),
+    }
+}
+
+#[derive(Deserialize)]
+struct IngestSchemaFile {
+    #[serde(default)]
+    indexes: Vec<IngestIndexSchema>,
+}
+
+#[derive(Deserialize)]
+struct IngestIndexSchema {
+    name: String,
+    #[serde(default)]
+    embed_fields: Vec<String>,
+}
+
+/// Loads the per-index `embed_fields` from `INGEST_SCHEMA_PATH`.
+/// A missing file means no index declares embed fields.
+pub fn load_embed_fields(
+    path: &str
+) -> Result<HashMap<String, Vec<String>>, Box<dyn Error + Send + Sync>> {
+    if !Path::new(path).exists() {
+        info!("Ingest schema {} not found; embedding schema fields for ingestion", path);
+        return Ok(HashMap::new());
+    }
+    let text = fs::read_to_string(path)?;
+    let file: IngestSchemaFile = serde_json::from_str(&text).map_err(|e|
+        format!("Invalid ingest schema {}: {}", path, e)
+    )?;
+    let mut embed_fields = HashMap::new();
+    for index in file.indexes {
+        if index.embed_fields.is_empty() {
+            warn!("Ingest schema index '{}' declares no embed_fields; ignoring it", index.name);
+            continue;
+        }
+        embed_fields.insert(index.name, index.embed_fields);
+    }
+    Ok(embed_fields)
+}
+
+/// Errors when any of the declared `embed_fields` is absent or null in `fields`.
+pub fn check_embed_fields(fields: &Map<String, JsonValue>, embed_fields: &[String]) -> Result<(), String> {
+    let missing: Vec<&str> = embed_fields
+        .iter()
+        .filter(|name| fields.get(name.as_str()).is_none_or(JsonValue::is_null))
+        .map(String::as_str)
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("document is missing embed field(s): {}", missing.join(", ")))
+    }
+}
+
+/// Text embedded for a document: the given fields (or every string field) joined in order.
+pub fn embedding_text(fields: &Map<String, JsonValue>, text_fields: &[String]) -> String {
+    let values: Vec<String> = if text_fields.is_empty() {
+        fields.values().filter_map(|v| v.as_str().map(str::to_string)).collect()
+    } else {
+        text_fields
+            .iter()
+            .filter_map(|name| fields.get(name))
+            .map(|v| match v {
+                JsonValue::String(s) => s.clone(),
+                other => other.to_string(),
+            })
+            .collect()
+    };
+    values
+        .into_iter()
+        .filter(|v| !v.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub struct QdrantDocumentWriter {
+    client: Qdrant,
+    dimension: u64,
+    distance: Distance,
+}
+
+impl QdrantDocumentWriter {
+    pub fn new(args: &Args) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let api_key = Some(args.secret.clone()).filter(|k| !k.is_empty());
+        let client = Qdrant::from_url(&args.host).api_key(api_key).build()?;
+        let distance = match args.metric.to_lowercase().as_str() {
+            "l2" | "euclidean" => Distance::Euclid,
+            "ip" | "dot" | "dotproduct" => Distance::Dot,
+            _ => Distance::Cosine,
+        };
+        Ok(Self {
+            client,
+            dimension: args.dimension as u64,
+            distance,
+        })
+    }
+
+    fn point_id(id: &str) -> Result<PointId, String> {
+        if let Ok(num) = id.parse::<u64>() {
+            return Ok(num.into());
+        }
+        Uuid::parse_str(id)
+            .map(|uuid| uuid.to_string().into())
+            .map_err(|_| format!("id '{}' must be an unsigned integer or a UUID for Qdrant", id))
+    }
+}
+
+#[async_trait]
+impl DocumentWriter for QdrantDocumentWriter {
+    fn validate_id(&self, id: &str) -> Result<(), String> {
+        Self::point_id(id).map(|_| ())
+    }
+
+    async fn upsert(
+        &self,
+        topic: &str,
+        documents: Vec<PreparedDocument>
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if !self.client.collection_exists(topic).await? {
+            self.client.create_collection(CreateCollection {
+                collection_name: topic.to_string(),
+                vectors_config: Some(
+                    VectorsConfig::from(VectorParams {
+                        size: self.dimension,
+                        distance: self.distance.into(),
+                        ..Default::default()
+                    })
+                ),
+                ..Default::default()
+            }).await?;
+            info!("Created Qdrant collection '{}' for ingestion", topic);
+        }
+
+        let points = documents
+            .into_iter()
+            .map(|doc| {
+                let id = Self::point_id(&doc.id)?;
+                Ok(PointStruct::new(id, doc.vector, doc.fields))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        self.client.upsert_points(UpsertPoints {
+            collection_name: topic.to_string(),
+            wait: Some(true),
+            points,
+            ordering: None,
+            shard_key_selector: None,
+        }).await?;
+        Ok(())
+    }
+}
@@ -1 +1,5 @@
+#[allow(clippy::module_inception)]
 pub mod rag;
+pub mod ingest;
+pub mod grounding;
+pub mod filter;
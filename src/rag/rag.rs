@@ -1,21 +1,58 @@
 use crate::config::prompt::{ self, PromptConfig };
+use crate::rag::filter::{ self, FieldFilter };
+use crate::rag::grounding::Grounding;
 use crate::llm::chat::ChatClient;
-use crate::llm::embedding::EmbeddingClient;
+use crate::llm::embedding::{ EmbeddingClient, EmbeddingInputType };
 
-use log::info;
+use arc_swap::ArcSwap;
+use log::{ info, warn };
 use serde::{ Deserialize, Serialize };
 use serde_json::Value;
 use vector_nexus::schema::IndexSchema;
 use vector_nexus::VectorStore;
 
 use std::{ error::Error as StdError, sync::Arc };
+use std::collections::HashSet;
+use std::future::Future;
+use std::time::{ Duration, Instant };
 use std::fmt;
 use strsim;
 
+const FUZZY_MATCH_THRESHOLD: f64 = 0.85;
+/// How many times the retrieval limit is searched when filters will drop hits.
+const FILTER_OVERFETCH: usize = 4;
+
+/// Lowercase words of `text` with a trailing plural `s` dropped, so `jobs` matches `job`.
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.len() > 2)
+        .map(|w| {
+            let w = w.to_lowercase();
+            match w.strip_suffix('s') {
+                Some(stem) if stem.len() > 2 => stem.to_string(),
+                _ => w,
+            }
+        })
+}
+
+/// Lowercases and strips spaces/underscores/hyphens so `WorkExperience` and `work_experience` compare equal.
+pub fn normalize_schema_name(name: &str) -> String {
+    name.trim()
+        .trim_matches('"')
+        .to_lowercase()
+        .replace(&['_', '-', ' '][..], "")
+}
+
 #[derive(Debug, Deserialize)]
 pub struct RagQueryArgs {
     pub query: String,
     pub limit: Option<usize>,
+    /// Index to search; inferred from the query when absent.
+    #[serde(default)]
+    pub topic: Option<String>,
+    /// Field constraints on the hits; filters on fields the index lacks are ignored.
+    #[serde(default)]
+    pub filters: Vec<FieldFilter>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,6 +78,20 @@ impl fmt::Display for RagEngineError {
 
 impl StdError for RagEngineError {}
 
+/// Where a query was routed, safe to expose to clients for retrieval debugging.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetrievalMetadata {
+    pub topic: String,
+    pub fields: Vec<String>,
+    pub hit_count: usize,
+    /// Filters applied to the hits, explicit or extracted from the question.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filters: Vec<FieldFilter>,
+    /// Grounding verdict when `--rag-grounding-check` is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grounding: Option<Grounding>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Document {
     pub score: f32,
@@ -48,12 +99,39 @@ pub struct Document {
     pub content: Value,
 }
 
+/// What `RagEngine::retrieve` found for a question.
+#[derive(Debug)]
+pub enum Retrieval {
+    /// A count question: the number of documents in the routed index.
+    Count { count: usize, metadata: RetrievalMetadata },
+    /// Hits for the answer prompt, with the schema JSON the prompt describes them by.
+    Documents { documents: Vec<Document>, metadata: RetrievalMetadata, schema_json: String },
+}
+
+/// Questions about the newest entry, answered from `PromptConfig::recency_fields`.
+fn is_recency_question(question: &str) -> bool {
+    let lower = question.to_lowercase();
+    ["latest", "recent", "newest", "current"].iter().any(|word| lower.contains(word))
+}
+
+/// Questions answered by counting an index rather than searching it: "how many ...",
+/// "number of ..." or the word "count". Amounts ("total", "how much") are searched.
+fn is_count_question(question: &str) -> bool {
+    let words: Vec<String> = question
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    words.iter().any(|w| w == "count")
+        || words.windows(2).any(|pair| matches!((pair[0].as_str(), pair[1].as_str()), ("how", "many") | ("number", "of")))
+}
+
 impl fmt::Display for Document {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Document ID: {} (Score: {:.4})\n", self.id, self.score)?;
+        writeln!(f, "Document ID: {} (Score: {:.4})", self.id, self.score)?;
         if let Some(doc_obj) = self.content.as_object() {
             if doc_obj.is_empty() {
-                write!(f, "  - (No fields retrieved for this document)\n")?;
+                writeln!(f, "  - (No fields retrieved for this document)")?;
             } else {
                 for (key, value) in doc_obj {
                     if key == "vector" || 
@@ -66,58 +144,169 @@ impl fmt::Display for Document {
                         Value::String(s) => s.clone(),
                         _ => value.to_string(),
                     };
-                    write!(f, "  - {}: {}\n", key, value_str)?;
+                    writeln!(f, "  - {}: {}", key, value_str)?;
                 }
             }
         } else {
-            write!(f, "  - Document content is not a valid JSON object.\n")?;
+            writeln!(f, "  - Document content is not a valid JSON object.")?;
+        }
+        writeln!(f)
+    }
+}
+
+/// Schema- and prompt-derived inputs to retrieval. Reloads build a new one off to
+/// the side and swap it in; a request keeps the snapshot it started with.
+pub struct RagEngineState {
+    pub index_schemas: Vec<IndexSchema>,
+    pub prompt_config: Arc<PromptConfig>,
+    pub function_schema: Value,
+}
+
+impl RagEngineState {
+    /// Schema fields of a known index.
+    pub fn index_fields(&self, topic: &str) -> Option<&[String]> {
+        self.index_schemas
+            .iter()
+            .find(|s| s.name == topic)
+            .map(|s| s.fields.as_slice())
+    }
+
+    pub fn schema_json(&self) -> String {
+        serde_json::to_string(&self.index_schemas).unwrap_or_default()
+    }
+
+    /// Deterministic last resort: the schema whose name and field names share the most
+    /// words with the question. Ties go to the earlier schema; no overlap gives `None`.
+    fn topic_by_field_overlap(&self, query: &str) -> Option<String> {
+        let question_words: HashSet<String> = words(query).collect();
+        let mut best: Option<(&IndexSchema, usize)> = None;
+        for schema in &self.index_schemas {
+            let schema_words: HashSet<String> = std::iter::once(schema.name.as_str())
+                .chain(schema.fields.iter().map(String::as_str))
+                .flat_map(words)
+                .collect();
+            let overlap = schema_words.intersection(&question_words).count();
+            if overlap > best.map_or(0, |(_, score)| score) {
+                best = Some((schema, overlap));
+            }
         }
-        write!(f, "\n")
+        best.map(|(schema, _)| schema.name.clone())
+    }
+
+    /// Maps an LLM topic onto a schema name: normalized exact match first, then strsim fuzzy match.
+    fn match_schema_topic(&self, raw_topic: &str) -> Option<String> {
+        let candidate = normalize_schema_name(raw_topic);
+        if candidate.is_empty() || candidate == "none" {
+            return None;
+        }
+
+        if let Some(schema) = self.index_schemas
+            .iter()
+            .find(|s| normalize_schema_name(&s.name) == candidate)
+        {
+            return Some(schema.name.clone());
+        }
+
+        let mut best: Option<&IndexSchema> = None;
+        let mut best_score = 0.0;
+        for schema in &self.index_schemas {
+            let score = strsim::jaro_winkler(&candidate, &normalize_schema_name(&schema.name));
+            if score > best_score {
+                best_score = score;
+                best = Some(schema);
+            }
+        }
+        if best_score >= FUZZY_MATCH_THRESHOLD {
+            if let Some(schema) = best {
+                info!(
+                    "Fuzzy-matched topic '{}' to schema '{}' (score {:.3})",
+                    raw_topic,
+                    schema.name,
+                    best_score
+                );
+                return Some(schema.name.clone());
+            }
+        }
+
+        None
     }
 }
 
-#[derive(Clone)]
 pub struct RagEngine {
     vector_store: Arc<dyn VectorStore>,
     chat_client: Arc<dyn ChatClient>,
     embedding_client: Arc<dyn EmbeddingClient>,
-    _query_generation_client: Arc<dyn ChatClient>,
-    index_schemas: Vec<IndexSchema>,
-    prompt_config: Arc<PromptConfig>,
-    _function_schema: Value,
+    query_generation_client: Arc<dyn ChatClient>,
+    state: ArcSwap<RagEngineState>,
     _vector_type: String,
     rag_default_limit: usize,
+    rag_context_docs: usize,
+    rag_concurrency: usize,
+    rag_field_max_chars: usize,
+    rag_topic_retries: usize,
+    rag_deterministic_fallback: bool,
     use_llm_query: bool,
+    rag_filter_extraction: bool,
+    use_query_client_for_routing: bool,
+    classifier_max_output_chars: usize,
+    rag_min_score: Option<f32>,
 }
 
 impl RagEngine {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         vector_store: Arc<dyn VectorStore>,
         chat_client: Arc<dyn ChatClient>,
         embedding_client: Arc<dyn EmbeddingClient>,
-        _query_generation_client: Arc<dyn ChatClient>,
+        query_generation_client: Arc<dyn ChatClient>,
         index_schemas: Vec<IndexSchema>,
         prompt_config: Arc<PromptConfig>,
-        _function_schema: Value,
+        function_schema: Value,
         _vector_type: String,
         rag_default_limit: usize,
-        use_llm_query: bool
+        rag_context_docs: usize,
+        rag_concurrency: usize,
+        rag_field_max_chars: usize,
+        rag_topic_retries: usize,
+        rag_deterministic_fallback: bool,
+        use_llm_query: bool,
+        rag_filter_extraction: bool,
+        use_query_client_for_routing: bool,
+        classifier_max_output_chars: usize,
+        rag_min_score: Option<f32>
     ) -> Self {
         Self {
             vector_store,
             chat_client,
             embedding_client,
-            _query_generation_client,
-            index_schemas,
-            prompt_config,
-            _function_schema,
+            query_generation_client,
+            state: ArcSwap::from_pointee(RagEngineState { index_schemas, prompt_config, function_schema }),
             _vector_type,
             rag_default_limit,
+            rag_context_docs,
+            rag_concurrency,
+            rag_field_max_chars,
+            rag_topic_retries,
+            rag_deterministic_fallback,
             use_llm_query,
+            rag_filter_extraction,
+            use_query_client_for_routing,
+            classifier_max_output_chars,
+            rag_min_score,
         }
     }
 
-    fn format_documents_for_prompt(hits: &Vec<(f32, String, Value)>) -> String {
+    /// The current schema state; take it once and use it for the whole request.
+    pub fn state(&self) -> Arc<RagEngineState> {
+        self.state.load_full()
+    }
+
+    /// Atomically replaces the schema state. Requests already running keep the old one.
+    pub fn swap_state(&self, state: RagEngineState) {
+        self.state.store(Arc::new(state));
+    }
+
+    fn format_documents_for_prompt(&self, hits: &Vec<(f32, String, Value)>) -> String {
         if hits.is_empty() {
             return "No relevant documents found.".to_string();
         }
@@ -142,6 +331,7 @@ impl RagEngine {
                             Value::String(s) => s.clone(),
                             _ => value.to_string(),
                         };
+                        let value_str = self.truncate_field(id, key, value_str);
                         docs_text.push_str(&format!("  - {}: {}\n", key, value_str));
                     }
                 }
@@ -153,155 +343,60 @@ impl RagEngine {
         docs_text
     }
 
-    pub async fn query_and_answer(
-        &self,
-        args: RagQueryArgs,
-        user_question: &str
-    ) -> Result<String, Box<dyn StdError + Send + Sync>> {
-        let schema_json_for_inference = serde_json::to_string(&self.index_schemas)?;
-        let topic_inference_prompt = prompt::get_rag_topic_prompt(
-            &self.prompt_config,
-            &schema_json_for_inference,
-            user_question
-        )?;
-        
-        info!("--- Topic Inference Prompt ---\n{}\n-----------------------------", topic_inference_prompt);
-        
-        let topic_resp = self.chat_client.complete(&topic_inference_prompt).await?;
-        let inferred_topic = topic_resp.response.trim().trim_matches('"').to_lowercase();
-        
-        info!("--- Inferred Topic (Trimmed, No Quotes, Lowercased): '{}' ---", inferred_topic);
-        
-        let final_topic = if inferred_topic.is_empty() || 
-                           inferred_topic == "none" || 
-                           !self.index_schemas.iter().any(|s| s.name == inferred_topic) {
-            info!("Primary topic inference failed, trying fallback resolver");
-            
-            let schema_summary = self.index_schemas.iter()
-                .map(|s| format!("- {}: fields={}", s.name, s.fields.join(", ")))
-                .collect::<Vec<_>>()
-                .join("\n");
-                
-            let fallback_prompt = prompt::get_fallback_topic_prompt(
-                &self.prompt_config,
-                &schema_summary,
-                user_question
-            )?;
-            
-            let fallback_resp = self.chat_client.complete(&fallback_prompt).await?;
-            let fallback_topic = fallback_resp.response.trim().trim_matches('"').to_lowercase();
-            
-            info!("--- Fallback Topic Resolution: '{}' ---", fallback_topic);
-            
-            if fallback_topic.is_empty() || 
-               fallback_topic == "none" || 
-               !self.index_schemas.iter().any(|s| s.name == fallback_topic) {
-                return Err(Box::new(RagEngineError(
-                    "Could not determine the correct data category for your question after multiple attempts. Please try rephrasing.".into()
-                )));
-            }
-            
-            fallback_topic
-        } else {
-            inferred_topic
-        };
-        
-        let lower_q = user_question.to_lowercase();
-        if
-            (lower_q.contains("count") ||
-                lower_q.contains("total") ||
-                lower_q.contains("how many") ||
-                lower_q.contains("how much")) &&
-            !final_topic.is_empty()
-        {
-            let cnt = self.vector_store
-                .count_documents(&final_topic).await
-                .map_err(|e| Box::new(RagEngineError(format!("Count failed: {}", e))))?;
-            return Ok(cnt.to_string());
-        }
-
+    async fn embed_query(&self, query: &str) -> Result<Vec<f32>, Box<dyn StdError + Send + Sync>> {
         let embed_resp = self.embedding_client
-            .embed(&args.query).await
+            .embed_with_type(query, EmbeddingInputType::Query).await
             .map_err(|e| Box::new(RagEngineError(format!("Embedding failed: {}", e))))?;
-        let vec_f32 = embed_resp.embedding;
-        let available_fields = self.index_schemas
-            .iter()
-            .find(|s| s.name == final_topic)
-            .map(|s| s.fields.as_slice())
-            .unwrap_or(&[]);
+        Ok(embed_resp.embedding)
+    }
 
-        let selected_fields = if self.use_llm_query {
-            info!("→ Attempting to resolve dynamic fields using LLM...");
-            self.resolve_dynamic_fields(user_question, available_fields).unwrap_or_else(|| {
-                info!(
-                    "→ LLM field resolution failed or returned none, falling back to all fields."
-                );
-                available_fields.to_vec()
-            })
+    /// Runs topic resolution and query embedding, concurrently unless
+    /// `rag_concurrency` is 1, and logs the wall-clock time saved.
+    async fn resolve_topic_and_embed(
+        &self,
+        state: &RagEngineState,
+        user_question: &str,
+        query: &str
+    ) -> Result<(String, Vec<f32>), Box<dyn StdError + Send + Sync>> {
+        let started = Instant::now();
+        let topic = timed(self.infer_query_topic(state, user_question));
+        let embedding = timed(self.embed_query(query));
+        let ((final_topic, topic_elapsed), (vec_f32, embed_elapsed)) = if self.rag_concurrency > 1 {
+            tokio::try_join!(topic, embedding)?
         } else {
-            info!("→ Skipping LLM field resolution, using all available fields.");
-            available_fields.to_vec()
-        };
-
-        let mut hits = {
-            let limit = args.limit.unwrap_or(self.rag_default_limit);
-
-            info!("→ Performing search with selected fields: {:?}", selected_fields);
-
-            self.vector_store.search_hybrid(
-                &final_topic,
-                user_question,
-                &vec_f32,
-                limit,
-                Some(&selected_fields)
-            ).await?
+            (topic.await?, embedding.await?)
         };
 
-        if
-            (final_topic == "experience" ||
-                final_topic == "education" ||
-                final_topic == "portfolio") &&
-            (lower_q.contains("latest") ||
-                lower_q.contains("recent") ||
-                lower_q.contains("newest") ||
-                lower_q.contains("current"))
-        {
-            hits.sort_by(|(_, _, a), (_, _, b)| {
-                let a_date = a
-                    .get("end_date")
-                    .and_then(|d| d.as_str())
-                    .unwrap_or("0000-00-00");
-
-                let b_date = b
-                    .get("end_date")
-                    .and_then(|d| d.as_str())
-                    .unwrap_or("0000-00-00");
-
-                b_date.cmp(a_date)
-            });
-
-            if hits.len() > 1 {
-                hits = vec![hits[0].clone()];
-                info!("Filtered to latest entry by end_date");
-            }
-        }
-
-        let docs_text = Self::format_documents_for_prompt(&hits);
+        let wall = started.elapsed();
+        let sequential = topic_elapsed + embed_elapsed;
+        info!(
+            "Topic resolution {:?} + embedding {:?} took {:?} wall-clock (saved {:?})",
+            topic_elapsed,
+            embed_elapsed,
+            wall,
+            sequential.saturating_sub(wall)
+        );
+        Ok((final_topic, vec_f32))
+    }
 
-        let retrieved_topics = if hits.is_empty() {
-            "none".to_string()
-        } else {
-            final_topic.clone()
+    /// Retrieves for `args` and answers `user_question` with the RAG final prompt.
+    /// Count questions are answered with the bare count.
+    pub async fn query_and_answer(
+        &self,
+        args: RagQueryArgs,
+        user_question: &str
+    ) -> Result<(String, RetrievalMetadata), Box<dyn StdError + Send + Sync>> {
+        let (documents, metadata, schema_json) = match self.retrieve(args).await? {
+            Retrieval::Count { count, metadata } => return Ok((count.to_string(), metadata)),
+            Retrieval::Documents { documents, metadata, schema_json } => (documents, metadata, schema_json),
         };
-
-        let schema_json_for_answer = serde_json
-            ::to_string_pretty(&self.index_schemas)
-            .map_err(|e| Box::new(RagEngineError(format!("Schema JSON error for answer: {}", e))))?;
+        let docs_text = self.format_documents(&documents);
+        let retrieved_topic = if documents.is_empty() { "none" } else { metadata.topic.as_str() };
 
         let final_prompt = prompt::get_rag_final_prompt(
-            &self.prompt_config,
-            &schema_json_for_answer,
-            &retrieved_topics,
+            &self.state().prompt_config,
+            &schema_json,
+            retrieved_topic,
             &docs_text,
             user_question
         )?;
@@ -312,117 +407,159 @@ impl RagEngine {
             .complete(&final_prompt).await
             .map_err(|e| Box::new(RagEngineError(format!("Final completion failed: {}", e))))?;
 
-        Ok(answer_resp.response)
+        Ok((answer_resp.response, metadata))
+    }
+
+    async fn infer_query_topic(
+        &self,
+        state: &RagEngineState,
+        query: &str
+    ) -> Result<String, Box<dyn StdError + Send + Sync>> {
+        if state.index_schemas.is_empty() {
+            // Not a question the user can rephrase: the store itself has nothing to search.
+            return Err(
+                Box::new(RagEngineError(
+                    "No indexes exist in the vector store, so there is no data to search. Check the vector store configuration or ingest documents.".into()
+                ))
+            );
+        }
+        let schema_json_for_inference = serde_json::to_string(&state.index_schemas)?;
+        let attempts = self.rag_topic_retries + 1;
+        for attempt in 1..=attempts {
+            if attempt > 1 {
+                info!("Retrying topic inference (attempt {}/{})", attempt, attempts);
+            }
+            if let Some(topic) = self.infer_topic_with_llm(state, query, &schema_json_for_inference).await? {
+                return Ok(topic);
+            }
+        }
+
+        if self.rag_deterministic_fallback {
+            if let Some(topic) = state.topic_by_field_overlap(query) {
+                info!("LLM topic inference failed; picked '{}' by field overlap with the question", topic);
+                return Ok(topic);
+            }
+            info!("LLM topic inference failed and no index shares words with the question");
+        }
+        Err(
+            Box::new(RagEngineError(
+                "Could not determine the correct data category for your question after multiple attempts. Please try rephrasing.".into()
+            ))
+        )
     }
 
-    async fn infer_query_topic(&self, query: &str) -> Result<String, Box<dyn StdError + Send + Sync>> {
-        let schema_json_for_inference = serde_json::to_string(&self.index_schemas)?;
+    /// One pass of primary inference then the fallback resolver. `None` if neither names a schema topic.
+    async fn infer_topic_with_llm(
+        &self,
+        state: &RagEngineState,
+        query: &str,
+        schema_json_for_inference: &str
+    ) -> Result<Option<String>, Box<dyn StdError + Send + Sync>> {
         let topic_inference_prompt = prompt::get_rag_topic_prompt(
-            &self.prompt_config,
-            &schema_json_for_inference,
+            &state.prompt_config,
+            schema_json_for_inference,
             query
         )?;
         
         info!("--- Topic Inference Prompt ---\n{}\n-----------------------------", topic_inference_prompt);
         
-        let topic_resp = self.chat_client.complete(&topic_inference_prompt).await?;
-        let inferred_topic = topic_resp.response.trim().trim_matches('"').to_lowercase();
+        let (routing_client, client_name) = self.routing_client();
+        info!("Topic inference handled by the {} client", client_name);
+        let topic_resp = routing_client.complete(&topic_inference_prompt).await?;
+        let inferred_topic = prompt::cap_classifier_output(&topic_resp.response, self.classifier_max_output_chars, "Topic")
+            .trim()
+            .trim_matches('"')
+            .to_string();
         
-        info!("--- Inferred Topic (Trimmed, No Quotes, Lowercased): '{}' ---", inferred_topic);
+        info!("--- Inferred Topic (Trimmed, No Quotes): '{}' ---", inferred_topic);
         
-        if inferred_topic.is_empty() || 
-           inferred_topic == "none" || 
-           !self.index_schemas.iter().any(|s| s.name == inferred_topic) {
-            info!("Primary topic inference failed, trying fallback resolver");
-            
-            let schema_summary = self.index_schemas.iter()
-                .map(|s| format!("- {}: fields={}", s.name, s.fields.join(", ")))
-                .collect::<Vec<_>>()
-                .join("\n");
-                
-            let fallback_prompt = prompt::get_fallback_topic_prompt(
-                &self.prompt_config,
-                &schema_summary,
-                query
-            )?;
-            
-            let fallback_resp = self.chat_client.complete(&fallback_prompt).await?;
-            let fallback_topic = fallback_resp.response.trim().trim_matches('"').to_lowercase();
-            
-            info!("--- Fallback Topic Resolution: '{}' ---", fallback_topic);
-            
-            if fallback_topic.is_empty() || 
-               fallback_topic == "none" || 
-               !self.index_schemas.iter().any(|s| s.name == fallback_topic) {
-                return Err(Box::new(RagEngineError(
-                    "Could not determine the correct data category for your question after multiple attempts. Please try rephrasing.".into()
-                )));
-            }
+        if let Some(topic) = state.match_schema_topic(&inferred_topic) {
+            return Ok(Some(topic));
+        }
+        info!("Primary topic inference failed, trying fallback resolver");
+        
+        let schema_summary = state.index_schemas.iter()
+            .map(|s| format!("- {}: fields={}", s.name, s.fields.join(", ")))
+            .collect::<Vec<_>>()
+            .join("\n");
             
-            Ok(fallback_topic)
+        let fallback_prompt = prompt::get_fallback_topic_prompt(
+            &state.prompt_config,
+            &schema_summary,
+            query
+        )?;
+        
+        let fallback_resp = routing_client.complete(&fallback_prompt).await?;
+        let fallback_topic = prompt::cap_classifier_output(&fallback_resp.response, self.classifier_max_output_chars, "Fallback topic")
+            .trim()
+            .trim_matches('"')
+            .to_string();
+        
+        info!("--- Fallback Topic Resolution: '{}' ---", fallback_topic);
+        
+        Ok(state.match_schema_topic(&fallback_topic))
+    }
+
+    /// Client for topic inference, with its name for logging.
+    fn routing_client(&self) -> (&Arc<dyn ChatClient>, &'static str) {
+        if self.use_query_client_for_routing {
+            (&self.query_generation_client, "query generation")
         } else {
-            Ok(inferred_topic)
+            (&self.chat_client, "chat")
         }
     }
 
-    async fn retrieve_documents(&self, args: &RagQueryArgs, topic: &str) -> Result<Vec<Document>, Box<dyn StdError + Send + Sync>> {
-        let embed_resp = self.embedding_client
-            .embed(&args.query).await
-            .map_err(|e| Box::new(RagEngineError(format!("Embedding failed: {}", e))))?;
-        let vec_f32 = embed_resp.embedding;
-        
-        let available_fields = self.index_schemas
-            .iter()
-            .find(|s| s.name == topic)
-            .map(|s| s.fields.as_slice())
-            .unwrap_or(&[]);
+    async fn retrieve_documents(
+        &self,
+        state: &RagEngineState,
+        args: &RagQueryArgs,
+        topic: &str,
+        vec_f32: &[f32]
+    ) -> Result<(Vec<Document>, Vec<String>, Vec<FieldFilter>), Box<dyn StdError + Send + Sync>> {
+        let available_fields = state.index_fields(topic).unwrap_or(&[]);
+
+        let mut filters = filter::known_fields(&args.filters, available_fields);
+        if args.filters.is_empty() && self.rag_filter_extraction {
+            filters = self.extract_filters(state, &args.query, available_fields).await;
+        }
 
         let selected_fields = if self.use_llm_query {
             info!("→ Attempting to resolve dynamic fields using LLM...");
-            self.resolve_dynamic_fields(&args.query, available_fields).unwrap_or_else(|| {
-                info!("→ LLM field resolution failed or returned none, falling back to all fields.");
-                available_fields.to_vec()
-            })
+            match self.select_fields_with_llm(state, &args.query, topic, available_fields).await {
+                Some(fields) => fields,
+                None =>
+                    self.resolve_dynamic_fields(&args.query, available_fields).unwrap_or_else(|| {
+                        info!("→ LLM field resolution failed or returned none, falling back to all fields.");
+                        available_fields.to_vec()
+                    }),
+            }
         } else {
             info!("→ Skipping LLM field resolution, using all available fields.");
             available_fields.to_vec()
         };
 
-        let mut hits = {
-            let limit = args.limit.unwrap_or(self.rag_default_limit);
-
-            info!("→ Performing search with selected fields: {:?}", selected_fields);
-
-            self.vector_store.search_hybrid(
-                topic,
-                &args.query,
-                &vec_f32,
-                limit,
-                Some(&selected_fields)
-            ).await?
-        };
-
-        let lower_q = args.query.to_lowercase();
-        if (topic == "experience" || topic == "education" || topic == "portfolio") && 
-           (lower_q.contains("latest") || lower_q.contains("recent") || 
-            lower_q.contains("newest") || lower_q.contains("current")) {
-            hits.sort_by(|(_, _, a), (_, _, b)| {
-                let a_date = a
-                    .get("end_date")
-                    .and_then(|d| d.as_str())
-                    .unwrap_or("0000-00-00");
-
-                let b_date = b
-                    .get("end_date")
-                    .and_then(|d| d.as_str())
-                    .unwrap_or("0000-00-00");
-
-                b_date.cmp(a_date)
-            });
-
-            if hits.len() > 1 {
-                hits = vec![hits[0].clone()];
-                info!("Filtered to latest entry by end_date");
+        let limit = args.limit.unwrap_or(self.rag_default_limit);
+        // Filters run on the returned hits, so search deeper to leave enough after them.
+        let search_limit = if filters.is_empty() { limit } else { limit.saturating_mul(FILTER_OVERFETCH) };
+        info!("→ Performing search with selected fields: {:?}", selected_fields);
+        let mut hits = self.vector_store.search_hybrid(
+            topic,
+            &args.query,
+            vec_f32,
+            search_limit,
+            Some(&selected_fields)
+        ).await?;
+        self.apply_min_score(&mut hits);
+        filter::apply(&filters, &mut hits);
+        hits.truncate(limit);
+
+        if let Some(date_field) = state.prompt_config.recency_fields.get(topic) {
+            if is_recency_question(&args.query) && hits.len() > 1 {
+                // ISO dates order as strings; entries without the field sort last.
+                let date = |doc: &Value| doc.get(date_field).and_then(|d| d.as_str()).unwrap_or("").to_string();
+                hits.sort_by_key(|(_, _, doc)| std::cmp::Reverse(date(doc)));
+                hits.truncate(1);
+                info!("Filtered to latest entry by {}", date_field);
             }
         }
 
@@ -430,21 +567,181 @@ impl RagEngine {
             .map(|(score, id, content)| Document { score, id, content })
             .collect();
             
-        Ok(documents)
+        Ok((documents, selected_fields, filters))
     }
 
-    pub async fn get_documents_for_query(
-        &self, 
+    /// Retrieval for an answer prompt. Count questions without an explicit topic or filters resolve
+    /// only the topic and count its documents; anything else is searched, with the hits
+    /// capped by `rag_context_docs`.
+    pub async fn retrieve(&self, args: RagQueryArgs) -> Result<Retrieval, Box<dyn StdError + Send + Sync>> {
+        let state = self.state();
+        if args.topic.is_none() && args.filters.is_empty() && is_count_question(&args.query) {
+            // Counts only need the topic, so skip the embedding.
+            let topic = self.infer_query_topic(&state, &args.query).await?;
+            let count = self.vector_store
+                .count_documents(&topic).await
+                .map_err(|e| Box::new(RagEngineError(format!("Count failed: {}", e))))?;
+            info!("Count question answered with {} documents in '{}'", count, topic);
+            let metadata = RetrievalMetadata { topic, fields: Vec::new(), hit_count: 0, filters: Vec::new(), grounding: None };
+            return Ok(Retrieval::Count { count, metadata });
+        }
+        let (mut documents, mut metadata) = self.search_in(&state, args).await?;
+        self.limit_context_docs(&mut documents);
+        metadata.hit_count = documents.len();
+        Ok(Retrieval::Documents { documents, metadata, schema_json: state.schema_json() })
+    }
+
+    /// Retrieval only: resolves the topic (unless `args.topic` names one), embeds the
+    /// query and returns every hit up to the limit, without the prompt context cap.
+    pub async fn search(
+        &self,
         args: RagQueryArgs
-    ) -> Result<(Vec<Document>, String, String), Box<dyn StdError + Send + Sync>> {
-        let topic = self.infer_query_topic(&args.query).await?;
-        let documents = self.retrieve_documents(&args, &topic).await?;
-        let schema_json = self.get_schema_json();
-        Ok((documents, topic, schema_json))
+    ) -> Result<(Vec<Document>, RetrievalMetadata), Box<dyn StdError + Send + Sync>> {
+        self.search_in(&self.state(), args).await
+    }
+
+    async fn search_in(
+        &self,
+        state: &RagEngineState,
+        args: RagQueryArgs
+    ) -> Result<(Vec<Document>, RetrievalMetadata), Box<dyn StdError + Send + Sync>> {
+        let (topic, vec_f32) = match args.topic.as_deref().filter(|t| !t.trim().is_empty()) {
+            Some(topic) => {
+                if state.index_fields(topic).is_none() {
+                    return Err(Box::new(RagEngineError(format!("Unknown topic '{}'", topic))));
+                }
+                (topic.to_string(), self.embed_query(&args.query).await?)
+            }
+            None => self.resolve_topic_and_embed(state, &args.query, &args.query).await?,
+        };
+        let (documents, fields, filters) = self.retrieve_documents(state, &args, &topic, &vec_f32).await?;
+        let metadata = RetrievalMetadata { topic, fields, hit_count: documents.len(), filters, grounding: None };
+        Ok((documents, metadata))
+    }
+
+    /// Schema fields of a known index, from the current state.
+    pub fn index_fields(&self, topic: &str) -> Option<Vec<String>> {
+        self.state().index_fields(topic).map(|f| f.to_vec())
+    }
+
+    /// Formats retrieved documents for the answer prompt, applying `rag_field_max_chars`.
+    pub fn format_documents(&self, documents: &[Document]) -> String {
+        let hits = documents
+            .iter()
+            .map(|doc| (doc.score, doc.id.clone(), doc.content.clone()))
+            .collect::<Vec<_>>();
+        self.format_documents_for_prompt(&hits)
+    }
+
+    /// Cuts a field value to `rag_field_max_chars` characters plus an ellipsis (0 disables).
+    fn truncate_field(&self, doc_id: &str, key: &str, value: String) -> String {
+        let max = self.rag_field_max_chars;
+        if max == 0 {
+            return value;
+        }
+        match value.char_indices().nth(max) {
+            Some((cut, _)) => {
+                info!(
+                    "Truncated field '{}' of document {} from {} to {} chars",
+                    key,
+                    doc_id,
+                    value.chars().count(),
+                    max
+                );
+                format!("{}…", &value[..cut])
+            }
+            None => value,
+        }
+    }
+
+    /// Drops hits scoring below `rag_min_score`. When none is left the query is answered as
+    /// having no results.
+    fn apply_min_score(&self, hits: &mut Vec<(f32, String, Value)>) {
+        let Some(min_score) = self.rag_min_score else {
+            return;
+        };
+        let total = hits.len();
+        hits.retain(|(score, _, _)| *score >= min_score);
+        let dropped = total - hits.len();
+        if dropped == 0 {
+            return;
+        }
+        if hits.is_empty() {
+            info!("All {} hits scored below RAG_MIN_SCORE {}; treating the query as having no results", total, min_score);
+        } else {
+            info!("Dropped {} of {} hits scoring below RAG_MIN_SCORE {}", dropped, total, min_score);
+        }
+    }
+
+    /// Keeps only the top `rag_context_docs` hits for the prompt (0 keeps all).
+    fn limit_context_docs<T>(&self, hits: &mut Vec<T>) {
+        if self.rag_context_docs > 0 && hits.len() > self.rag_context_docs {
+            info!("Using top {} of {} hits in the prompt", self.rag_context_docs, hits.len());
+            hits.truncate(self.rag_context_docs);
+        }
     }
 
     pub fn get_schema_json(&self) -> String {
-        serde_json::to_string(&self.index_schemas).unwrap_or_default()
+        self.state().schema_json()
+    }
+
+    /// Asks the query generation client which of `available_fields` the question needs,
+    /// using the `rag_dynamic_query_generation` template. `None` when the call fails or
+    /// the reply names no known field, so the caller can fall back to string matching.
+    async fn select_fields_with_llm(
+        &self,
+        state: &RagEngineState,
+        user_question: &str,
+        topic: &str,
+        available_fields: &[String]
+    ) -> Option<Vec<String>> {
+        if available_fields.is_empty() {
+            return None;
+        }
+        let fields_json = serde_json::to_string(available_fields).ok()?;
+        let field_prompt = match prompt::get_rag_field_selection_prompt(&state.prompt_config, topic, &fields_json, user_question) {
+            Ok(field_prompt) => field_prompt,
+            Err(e) => {
+                warn!("Cannot build the field selection prompt: {}", e);
+                return None;
+            }
+        };
+        let reply = match self.query_generation_client.complete(&field_prompt).await {
+            Ok(resp) => resp.response,
+            Err(e) => {
+                warn!("Field selection by the query generation client failed: {}", e);
+                return None;
+            }
+        };
+        let fields = parse_selected_fields(&reply, available_fields);
+        match &fields {
+            Some(fields) => info!("→ LLM selected fields: {:?}", fields),
+            None => info!("→ LLM field selection named no known field: '{}'", reply.trim()),
+        }
+        fields
+    }
+
+    /// Asks the query generation client for the field constraints in the question,
+    /// using `query_templates.rag_filter_extraction`. Failures and unknown fields
+    /// leave the search unfiltered.
+    async fn extract_filters(
+        &self,
+        state: &RagEngineState,
+        user_question: &str,
+        available_fields: &[String]
+    ) -> Vec<FieldFilter> {
+        if available_fields.is_empty() {
+            return Vec::new();
+        }
+        let fields_json = serde_json::to_string(available_fields).unwrap_or_default();
+        let filter_prompt = prompt::get_rag_filter_extraction_prompt(&state.prompt_config, &fields_json, user_question);
+        match self.query_generation_client.complete(&filter_prompt).await {
+            Ok(resp) => filter::known_fields(&filter::parse_filters(&resp.response), available_fields),
+            Err(e) => {
+                warn!("Filter extraction by the query generation client failed: {}", e);
+                Vec::new()
+            }
+        }
     }
 
     fn resolve_dynamic_fields(
@@ -487,10 +784,78 @@ impl RagEngine {
                 best = Some(f);
             }
         }
-        if best_score >= 0.85 {
+        if best_score >= FUZZY_MATCH_THRESHOLD {
             return best.map(|f| vec![f.clone()]);
         }
 
         None
     }
 }
+
+/// Field names from a field selection reply, which is either the template's
+/// `{"arguments":{"fields":[...]}}` object, a JSON array or a comma-separated list.
+/// Names are matched to `available_fields` ignoring case, underscores and hyphens;
+/// unknown names are dropped. `None` if no name matches.
+fn parse_selected_fields(reply: &str, available_fields: &[String]) -> Option<Vec<String>> {
+    let reply = reply.trim();
+    let json_fields = reply
+        .find(['{', '['])
+        // A streaming read tolerates text after the JSON, such as a closing code fence.
+        .and_then(|start| serde_json::Deserializer::from_str(&reply[start..]).into_iter::<Value>().next()?.ok())
+        .and_then(|value| {
+            let list = value.pointer("/arguments/fields").or_else(|| value.get("fields")).unwrap_or(&value);
+            list.as_array().map(|items| {
+                items.iter().filter_map(|item| item.as_str().map(str::to_string)).collect::<Vec<_>>()
+            })
+        });
+    let candidates = json_fields.unwrap_or_else(|| {
+        reply
+            .split([',', '\n'])
+            .map(|name| name.trim().trim_matches(|c: char| c == '"' || c == '\'' || c == '`').to_string())
+            .collect()
+    });
+
+    let mut selected: Vec<String> = Vec::new();
+    for candidate in candidates {
+        let wanted = normalize_schema_name(&candidate);
+        if let Some(field) = available_fields.iter().find(|f| normalize_schema_name(f) == wanted) {
+            if !selected.contains(field) {
+                selected.push(field.clone());
+            }
+        }
+    }
+    (!selected.is_empty()).then_some(selected)
+}
+
+async fn timed<T, E>(fut: impl Future<Output = Result<T, E>>) -> Result<(T, Duration), E> {
+    let started = Instant::now();
+    let value = fut.await?;
+    Ok((value, started.elapsed()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_questions_need_count_phrasing() {
+        assert!(is_count_question("How many jobs have I had?"));
+        assert!(is_count_question("count my skills"));
+        assert!(is_count_question("What is the number of projects?"));
+        assert!(!is_count_question("What was the total budget?"));
+        assert!(!is_count_question("How much did it cost?"));
+        assert!(!is_count_question("Which country and account?"));
+    }
+
+    #[test]
+    fn selected_fields_are_matched_to_the_index() {
+        let available = ["title".to_string(), "description".to_string(), "start_date".to_string()];
+        assert_eq!(parse_selected_fields("title, description", &available).unwrap(), ["title", "description"]);
+        assert_eq!(
+            parse_selected_fields("```json\n{\"arguments\": {\"fields\": [\"Start-Date\", \"url\"]}}\n```", &available).unwrap(),
+            ["start_date"]
+        );
+        assert_eq!(parse_selected_fields("[\"TITLE\", \"title\"]", &available).unwrap(), ["title"]);
+        assert!(parse_selected_fields("none of them", &available).is_none());
+    }
+}
@@ -1,19 +1,41 @@
-use crate::agent::AIAgent;
+use crate::agent::{AIAgent, MessageOptions};
 use crate::cli::Args;
+use crate::config::postprocess::PostprocessStream;
+use crate::error::AgentError;
+use crate::jobs::JobStore;
+use crate::llm::circuit_breaker::{ self, BreakerState };
+use crate::metrics;
+use crate::rag::filter::FieldFilter;
+use crate::rag::ingest::{ IngestDocument, IngestResult };
+use crate::rag::rag::RetrievalMetadata;
+use crate::server::error::{ self as api_error, ApiError };
+use crate::server::shutdown::Shutdown;
+use crate::server::streams::ConversationStreams;
+use crate::server::thinking::{ Segment, ThinkingSplitter };
+use crate::server::websocket;
 use std::error::Error;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
 use axum::{
-    routing::get,
+    extract::DefaultBodyLimit,
+    routing::{get, patch, post},
     Router,
-    extract::{State, Query},
+    extract::{State, Query, Path},
     response::IntoResponse,
-    http::StatusCode,
+    response::sse::{ Event, KeepAlive, Sse },
+    http::{ header, HeaderMap, StatusCode },
 };
 use serde::{Deserialize, Serialize};
+use axum_server::tls_rustls::RustlsConfig;
+use tower_http::catch_panic::CatchPanicLayer;
 use tower_http::cors::{Any, CorsLayer};
-use log::{info, error};
+use tower_http::limit::RequestBodyLimitLayer;
+use tower_http::timeout::TimeoutLayer;
+use log::{info, error, warn};
+use uuid::Uuid;
+use futures::StreamExt;
 
 #[derive(Deserialize)]
 pub struct ReloadRequest {
@@ -27,23 +49,179 @@ struct ReloadResponse {
     details: Option<Vec<String>>,
 }
 
+#[derive(Deserialize)]
+pub struct JobRequest {
+    pub content: String,
+    pub conversation_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RawChatQuery {
+    pub content: String,
+    pub conversation_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ChatRequest {
+    pub message: String,
+    /// Conversation to continue; a new one is started when absent.
+    pub conversation_id: Option<String>,
+    /// Number of RAG results to retrieve, clamped to `--rag-max-limit`.
+    pub rag_limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct ChatResponse {
+    conversation_id: String,
+    response: String,
+    thinking: String,
+    /// Retrieval details when the answer came from RAG.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<RetrievalMetadata>,
+}
+
+#[derive(Deserialize)]
+pub struct IngestRequest {
+    pub topic: String,
+    pub documents: Vec<IngestDocument>,
+    /// Fields embedded for each document; defaults to the topic's schema fields.
+    pub text_fields: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+pub struct SearchRequest {
+    pub query: String,
+    /// Index to search; inferred from the query when absent.
+    pub topic: Option<String>,
+    pub limit: Option<usize>,
+    /// Field constraints; filters on fields the index lacks are ignored.
+    #[serde(default)]
+    pub filters: Vec<FieldFilter>,
+}
+
+#[derive(Serialize)]
+struct SearchHit {
+    id: String,
+    score: f32,
+    fields: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct SearchResponse {
+    topic: String,
+    results: Vec<SearchHit>,
+}
+
+#[derive(Deserialize)]
+pub struct ConversationListQuery {
+    pub limit: Option<usize>,
+}
+
+/// Fields to replace; absent fields are left unchanged.
+#[derive(Deserialize)]
+pub struct ConversationUpdate {
+    pub title: Option<String>,
+    pub tags: Option<Vec<String>>,
+}
+
+const DEFAULT_CONVERSATION_LIST_LIMIT: usize = 50;
+const MAX_CONVERSATION_LIST_LIMIT: usize = 500;
+
+#[derive(Serialize)]
+struct IngestResponse {
+    success: bool,
+    topic: String,
+    indexed: usize,
+    failed: usize,
+    results: Vec<IngestResult>,
+}
+
 #[derive(Clone)]
 struct AppState {
     agent: Arc<Mutex<AIAgent>>,
     args: Args,
+    streams: Arc<ConversationStreams>,
+}
+
+/// State of the job routes, registered only with `--jobs-enabled`.
+#[derive(Clone)]
+struct JobsState {
+    jobs: Arc<JobStore>,
 }
 
 pub async fn start_http_server(
     http_port: u16,
     agent: Arc<Mutex<AIAgent>>,
     args: Args,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
+    streams: Arc<ConversationStreams>,
+    shutdown: Shutdown,
+) -> Result<tokio::task::JoinHandle<()>, Box<dyn Error + Send + Sync>> {
     let addr = format!("0.0.0.0:{}", http_port).parse::<SocketAddr>()?;
     info!("Starting HTTP API server on: http://{}", addr);
+    let app = build_app(agent, &args, streams, None)?;
+    let tls = tls_config(&args).await?;
+
+    // The handle finishes once the server has drained after a shutdown.
+    Ok(tokio::spawn(async move {
+        if let Err(e) = serve(addr, app, tls, shutdown).await {
+            error!("HTTP server error: {}", e);
+        }
+    }))
+}
+
+/// Serves the HTTP API and WebSocket upgrades on `WS_PATH` from one listener at
+/// `addr` (`--unified-server`). Runs until the server stops.
+pub async fn serve_unified(
+    addr: &str,
+    agent: Arc<Mutex<AIAgent>>,
+    args: Args,
+    streams: Arc<ConversationStreams>,
+    shutdown: Shutdown,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if !args.ws_path.starts_with('/') || args.ws_path.starts_with("/api/") {
+        return Err(format!("WS_PATH '{}' must start with '/' and not be under /api/", args.ws_path).into());
+    }
+    let addr = tokio::net::lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| format!("SERVER_ADDR '{}' did not resolve to an address", addr))?;
+    info!("Starting unified HTTP and WebSocket server on {} (WebSocket path {})", addr, args.ws_path);
+    if args.http_port.is_some() {
+        info!("HTTP_PORT is ignored in unified mode; the HTTP API is served on SERVER_ADDR");
+    }
+
+    let upgrade = websocket::UpgradeContext::new(agent.clone(), &args, streams.clone(), shutdown.clone());
+    let app = build_app(agent, &args, streams, Some(upgrade))?;
+    let tls = tls_config(&args).await?;
+    serve(addr, app, tls, shutdown.clone()).await?;
+    // Upgraded WebSocket connections are no longer tracked by the HTTP server.
+    shutdown.drain().await;
+    Ok(())
+}
+
+/// The HTTP API router, plus the WebSocket route when `upgrade` is given.
+fn build_app(
+    agent: Arc<Mutex<AIAgent>>,
+    args: &Args,
+    streams: Arc<ConversationStreams>,
+    upgrade: Option<websocket::UpgradeContext>,
+) -> Result<Router, Box<dyn Error + Send + Sync>> {
+    if args.http_max_body_bytes == 0 || args.http_request_timeout_secs == 0 {
+        return Err("HTTP_MAX_BODY_BYTES and HTTP_REQUEST_TIMEOUT_SECS must be greater than 0".into());
+    }
+
+    let jobs = if args.jobs_enabled {
+        let jobs = Arc::new(JobStore::new(args)?);
+        jobs.clone().spawn_worker(agent.clone(), streams.clone());
+        Some(JobsState { jobs })
+    } else {
+        None
+    };
 
     let app_state = AppState {
         agent,
         args: args.clone(),
+        streams,
     };
 
     let cors = CorsLayer::new()
@@ -51,49 +229,98 @@ pub async fn start_http_server(
         .allow_methods(Any)
         .allow_headers(Any);
 
-    let app = Router::new()
+    // Every /api route needs a client key; probes and metrics stay open.
+    let auth = axum::middleware::from_fn_with_state(args.clone(), require_client_auth);
+    let mut api = Router::new()
         .route("/api/reload-prompts", get(reload_prompts_handler))
-        .layer(cors)
-        .with_state(app_state);
-
-    if args.enable_tls && args.tls_cert_path.is_some() && args.tls_key_path.is_some() {
-        let cert_path = args.tls_cert_path.as_ref().unwrap();
-        let key_path = args.tls_key_path.as_ref().unwrap();
-        
-        let tls_config = axum_server::tls_rustls::RustlsConfig::from_pem_file(
-            cert_path,
-            key_path
-        ).await?;
-
-        tokio::spawn(async move {
-            let result = axum_server::bind_rustls(addr, tls_config)
-                .serve(app.into_make_service())
-                .await;
-
-            if let Err(e) = result {
-                error!("HTTPS server error: {}", e);
-            }
-        });
+        .route("/api/chat", post(chat_handler))
+        .route("/api/chat/raw", get(raw_chat_handler))
+        .route("/api/chat/stream", get(chat_stream_handler))
+        .route("/api/documents", post(ingest_documents_handler))
+        .route("/api/search", post(search_handler))
+        .route("/api/conversations", get(list_conversations_handler))
+        .route("/api/conversations/{id}", patch(update_conversation_handler).delete(delete_conversation_handler))
+        .with_state(app_state.clone());
+    if let Some(jobs) = jobs {
+        api = api.merge(
+            Router::new()
+                .route("/api/jobs", post(create_job_handler))
+                .route("/api/jobs/{id}", get(get_job_handler))
+                .with_state(jobs)
+        );
+    }
 
-        info!("HTTPS server started with TLS enabled");
-    } else {
+    let mut app = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .route("/healthz", get(healthz_handler))
+        .route("/readyz", get(readyz_handler))
+        .with_state(app_state)
+        .merge(api.route_layer(auth))
+        .fallback(api_error::not_found_handler);
+    if let Some(upgrade) = upgrade {
+        app = app.merge(
+            Router::new()
+                .route(&args.ws_path, get(websocket::upgrade_handler))
+                .with_state(upgrade)
+        );
+    }
 
-        tokio::spawn(async move {
-            match tokio::net::TcpListener::bind(addr).await {
-                Ok(listener) => {
-                    if let Err(e) = axum::serve(listener, app.into_make_service()).await {
-                        error!("HTTP server error: {}", e);
-                    }
-                },
-                Err(e) => {
-                    error!("Failed to bind HTTP server to {}: {}. Try a different port.", addr, e);
-                }
-            }
-        });
+    Ok(app
+        // Replaces axum's 2 MB extractor default so HTTP_MAX_BODY_BYTES is the only limit.
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(args.http_max_body_bytes))
+        .layer(TimeoutLayer::new(Duration::from_secs(args.http_request_timeout_secs)))
+        .layer(axum::middleware::map_response(api_error::json_error_envelope))
+        .layer(CatchPanicLayer::custom(api_error::handle_panic))
+        .layer(cors))
+}
 
-        info!("HTTP server started");
+/// TLS settings shared with the WebSocket server, when `--enable-tls` has a certificate and key.
+async fn tls_config(args: &Args) -> Result<Option<RustlsConfig>, Box<dyn Error + Send + Sync>> {
+    match (args.enable_tls, args.tls_cert_path.as_ref(), args.tls_key_path.as_ref()) {
+        (true, Some(cert_path), Some(key_path)) => Ok(Some(RustlsConfig::from_pem_file(cert_path, key_path).await?)),
+        _ => Ok(None),
     }
+}
 
+async fn serve(
+    addr: SocketAddr,
+    app: Router,
+    tls: Option<RustlsConfig>,
+    shutdown: Shutdown,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Peer addresses are needed by the WebSocket route for rate limiting and logs.
+    let service = app.into_make_service_with_connect_info::<SocketAddr>();
+    match tls {
+        Some(tls_config) => {
+            let handle = axum_server::Handle::new();
+            tokio::spawn({
+                let handle = handle.clone();
+                async move {
+                    shutdown.triggered().await;
+                    handle.graceful_shutdown(Some(shutdown.grace()));
+                }
+            });
+            info!("HTTPS server started with TLS enabled");
+            axum_server::bind_rustls(addr, tls_config).handle(handle).serve(service).await?;
+        }
+        None => {
+            let listener = tokio::net::TcpListener::bind(addr).await.map_err(|e|
+                format!("Failed to bind HTTP server to {}: {}. Try a different port.", addr, e)
+            )?;
+            info!("HTTP server started");
+            let server = axum::serve(listener, service).with_graceful_shutdown({
+                let shutdown = shutdown.clone();
+                async move { shutdown.triggered().await }
+            });
+            // Graceful shutdown waits for every open request; the grace period bounds it.
+            tokio::select! {
+                result = server => result?,
+                _ = shutdown.grace_elapsed() => warn!("HTTP requests still open after the shutdown grace period; dropping them"),
+            }
+        }
+    }
+    info!("HTTP server stopped");
     Ok(())
 }
 
@@ -101,7 +328,7 @@ async fn reload_prompts_handler(
     State(state): State<AppState>,
     Query(req): Query<ReloadRequest>,
 ) -> impl IntoResponse {
-    let mut agent = match state.agent.try_lock() {
+    let agent = match state.agent.try_lock() {
         Ok(g) => g,
         Err(_) => return (StatusCode::SERVICE_UNAVAILABLE, axum::Json(ReloadResponse {
             success: false,
@@ -157,4 +384,847 @@ async fn reload_prompts_handler(
         message: if ok { "Reload complete".into() } else { "Reload errors".into() },
         details: Some(results),
     })).into_response()
-}
\ No newline at end of file
+}
+/// Checks `Authorization: Bearer <ADMIN_TOKEN>`; admin endpoints are off without a token.
+fn require_admin(headers: &HeaderMap, args: &Args) -> Result<(), ApiError> {
+    let Some(expected) = args.admin_token.as_deref().filter(|t| !t.is_empty()) else {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "Admin endpoints are disabled; set ADMIN_TOKEN to enable them"));
+    };
+    if bearer_matches(headers, expected) {
+        Ok(())
+    } else {
+        Err(ApiError::new(StatusCode::UNAUTHORIZED, "Invalid or missing admin token"))
+    }
+}
+
+/// Checks `Authorization: Bearer <SERVER_API_KEY>`, also accepting `ADMIN_TOKEN`;
+/// open when no key is configured.
+fn require_api_key(headers: &HeaderMap, args: &Args) -> Result<(), ApiError> {
+    let Some(expected) = args.server_api_key.as_deref().filter(|k| !k.is_empty()) else {
+        return Ok(());
+    };
+    let admin = args.admin_token.as_deref().filter(|t| !t.is_empty());
+    if bearer_matches(headers, expected) || admin.is_some_and(|token| bearer_matches(headers, token)) {
+        Ok(())
+    } else {
+        Err(ApiError::new(StatusCode::UNAUTHORIZED, "Invalid or missing API key"))
+    }
+}
+
+/// Middleware applying `require_api_key` to the /api routes.
+async fn require_client_auth(
+    State(args): State<Args>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    match require_api_key(request.headers(), &args) {
+        Ok(()) => next.run(request).await,
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Compares the bearer token with `expected` in constant time.
+fn bearer_matches(headers: &HeaderMap, expected: &str) -> bool {
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or("");
+    provided.len() == expected.len()
+        && provided.bytes().zip(expected.bytes()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+async fn search_handler(
+    State(state): State<AppState>,
+    axum::Json(req): axum::Json<SearchRequest>,
+) -> impl IntoResponse {
+    if req.query.trim().is_empty() {
+        return ApiError::new(StatusCode::BAD_REQUEST, "query must not be empty").into_response();
+    }
+
+    // Work on a clone so retrieval does not hold the agent lock across embedding calls.
+    let agent = state.agent.lock().await.clone();
+    let topic = req.topic.filter(|t| !t.trim().is_empty());
+    if let Some(topic) = topic.as_deref().filter(|t| !agent.has_topic(t)) {
+        return ApiError::new(StatusCode::BAD_REQUEST, format!("Unknown topic '{}'", topic)).into_response();
+    }
+
+    match agent.search_documents(&req.query, topic, req.limit, req.filters).await {
+        Ok((documents, metadata)) => axum::Json(SearchResponse {
+            topic: metadata.topic,
+            results: documents
+                .into_iter()
+                .map(|doc| SearchHit { id: doc.id, score: doc.score, fields: doc.content })
+                .collect(),
+        }).into_response(),
+        Err(e) => {
+            error!("Search failed: {}", e);
+            ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+async fn ingest_documents_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::Json(req): axum::Json<IngestRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = require_admin(&headers, &state.args) {
+        return e.into_response();
+    }
+    if req.topic.trim().is_empty() {
+        return ApiError::new(StatusCode::BAD_REQUEST, "topic must not be empty").into_response();
+    }
+    if req.documents.is_empty() {
+        return ApiError::new(StatusCode::BAD_REQUEST, "documents must not be empty").into_response();
+    }
+
+    // Work on a clone so ingestion does not hold the agent lock across embedding calls.
+    let agent = state.agent.lock().await.clone();
+    if !agent.can_ingest() {
+        return ApiError::new(
+            StatusCode::NOT_IMPLEMENTED,
+            format!("Document ingestion is not supported for VECTOR_TYPE '{}'", state.args.vector_type)
+        ).into_response();
+    }
+
+    match agent.ingest_documents(&req.topic, req.documents, req.text_fields).await {
+        Ok(results) => {
+            let indexed = results.iter().filter(|r| r.success).count();
+            let failed = results.len() - indexed;
+            axum::Json(IngestResponse {
+                success: failed == 0,
+                topic: req.topic,
+                indexed,
+                failed,
+                results,
+            }).into_response()
+        }
+        Err(e) => {
+            error!("Document ingestion failed: {}", e);
+            ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+fn conversations_disabled() -> ApiError {
+    ApiError::new(StatusCode::NOT_IMPLEMENTED, "Conversation metadata is disabled; set CONVERSATION_METADATA=true to enable it")
+}
+
+async fn list_conversations_handler(
+    State(state): State<AppState>,
+    Query(query): Query<ConversationListQuery>,
+) -> impl IntoResponse {
+    let agent = state.agent.lock().await.clone();
+    if !agent.tracks_conversations() {
+        return conversations_disabled().into_response();
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_CONVERSATION_LIST_LIMIT).clamp(1, MAX_CONVERSATION_LIST_LIMIT);
+    match agent.list_conversations(limit).await {
+        Ok(conversations) => axum::Json(serde_json::json!({ "conversations": conversations })).into_response(),
+        Err(e) => {
+            error!("Failed to list conversations: {}", e);
+            ApiError::new(StatusCode::SERVICE_UNAVAILABLE, format!("Failed to list conversations: {}", e)).into_response()
+        }
+    }
+}
+
+async fn update_conversation_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    axum::Json(req): axum::Json<ConversationUpdate>,
+) -> impl IntoResponse {
+    if !crate::history::is_valid_conversation_id(&id) {
+        return ApiError::new(StatusCode::BAD_REQUEST, "invalid conversation_id").into_response();
+    }
+    let agent = state.agent.lock().await.clone();
+    if !agent.tracks_conversations() {
+        return conversations_disabled().into_response();
+    }
+    match agent.update_conversation(&id, req.title, req.tags).await {
+        Ok(Some(metadata)) => axum::Json(metadata).into_response(),
+        Ok(None) => ApiError::new(StatusCode::NOT_FOUND, format!("Conversation '{}' not found", id)).into_response(),
+        Err(e) => {
+            error!("Failed to update conversation {}: {}", id, e);
+            ApiError::new(StatusCode::SERVICE_UNAVAILABLE, format!("Failed to update conversation: {}", e)).into_response()
+        }
+    }
+}
+
+/// Deletes a conversation's history and metadata, e.g. for a user's data removal request.
+/// Works whether or not conversation metadata is enabled.
+async fn delete_conversation_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    if !crate::history::is_valid_conversation_id(&id) {
+        return ApiError::new(StatusCode::BAD_REQUEST, "invalid conversation_id").into_response();
+    }
+    // Holding the stream slot keeps an in-flight reply from writing the history back.
+    let Ok(_guard) = state.streams.acquire(&id).await else {
+        return ApiError::new(StatusCode::CONFLICT, "conversation busy").into_response();
+    };
+    let agent = state.agent.lock().await.clone();
+    match agent.delete_conversation(&id).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            error!("Failed to delete conversation {}: {}", id, e);
+            ApiError::new(StatusCode::SERVICE_UNAVAILABLE, format!("Failed to delete conversation: {}", e)).into_response()
+        }
+    }
+}
+
+async fn create_job_handler(
+    State(state): State<JobsState>,
+    axum::Json(req): axum::Json<JobRequest>,
+) -> impl IntoResponse {
+    if req.content.trim().is_empty() {
+        return ApiError::new(StatusCode::BAD_REQUEST, "content must not be empty").into_response();
+    }
+
+    let conversation_id = match req.conversation_id.filter(|id| !id.trim().is_empty()) {
+        Some(id) if !crate::history::is_valid_conversation_id(&id) => {
+            return ApiError::new(StatusCode::BAD_REQUEST, "invalid conversation_id").into_response();
+        }
+        Some(id) => id,
+        None => Uuid::new_v4().to_string(),
+    };
+
+    match state.jobs.enqueue(conversation_id, req.content).await {
+        Ok(job) => (StatusCode::ACCEPTED, axum::Json(serde_json::json!({
+            "job_id": job.id,
+            "status": job.status,
+            "conversation_id": job.conversation_id,
+        }))).into_response(),
+        Err(e) => {
+            error!("Failed to enqueue job: {}", e);
+            ApiError::new(StatusCode::SERVICE_UNAVAILABLE, format!("Failed to enqueue job: {}", e)).into_response()
+        }
+    }
+}
+
+async fn get_job_handler(
+    State(state): State<JobsState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.jobs.get(&id).await {
+        Ok(Some(job)) => (StatusCode::OK, axum::Json(job)).into_response(),
+        Ok(None) => ApiError::new(StatusCode::NOT_FOUND, format!("Job '{}' not found", id)).into_response(),
+        Err(e) => {
+            error!("Failed to load job {}: {}", id, e);
+            ApiError::new(StatusCode::SERVICE_UNAVAILABLE, format!("Failed to load job: {}", e)).into_response()
+        }
+    }
+}
+
+/// Outcome of one `/readyz` dependency check.
+#[derive(Serialize)]
+struct DependencyStatus {
+    /// `ok`, `error` or `skipped`.
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl DependencyStatus {
+    fn skipped() -> Self {
+        Self { status: "skipped", latency_ms: None, error: None }
+    }
+}
+
+/// Runs `check` when `enabled`, failing it after `timeout`. A check resolving to `None`
+/// does not apply to this configuration and is reported as skipped.
+async fn readiness_check(
+    enabled: bool,
+    timeout: Duration,
+    check: impl std::future::Future<Output = Option<Result<(), AgentError>>>
+) -> DependencyStatus {
+    if !enabled {
+        return DependencyStatus::skipped();
+    }
+    let started = std::time::Instant::now();
+    let result = match tokio::time::timeout(timeout, check).await {
+        Ok(None) => return DependencyStatus::skipped(),
+        Ok(Some(result)) => result.map_err(|e| e.to_string()),
+        Err(_) => Err(format!("timed out after {}s", timeout.as_secs())),
+    };
+    let latency_ms = Some(started.elapsed().as_millis() as u64);
+    match result {
+        Ok(()) => DependencyStatus { status: "ok", latency_ms, error: None },
+        Err(e) => DependencyStatus { status: "error", latency_ms, error: Some(e) },
+    }
+}
+
+/// Liveness: answers 200 as long as the server is serving requests.
+async fn healthz_handler() -> impl IntoResponse {
+    axum::Json(serde_json::json!({ "status": "ok" }))
+}
+
+/// Readiness: runs the enabled dependency checks concurrently and answers 503 when any fails.
+async fn readyz_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let agent = state.agent.lock().await.clone();
+    let args = &state.args;
+    let timeout = Duration::from_secs(args.readyz_timeout_secs);
+    let (vector_store, cache, embedding) = tokio::join!(
+        readiness_check(args.readyz_check_vector_store, timeout, async { Some(agent.ping_vector_store().await) }),
+        readiness_check(args.readyz_check_cache, timeout, agent.ping_cache()),
+        readiness_check(args.readyz_check_embedding, timeout, async { Some(agent.ping_embedding().await) }),
+    );
+    let checks = [("vector_store", vector_store), ("cache", cache), ("embedding", embedding)];
+    let ready = checks.iter().all(|(_, check)| check.status != "error");
+    for (name, check) in &checks {
+        if let Some(e) = &check.error {
+            error!("Readiness check {} failed: {}", name, e);
+        }
+    }
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    let checks: std::collections::BTreeMap<_, _> = checks.into_iter().collect();
+    (status, axum::Json(serde_json::json!({
+        "status": if ready { "ready" } else { "not_ready" },
+        "checks": checks,
+    }))).into_response()
+}
+
+async fn metrics_handler() -> impl IntoResponse {
+    let mut body = String::new();
+    body.push_str("# HELP provider_circuit_state Circuit breaker state per provider (0=closed, 1=open, 2=half_open).\n");
+    body.push_str("# TYPE provider_circuit_state gauge\n");
+    let breakers = circuit_breaker::snapshot();
+    for b in &breakers {
+        let value = match b.state {
+            BreakerState::Closed => 0,
+            BreakerState::Open => 1,
+            BreakerState::HalfOpen => 2,
+        };
+        body.push_str(&format!("provider_circuit_state{{provider=\"{}\"}} {}\n", b.name, value));
+    }
+    body.push_str("# HELP provider_consecutive_failures Consecutive retryable failures per provider.\n");
+    body.push_str("# TYPE provider_consecutive_failures gauge\n");
+    for b in &breakers {
+        body.push_str(&format!("provider_consecutive_failures{{provider=\"{}\"}} {}\n", b.name, b.consecutive_failures));
+    }
+    metrics::render(&mut body);
+
+    (
+        StatusCode::OK,
+        [(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        body,
+    )
+}
+
+async fn chat_handler(
+    State(state): State<AppState>,
+    axum::Json(req): axum::Json<ChatRequest>,
+) -> impl IntoResponse {
+    if req.message.trim().is_empty() {
+        return ApiError::new(StatusCode::BAD_REQUEST, "message must not be empty").into_response();
+    }
+    let conversation_id = match req.conversation_id {
+        Some(id) if !crate::history::is_valid_conversation_id(&id) => {
+            return ApiError::new(StatusCode::BAD_REQUEST, "invalid conversation_id").into_response();
+        }
+        Some(id) => id,
+        None => Uuid::new_v4().to_string(),
+    };
+
+    // One reply per conversation at a time, as for the streaming routes.
+    let Ok(guard) = state.streams.acquire(&conversation_id).await else {
+        return ApiError::new(StatusCode::CONFLICT, "conversation busy").into_response();
+    };
+    let agent = state.agent.lock().await.clone();
+    let options = MessageOptions { rag_limit: req.rag_limit, ..Default::default() };
+    let result = tokio::select! {
+        result = agent.process_message_with_options(&conversation_id, &req.message, &options) => result,
+        _ = guard.cancelled() => {
+            return ApiError::new(StatusCode::CONFLICT, "cancelled by a newer message").into_response();
+        }
+    };
+    match result {
+        Ok(answer) => (StatusCode::OK, axum::Json(ChatResponse {
+            conversation_id,
+            response: answer.response,
+            thinking: answer.thinking,
+            metadata: answer.metadata,
+        })).into_response(),
+        Err(e) => {
+            error!("Chat request failed: {}", e);
+            let status = if matches!(e, AgentError::Timeout(_)) {
+                StatusCode::GATEWAY_TIMEOUT
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            ApiError::new(status, e.to_string()).into_response()
+        }
+    }
+}
+
+async fn raw_chat_handler(
+    State(state): State<AppState>,
+    Query(req): Query<RawChatQuery>,
+) -> impl IntoResponse {
+    if req.content.trim().is_empty() {
+        return ApiError::new(StatusCode::BAD_REQUEST, "content must not be empty").into_response();
+    }
+    let conversation_id = match req.conversation_id.filter(|id| !id.trim().is_empty()) {
+        Some(id) if !crate::history::is_valid_conversation_id(&id) => {
+            return ApiError::new(StatusCode::BAD_REQUEST, "invalid conversation_id").into_response();
+        }
+        Some(id) => id,
+        None => Uuid::new_v4().to_string(),
+    };
+
+    let Ok(guard) = state.streams.acquire(&conversation_id).await else {
+        return ApiError::new(StatusCode::CONFLICT, "conversation busy").into_response();
+    };
+    let (stream_result, postprocessor) = {
+        let agent = state.agent.lock().await;
+        let postprocessor = agent.response_postprocessor().await;
+        (agent.process_message_stream(&conversation_id, &req.content, &MessageOptions::default()).await, postprocessor)
+    };
+    let stream = match stream_result {
+        Ok(outcome) => outcome.stream,
+        Err(e) => {
+            error!("Raw chat stream failed: {}", e);
+            let status = if matches!(e, AgentError::Timeout(_)) {
+                StatusCode::GATEWAY_TIMEOUT
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            return ApiError::new(status, e.to_string()).into_response();
+        }
+    };
+
+    // The guard lives as long as the body; a newer message under the cancel policy ends it early.
+    let stream = Box::pin(stream.take_until(async move { guard.cancelled().await }));
+    let body_stream = futures::stream::unfold(
+        (stream, ThinkingSplitter::default(), postprocessor.stream(), false),
+        |(mut stream, mut splitter, mut answer, done)| async move {
+            if done {
+                return None;
+            }
+            loop {
+                match stream.next().await {
+                    Some(Ok(chunk)) => {
+                        let text = answer.push(&answer_text(splitter.push(&chunk)));
+                        if !text.is_empty() {
+                            return Some((Ok(text), (stream, splitter, answer, false)));
+                        }
+                    }
+                    Some(Err(e)) => return Some((Err(e), (stream, splitter, answer, true))),
+                    None => {
+                        let mut rest = answer.push(&answer_text(splitter.finish()));
+                        rest.push_str(&answer.finish());
+                        rest.push('\n');
+                        return Some((Ok(rest), (stream, splitter, answer, true)));
+                    }
+                }
+            }
+        }
+    );
+
+    (
+        StatusCode::OK,
+        [
+            (axum::http::header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+            (axum::http::HeaderName::from_static("x-conversation-id"), conversation_id.as_str()),
+        ],
+        axum::body::Body::from_stream(body_stream),
+    ).into_response()
+}
+
+/// The answer parts of `segments`, with reasoning left out.
+fn answer_text(segments: impl IntoIterator<Item = Segment>) -> String {
+    segments
+        .into_iter()
+        .filter_map(|segment| match segment {
+            Segment::Answer(text) => Some(text),
+            Segment::Thinking(_) => None,
+        })
+        .collect()
+}
+
+/// Streams the answer as server-sent events for `EventSource` clients: answer
+/// fragments as unnamed events, reasoning as `thinking` events, then one `done`
+/// event, or an `error` event if the stream fails or a newer message cancels it.
+async fn chat_stream_handler(
+    State(state): State<AppState>,
+    Query(req): Query<RawChatQuery>,
+) -> impl IntoResponse {
+    if req.content.trim().is_empty() {
+        return ApiError::new(StatusCode::BAD_REQUEST, "content must not be empty").into_response();
+    }
+    let conversation_id = match req.conversation_id.filter(|id| !id.trim().is_empty()) {
+        Some(id) if !crate::history::is_valid_conversation_id(&id) => {
+            return ApiError::new(StatusCode::BAD_REQUEST, "invalid conversation_id").into_response();
+        }
+        Some(id) => id,
+        None => Uuid::new_v4().to_string(),
+    };
+
+    let Ok(guard) = state.streams.acquire(&conversation_id).await else {
+        return ApiError::new(StatusCode::CONFLICT, "conversation busy").into_response();
+    };
+    let (stream_result, postprocessor) = {
+        let agent = state.agent.lock().await;
+        let postprocessor = agent.response_postprocessor().await;
+        (agent.process_message_stream(&conversation_id, &req.content, &MessageOptions::default()).await, postprocessor)
+    };
+    let stream = match stream_result {
+        Ok(outcome) => outcome.stream,
+        Err(e) => {
+            error!("Chat event stream failed: {}", e);
+            let status = if matches!(e, AgentError::Timeout(_)) {
+                StatusCode::GATEWAY_TIMEOUT
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            return ApiError::new(status, e.to_string()).into_response();
+        }
+    };
+
+    let guard = Arc::new(guard);
+    let cancelled = guard.clone();
+    let stream = Box::pin(stream.take_until(async move { cancelled.cancelled().await }));
+    let events = futures::stream::unfold(
+        Some((stream, ThinkingSplitter::default(), postprocessor.stream(), guard, conversation_id)),
+        |state| async move {
+            let (mut stream, mut splitter, mut answer, guard, conversation_id) = state?;
+            loop {
+                let events: Vec<Event> = match stream.next().await {
+                    Some(Ok(chunk)) => splitter.push(&chunk).into_iter().filter_map(|s| segment_event(s, &mut answer)).collect(),
+                    Some(Err(e)) => {
+                        error!("Chat event stream error: {}", e);
+                        let event = Event::default().event("error").data(e.to_string());
+                        return Some((vec![event], None));
+                    }
+                    None => {
+                        let mut events: Vec<Event> = splitter
+                            .finish()
+                            .into_iter()
+                            .filter_map(|s| segment_event(s, &mut answer))
+                            .collect();
+                        let rest = answer.finish();
+                        if !rest.is_empty() && !guard.is_cancelled() {
+                            events.push(Event::default().data(rest));
+                        }
+                        events.push(if guard.is_cancelled() {
+                            Event::default().event("error").data("cancelled by a newer message")
+                        } else {
+                            let data = serde_json::json!({ "conversation_id": conversation_id });
+                            Event::default().event("done").data(data.to_string())
+                        });
+                        return Some((events, None));
+                    }
+                };
+                if !events.is_empty() {
+                    return Some((events, Some((stream, splitter, answer, guard, conversation_id))));
+                }
+            }
+        }
+    ).flat_map(|events| futures::stream::iter(events.into_iter().map(Ok::<_, std::convert::Infallible>)));
+
+    Sse::new(events).keep_alive(KeepAlive::default()).into_response()
+}
+
+/// The event for `segment`; answer text goes through the `response_postprocess`
+/// rules, which may hold it back for now.
+fn segment_event(segment: Segment, answer: &mut PostprocessStream) -> Option<Event> {
+    match segment {
+        Segment::Answer(text) => Some(answer.push(&text)).filter(|t| !t.is_empty()).map(|t| Event::default().data(t)),
+        Segment::Thinking(text) => Some(Event::default().event("thinking").data(text)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::prompt::{ load_prompts_from_str, PromptConfig };
+    use crate::server::streams::StreamPolicy;
+    use crate::testing::{ InMemoryHistoryStore, MockChatClient, MockEmbeddingClient, MockVectorStore };
+    use axum::body::Body;
+    use axum::http::Request;
+    use clap::Parser;
+    use tower::ServiceExt;
+    use vector_nexus::schema::IndexSchema;
+
+    const API_KEY: &str = "secret";
+
+    fn app(flags: &[&str], responses: &[&str]) -> Router {
+        app_with(flags, responses, Vec::new(), |_| {}).0
+    }
+
+    /// The app over a store with `schemas`, filled by `fill`, and the stream slots it
+    /// shares with the handlers.
+    fn app_with(
+        flags: &[&str],
+        responses: &[&str],
+        schemas: Vec<IndexSchema>,
+        fill: impl FnOnce(&MockVectorStore)
+    ) -> (Router, Arc<ConversationStreams>) {
+        let store = MockVectorStore::new(schemas.clone());
+        fill(&store);
+        let chat = MockChatClient::new(responses.iter().copied());
+        app_over(flags, prompts(), chat, store, schemas)
+    }
+
+    fn prompts() -> PromptConfig {
+        load_prompts_from_str(include_str!("../../json/prompts.json")).unwrap().as_ref().clone()
+    }
+
+    fn app_over(
+        flags: &[&str],
+        prompts: PromptConfig,
+        chat: MockChatClient,
+        store: MockVectorStore,
+        schemas: Vec<IndexSchema>
+    ) -> (Router, Arc<ConversationStreams>) {
+        let args = Args::parse_from(std::iter::once("dynamic-agent").chain(flags.iter().copied()));
+        let agent = AIAgent::for_test(
+            args.clone(),
+            prompts,
+            Arc::new(chat),
+            Arc::new(MockEmbeddingClient::new(8)),
+            Arc::new(store),
+            Arc::new(InMemoryHistoryStore::new(100)),
+            schemas
+        ).unwrap();
+        let streams = Arc::new(ConversationStreams::new(StreamPolicy::Reject));
+        (build_app(Arc::new(Mutex::new(agent)), &args, streams.clone(), None).unwrap(), streams)
+    }
+
+    fn request(method: &str, uri: &str, key: Option<&str>, body: Option<serde_json::Value>) -> Request<Body> {
+        let mut builder = Request::builder().method(method).uri(uri);
+        if let Some(key) = key {
+            builder = builder.header(header::AUTHORIZATION, format!("Bearer {}", key));
+        }
+        match body {
+            Some(body) => builder
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+            None => builder.body(Body::empty()).unwrap(),
+        }
+    }
+
+    async fn status(app: &Router, request: Request<Body>) -> StatusCode {
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    async fn body_text(response: axum::response::Response) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn job_routes_require_jobs_enabled() {
+        let app = app(&[], &[]);
+        let body = serde_json::json!({ "content": "hi" });
+        assert_eq!(status(&app, request("POST", "/api/jobs", None, Some(body))).await, StatusCode::NOT_FOUND);
+        assert_eq!(status(&app, request("GET", "/api/jobs/abc", None, None)).await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn job_routes_check_the_api_key_and_conversation_id() {
+        // Nothing listens on port 1, so the worker only logs connection errors.
+        let app = app(&["--jobs-enabled", "--job-redis-url", "redis://127.0.0.1:1", "--server-api-key", API_KEY], &[]);
+        let body = serde_json::json!({ "content": "hi" });
+        assert_eq!(status(&app, request("POST", "/api/jobs", None, Some(body.clone()))).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(&app, request("GET", "/api/jobs/abc", Some("wrong"), None)).await, StatusCode::UNAUTHORIZED);
+
+        let body = serde_json::json!({ "content": "hi", "conversation_id": "../etc" });
+        assert_eq!(status(&app, request("POST", "/api/jobs", Some(API_KEY), Some(body))).await, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn search_requires_the_api_key() {
+        let app = app(&["--server-api-key", API_KEY], &[]);
+        let body = serde_json::json!({ "query": "rust" });
+        assert_eq!(status(&app, request("POST", "/api/search", None, Some(body.clone()))).await, StatusCode::UNAUTHORIZED);
+        // An empty index list fails the search itself, past the key check.
+        assert_ne!(status(&app, request("POST", "/api/search", Some(API_KEY), Some(body))).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn raw_chat_checks_the_api_key_and_conversation_id() {
+        let app = app(&["--server-api-key", API_KEY], &["GENERAL_CHAT", "Hello!"]);
+        assert_eq!(status(&app, request("GET", "/api/chat/raw?content=hi", None, None)).await, StatusCode::UNAUTHORIZED);
+        let invalid = "/api/chat/raw?content=hi&conversation_id=..%2Fetc";
+        assert_eq!(status(&app, request("GET", invalid, Some(API_KEY), None)).await, StatusCode::BAD_REQUEST);
+
+        let response = app.clone().oneshot(request("GET", "/api/chat/raw?content=hi&conversation_id=c1", Some(API_KEY), None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_text(response).await, "Hello!\n");
+    }
+
+    /// An app whose answers arrive in 4-character fragments and pass through a
+    /// `response_postprocess` rule.
+    fn postprocessed_app(responses: &[&str]) -> Router {
+        let mut prompts = prompts();
+        prompts.response_postprocess = serde_json::from_value(serde_json::json!({
+            "replace": [{ "pattern": "\\\\boxed\\{([^}]*)\\}", "replacement": "$1" }],
+            "strip_prefixes": ["Answer:\\s*"],
+            "max_match_chars": 16,
+        })).unwrap();
+        let chat = MockChatClient::new(responses.iter().copied()).fragmented(4);
+        app_over(&[], prompts, chat, MockVectorStore::new(Vec::new()), Vec::new()).0
+    }
+
+    const BOXED_REPLY: &str = "Answer: the first is \\boxed{42} and the second is \\boxed{7}.";
+
+    #[tokio::test]
+    async fn postprocess_rules_apply_to_every_chat_route() {
+        // Each route asks something new, so the intent is classified every time.
+        let app = postprocessed_app(&["GENERAL_CHAT", BOXED_REPLY, "GENERAL_CHAT", BOXED_REPLY, "GENERAL_CHAT", BOXED_REPLY]);
+
+        let chat = serde_json::json!({ "message": "hi" });
+        let response = app.clone().oneshot(request("POST", "/api/chat", None, Some(chat))).await.unwrap();
+        let body: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(body["response"], "the first is 42 and the second is 7.");
+
+        let response = app.clone().oneshot(request("GET", "/api/chat/raw?content=hello", None, None)).await.unwrap();
+        assert_eq!(body_text(response).await, "the first is 42 and the second is 7.\n");
+
+        let response = app.clone().oneshot(request("GET", "/api/chat/stream?content=hey", None, None)).await.unwrap();
+        let body = body_text(response).await;
+        let answer: String = body
+            .split("\n\n")
+            .filter(|event| !event.starts_with("event:"))
+            .filter_map(|event| event.strip_prefix("data: "))
+            .collect();
+        assert_eq!(answer, "the first is 42 and the second is 7.");
+    }
+
+    #[tokio::test]
+    async fn conversation_routes_require_the_api_key() {
+        let app = app(&["--conversation-metadata", "--server-api-key", API_KEY], &[]);
+        let update = serde_json::json!({ "title": "Renamed" });
+        assert_eq!(status(&app, request("GET", "/api/conversations", None, None)).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(&app, request("PATCH", "/api/conversations/c1", Some("wrong"), Some(update.clone()))).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(&app, request("DELETE", "/api/conversations/c1", None, None)).await, StatusCode::UNAUTHORIZED);
+
+        assert_eq!(status(&app, request("GET", "/api/conversations", Some(API_KEY), None)).await, StatusCode::OK);
+        assert_eq!(status(&app, request("PATCH", "/api/conversations/..%2Fetc", Some(API_KEY), Some(update.clone()))).await, StatusCode::BAD_REQUEST);
+        assert_eq!(status(&app, request("PATCH", "/api/conversations/c1", Some(API_KEY), Some(update))).await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn api_routes_require_a_client_key_but_probes_stay_open() {
+        let app = app(&["--server-api-key", API_KEY, "--admin-token", "admin"], &[]);
+        assert_eq!(status(&app, request("GET", "/api/reload-prompts", None, None)).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(&app, request("GET", "/api/chat/stream?content=hi", None, None)).await, StatusCode::UNAUTHORIZED);
+        let chat = serde_json::json!({ "message": "hi" });
+        assert_eq!(status(&app, request("POST", "/api/chat", Some("wrong"), Some(chat))).await, StatusCode::UNAUTHORIZED);
+
+        assert_eq!(status(&app, request("GET", "/healthz", None, None)).await, StatusCode::OK);
+        assert_eq!(status(&app, request("GET", "/metrics", None, None)).await, StatusCode::OK);
+        assert_eq!(status(&app, request("GET", "/api/unknown", None, None)).await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn admin_token_is_accepted_as_a_client_key() {
+        let app = app(&["--server-api-key", API_KEY, "--admin-token", "admin"], &[]);
+        let search = serde_json::json!({ "query": "rust" });
+        assert_ne!(status(&app, request("POST", "/api/search", Some("admin"), Some(search))).await, StatusCode::UNAUTHORIZED);
+
+        // The client key alone does not open the admin endpoint.
+        let documents = serde_json::json!({ "topic": "skills", "documents": [{ "fields": { "name": "Rust" } }] });
+        assert_eq!(status(&app, request("POST", "/api/documents", Some(API_KEY), Some(documents))).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn chat_returns_retrieval_metadata() {
+        let skills = IndexSchema {
+            name: "skills".to_string(),
+            fields: vec!["name".to_string(), "level".to_string()],
+            prefix: "qdrant:skills".to_string(),
+        };
+        let (app, _) = app_with(&[], &["PROFILE_INFO", "skills", "Rust, at an expert level."], vec![skills], |store| {
+            store.add_document("skills", "skill-1", serde_json::json!({ "name": "Rust", "level": "expert" }));
+        });
+
+        let body = serde_json::json!({ "message": "What are your skills?", "conversation_id": "c1" });
+        let response = app.clone().oneshot(request("POST", "/api/chat", None, Some(body))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(body["response"], "Rust, at an expert level.");
+        assert_eq!(body["metadata"]["topic"], "skills");
+        assert_eq!(body["metadata"]["hit_count"], 1);
+    }
+
+    #[tokio::test]
+    async fn chat_rag_limit_is_clamped_to_the_server_maximum() {
+        let skills = IndexSchema {
+            name: "skills".to_string(),
+            fields: vec!["name".to_string()],
+            prefix: "qdrant:skills".to_string(),
+        };
+        let responses = ["PROFILE_INFO", "skills", "Rust and Go.", "PROFILE_INFO", "skills", "Rust."];
+        let (app, _) = app_with(&["--rag-max-limit", "1"], &responses, vec![skills], |store| {
+            store.add_document("skills", "skill-1", serde_json::json!({ "name": "Rust" }));
+            store.add_document("skills", "skill-2", serde_json::json!({ "name": "Go" }));
+        });
+
+        let hit_count = |body: serde_json::Value| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request("POST", "/api/chat", None, Some(body))).await.unwrap();
+                let body: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+                body["metadata"]["hit_count"].clone()
+            }
+        };
+        assert_eq!(hit_count(serde_json::json!({ "message": "What are your skills?" })).await, 2);
+        assert_eq!(hit_count(serde_json::json!({ "message": "What are your skills?", "rag_limit": 5 })).await, 1);
+    }
+
+    #[tokio::test]
+    async fn chat_reports_grounding_and_applies_the_action_below_the_threshold() {
+        let skills = IndexSchema {
+            name: "skills".to_string(),
+            fields: vec!["name".to_string()],
+            prefix: "qdrant:skills".to_string(),
+        };
+        let flags = ["--rag-grounding-check", "--rag-grounding-threshold", "0.7", "--rag-grounding-action", "disclaimer"];
+        let responses = [
+            "PROFILE_INFO", "skills", "Rust.", r#"{"grounded": true, "confidence": 0.75}"#,
+            "PROFILE_INFO", "skills", "Rust and COBOL.", r#"{"grounded": true, "confidence": 0.5}"#,
+        ];
+        let (app, _) = app_with(&flags, &responses, vec![skills], |store| {
+            store.add_document("skills", "skill-1", serde_json::json!({ "name": "Rust" }));
+        });
+
+        let ask = || {
+            let app = app.clone();
+            async move {
+                let body = serde_json::json!({ "message": "What are your skills?" });
+                let response = app.oneshot(request("POST", "/api/chat", None, Some(body))).await.unwrap();
+                serde_json::from_str::<serde_json::Value>(&body_text(response).await).unwrap()
+            }
+        };
+        let grounded = ask().await;
+        assert_eq!(grounded["response"], "Rust.");
+        assert_eq!(grounded["metadata"]["grounding"], serde_json::json!({ "grounded": true, "confidence": 0.75 }));
+
+        let ungrounded = ask().await;
+        let response = ungrounded["response"].as_str().unwrap();
+        assert!(response.starts_with("Rust and COBOL.\n\n"));
+        assert!(response.contains("may not be supported by the available documents"));
+        assert_eq!(ungrounded["metadata"]["grounding"], serde_json::json!({ "grounded": false, "confidence": 0.5 }));
+    }
+
+    #[tokio::test]
+    async fn chat_omits_metadata_without_retrieval_and_respects_busy_conversations() {
+        let (app, streams) = app_with(&[], &["GENERAL_CHAT", "Hello!"], Vec::new(), |_| {});
+        let body = serde_json::json!({ "message": "hi", "conversation_id": "c1" });
+
+        let busy = streams.acquire("c1").await.unwrap();
+        assert_eq!(status(&app, request("POST", "/api/chat", None, Some(body.clone()))).await, StatusCode::CONFLICT);
+        drop(busy);
+
+        let response = app.clone().oneshot(request("POST", "/api/chat", None, Some(body))).await.unwrap();
+        let body: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(body["response"], "Hello!");
+        assert!(body.get("metadata").is_none());
+    }
+}
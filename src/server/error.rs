@@ -0,0 +1,118 @@
+use axum::{
+    body::{ self, Body },
+    http::{ header, StatusCode },
+    response::{ IntoResponse, Response },
+    Json,
+};
+use log::error;
+use serde::Serialize;
+use std::any::Any;
+
+/// Largest error body read back when wrapping a non-JSON error response.
+const MAX_ERROR_BODY_BYTES: usize = 64 * 1024;
+
+#[derive(Serialize)]
+struct ErrorEnvelope {
+    success: bool,
+    error: ErrorDetail,
+}
+
+#[derive(Serialize)]
+struct ErrorDetail {
+    code: String,
+    message: String,
+}
+
+/// HTTP API error rendered as `{ "success": false, "error": { "code", "message" } }`.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    code: String,
+    message: String,
+}
+
+impl ApiError {
+    /// Error whose code is derived from the status, e.g. `not_found`.
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code: status_code_name(status),
+            message: message.into(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorEnvelope {
+            success: false,
+            error: ErrorDetail {
+                code: self.code,
+                message: self.message,
+            },
+        };
+        (self.status, Json(body)).into_response()
+    }
+}
+
+fn status_code_name(status: StatusCode) -> String {
+    status
+        .canonical_reason()
+        .map(|reason| reason.to_lowercase().replace([' ', '-'], "_"))
+        .unwrap_or_else(|| "error".to_string())
+}
+
+/// Fallback for unmatched routes.
+pub async fn not_found_handler() -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "Route not found")
+}
+
+/// Rewrites plain-text or empty error responses (extractor rejections,
+/// method-not-allowed, ...) into the JSON error envelope.
+pub async fn json_error_envelope(response: Response) -> Response {
+    let status = response.status();
+    if !(status.is_client_error() || status.is_server_error()) {
+        return response;
+    }
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if is_json {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let message = match body::to_bytes(body, MAX_ERROR_BODY_BYTES).await {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).trim().to_string(),
+        Err(_) => String::new(),
+    };
+    let message = if message.is_empty() {
+        status.canonical_reason().unwrap_or("Request failed").to_string()
+    } else {
+        message
+    };
+
+    let mut wrapped = ApiError::new(status, message).into_response();
+    for (name, value) in parts.headers.iter() {
+        if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+            wrapped.headers_mut().insert(name.clone(), value.clone());
+        }
+    }
+    wrapped
+}
+
+/// Panic handler for `CatchPanicLayer`, returning a 500 in the JSON error envelope.
+pub fn handle_panic(panic: Box<dyn Any + Send + 'static>) -> Response<Body> {
+    let detail = if let Some(s) = panic.downcast_ref::<String>() {
+        s.clone()
+    } else if let Some(s) = panic.downcast_ref::<&str>() {
+        s.to_string()
+    } else {
+        "unknown panic".to_string()
+    };
+    error!("HTTP handler panicked: {}", detail);
+
+    ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error").into_response()
+}
@@ -1,10 +1,18 @@
 pub mod api;
+pub mod error;
 pub mod websocket;
+pub mod rate_limit;
+pub mod shutdown;
+pub mod streams;
+pub mod thinking;
 
 use crate::agent::AIAgent;
 use crate::cli::Args;
+use shutdown::Shutdown;
+use streams::ConversationStreams;
 use std::error::Error;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
  
 
@@ -29,30 +37,58 @@ impl Server {
         }
     }
 
+    /// Serves until SIGINT or SIGTERM, then stops accepting connections and returns once
+    /// open ones finish or `SHUTDOWN_GRACE_SECS` runs out.
     pub async fn run(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
-        if let Some(http_port) = self.args.http_port {
-            self.start_http_server(http_port).await?;
+        // Shared so a conversation streams once, whichever server the messages arrive on.
+        let streams = Arc::new(ConversationStreams::new(self.args.conversation_stream_policy.parse()?));
+        let shutdown = Shutdown::new(Duration::from_secs(self.args.shutdown_grace_secs));
+        shutdown.listen_for_signals();
+
+        if self.args.unified_server {
+            return api::serve_unified(&self.addr, self.agent.clone(), self.args.clone(), streams, shutdown).await;
         }
+
+        let http_server = match self.args.http_port {
+            Some(http_port) => Some(self.start_http_server(http_port, streams.clone(), shutdown.clone()).await?),
+            None => None,
+        };
         
-        self.start_ws_server().await?;
+        self.start_ws_server(streams, shutdown).await?;
+        if let Some(http_server) = http_server {
+            let _ = http_server.await;
+        }
         
         Ok(())
     }
     
-    async fn start_http_server(&self, http_port: u16) -> Result<(), Box<dyn Error + Send + Sync>> {
+    async fn start_http_server(
+        &self,
+        http_port: u16,
+        streams: Arc<ConversationStreams>,
+        shutdown: Shutdown
+    ) -> Result<tokio::task::JoinHandle<()>, Box<dyn Error + Send + Sync>> {
         api::start_http_server(
             http_port,
             self.agent.clone(),
             self.args.clone(),
+            streams,
+            shutdown,
         ).await
     }
     
-    async fn start_ws_server(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+    async fn start_ws_server(
+        &self,
+        streams: Arc<ConversationStreams>,
+        shutdown: Shutdown
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
         websocket::start_ws_server(
             &self.addr,
             self.agent.clone(),
-            None,
+            self.args.server_api_key.clone(),
             self.args.clone(),
+            streams,
+            shutdown,
         ).await
     }
-}
\ No newline at end of file
+}
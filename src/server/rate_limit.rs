@@ -0,0 +1,153 @@
+use crate::cli::Args;
+use governor::{ clock::{ Clock, DefaultClock }, DefaultDirectRateLimiter, DefaultKeyedRateLimiter, Quota, RateLimiter };
+use std::fmt;
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+
+/// Who a WebSocket connection belongs to, resolved during the handshake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientIdentity {
+    User(String),
+    Anonymous(IpAddr),
+}
+
+impl fmt::Display for ClientIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientIdentity::User(user) => write!(f, "user:{}", user),
+            ClientIdentity::Anonymous(ip) => write!(f, "anonymous:{}", ip),
+        }
+    }
+}
+
+/// Per-minute message quota shared by every connection of the same identity;
+/// anonymous clients are keyed by IP instead.
+pub struct MessageRateLimiter {
+    users: DefaultKeyedRateLimiter<String>,
+    ips: DefaultKeyedRateLimiter<IpAddr>,
+}
+
+impl MessageRateLimiter {
+    /// Returns `None` when `per_minute` is 0 (limiting disabled).
+    pub fn new(per_minute: u32) -> Option<Self> {
+        let quota = Quota::per_minute(NonZeroU32::new(per_minute)?);
+        Some(Self {
+            users: RateLimiter::keyed(quota),
+            ips: RateLimiter::keyed(quota),
+        })
+    }
+
+    /// `Err(retry_after_secs)` when the identity has exhausted its quota.
+    pub fn check(&self, identity: &ClientIdentity) -> Result<(), u64> {
+        let result = match identity {
+            ClientIdentity::User(user) => self.users.check_key(user),
+            ClientIdentity::Anonymous(ip) => self.ips.check_key(ip),
+        };
+        result.map_err(|not_until| {
+            let wait = not_until.wait_time_from(DefaultClock::default().now());
+            wait.as_secs() + u64::from(wait.subsec_nanos() > 0)
+        })
+    }
+}
+
+/// Source addresses tracked before buckets that have refilled are dropped.
+const MAX_TRACKED_IPS: usize = 10_000;
+
+/// Which connection limit refused a new WebSocket connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionLimit {
+    PerIp,
+    Global,
+}
+
+impl fmt::Display for ConnectionLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionLimit::PerIp => write!(f, "per-IP"),
+            ConnectionLimit::Global => write!(f, "global"),
+        }
+    }
+}
+
+/// New WebSocket connections per second: one bucket per source IP, so a noisy client
+/// only exhausts its own, plus a global ceiling across all clients.
+pub struct ConnectionRateLimiter {
+    per_ip: Option<DefaultKeyedRateLimiter<IpAddr>>,
+    global: Option<DefaultDirectRateLimiter>,
+}
+
+impl ConnectionRateLimiter {
+    /// A rate of 0 disables that limit.
+    pub fn new(per_ip_per_sec: u32, global_per_sec: u32) -> Self {
+        Self {
+            per_ip: NonZeroU32::new(per_ip_per_sec).map(|n| RateLimiter::keyed(Quota::per_second(n))),
+            global: NonZeroU32::new(global_per_sec).map(|n| RateLimiter::direct(Quota::per_second(n))),
+        }
+    }
+
+    pub fn from_args(args: &Args) -> Self {
+        Self::new(args.ws_rate_limit_per_sec, args.ws_global_rate_limit_per_sec)
+    }
+
+    /// The per-IP bucket is checked first, so refused connections from a throttled
+    /// address do not use up the global ceiling.
+    pub fn check(&self, ip: IpAddr) -> Result<(), ConnectionLimit> {
+        if let Some(per_ip) = &self.per_ip {
+            if per_ip.len() > MAX_TRACKED_IPS {
+                per_ip.retain_recent();
+            }
+            per_ip.check_key(&ip).map_err(|_| ConnectionLimit::PerIp)?;
+        }
+        if let Some(global) = &self.global {
+            global.check().map_err(|_| ConnectionLimit::Global)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([10, 0, 0, last])
+    }
+
+    #[test]
+    fn throttles_each_ip_independently() {
+        let limiter = ConnectionRateLimiter::new(2, 0);
+        assert_eq!(limiter.check(ip(1)), Ok(()));
+        assert_eq!(limiter.check(ip(1)), Ok(()));
+        assert_eq!(limiter.check(ip(1)), Err(ConnectionLimit::PerIp));
+        // A burst from one address leaves the others their full quota.
+        assert_eq!(limiter.check(ip(2)), Ok(()));
+        assert_eq!(limiter.check(ip(2)), Ok(()));
+    }
+
+    #[test]
+    fn global_ceiling_applies_across_ips() {
+        let limiter = ConnectionRateLimiter::new(10, 3);
+        for last in 1..=3 {
+            assert_eq!(limiter.check(ip(last)), Ok(()));
+        }
+        assert_eq!(limiter.check(ip(4)), Err(ConnectionLimit::Global));
+    }
+
+    #[test]
+    fn refused_per_ip_connections_do_not_use_the_global_ceiling() {
+        let limiter = ConnectionRateLimiter::new(1, 2);
+        assert_eq!(limiter.check(ip(1)), Ok(()));
+        for _ in 0..5 {
+            assert_eq!(limiter.check(ip(1)), Err(ConnectionLimit::PerIp));
+        }
+        assert_eq!(limiter.check(ip(2)), Ok(()));
+    }
+
+    #[test]
+    fn zero_disables_a_limit() {
+        let limiter = ConnectionRateLimiter::new(0, 0);
+        for _ in 0..100 {
+            assert_eq!(limiter.check(ip(1)), Ok(()));
+        }
+    }
+}
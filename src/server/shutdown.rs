@@ -0,0 +1,146 @@
+//! Graceful shutdown on SIGINT/SIGTERM.
+//!
+//! Once triggered, the servers stop accepting connections, idle WebSocket
+//! connections are closed, and answers already streaming may finish until the
+//! grace period ends. `Server::run` then returns and the remaining work is dropped.
+
+use log::{ info, warn };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::{ Arc, OnceLock };
+use std::time::Duration;
+use tokio::sync::{ watch, Notify };
+use tokio::time::Instant;
+
+#[derive(Clone)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    grace: Duration,
+    triggered: watch::Sender<bool>,
+    /// When the grace period ends; set by `trigger`.
+    deadline: OnceLock<Instant>,
+    /// Connections holding a `ConnectionGuard`.
+    active: AtomicUsize,
+    idle: Notify,
+}
+
+impl Shutdown {
+    pub fn new(grace: Duration) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                grace,
+                triggered: watch::Sender::new(false),
+                deadline: OnceLock::new(),
+                active: AtomicUsize::new(0),
+                idle: Notify::new(),
+            }),
+        }
+    }
+
+    /// Triggers the shutdown on the first SIGINT (Ctrl-C) or SIGTERM.
+    pub fn listen_for_signals(&self) {
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            wait_for_signal().await;
+            info!("Shutdown signal received; draining connections for up to {:?}", shutdown.inner.grace);
+            shutdown.trigger();
+        });
+    }
+
+    pub fn grace(&self) -> Duration {
+        self.inner.grace
+    }
+
+    /// Starts the shutdown and its grace period. Later calls have no effect.
+    pub fn trigger(&self) {
+        self.inner.deadline.get_or_init(|| Instant::now() + self.inner.grace);
+        self.inner.triggered.send_replace(true);
+    }
+
+    /// Resolves once the shutdown is triggered.
+    pub async fn triggered(&self) {
+        let mut rx = self.inner.triggered.subscribe();
+        // The sender lives in `inner`, so the channel cannot close while `self` exists.
+        let _ = rx.wait_for(|triggered| *triggered).await;
+    }
+
+    /// Resolves once the grace period after the trigger has elapsed.
+    pub async fn grace_elapsed(&self) {
+        self.triggered().await;
+        if let Some(deadline) = self.inner.deadline.get() {
+            tokio::time::sleep_until(*deadline).await;
+        }
+    }
+
+    /// Counts a connection as in flight until the guard is dropped.
+    pub fn track(&self) -> ConnectionGuard {
+        self.inner.active.fetch_add(1, Ordering::SeqCst);
+        ConnectionGuard { inner: Arc::clone(&self.inner) }
+    }
+
+    /// Waits for tracked connections to finish, at most until the grace period ends.
+    pub async fn drain(&self) {
+        let all_closed = async {
+            loop {
+                let notified = self.inner.idle.notified();
+                if self.inner.active.load(Ordering::SeqCst) == 0 {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        tokio::select! {
+            _ = all_closed => info!("All connections closed"),
+            _ = self.grace_elapsed() => {
+                warn!(
+                    "Shutdown grace period ended with {} connection(s) still open; dropping them",
+                    self.inner.active.load(Ordering::SeqCst)
+                );
+            }
+        }
+    }
+}
+
+/// Held by an in-flight connection; see `Shutdown::track`.
+pub struct ConnectionGuard {
+    inner: Arc<Inner>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if self.inner.active.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.inner.idle.notify_waiters();
+        }
+    }
+}
+
+async fn wait_for_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
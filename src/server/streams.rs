@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{ Arc, Mutex as StdMutex };
+use tokio::sync::{ watch, Mutex, OwnedMutexGuard };
+
+/// What happens when a conversation already has an active stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamPolicy {
+    /// Wait for the active stream to finish.
+    Queue,
+    /// Refuse the new message with "conversation busy".
+    Reject,
+    /// Stop the active stream and start the new one.
+    Cancel,
+}
+
+impl FromStr for StreamPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "queue" => Ok(StreamPolicy::Queue),
+            "reject" => Ok(StreamPolicy::Reject),
+            "cancel" => Ok(StreamPolicy::Cancel),
+            other => Err(format!("Invalid conversation stream policy '{}': expected queue, reject or cancel", other)),
+        }
+    }
+}
+
+/// The conversation already has an active stream and the policy is `reject`.
+#[derive(Debug)]
+pub struct ConversationBusy;
+
+struct Slot {
+    lock: Arc<Mutex<()>>,
+    /// Cancellation signal of the stream currently holding `lock`.
+    active: StdMutex<Option<watch::Sender<bool>>>,
+}
+
+type Slots = Arc<StdMutex<HashMap<String, Arc<Slot>>>>;
+
+/// Allows one active response stream per conversation ID, shared by every server.
+pub struct ConversationStreams {
+    policy: StreamPolicy,
+    slots: Slots,
+}
+
+impl ConversationStreams {
+    pub fn new(policy: StreamPolicy) -> Self {
+        Self { policy, slots: Arc::new(StdMutex::new(HashMap::new())) }
+    }
+
+    /// Claims the conversation for a new stream, applying the policy when it is busy.
+    /// The claim lasts until the returned guard is dropped.
+    pub async fn acquire(&self, conversation_id: &str) -> Result<StreamGuard, ConversationBusy> {
+        let slot = {
+            let mut slots = self.slots.lock().unwrap();
+            Arc::clone(
+                slots.entry(conversation_id.to_string()).or_insert_with(|| {
+                    Arc::new(Slot { lock: Arc::new(Mutex::new(())), active: StdMutex::new(None) })
+                })
+            )
+        };
+        // Built before awaiting so an abandoned wait still cleans up the slot.
+        let mut guard = StreamGuard {
+            conversation_id: conversation_id.to_string(),
+            slot: Arc::clone(&slot),
+            slots: Arc::clone(&self.slots),
+            permit: None,
+            cancelled: None,
+        };
+
+        let permit = match self.policy {
+            StreamPolicy::Queue => slot.lock.clone().lock_owned().await,
+            StreamPolicy::Reject => slot.lock.clone().try_lock_owned().map_err(|_| ConversationBusy)?,
+            StreamPolicy::Cancel => {
+                if let Some(active) = slot.active.lock().unwrap().as_ref() {
+                    let _ = active.send(true);
+                }
+                slot.lock.clone().lock_owned().await
+            }
+        };
+        let (cancel_tx, cancel_rx) = watch::channel(false);
+        *slot.active.lock().unwrap() = Some(cancel_tx);
+        guard.permit = Some(permit);
+        guard.cancelled = Some(cancel_rx);
+        Ok(guard)
+    }
+}
+
+/// An active stream's claim on its conversation.
+pub struct StreamGuard {
+    conversation_id: String,
+    slot: Arc<Slot>,
+    slots: Slots,
+    permit: Option<OwnedMutexGuard<()>>,
+    cancelled: Option<watch::Receiver<bool>>,
+}
+
+impl StreamGuard {
+    /// Resolves once a newer message cancels this stream; pending forever otherwise.
+    pub async fn cancelled(&self) {
+        if let Some(mut rx) = self.cancelled.clone() {
+            if rx.wait_for(|cancelled| *cancelled).await.is_ok() {
+                return;
+            }
+        }
+        std::future::pending::<()>().await
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.as_ref().is_some_and(|rx| *rx.borrow())
+    }
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        if let Some(permit) = self.permit.take() {
+            // Cleared before releasing the lock so the next holder's signal is not lost.
+            *self.slot.active.lock().unwrap() = None;
+            drop(permit);
+        }
+        let mut slots = self.slots.lock().unwrap();
+        // Only the map and this guard hold the slot, so nobody is streaming or waiting.
+        if Arc::strong_count(&self.slot) == 2 {
+            slots.remove(&self.conversation_id);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Splits streamed model output into reasoning and answer text, shared by the
+//! WebSocket and HTTP streaming endpoints.
+
+const THINK_OPEN: &str = "<think>";
+const THINK_CLOSE: &str = "</think>";
+const CODE_FENCE: &str = "```";
+
+/// A run of streamed text, either inside `<think>...</think>` or part of the answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    Thinking(String),
+    Answer(String),
+}
+
+/// Separates `<think>` sections from the answer in a token stream, holding back
+/// partial tags that may be split across chunks. A code fence inside a thinking
+/// section also ends it, as some models open their answer with one and never
+/// close the tag.
+#[derive(Default)]
+pub struct ThinkingSplitter {
+    pending: String,
+    in_think: bool,
+    /// Set after a thinking section closes, until the answer's leading blank lines are skipped.
+    trim_answer: bool,
+}
+
+impl ThinkingSplitter {
+    /// Adds a chunk and returns the segments that are complete so far, in order.
+    pub fn push(&mut self, chunk: &str) -> Vec<Segment> {
+        self.pending.push_str(chunk);
+        let mut out = Vec::new();
+        loop {
+            if self.in_think {
+                let close = self.pending.find(THINK_CLOSE).map(|pos| (pos, THINK_CLOSE.len()));
+                let fence = self.pending.find(CODE_FENCE).map(|pos| (pos, 0));
+                match close.into_iter().chain(fence).min_by_key(|(pos, _)| *pos) {
+                    Some((pos, skip)) => {
+                        push(&mut out, Segment::Thinking(self.pending[..pos].to_string()));
+                        self.pending.drain(..pos + skip);
+                        self.in_think = false;
+                        self.trim_answer = true;
+                    }
+                    None => {
+                        let keep = partial_tag_len(&self.pending, THINK_CLOSE)
+                            .max(partial_tag_len(&self.pending, CODE_FENCE));
+                        let emit_to = self.pending.len() - keep;
+                        push(&mut out, Segment::Thinking(self.pending[..emit_to].to_string()));
+                        self.pending.drain(..emit_to);
+                        return out;
+                    }
+                }
+            } else {
+                if self.trim_answer {
+                    let trimmed = self.pending.len() - self.pending.trim_start_matches(['\n', ' ']).len();
+                    self.pending.drain(..trimmed);
+                    if self.pending.is_empty() {
+                        return out;
+                    }
+                    self.trim_answer = false;
+                }
+                match self.pending.find(THINK_OPEN) {
+                    Some(pos) => {
+                        push(&mut out, Segment::Answer(self.pending[..pos].to_string()));
+                        self.pending.drain(..pos + THINK_OPEN.len());
+                        self.in_think = true;
+                    }
+                    None => {
+                        let keep = partial_tag_len(&self.pending, THINK_OPEN);
+                        let emit_to = self.pending.len() - keep;
+                        push(&mut out, Segment::Answer(self.pending[..emit_to].to_string()));
+                        self.pending.drain(..emit_to);
+                        return out;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Releases text held back as a possible partial tag once the stream has ended.
+    pub fn finish(&mut self) -> Option<Segment> {
+        let rest = std::mem::take(&mut self.pending);
+        if rest.is_empty() {
+            None
+        } else if self.in_think {
+            Some(Segment::Thinking(rest))
+        } else {
+            Some(Segment::Answer(rest))
+        }
+    }
+}
+
+fn push(out: &mut Vec<Segment>, segment: Segment) {
+    if !matches!(&segment, Segment::Thinking(s) | Segment::Answer(s) if s.is_empty()) {
+        out.push(segment);
+    }
+}
+
+/// Length of the longest suffix of `text` that is a proper prefix of `tag`.
+fn partial_tag_len(text: &str, tag: &str) -> usize {
+    (1..tag.len())
+        .rev()
+        .find(|&n| text.ends_with(&tag[..n]))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `text` in chunks of `size` characters and merges adjacent segments of the same kind.
+    fn split(text: &str, size: usize) -> Vec<Segment> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut splitter = ThinkingSplitter::default();
+        let mut segments: Vec<Segment> = chars
+            .chunks(size)
+            .flat_map(|chunk| splitter.push(&chunk.iter().collect::<String>()))
+            .collect();
+        segments.extend(splitter.finish());
+        segments.into_iter().fold(Vec::new(), |mut merged, segment| {
+            match (merged.last_mut(), segment) {
+                (Some(Segment::Thinking(last)), Segment::Thinking(text)) => last.push_str(&text),
+                (Some(Segment::Answer(last)), Segment::Answer(text)) => last.push_str(&text),
+                (_, segment) => merged.push(segment),
+            }
+            merged
+        })
+    }
+
+    #[test]
+    fn splits_think_blocks_regardless_of_chunking() {
+        let text = "<think>plan é</think>\n\nFirst <think>more</think> second";
+        for size in 1..=5 {
+            assert_eq!(
+                split(text, size),
+                vec![
+                    Segment::Thinking("plan é".into()),
+                    Segment::Answer("First ".into()),
+                    Segment::Thinking("more".into()),
+                    Segment::Answer("second".into()),
+                ],
+                "chunk size {}",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn code_fence_ends_an_unclosed_think_block() {
+        for size in 1..=5 {
+            assert_eq!(
+                split("<think>hmm```rust\nfn main() {}\n```", size),
+                vec![Segment::Thinking("hmm".into()), Segment::Answer("```rust\nfn main() {}\n```".into())],
+                "chunk size {}",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn partial_tags_are_released_at_the_end() {
+        let mut splitter = ThinkingSplitter::default();
+        assert_eq!(splitter.push("a < b <thi"), vec![Segment::Answer("a < b ".into())]);
+        assert_eq!(splitter.finish(), Some(Segment::Answer("<thi".into())));
+        assert_eq!(splitter.finish(), None);
+    }
+}
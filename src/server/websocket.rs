@@ -1,25 +1,41 @@
-use crate::agent::AIAgent;
+use crate::agent::{AIAgent, MessageOptions, ProgressEvent, RequestTimeout};
 use crate::cli::Args;
-use crate::models::websocket::{ClientMessage, ServerMessage};
+use crate::models::websocket::{ClientMessage, ErrorCode, ProtocolVersion, ServerMessage};
+use crate::config::prompt::PromptError;
+use crate::rag::rag::RagEngineError;
+use crate::history::{ is_valid_conversation_id, WindowPolicy };
+use crate::llm::http::ProviderTimeout;
+use crate::server::rate_limit::{ClientIdentity, ConnectionRateLimiter, MessageRateLimiter};
+use crate::server::shutdown::Shutdown;
+use crate::server::streams::ConversationStreams;
+use crate::config::postprocess::{ PostprocessStream, Postprocessor };
+use crate::server::thinking::{ Segment, ThinkingSplitter };
 use std::error::Error;
 use std::fs::File;
 use std::io::BufReader;
 use std::net::SocketAddr;
-use std::num::NonZeroU32;
 use std::sync::Arc;
-use std::collections::HashMap;
-use tokio::sync::Mutex;
+use std::collections::{ HashMap, VecDeque };
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio::sync::{ mpsc, Mutex };
 use tokio::net::TcpListener;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_tungstenite::{accept_hdr_async, WebSocketStream};
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
 use tokio_tungstenite::tungstenite::handshake::server::{Request, Response, ErrorResponse};
-use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_tungstenite::tungstenite::http::header::{HeaderValue, SEC_WEBSOCKET_PROTOCOL};
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Message, Role};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Request as HttpRequest, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response as HttpResponse};
+use hyper_util::rt::TokioIo;
 use tokio_rustls::TlsAcceptor;
 use rustls::ServerConfig;
 use rustls::pki_types::{CertificateDer, PrivateKeyDer};
 use rustls_pemfile::{certs, pkcs8_private_keys};
-use lazy_static::lazy_static;
-use governor::{RateLimiter, Quota, state::{InMemoryState, NotKeyed}, clock::DefaultClock};
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
 use chrono::Utc;
@@ -31,9 +47,44 @@ use uuid::Uuid;
 
 type HmacSha256 = Hmac<Sha256>;
 
-lazy_static! {
-    static ref CONNECTION_LIMITER: RateLimiter<NotKeyed, InMemoryState, DefaultClock> =
-        RateLimiter::direct(Quota::per_second(NonZeroU32::new(10).unwrap()));
+/// Frames held back while an answer streams; past this the socket is not read until it ends.
+const MAX_PENDING_FRAMES: usize = 16;
+
+/// Per-connection limits derived from CLI args.
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionLimits {
+    pub max_message_size: usize,
+    /// Deadline for the first streamed fragment; `None` waits indefinitely.
+    pub first_token_timeout: Option<Duration>,
+    /// Deadline between consecutive fragments once streaming has started.
+    pub inter_token_timeout: Option<Duration>,
+    /// Buffered bytes that, once exceeded, are sent to the client.
+    pub flush_bytes: usize,
+    /// Longest time buffered text waits for `flush_bytes`; `None` waits for the threshold.
+    pub flush_interval: Option<Duration>,
+    /// Reasoning tokens forwarded per answer; `None` forwards all of it.
+    pub max_thinking_tokens: Option<u32>,
+}
+
+impl ConnectionLimits {
+    pub fn from_args(args: &Args) -> Self {
+        let secs = |s: u64| (s > 0).then(|| Duration::from_secs(s));
+        Self {
+            max_message_size: args.max_message_size,
+            first_token_timeout: secs(args.stream_first_token_timeout_secs),
+            inter_token_timeout: secs(args.stream_inter_token_timeout_secs),
+            flush_bytes: args.stream_flush_bytes,
+            flush_interval: (args.stream_flush_ms > 0).then(|| Duration::from_millis(args.stream_flush_ms)),
+            max_thinking_tokens: args.max_thinking_tokens,
+        }
+    }
+}
+
+/// What a client message asks the agent to answer.
+enum Turn {
+    Message(String),
+    /// Re-run a previous user message, optionally identified by ID.
+    Regenerate(Option<String>),
 }
 
 fn load_tls_config(
@@ -73,6 +124,8 @@ pub async fn start_ws_server(
     agent: Arc<Mutex<AIAgent>>,
     api_key: Option<String>,
     args: Args,
+    streams: Arc<ConversationStreams>,
+    shutdown: Shutdown,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     let listener = TcpListener::bind(addr).await?;
 
@@ -112,11 +165,20 @@ pub async fn start_ws_server(
         None
     };
 
+    let message_limiter = MessageRateLimiter::new(args.user_rate_limit).map(Arc::new);
+    if message_limiter.is_some() {
+        info!("Per-identity message rate limit: {} requests/minute", args.user_rate_limit);
+    }
+    let connection_limiter = ConnectionRateLimiter::from_args(&args);
+
     loop {
-        let (stream, peer) = listener.accept().await?;
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown.triggered() => break,
+        };
 
-        if let Err(_) = CONNECTION_LIMITER.check() {
-            warn!("Global connection rate limit exceeded for {}. Dropping connection.", peer);
+        if let Err(limit) = connection_limiter.check(peer.ip()) {
+            warn!("{} connection rate limit exceeded for {}. Dropping connection.", limit, peer);
             continue;
         }
 
@@ -124,9 +186,14 @@ pub async fn start_ws_server(
         let agent_clone = Arc::clone(&agent);
         let required_api_key = api_key.clone();
         let tls_acceptor_clone = tls_acceptor.clone();
-        let max_message_size = args.max_message_size;
+        let limits = ConnectionLimits::from_args(&args);
+        let message_limiter_clone = message_limiter.clone();
+        let streams_clone = streams.clone();
+        let shutdown_clone = shutdown.clone();
+        let connection = shutdown.track();
 
         tokio::spawn(async move {
+            let _connection = connection;
             let process_result = if let Some(acceptor) = tls_acceptor_clone {
                 match acceptor.accept(stream).await {
                     Ok(tls_stream) => {
@@ -136,7 +203,10 @@ pub async fn start_ws_server(
                             tls_stream,
                             agent_clone,
                             required_api_key,
-                            max_message_size
+                            limits,
+                            message_limiter_clone,
+                            streams_clone,
+                            shutdown_clone
                         ).await
                     }
                     Err(e) => {
@@ -150,7 +220,10 @@ pub async fn start_ws_server(
                     stream, 
                     agent_clone, 
                     required_api_key, 
-                    max_message_size
+                    limits,
+                    message_limiter_clone,
+                    streams_clone,
+                    shutdown_clone
                 ).await
             };
 
@@ -159,72 +232,254 @@ pub async fn start_ws_server(
             }
         });
     }
+
+    info!("WebSocket server stopped accepting connections");
+    drop(listener);
+    shutdown.drain().await;
+    Ok(())
+}
+
+/// Checks a handshake's subprotocol and HMAC credentials. Returns the negotiated
+/// version (`None` when the client asked for none) and identity, or the HTTP status
+/// and reason to reject the connection with.
+fn authorize_handshake(
+    peer: SocketAddr,
+    requested_protocols: Option<&str>,
+    query: &str,
+    required_api_key: Option<&str>
+) -> Result<(Option<ProtocolVersion>, ClientIdentity), (u16, String)> {
+    let version = match ProtocolVersion::negotiate(requested_protocols) {
+        Ok(Some(version)) => {
+            info!("Negotiated subprotocol {} for {}", version.as_str(), peer);
+            Some(version)
+        }
+        Ok(None) => None,
+        Err(requested) => {
+            warn!("Unsupported subprotocol(s) '{}' requested by {}", requested, peer);
+            return Err((
+                400,
+                format!(
+                    "unsupported subprotocol '{}'; supported: {}",
+                    requested,
+                    ProtocolVersion::supported_list()
+                ),
+            ));
+        }
+    };
+    let anonymous = ClientIdentity::Anonymous(peer.ip());
+
+    let secret = match required_api_key {
+        Some(k) if !k.is_empty() => k,
+        _ => return Ok((version, anonymous)),
+    };
+
+    let params: HashMap<String, String> =
+        form_urlencoded::parse(query.as_bytes()).into_owned().collect();
+
+    info!("Auth params from {}: {}", peer, param_names(&params));
+
+    let ts = params.get("ts")
+        .or_else(|| params.get("X-Api-Ts"))
+        .map(|s| s.as_str());
+    let sig = params.get("sig")
+        .or_else(|| params.get("X-Api-Sign"))
+        .map(|s| s.as_str());
+    let user = params.get("user")
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty());
+
+    let (Some(ts), Some(sig)) = (ts, sig) else {
+        return Err((401, "missing ts/sig".into()));
+    };
+    let now = Utc::now().timestamp();
+    let ts_i: i64 = ts.parse().unwrap_or(0);
+    if (now - ts_i).abs() > 300 {
+        return Err((401, "timestamp out of range".into()));
+    }
+
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(ts.as_bytes());
+    if let Some(user) = user {
+        // The identity is only trusted when it is covered by the signature.
+        mac.update(b":");
+        mac.update(user.as_bytes());
+    }
+    let expected = hex::encode(mac.finalize().into_bytes());
+
+    if expected != sig {
+        return Err((401, "bad signature".into()));
+    }
+    let identity = match user {
+        Some(user) => ClientIdentity::User(user.to_string()),
+        None => anonymous,
+    };
+    Ok((version, identity))
+}
+
+/// Names of the query parameters, sorted, for logging without the signature or timestamp.
+fn param_names(params: &HashMap<String, String>) -> String {
+    let mut names: Vec<&str> = params.keys().map(String::as_str).collect();
+    names.sort_unstable();
+    names.join(", ")
+}
+
+/// Everything the unified server's WebSocket route needs to accept connections.
+#[derive(Clone)]
+pub struct UpgradeContext {
+    agent: Arc<Mutex<AIAgent>>,
+    api_key: Option<String>,
+    limits: ConnectionLimits,
+    message_limiter: Option<Arc<MessageRateLimiter>>,
+    connection_limiter: Arc<ConnectionRateLimiter>,
+    streams: Arc<ConversationStreams>,
+    shutdown: Shutdown,
+}
+
+impl UpgradeContext {
+    pub fn new(agent: Arc<Mutex<AIAgent>>, args: &Args, streams: Arc<ConversationStreams>, shutdown: Shutdown) -> Self {
+        let message_limiter = MessageRateLimiter::new(args.user_rate_limit).map(Arc::new);
+        if message_limiter.is_some() {
+            info!("Per-identity message rate limit: {} requests/minute", args.user_rate_limit);
+        }
+        Self {
+            agent,
+            api_key: args.server_api_key.clone(),
+            limits: ConnectionLimits::from_args(args),
+            message_limiter,
+            connection_limiter: Arc::new(ConnectionRateLimiter::from_args(args)),
+            streams,
+            shutdown,
+        }
+    }
 }
 
+/// Upgrades a request on the unified server's `WS_PATH` to a WebSocket and serves it
+/// like a connection to the standalone server, with the same handshake checks.
+pub async fn upgrade_handler(
+    State(ctx): State<UpgradeContext>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    mut req: HttpRequest
+) -> HttpResponse {
+    if let Err(limit) = ctx.connection_limiter.check(peer.ip()) {
+        warn!("{} connection rate limit exceeded for {}. Rejecting upgrade.", limit, peer);
+        return (StatusCode::TOO_MANY_REQUESTS, "too many connections").into_response();
+    }
+
+    let headers = req.headers();
+    let is_upgrade = headers
+        .get(header::UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+    let key = headers.get(header::SEC_WEBSOCKET_KEY).map(|k| derive_accept_key(k.as_bytes()));
+    let (true, Some(accept_key)) = (is_upgrade, key) else {
+        return (StatusCode::BAD_REQUEST, "expected a WebSocket upgrade request").into_response();
+    };
+    if headers.get(header::SEC_WEBSOCKET_VERSION).is_none_or(|v| v.as_bytes() != b"13") {
+        return (StatusCode::BAD_REQUEST, "unsupported WebSocket version; expected 13").into_response();
+    }
+
+    let requested_protocols = headers.get(header::SEC_WEBSOCKET_PROTOCOL).and_then(|v| v.to_str().ok());
+    let query = req.uri().query().unwrap_or("");
+    let (version, identity) = match authorize_handshake(peer, requested_protocols, query, ctx.api_key.as_deref()) {
+        Ok(accepted) => accepted,
+        Err((status, reason)) => {
+            let status = StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_REQUEST);
+            return (status, reason).into_response();
+        }
+    };
+
+    info!("Incoming connection from: {}", peer);
+    let on_upgrade = hyper::upgrade::on(&mut req);
+    let connection = ctx.shutdown.track();
+    tokio::spawn(async move {
+        let _connection = connection;
+        match on_upgrade.await {
+            Ok(upgraded) => {
+                let ws = WebSocketStream::from_raw_socket(TokioIo::new(upgraded), Role::Server, None).await;
+                handle_connection(
+                    peer,
+                    ws,
+                    ctx.agent,
+                    ctx.limits,
+                    version.unwrap_or(ProtocolVersion::V1),
+                    identity,
+                    ctx.message_limiter,
+                    ctx.streams,
+                    ctx.shutdown
+                ).await;
+            }
+            Err(e) => error!("WebSocket upgrade failed for {}: {}", peer, e),
+        }
+    });
+
+    let mut response = HttpResponse::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::CONNECTION, "upgrade")
+        .header(header::UPGRADE, "websocket")
+        .header(header::SEC_WEBSOCKET_ACCEPT, accept_key);
+    if let Some(version) = version {
+        response = response.header(header::SEC_WEBSOCKET_PROTOCOL, version.as_str());
+    }
+    response.body(Body::empty()).unwrap()
+}
+
+#[allow(clippy::result_large_err, clippy::too_many_arguments)]
 async fn process_connection<S>(
     peer: SocketAddr,
     stream: S,
     agent_clone: Arc<Mutex<AIAgent>>,
     required_api_key: Option<String>,
-    max_message_size: usize
+    limits: ConnectionLimits,
+    message_limiter: Option<Arc<MessageRateLimiter>>,
+    streams: Arc<ConversationStreams>,
+    shutdown: Shutdown
 ) -> Result<(), Box<dyn Error + Send + Sync>>
     where S: AsyncRead + AsyncWrite + Unpin + Send + 'static
 {
-    let auth_callback = |req: &Request,  response: Response| -> Result<Response, ErrorResponse> {
-        let secret = match &required_api_key {
-            Some(k) if !k.is_empty() => k,
-            _ => return Ok(response), 
-        };
-
-        let qs = req.uri().query().unwrap_or("");
-        let params: HashMap<String, String> =
-            form_urlencoded::parse(qs.as_bytes()).into_owned().collect();
-
-        info!("Auth params from {}: {:?}", peer, params);
-
-        let ts = params.get("ts")
-            .or_else(|| params.get("X-Api-Ts"))
-            .map(|s| s.as_str());
-        let sig = params.get("sig")
-            .or_else(|| params.get("X-Api-Sign")) 
-            .map(|s| s.as_str());
+    let mut protocol_version = ProtocolVersion::V1;
+    let mut identity = ClientIdentity::Anonymous(peer.ip());
 
-        if let (Some(ts), Some(sig)) = (ts, sig) {
-            let now = Utc::now().timestamp();
-            let ts_i: i64 = ts.parse().unwrap_or(0);
-            if (now - ts_i).abs() > 300 {
-                let res = Response::builder()
-                    .status(401) 
-                    .body(Some("timestamp out of range".into()))
-                    .unwrap();
-                return Err(ErrorResponse::from(res));
-            }
+    let auth_callback = |req: &Request, mut response: Response| -> Result<Response, ErrorResponse> {
+        let requested_protocols = req.headers()
+            .get(SEC_WEBSOCKET_PROTOCOL)
+            .and_then(|v| v.to_str().ok());
+        let query = req.uri().query().unwrap_or("");
 
-            let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
-            mac.update(ts.as_bytes());
-            let expected = hex::encode(mac.finalize().into_bytes());
-
-            if expected == sig {
+        match authorize_handshake(peer, requested_protocols, query, required_api_key.as_deref()) {
+            Ok((version, client)) => {
+                if let Some(version) = version {
+                    response.headers_mut().insert(
+                        SEC_WEBSOCKET_PROTOCOL,
+                        HeaderValue::from_static(version.as_str())
+                    );
+                    protocol_version = version;
+                }
+                identity = client;
                 Ok(response)
-            } else {
+            }
+            Err((status, reason)) => {
                 let res = Response::builder()
-                    .status(401) 
-                    .body(Some("bad signature".into()))
+                    .status(status)
+                    .body(Some(reason))
                     .unwrap();
                 Err(ErrorResponse::from(res))
             }
-        } else {
-            let res = Response::builder()
-                .status(401) 
-                .body(Some("missing ts/sig".into()))
-                .unwrap();
-            Err(ErrorResponse::from(res))
         }
     };
 
     match accept_hdr_async(stream, auth_callback).await {
         Ok(ws) => {
-            handle_connection(peer, ws, agent_clone, max_message_size).await;
+            handle_connection(
+                peer,
+                ws,
+                agent_clone,
+                limits,
+                protocol_version,
+                identity,
+                message_limiter,
+                streams,
+                shutdown
+            ).await;
             Ok(())
         }
         Err(e) => {
@@ -234,38 +489,62 @@ async fn process_connection<S>(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_connection<S>(
     peer: SocketAddr,
     websocket: WebSocketStream<S>,
     agent: Arc<Mutex<AIAgent>>,
-    max_message_size: usize
+    limits: ConnectionLimits,
+    protocol_version: ProtocolVersion,
+    identity: ClientIdentity,
+    message_limiter: Option<Arc<MessageRateLimiter>>,
+    streams: Arc<ConversationStreams>,
+    shutdown: Shutdown
 )
     where S: AsyncRead + AsyncWrite + Unpin
 {
-    info!("New WebSocket connection: {}", peer);
+    info!(
+        "New WebSocket connection: {} (protocol {}, identity {})",
+        peer,
+        protocol_version.as_str(),
+        identity
+    );
 
     let (mut tx, mut rx) = websocket.split();
-    let conversation_id = Uuid::new_v4().to_string();
+    let mut conversation_id = Uuid::new_v4().to_string();
     info!("Assigned conversation ID {} to {}", conversation_id, peer);
+    let mut window_policy = WindowPolicy::default();
 
-    let mut buffer = String::new();
-    let mut in_thinking_section = false;
-    let mut partial_close_tag = false;
-    let mut partial_open_tag = false; 
+    // Frames read while an answer was streaming, handled once the turn ends.
+    let mut pending = VecDeque::new();
 
-    while let Some(msg) = rx.next().await {
+    loop {
+        let msg = match pending.pop_front() {
+            Some(frame) => frame,
+            // A shutdown closes the connection between turns, never during an answer.
+            None => tokio::select! {
+                frame = rx.next() => match frame {
+                    Some(frame) => frame,
+                    None => break,
+                },
+                _ = shutdown.triggered() => {
+                    info!("Closing connection {} for shutdown", peer);
+                    let close = CloseFrame { code: CloseCode::Away, reason: "server shutting down".into() };
+                    let _ = tx.send(Message::Close(Some(close))).await;
+                    break;
+                }
+            },
+        };
         match msg {
             Ok(message) => {
-                if message.len() > max_message_size {
+                if message.len() > limits.max_message_size {
                     warn!(
                         "Message from {} exceeds size limit ({} > {})",
                         peer,
                         message.len(),
-                        max_message_size
+                        limits.max_message_size
                     );
-                    let error_msg = ServerMessage::Error {
-                        message: "Message too large".to_string(),
-                    };
+                    let error_msg = error_message(protocol_version, ErrorCode::BadRequest, "Message too large", None);
                     let json = serde_json::to_string(&error_msg).unwrap();
                     if tx.send(Message::Text(json)).await.is_err() {
                         error!("Failed to send size limit error to {}", peer);
@@ -275,14 +554,99 @@ pub async fn handle_connection<S>(
 
                 match message {
                     Message::Text(text) => {
-                        match serde_json::from_str::<ClientMessage>(&text) {
-                            Ok(ClientMessage::Chat { content, capabilities }) => {
+                        match protocol_version.parse_client_message(&text) {
+                            Ok(client_message) => {
+                                let requested_conversation = match &client_message {
+                                    ClientMessage::Chat { conversation_id, .. } => conversation_id.clone(),
+                                    ClientMessage::Resume { conversation_id } => Some(conversation_id.clone()),
+                                    _ => None,
+                                };
+                                if let Some(requested) = requested_conversation {
+                                    if !is_valid_conversation_id(&requested) {
+                                        warn!("Rejected malformed conversation ID from {}", peer);
+                                        let error_msg = error_message(
+                                            protocol_version,
+                                            ErrorCode::BadRequest,
+                                            "invalid conversation_id: expected 1-128 letters, digits, '-' or '_'",
+                                            None
+                                        );
+                                        if tx.send(Message::Text(serde_json::to_string(&error_msg).unwrap())).await.is_err() {
+                                            error!("Failed to send conversation ID error to {}", peer);
+                                            break;
+                                        }
+                                        continue;
+                                    }
+                                    if requested != conversation_id {
+                                        info!("{} switched from conversation {} to {}", peer, conversation_id, requested);
+                                        conversation_id = requested;
+                                    }
+                                }
+                                let (turn, images, requested_policy, language, rag_limit, capabilities) = match client_message {
+                                    ClientMessage::Chat { content, images, window_policy, language, rag_limit, capabilities, .. } =>
+                                        (Turn::Message(content), images, window_policy, language, rag_limit, capabilities),
+                                    ClientMessage::Regenerate { from_message_id, window_policy, language, rag_limit, capabilities } =>
+                                        (Turn::Regenerate(from_message_id), Vec::new(), window_policy, language, rag_limit, capabilities),
+                                    ClientMessage::Resume { .. } => continue,
+                                    ClientMessage::Cancel {} => {
+                                        info!("Ignoring cancel from {}: no answer is streaming", peer);
+                                        continue;
+                                    }
+                                };
+                                if let Some(limiter) = &message_limiter {
+                                    if let Err(retry_after) = limiter.check(&identity) {
+                                        warn!("Rate limit exceeded for {} ({})", identity, peer);
+                                        let error_msg = error_message(protocol_version, ErrorCode::RateLimited, "rate limited", Some(retry_after));
+                                        let json = serde_json::to_string(&error_msg).unwrap();
+                                        if tx.send(Message::Text(json)).await.is_err() {
+                                            error!("Failed to send rate limit error to {}", peer);
+                                            break;
+                                        }
+                                        continue;
+                                    }
+                                }
+
+                                let Ok(stream_guard) = streams.acquire(&conversation_id).await else {
+                                    let error_msg = error_message(protocol_version, ErrorCode::Busy, "conversation busy", None);
+                                    let json = serde_json::to_string(&error_msg).unwrap();
+                                    if tx.send(Message::Text(json)).await.is_err() {
+                                        error!("Failed to send busy error to {}", peer);
+                                        break;
+                                    }
+                                    continue;
+                                };
+
+                                if let Some(policy) = requested_policy {
+                                    window_policy = policy;
+                                }
+                                let client_supports_progress = capabilities
+                                    .as_ref()
+                                    .map(|caps| caps.supports_progress)
+                                    .unwrap_or(false);
+                                let (progress_tx, mut progress_rx) = mpsc::unbounded_channel();
+                                let options = MessageOptions {
+                                    images,
+                                    window_policy,
+                                    language,
+                                    progress: client_supports_progress.then_some(progress_tx),
+                                    rag_limit,
+                                    message_id: matches!(turn, Turn::Message(_)).then(|| Uuid::new_v4().to_string()),
+                                    bypass_cache: false,
+                                };
                                 let client_supports_thinking = capabilities
                                     .as_ref()
                                     .map(|caps| caps.supports_thinking)
                                     .unwrap_or(false);
 
-                                if client_supports_thinking {
+                                let client_wants_status = capabilities
+                                    .as_ref()
+                                    .map(|caps| caps.wants_status_events)
+                                    .unwrap_or(true);
+                                let client_supports_metadata = capabilities
+                                    .as_ref()
+                                    .map(|caps| caps.supports_metadata)
+                                    .unwrap_or(false);
+
+                                if client_supports_thinking && client_wants_status {
                                     let thinking_start = ServerMessage::Thinking { 
                                         started: true,
                                     };
@@ -292,162 +656,157 @@ pub async fn handle_connection<S>(
                                     }
                                 }
 
-                                let typing_msg = ServerMessage::Typing;
-                                if let Err(e) = tx.send(Message::Text(serde_json::to_string(&typing_msg).unwrap())).await {
-                                    error!("Error sending typing status to {}: {}", peer, e);
-                                    break;
+                                if client_wants_status {
+                                    let typing_msg = ServerMessage::Typing;
+                                    if let Err(e) = tx.send(Message::Text(serde_json::to_string(&typing_msg).unwrap())).await {
+                                        error!("Error sending typing status to {}: {}", peer, e);
+                                        break;
+                                    }
                                 }
 
-                                let stream_result = agent
-                                    .lock().await
-                                    .process_message_stream(&conversation_id, &content)
-                                    .await;
+                                let stream_future = async {
+                                    let agent_guard = agent.lock().await;
+                                    let postprocessor = agent_guard.response_postprocessor().await;
+                                    match &turn {
+                                        Turn::Message(content) => agent_guard
+                                            .process_message_stream(&conversation_id, content, &options).await
+                                            .map(|outcome| (options.message_id.clone(), outcome, postprocessor)),
+                                        Turn::Regenerate(from_message_id) => agent_guard
+                                            .regenerate_stream(&conversation_id, from_message_id.as_deref(), &options).await
+                                            .map(|(message_id, outcome)| (Some(message_id), outcome, postprocessor)),
+                                    }
+                                };
+                                tokio::pin!(stream_future);
+                                let stream_result = loop {
+                                    tokio::select! {
+                                        result = &mut stream_future => break result,
+                                        Some(event) = progress_rx.recv() => {
+                                            send_progress(&mut tx, event).await;
+                                        }
+                                    }
+                                };
+                                while let Ok(event) = progress_rx.try_recv() {
+                                    send_progress(&mut tx, event).await;
+                                }
 
                                 match stream_result {
-                                    Ok(mut stream) => {
-                                        while let Some(chunk_res) = stream.next().await {
-                                            match chunk_res {
-                                                Ok(fragment) => {
-                                                    let text = fragment.as_str();
-                                                    buffer.push_str(text);
-                                                    
-                                                    if buffer.contains("```") && in_thinking_section {
-                                                        in_thinking_section = false;
-                                                        partial_close_tag = false;
-                                                        let reasoning = buffer.split("```").next().unwrap_or("");
-                                                        if !reasoning.trim().is_empty() {
-                                                            let msg = ServerMessage::ThinkingFragment { content: reasoning.to_string() };
-                                                            tx.send(Message::Text(serde_json::to_string(&msg).unwrap())).await.unwrap();
-                                                        }
-
-                                                        let after = buffer.splitn(2, "```").nth(1).unwrap_or("").to_string();
-                                                        buffer = after;
-                                                    }
-                                                    
-                                                    if !in_thinking_section && partial_open_tag && 
-                                                       (text.starts_with(">") || text.starts_with("k>") || text.starts_with("nk>") || text.starts_with("ink>")) {
-                                                        
-                                                        partial_open_tag = false;
-                                                        in_thinking_section = true;
-                                                        let after_tag_pos = text.find(">").unwrap_or(0) + 1;
-                                                        let after_tag = &text[after_tag_pos..];
-                                                        let msg = ServerMessage::ThinkingFragment { 
-                                                            content: after_tag.to_string() 
-                                                        };
-                                                        tx.send(Message::Text(serde_json::to_string(&msg).unwrap())).await.unwrap();
-                                                        
-                                                        buffer = after_tag.to_string();
-                                                        continue;
-                                                    }
-                                                    
-                                                    if !in_thinking_section && 
-                                                       (buffer.ends_with("<t") || buffer.ends_with("<th") || 
-                                                        buffer.ends_with("<thi") || buffer.ends_with("<thin") || 
-                                                        buffer.ends_with("<think")) {
-                                                        partial_open_tag = true;
-                                                        continue;
-                                                    }
-                                                    
-                                                    if in_thinking_section && !buffer.contains("</think>") {
-                                                        if buffer.ends_with("<") || (buffer.ends_with("</") && !text.starts_with("think>")) {
-                                                            partial_close_tag = true;
-                                                            continue;
+                                    Ok((message_id, outcome, postprocessor)) => {
+                                        if let Some(metadata) = outcome.metadata.filter(|_| client_supports_metadata) {
+                                            let metadata_msg = ServerMessage::Metadata {
+                                                topic: metadata.topic,
+                                                fields: metadata.fields,
+                                                hit_count: metadata.hit_count,
+                                            };
+                                            if let Err(e) = tx.send(Message::Text(serde_json::to_string(&metadata_msg).unwrap())).await {
+                                                error!("Error sending retrieval metadata to {}: {}", peer, e);
+                                            }
+                                        }
+                                        let mut stream = Box::pin(outcome.stream.take_until(stream_guard.cancelled()));
+                                        let mut splitter = ThinkingSplitter::default();
+                                        let mut buffer = String::new();
+                                        let mut in_thinking_section = false;
+                                        let mut output = TurnOutput::new(limits.max_thinking_tokens, &postprocessor);
+                                        let mut received_any = false;
+                                        let mut waiting_since = Instant::now();
+                                        let mut last_flush = Instant::now();
+                                        let mut cancelled_by_client = false;
+                                        let mut client_gone = false;
+                                        loop {
+                                            let deadline = if received_any {
+                                                limits.inter_token_timeout
+                                            } else {
+                                                limits.first_token_timeout
+                                            };
+                                            let flush_at = limits.flush_interval
+                                                .filter(|_| !buffer.is_empty())
+                                                .map(|interval| last_flush + interval);
+                                            let next_fragment = async {
+                                                match deadline {
+                                                    Some(d) => tokio::time::timeout_at(waiting_since + d, stream.next()).await.map_err(|_| d),
+                                                    None => Ok(stream.next().await),
+                                                }
+                                            };
+                                            let next = tokio::select! {
+                                                next = next_fragment => next,
+                                                _ = tokio::time::sleep_until(flush_at.unwrap_or_else(Instant::now)), if flush_at.is_some() => {
+                                                    flush_buffer(&mut tx, &mut buffer, in_thinking_section, &mut output).await;
+                                                    last_flush = Instant::now();
+                                                    continue;
+                                                }
+                                                incoming = rx.next(), if pending.len() < MAX_PENDING_FRAMES => {
+                                                    match incoming {
+                                                        Some(Ok(Message::Text(text))) if matches!(
+                                                            protocol_version.parse_client_message(&text),
+                                                            Ok(ClientMessage::Cancel {})
+                                                        ) => {
+                                                            info!("Stream for conversation {} cancelled by the client", conversation_id);
+                                                            cancelled_by_client = true;
+                                                            break;
                                                         }
-                                                        
-                                                        if partial_close_tag && text.starts_with("think>") || text.starts_with("/think>") {
-                                                            in_thinking_section = false;
-                                                            partial_close_tag = false;
-                                                            
-                                                            let think_content = if buffer.ends_with("</") {
-                                                                &buffer[..buffer.len()-2]
-                                                            } else if buffer.ends_with("<") {
-                                                                &buffer[..buffer.len()-1]
-                                                            } else {
-                                                                buffer.as_str()
-                                                            };
-                                                            
-                                                            if !think_content.is_empty() {
-                                                                let think_msg = ServerMessage::ThinkingFragment { 
-                                                                    content: think_content.to_string() 
-                                                                };
-                                                                tx.send(Message::Text(serde_json::to_string(&think_msg).unwrap())).await.unwrap();
-                                                            }
-                                                            
-                                                            let after_tag_pos = text.find(">").unwrap_or(0) + 1;
-                                                            if after_tag_pos < text.len() {
-                                                                let after_content = &text[after_tag_pos..];
-                                                                if !after_content.is_empty() {
-                                                                    let clean_content = clean_response_text(after_content);
-                                                                    let part = ServerMessage::Partial { content: clean_content };
-                                                                    tx.send(Message::Text(serde_json::to_string(&part).unwrap())).await.unwrap();
-                                                                }
+                                                        Some(Ok(Message::Ping(ping_data))) => {
+                                                            if tx.send(Message::Pong(ping_data)).await.is_err() {
+                                                                client_gone = true;
+                                                                break;
                                                             }
-                                                            
-                                                            buffer.clear();
-                                                            continue;
                                                         }
-                                                    }
-                                                    
-                                                    if !in_thinking_section && buffer.contains("<think>") {
-                                                        in_thinking_section = true;
-                                                        let start_pos = buffer.find("<think>").unwrap();
-                                                        let after_tag = &buffer[start_pos + "<think>".len()..];
-                                                        let msg = ServerMessage::ThinkingFragment { 
-                                                            content: after_tag.to_string() 
-                                                        };
-                                                        tx.send(Message::Text(serde_json::to_string(&msg).unwrap())).await.unwrap();
-                                                        
-                                                        buffer = after_tag.to_string();
-                                                        continue;
-                                                    }
-                                                    
-                                                    if in_thinking_section && buffer.contains("</think>") {
-                                                        let end_pos = buffer.find("</think>").unwrap();
-                                                        let thinking_part = &buffer[..end_pos];
-                                                        
-                                                        if !thinking_part.is_empty() {
-                                                            let think_msg = ServerMessage::ThinkingFragment { 
-                                                                content: thinking_part.to_string() 
-                                                            };
-                                                            tx.send(Message::Text(serde_json::to_string(&think_msg).unwrap())).await.unwrap();
+                                                        Some(Ok(Message::Pong(_))) => {}
+                                                        Some(frame @ Ok(Message::Close(_))) | Some(frame @ Err(_)) => {
+                                                            pending.push_back(frame);
+                                                            client_gone = true;
+                                                            break;
                                                         }
-                                                        
-                                                        in_thinking_section = false;
-                                                        
-                                                        let after = buffer[end_pos + "</think>".len()..].to_string();
-                                                        buffer.clear();
-                                                        
-                                                        if !after.is_empty() {
-                                                            let markdown_content = clean_markdown_content(&after);
-                                                            let part = ServerMessage::Partial { 
-                                                                content: markdown_content
-                                                            };
-                                                            tx.send(Message::Text(serde_json::to_string(&part).unwrap())).await.unwrap();
+                                                        Some(frame) => pending.push_back(frame),
+                                                        None => {
+                                                            client_gone = true;
+                                                            break;
                                                         }
-                                                        continue;
                                                     }
-                                                    
-                                                    if buffer.len() > 20 { 
-                                                        if in_thinking_section {
-                                                            let think_msg = ServerMessage::ThinkingFragment { 
-                                                                content: buffer.clone() 
-                                                            };
-                                                            tx.send(Message::Text(serde_json::to_string(&think_msg).unwrap())).await.unwrap();
-                                                        } else {
-                                                            let clean_content = clean_response_text(&buffer);
-                                                            let part = ServerMessage::Partial { 
-                                                                content: clean_content 
-                                                            };
-                                                            tx.send(Message::Text(serde_json::to_string(&part).unwrap())).await.unwrap();
+                                                    continue;
+                                                }
+                                            };
+                                            let next = match next {
+                                                Ok(item) => item,
+                                                Err(d) => {
+                                                    let reason = if received_any {
+                                                        "model stopped responding mid-stream"
+                                                    } else {
+                                                        "model did not respond in time"
+                                                    };
+                                                    warn!("Stream timeout for {} after {:?}: {}", peer, d, reason);
+                                                    let error_msg = error_message(protocol_version, ErrorCode::Timeout, reason, None);
+                                                    let json = serde_json::to_string(&error_msg).unwrap();
+                                                    if let Err(e) = tx.send(Message::Text(json)).await {
+                                                        error!("Error sending timeout error to {}: {}", peer, e);
+                                                    }
+                                                    break;
+                                                }
+                                            };
+                                            let Some(chunk_res) = next else {
+                                                break;
+                                            };
+                                            received_any = true;
+                                            waiting_since = Instant::now();
+                                            match chunk_res {
+                                                Ok(fragment) => {
+                                                    for segment in splitter.push(&fragment) {
+                                                        if buffer_segment(&mut tx, &mut buffer, &mut in_thinking_section, &mut output, segment).await {
+                                                            last_flush = Instant::now();
                                                         }
-                                                        buffer.clear();
+                                                    }
+
+                                                    if buffer.len() > limits.flush_bytes {
+                                                        flush_buffer(&mut tx, &mut buffer, in_thinking_section, &mut output).await;
+                                                        last_flush = Instant::now();
                                                     }
                                                 }
                                                 Err(e) => {
                                                     error!("Stream error for {}: {}", peer, e);
-                                                    let error_msg = ServerMessage::Error {
-                                                        message: format!("Stream error: {}", e),
-                                                    };
+                                                    let error_msg = error_message(
+                                                        protocol_version,
+                                                        classify_error(e.as_ref(), ErrorCode::ProviderError),
+                                                        format!("Stream error: {}", e),
+                                                        None
+                                                    );
                                                     let json = serde_json::to_string(&error_msg).unwrap();
                                                     if let Err(e_inner) = tx.send(Message::Text(json)).await {
                                                         error!("Error sending stream error to {}: {}", peer, e_inner);
@@ -457,30 +816,58 @@ pub async fn handle_connection<S>(
                                             }
                                         }
 
+                                        drop(stream);
+                                        if client_gone {
+                                            info!("Client {} went away while streaming; answer dropped", peer);
+                                            continue;
+                                        }
+                                        if cancelled_by_client {
+                                            // Unsent text is discarded.
+                                            buffer.clear();
+                                        } else if let Some(segment) = splitter.finish() {
+                                            buffer_segment(&mut tx, &mut buffer, &mut in_thinking_section, &mut output, segment).await;
+                                        }
+
+                                        let mut rest = String::new();
                                         if !buffer.is_empty() {
                                             if in_thinking_section {
-                                                let think_msg = ServerMessage::ThinkingFragment { content: buffer.clone() };
-                                                tx.send(Message::Text(serde_json::to_string(&think_msg).unwrap())).await.unwrap();
+                                                send_thinking(&mut tx, &mut output.budget, &buffer).await;
                                             } else {
-                                                let part = ServerMessage::Partial { content: buffer.clone() };
-                                                tx.send(Message::Text(serde_json::to_string(&part).unwrap())).await.unwrap();
+                                                rest = output.answer.push(&buffer);
+                                            }
+                                        }
+                                        if !cancelled_by_client {
+                                            rest.push_str(&output.answer.finish());
+                                        }
+                                        if !rest.is_empty() {
+                                            let part = ServerMessage::Partial { content: rest };
+                                            if let Err(e) = tx.send(Message::Text(serde_json::to_string(&part).unwrap())).await {
+                                                error!("Error sending final fragment to {}: {}", peer, e);
                                             }
                                         }
 
-                                        let done_msg = ServerMessage::Done {
-                                            timestamp: Utc::now().timestamp(),
+                                        let final_msg = if stream_guard.is_cancelled() {
+                                            info!("Stream for conversation {} cancelled by a newer message", conversation_id);
+                                            error_message(protocol_version, ErrorCode::Cancelled, "cancelled by a newer message", None)
+                                        } else {
+                                            ServerMessage::Done {
+                                                timestamp: Utc::now().timestamp(),
+                                                message_id,
+                                                conversation_id: Some(conversation_id.clone()),
+                                                thinking_truncated: output.budget.exceeded.then_some(true),
+                                                cancelled: cancelled_by_client.then_some(true),
+                                            }
                                         };
-                                        let json = serde_json::to_string(&done_msg).unwrap();
+                                        let json = serde_json::to_string(&final_msg).unwrap();
                                         if let Err(e) = tx.send(Message::Text(json)).await {
                                             error!("Error sending done message to {}: {}", peer, e);
                                         }
                                     }
                                     Err(e) => {
-                                        let error_message = format!("Error initiating stream: {}", e);
-                                        error!("Agent streaming error for {}: {}", peer, error_message);
-                                        let error_msg = ServerMessage::Error {
-                                            message: error_message,
-                                        };
+                                        let message = format!("Error initiating stream: {}", e);
+                                        error!("Agent streaming error for {}: {}", peer, message);
+                                        let code = classify_error(&e, ErrorCode::Internal);
+                                        let error_msg = error_message(protocol_version, code, message, None);
                                         let json = serde_json::to_string(&error_msg).unwrap();
                                         if let Err(e_inner) = tx.send(Message::Text(json)).await {
                                             error!("Error sending error message to {}: {}", peer, e_inner);
@@ -490,9 +877,12 @@ pub async fn handle_connection<S>(
                             }
                             Err(e) => {
                                 error!("Failed to parse message from {}: {}", peer, e);
-                                let error_msg = ServerMessage::Error {
-                                    message: format!("Failed to parse message: {}", e),
-                                };
+                                let error_msg = error_message(
+                                    protocol_version,
+                                    ErrorCode::BadRequest,
+                                    format!("Failed to parse message: {}", e),
+                                    None
+                                );
                                 let json = serde_json::to_string(&error_msg).unwrap();
                                 if let Err(e) = tx.send(Message::Text(json)).await {
                                     error!("Error sending parse error to {}: {}", peer, e);
@@ -532,9 +922,7 @@ pub async fn handle_connection<S>(
                     }
                     tokio_tungstenite::tungstenite::Error::Capacity(ref cap_err) => {
                         error!("WebSocket capacity error for {}: {}", peer, cap_err);
-                        let error_msg = ServerMessage::Error {
-                            message: "Server capacity error".to_string(),
-                        };
+                        let error_msg = error_message(protocol_version, ErrorCode::BadRequest, "Server capacity error", None);
                         let json = serde_json::to_string(&error_msg).unwrap();
                         let _ = tx.send(Message::Text(json)).await;
                     }
@@ -582,34 +970,170 @@ where
     Ok(())
 }
 
-fn clean_response_text(text: &str) -> String {
-    let mut cleaned = text.to_string();
-    cleaned = cleaned.replace("\\boxed{", "").replace("\\text{", "").replace("}", "");
-    cleaned = cleaned.replace("\\<strong>", "**").replace("\\</strong>", "**");
-    
-    let meta_patterns = [
-        "The user's input is",
-        "The appropriate response",
-        "Final Answer:",
-        "In response to",
-        "I'll respond with"
-    ];
-    
-    for pattern in &meta_patterns {
-        if let Some(pos) = cleaned.find(pattern) {
-            if let Some(end_pos) = cleaned[pos..].find("\n\n") {
-                cleaned = cleaned[pos + end_pos + 2..].to_string();
+/// Builds an `error` message; the code is only included for clients that negotiated it.
+fn error_message(
+    protocol_version: ProtocolVersion,
+    code: ErrorCode,
+    message: impl Into<String>,
+    retry_after: Option<u64>
+) -> ServerMessage {
+    ServerMessage::Error {
+        message: message.into(),
+        code: protocol_version.supports_error_codes().then_some(code),
+        retry_after,
+    }
+}
+
+/// Maps an agent or stream error to a code by inspecting its source chain.
+/// Errors that carry no recognisable type get `fallback`.
+fn classify_error(err: &(dyn Error + 'static), fallback: ErrorCode) -> ErrorCode {
+    let mut current = Some(err);
+    while let Some(e) = current {
+        if let Some(req) = e.downcast_ref::<reqwest::Error>() {
+            return if req.is_timeout() { ErrorCode::Timeout } else { ErrorCode::ProviderError };
+        }
+        if e.is::<RequestTimeout>() || e.is::<ProviderTimeout>() {
+            return ErrorCode::Timeout;
+        }
+        if e.is::<RagEngineError>() {
+            return ErrorCode::ProviderError;
+        }
+        if e.is::<PromptError>() {
+            return ErrorCode::Internal;
+        }
+        current = e.source();
+    }
+    fallback
+}
+
+async fn send_progress<Si>(tx: &mut Si, event: ProgressEvent)
+where
+    Si: futures::Sink<Message> + Unpin,
+{
+    let msg = ServerMessage::Progress { stage: event.stage, detail: event.detail };
+    if tx.send(Message::Text(serde_json::to_string(&msg).unwrap())).await.is_err() {
+        warn!("Failed to send progress event");
+    }
+}
+
+/// Per-turn state for the fragments sent to the client.
+struct TurnOutput {
+    budget: ThinkingBudget,
+    answer: PostprocessStream,
+}
+
+impl TurnOutput {
+    fn new(max_thinking_tokens: Option<u32>, postprocessor: &Postprocessor) -> Self {
+        Self { budget: ThinkingBudget::new(max_thinking_tokens), answer: postprocessor.stream() }
+    }
+}
+
+/// Sends the buffered text as a thinking or answer fragment and clears it.
+async fn flush_buffer<Si>(tx: &mut Si, buffer: &mut String, in_thinking_section: bool, output: &mut TurnOutput)
+where
+    Si: futures::Sink<Message> + Unpin,
+{
+    if in_thinking_section {
+        send_thinking(tx, &mut output.budget, buffer).await;
+    } else {
+        // The `response_postprocess` rules may hold back the end of the answer.
+        let content = output.answer.push(buffer);
+        if !content.is_empty() {
+            let msg = ServerMessage::Partial { content };
+            if tx.send(Message::Text(serde_json::to_string(&msg).unwrap())).await.is_err() {
+                warn!("Failed to send buffered fragment");
             }
         }
     }
-    
-    cleaned = cleaned.replace("\n\n\n", "\n\n").trim().to_string();
-    
-    cleaned
+    buffer.clear();
 }
 
-fn clean_markdown_content(text: &str) -> String {
-    let cleaned = text.trim_start_matches(|c: char| c == '\n' || c == ' ');
-    
-    cleaned.replace("\\boxed{", "").replace("\\text{", "").replace("}", "")
-}
\ No newline at end of file
+/// Appends a segment to the buffer, first flushing what is buffered when the segment
+/// switches between reasoning and answer. Returns whether it flushed.
+async fn buffer_segment<Si>(
+    tx: &mut Si,
+    buffer: &mut String,
+    in_thinking_section: &mut bool,
+    output: &mut TurnOutput,
+    segment: Segment
+) -> bool
+where
+    Si: futures::Sink<Message> + Unpin,
+{
+    let (is_thinking, text) = match segment {
+        Segment::Thinking(text) => (true, text),
+        Segment::Answer(text) => (false, text),
+    };
+    let flushed = is_thinking != *in_thinking_section && !buffer.is_empty();
+    if flushed {
+        flush_buffer(tx, buffer, *in_thinking_section, output).await;
+    }
+    *in_thinking_section = is_thinking;
+    buffer.push_str(&text);
+    flushed
+}
+
+/// Reasoning a turn may still forward to the client (`--max-thinking-tokens`),
+/// counted with the same ~4 characters per token estimate as usage fallbacks.
+struct ThinkingBudget {
+    remaining_chars: Option<usize>,
+    exceeded: bool,
+}
+
+impl ThinkingBudget {
+    fn new(max_tokens: Option<u32>) -> Self {
+        Self { remaining_chars: max_tokens.map(|t| t as usize * 4), exceeded: false }
+    }
+
+    /// The part of `fragment` still within budget; empty once the budget is spent.
+    fn admit<'a>(&mut self, fragment: &'a str) -> &'a str {
+        let Some(remaining) = self.remaining_chars.as_mut() else {
+            return fragment;
+        };
+        if self.exceeded {
+            return "";
+        }
+        match fragment.char_indices().nth(*remaining) {
+            Some((cut, _)) => {
+                self.exceeded = true;
+                info!("Thinking budget reached; dropping the rest of the reasoning");
+                &fragment[..cut]
+            }
+            None => {
+                *remaining -= fragment.chars().count();
+                fragment
+            }
+        }
+    }
+}
+
+/// Sends a reasoning fragment, trimmed to what the budget still allows.
+async fn send_thinking<Si>(tx: &mut Si, budget: &mut ThinkingBudget, content: &str)
+where
+    Si: futures::Sink<Message> + Unpin,
+{
+    let content = budget.admit(content);
+    if content.is_empty() {
+        return;
+    }
+    let msg = ServerMessage::ThinkingFragment { content: content.to_string() };
+    if tx.send(Message::Text(serde_json::to_string(&msg).unwrap())).await.is_err() {
+        warn!("Failed to send thinking fragment");
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auth_log_names_params_without_their_values() {
+        let params: HashMap<String, String> = form_urlencoded::parse(b"ts=1700000000&sig=deadbeef&user=ada")
+            .into_owned()
+            .collect();
+        let logged = param_names(&params);
+        assert_eq!(logged, "sig, ts, user");
+        assert!(!logged.contains("deadbeef") && !logged.contains("1700000000"));
+    }
+}
@@ -0,0 +1,201 @@
+//! In-process stand-ins for the LLM providers and the vector store, enabled by the
+//! `testing` feature. Combine them with `AIAgent::for_test` to run the agent
+//! without Redis, Qdrant or a model server.
+
+use async_trait::async_trait;
+use futures::Stream;
+use rllm::builder::LLMBackend;
+use serde_json::Value;
+use std::collections::{ HashMap, VecDeque };
+use std::error::Error;
+use std::pin::Pin;
+use std::sync::Mutex;
+use vector_nexus::db::VectorStore;
+use vector_nexus::schema::IndexSchema;
+
+use crate::llm::chat::{ ChatClient, CompletionResponse };
+use crate::llm::embedding::{ EmbeddingClient, EmbeddingResponse };
+
+pub use crate::history::InMemoryHistoryStore;
+
+/// Chat client that answers with scripted responses in order and records every prompt.
+/// Streaming returns the next response as a single fragment, or in pieces after `fragmented`.
+pub struct MockChatClient {
+    responses: Mutex<VecDeque<String>>,
+    prompts: Mutex<Vec<String>>,
+    fragment_chars: Option<usize>,
+}
+
+impl MockChatClient {
+    pub fn new<I, S>(responses: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            responses: Mutex::new(responses.into_iter().map(Into::into).collect()),
+            prompts: Mutex::new(Vec::new()),
+            fragment_chars: None,
+        }
+    }
+
+    /// Streams each response in fragments of `chars` characters.
+    pub fn fragmented(mut self, chars: usize) -> Self {
+        self.fragment_chars = Some(chars.max(1));
+        self
+    }
+
+    /// Queues another response after the remaining ones.
+    pub fn push_response(&self, response: impl Into<String>) {
+        self.responses.lock().unwrap().push_back(response.into());
+    }
+
+    /// Prompts received so far, oldest first.
+    pub fn prompts(&self) -> Vec<String> {
+        self.prompts.lock().unwrap().clone()
+    }
+
+    fn next_response(&self, prompt: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        self.prompts.lock().unwrap().push(prompt.to_string());
+        self.responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or_else(|| format!("MockChatClient has no scripted response left for prompt: {}", prompt).into())
+    }
+}
+
+#[async_trait]
+impl ChatClient for MockChatClient {
+    async fn complete(&self, prompt: &str) -> Result<CompletionResponse, Box<dyn Error + Send + Sync>> {
+        Ok(CompletionResponse { response: self.next_response(prompt)?, ..Default::default() })
+    }
+
+    async fn stream_completion(
+        &self,
+        prompt: &str
+    ) -> Result<
+        Pin<Box<dyn Stream<Item = Result<String, Box<dyn Error + Send + Sync>>> + Send>>,
+        Box<dyn Error + Send + Sync>
+    > {
+        let response = self.next_response(prompt)?;
+        let Some(size) = self.fragment_chars else {
+            return Ok(Box::pin(futures::stream::once(async move { Ok(response) })));
+        };
+        let chars: Vec<char> = response.chars().collect();
+        let fragments: Vec<_> = chars.chunks(size).map(|c| Ok(c.iter().collect())).collect();
+        Ok(Box::pin(futures::stream::iter(fragments)))
+    }
+
+    fn get_api_key(&self) -> String {
+        String::new()
+    }
+
+    fn get_model(&self) -> String {
+        "mock".to_string()
+    }
+
+    fn get_base_url(&self) -> Option<String> {
+        None
+    }
+
+    fn get_llm_backend(&self) -> LLMBackend {
+        LLMBackend::Ollama
+    }
+
+    fn supports_native_streaming(&self) -> bool {
+        true
+    }
+}
+
+/// Embedding client returning a deterministic unit vector per text: byte values
+/// are folded into `dimension` buckets, so identical texts embed identically.
+pub struct MockEmbeddingClient {
+    dimension: usize,
+}
+
+impl MockEmbeddingClient {
+    pub fn new(dimension: usize) -> Self {
+        Self { dimension: dimension.max(1) }
+    }
+}
+
+#[async_trait]
+impl EmbeddingClient for MockEmbeddingClient {
+    async fn embed(&self, text: &str) -> Result<EmbeddingResponse, Box<dyn Error + Send + Sync>> {
+        let mut embedding = vec![0.0f32; self.dimension];
+        for (i, byte) in text.bytes().enumerate() {
+            embedding[i % self.dimension] += byte as f32;
+        }
+        let norm = embedding.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            embedding.iter_mut().for_each(|v| *v /= norm);
+        }
+        Ok(EmbeddingResponse { embedding })
+    }
+}
+
+/// Vector store holding documents per topic in memory. Every search returns the
+/// topic's documents in insertion order with score 1.0, ignoring the query.
+#[derive(Default)]
+pub struct MockVectorStore {
+    schemas: Vec<IndexSchema>,
+    documents: Mutex<HashMap<String, Vec<(String, Value)>>>,
+}
+
+impl MockVectorStore {
+    pub fn new(schemas: Vec<IndexSchema>) -> Self {
+        Self { schemas, documents: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn add_document(&self, topic: &str, id: &str, document: Value) {
+        self.documents
+            .lock()
+            .unwrap()
+            .entry(topic.to_string())
+            .or_default()
+            .push((id.to_string(), document));
+    }
+
+    fn hits(&self, topic: &str, limit: usize) -> Vec<(f32, String, Value)> {
+        self.documents
+            .lock()
+            .unwrap()
+            .get(topic)
+            .map(|docs| docs.iter().take(limit).map(|(id, doc)| (1.0, id.clone(), doc.clone())).collect())
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl VectorStore for MockVectorStore {
+    async fn search(
+        &self,
+        _query_vec: &[f32],
+        limit: usize,
+        topic: &str,
+        _schema_fields: Option<&Vec<String>>
+    ) -> Result<Vec<(f32, String, Value)>, Box<dyn Error + Send + Sync>> {
+        Ok(self.hits(topic, limit))
+    }
+
+    async fn search_hybrid(
+        &self,
+        topic: &str,
+        _text_query: &str,
+        _query_vec: &[f32],
+        limit: usize,
+        _schema_fields: Option<&Vec<String>>
+    ) -> Result<Vec<(f32, String, Value)>, Box<dyn Error + Send + Sync>> {
+        Ok(self.hits(topic, limit))
+    }
+
+    async fn count_documents(&self, topic: &str) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        Ok(self.documents.lock().unwrap().get(topic).map_or(0, Vec::len))
+    }
+
+    /// Returns the configured schemas without writing `output_path`.
+    async fn generate_schema(&self, _output_path: &str) -> Result<Vec<IndexSchema>, Box<dyn Error + Send + Sync>> {
+        Ok(self.schemas.clone())
+    }
+}
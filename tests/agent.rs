@@ -0,0 +1,354 @@
+//! `process_message` and `process_message_stream` end to end over the `testing` mocks:
+//! intent routing, RAG topic resolution, field selection and filters, counts and
+//! recency, the response cache and history persistence.
+
+mod common;
+
+use common::redis::FakeRedis;
+use common::{ agent, agent_with, args, profile_store, prompts, schema, GENERAL_CHAT, PROFILE_INFO };
+use dynamic_agent::agent::{ AIAgent, MessageOptions, ProgressStage, ResponseStream };
+use dynamic_agent::config::prompt::PromptConfig;
+use dynamic_agent::history::{ HistoryStore, InMemoryHistoryStore };
+use dynamic_agent::testing::{ MockChatClient, MockEmbeddingClient, MockVectorStore };
+use serde_json::{ json, Value };
+use std::sync::Arc;
+use vector_nexus::schema::IndexSchema;
+use futures::TryStreamExt;
+use tokio::sync::mpsc;
+
+async fn collect(stream: ResponseStream) -> String {
+    stream.try_collect::<Vec<_>>().await.unwrap().concat()
+}
+
+/// Agent over a single index holding `documents`, in the given order.
+fn index_agent(
+    flags: &[&str],
+    prompt_config: PromptConfig,
+    index: IndexSchema,
+    documents: &[(&str, Value)],
+    responses: &[&str]
+) -> (AIAgent, Arc<MockChatClient>) {
+    let schemas = vec![index];
+    let store = MockVectorStore::new(schemas.clone());
+    for (id, document) in documents {
+        store.add_document(&schemas[0].name, id, document.clone());
+    }
+    let chat = Arc::new(MockChatClient::new(responses.iter().copied()));
+    let agent = AIAgent::for_test(
+        args(flags),
+        prompt_config,
+        chat.clone(),
+        Arc::new(MockEmbeddingClient::new(8)),
+        Arc::new(store),
+        Arc::new(InMemoryHistoryStore::new(100)),
+        schemas
+    ).unwrap();
+    (agent, chat)
+}
+
+/// Agent over a `releases` index whose entries are stored out of date order.
+fn releases_agent(prompt_config: PromptConfig, responses: &[&str]) -> (AIAgent, Arc<MockChatClient>) {
+    let documents = [
+        ("r-1", json!({ "version": "v1.0", "released_on": "2023-01-10" })),
+        ("r-3", json!({ "version": "v2.0", "released_on": "2024-05-01" })),
+        ("r-2", json!({ "version": "v1.5", "released_on": "2023-09-01" })),
+    ];
+    index_agent(&[], prompt_config, schema("releases", &["version", "released_on"]), &documents, responses)
+}
+
+#[tokio::test]
+async fn general_chat_answers_without_retrieval() {
+    let t = agent(&[GENERAL_CHAT, "Hello there!"]);
+
+    let reply = t.agent.process_message("conv-1", "hi").await.unwrap();
+
+    assert_eq!(reply.response, "Hello there!");
+    assert!(reply.metadata.is_none());
+    let prompts = t.chat.prompts();
+    assert_eq!(prompts.len(), 2);
+    assert!(prompts[0].contains("Classify the user message"));
+    assert!(prompts[1].contains("hi"));
+}
+
+#[tokio::test]
+async fn profile_info_retrieves_from_the_inferred_topic() {
+    let t = agent(&[PROFILE_INFO, "experience", "You worked at Initech and Globex."]);
+
+    let reply = t.agent.process_message("conv-1", "where did I work?").await.unwrap();
+
+    assert_eq!(reply.response, "You worked at Initech and Globex.");
+    let metadata = reply.metadata.expect("RAG answers carry retrieval metadata");
+    assert_eq!(metadata.topic, "experience");
+    assert_eq!(metadata.hit_count, 2);
+    let prompts = t.chat.prompts();
+    assert_eq!(prompts.len(), 3);
+    assert!(prompts[1].contains("Identify the single most relevant index"));
+    assert!(prompts[2].contains("Initech") && prompts[2].contains("Globex"));
+}
+
+#[tokio::test]
+async fn unmatched_topic_goes_to_the_fallback_resolver() {
+    let t = agent(&[PROFILE_INFO, "None", "skills", "You know Rust."]);
+
+    let reply = t.agent.process_message("conv-1", "what am I good at?").await.unwrap();
+
+    assert_eq!(reply.metadata.expect("metadata").topic, "skills");
+    let prompts = t.chat.prompts();
+    assert_eq!(prompts.len(), 4);
+    assert!(prompts[2].contains("Primary classifier couldn't determine a topic"));
+    assert!(prompts[3].contains("Rust"));
+}
+
+#[tokio::test]
+async fn exchanges_are_persisted_and_fed_back_as_history() {
+    let t = agent(&[GENERAL_CHAT, "Nice to meet you, Ada.", GENERAL_CHAT, "Your name is Ada."]);
+
+    t.agent.process_message("conv-1", "my name is Ada").await.unwrap();
+    let conversation = t.history.get_conversation("conv-1", 10).await.unwrap();
+    let turns: Vec<(&str, &str)> = conversation.messages
+        .iter()
+        .map(|m| (m.role.as_str(), m.content.as_str()))
+        .collect();
+    assert_eq!(turns, [("user", "my name is Ada"), ("assistant", "Nice to meet you, Ada.")]);
+
+    t.agent.process_message("conv-1", "what is my name?").await.unwrap();
+    let prompts = t.chat.prompts();
+    assert!(prompts[3].contains("Nice to meet you, Ada."));
+    assert_eq!(t.history.get_conversation("conv-1", 10).await.unwrap().messages.len(), 4);
+    assert!(t.history.get_conversation("conv-2", 10).await.unwrap().messages.is_empty());
+}
+
+#[tokio::test]
+async fn repeated_question_is_served_from_the_cache() {
+    let redis = FakeRedis::start().await;
+    let args = args(&["--enable-cache", "--cache-redis-url", redis.url()]);
+    let t = agent_with(args.clone(), profile_store(), &[GENERAL_CHAT, "Hello!"]);
+    let agent = t.agent.with_response_cache(&args).await;
+
+    let miss = agent.process_message("conv-1", "Hi").await.unwrap();
+    assert_eq!(miss.response, "Hello!");
+    assert_eq!(t.chat.prompts().len(), 2);
+    assert_eq!(redis.keys().len(), 1);
+
+    let hit = agent.process_message("conv-2", "  hi ").await.unwrap();
+    assert_eq!(hit.response, "Hello!");
+    assert_eq!(t.chat.prompts().len(), 2, "a cache hit makes no LLM call");
+
+    t.chat.push_response(GENERAL_CHAT);
+    t.chat.push_response("Bye!");
+    let other = agent.process_message("conv-1", "bye").await.unwrap();
+    assert_eq!(other.response, "Bye!");
+    assert_eq!(t.chat.prompts().len(), 4);
+}
+
+#[tokio::test]
+async fn count_questions_are_answered_with_the_index_size() {
+    let t = agent(&[PROFILE_INFO, "experience"]);
+
+    let reply = t.agent.process_message("conv-1", "How many jobs have I had?").await.unwrap();
+
+    assert_eq!(reply.response, "2");
+    let metadata = reply.metadata.expect("metadata");
+    assert_eq!(metadata.topic, "experience");
+    assert_eq!(t.chat.prompts().len(), 2, "a count needs no answer prompt");
+}
+
+#[tokio::test]
+async fn amount_questions_are_searched_not_counted() {
+    let t = agent(&[PROFILE_INFO, "experience", "Six years in total."]);
+
+    let reply = t.agent.process_message("conv-1", "how much experience do I have in total?").await.unwrap();
+
+    assert_eq!(reply.response, "Six years in total.");
+    assert_eq!(reply.metadata.expect("metadata").hit_count, 2);
+    assert_eq!(t.chat.prompts().len(), 3);
+}
+
+#[tokio::test]
+async fn latest_questions_keep_the_newest_entry_by_end_date() {
+    let t = agent(&[PROFILE_INFO, "experience", "You lead at Globex."]);
+
+    let reply = t.agent.process_message("conv-1", "what is my latest job?").await.unwrap();
+
+    assert_eq!(reply.metadata.expect("metadata").hit_count, 1);
+    let prompts = t.chat.prompts();
+    assert!(prompts[2].contains("Globex"));
+    assert!(!prompts[2].contains("Initech"));
+}
+
+#[tokio::test]
+async fn streamed_general_chat_is_classified_first() {
+    let t = agent(&[GENERAL_CHAT, "Hello there!"]);
+
+    let outcome = t.agent.process_message_stream("conv-1", "hi", &MessageOptions::default()).await.unwrap();
+
+    assert_eq!(collect(outcome.stream).await, "Hello there!");
+    let prompts = t.chat.prompts();
+    assert_eq!(prompts.len(), 2);
+    assert!(prompts[0].contains("Classify the user message"));
+    let conversation = t.history.get_conversation("conv-1", 10).await.unwrap();
+    assert_eq!(conversation.messages.len(), 2);
+}
+
+#[tokio::test]
+async fn streamed_profile_questions_retrieve_and_report_progress() {
+    let t = agent(&[PROFILE_INFO, "experience", "You worked at Initech and Globex."]);
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let options = MessageOptions { progress: Some(tx), ..Default::default() };
+
+    let outcome = t.agent.process_message_stream("conv-1", "where did I work?", &options).await.unwrap();
+
+    assert_eq!(collect(outcome.stream).await, "You worked at Initech and Globex.");
+    let prompts = t.chat.prompts();
+    assert_eq!(prompts.len(), 3);
+    assert!(prompts[1].contains("Identify the single most relevant index"));
+    assert!(prompts[2].contains("Initech") && prompts[2].contains("Globex"));
+    let mut stages = Vec::new();
+    while let Ok(event) = rx.try_recv() {
+        stages.push(event.stage);
+    }
+    assert_eq!(stages, [ProgressStage::Classifying, ProgressStage::Retrieving, ProgressStage::Generating]);
+}
+
+#[tokio::test]
+async fn streamed_count_questions_yield_the_count() {
+    let t = agent(&[PROFILE_INFO, "experience"]);
+
+    let outcome = t.agent.process_message_stream("conv-1", "count my jobs", &MessageOptions::default()).await.unwrap();
+
+    assert_eq!(collect(outcome.stream).await, "2");
+    assert_eq!(t.chat.prompts().len(), 2);
+}
+
+#[tokio::test]
+async fn recency_follows_the_configured_date_field_of_any_index() {
+    let mut prompt_config = prompts();
+    prompt_config.recency_fields.insert("releases".to_string(), "released_on".to_string());
+    let (agent, chat) = releases_agent(prompt_config, &[PROFILE_INFO, "releases", "v2.0"]);
+
+    let reply = agent.process_message("conv-1", "what is the newest release?").await.unwrap();
+
+    assert_eq!(reply.metadata.expect("metadata").hit_count, 1);
+    let prompts = chat.prompts();
+    assert!(prompts[2].contains("v2.0"));
+    assert!(!prompts[2].contains("v1.5") && !prompts[2].contains("v1.0"));
+}
+
+#[tokio::test]
+async fn indexes_without_a_date_field_are_not_narrowed() {
+    let (agent, chat) = releases_agent(prompts(), &[PROFILE_INFO, "releases", "v1.0, v2.0 and v1.5"]);
+
+    let reply = agent.process_message("conv-1", "what is the newest release?").await.unwrap();
+
+    assert_eq!(reply.metadata.expect("metadata").hit_count, 3);
+    assert!(chat.prompts()[2].contains("v1.0"));
+}
+
+#[tokio::test]
+async fn llm_query_lets_the_query_client_pick_the_fields() {
+    let documents = [("p-1", json!({ "title": "Agent", "description": "A RAG agent", "url": "https://example.com" }))];
+    let index = schema("projects", &["title", "description", "url"]);
+    let responses = [PROFILE_INFO, "projects", "title, description", "An agent."];
+    let (agent, chat) = index_agent(&["--llm-query"], prompts(), index, &documents, &responses);
+
+    let reply = agent.process_message("conv-1", "which projects have I built?").await.unwrap();
+
+    assert_eq!(reply.metadata.expect("metadata").fields, ["title", "description"]);
+    let prompts = chat.prompts();
+    assert_eq!(prompts.len(), 4);
+    assert!(prompts[2].contains(r#"["title","description","url"]"#));
+}
+
+#[tokio::test]
+async fn unusable_field_selection_falls_back_to_all_fields() {
+    let documents = [("p-1", json!({ "title": "Agent", "description": "A RAG agent", "url": "https://example.com" }))];
+    let index = schema("projects", &["title", "description", "url"]);
+    let responses = [PROFILE_INFO, "projects", "I am not sure.", "An agent."];
+    let (agent, _chat) = index_agent(&["--llm-query"], prompts(), index, &documents, &responses);
+
+    let reply = agent.process_message("conv-1", "tell me about my work").await.unwrap();
+
+    assert_eq!(reply.metadata.expect("metadata").fields, ["title", "description", "url"]);
+}
+
+#[tokio::test]
+async fn extracted_filters_constrain_the_hits() {
+    let documents = [
+        ("p-1", json!({ "title": "Agent", "language": "Rust", "stars": 40 })),
+        ("p-2", json!({ "title": "Dashboard", "language": "TypeScript", "stars": 12 })),
+        ("p-3", json!({ "title": "Indexer", "language": "rust", "stars": 12 })),
+    ];
+    let index = schema("projects", &["title", "language", "stars"]);
+    let extracted = r#"[{"field": "language", "op": "eq", "value": "Rust"}, {"field": "stars", "op": "eq", "value": 12}, {"field": "owner", "op": "eq", "value": "me"}]"#;
+    let responses = [PROFILE_INFO, "projects", extracted, "The indexer."];
+    let (agent, chat) = index_agent(&["--rag-filter-extraction"], prompts(), index, &documents, &responses);
+
+    let reply = agent.process_message("conv-1", "my Rust projects with 12 stars").await.unwrap();
+
+    let metadata = reply.metadata.expect("metadata");
+    assert_eq!(metadata.hit_count, 1);
+    let filtered: Vec<&str> = metadata.filters.iter().map(|f| f.field.as_str()).collect();
+    assert_eq!(filtered, ["language", "stars"]);
+    let prompts = chat.prompts();
+    assert!(prompts[3].contains("Indexer") && !prompts[3].contains("Agent") && !prompts[3].contains("Dashboard"));
+}
+
+#[tokio::test]
+async fn repeated_messages_reuse_the_cached_intent() {
+    let t = agent(&[GENERAL_CHAT, "Hello!", "Hello again!"]);
+
+    t.agent.process_message("conv-1", "Hi there").await.unwrap();
+    let reply = t.agent.process_message("conv-2", "  hi THERE ").await.unwrap();
+
+    assert_eq!(reply.response, "Hello again!");
+    let prompts = t.chat.prompts();
+    assert_eq!(prompts.len(), 3, "the second message skips classification");
+    assert!(!prompts[2].contains("Classify the user message"));
+
+    let uncached = agent_with(args(&["--intent-cache-size", "0"]), profile_store(), &[GENERAL_CHAT, "Hello!", GENERAL_CHAT, "Hello!"]);
+    uncached.agent.process_message("conv-1", "Hi there").await.unwrap();
+    uncached.agent.process_message("conv-2", "Hi there").await.unwrap();
+    assert_eq!(uncached.chat.prompts().len(), 4);
+}
+
+/// Writes `contents` with a modification time safely after the last load; file
+/// timestamps are coarser than `SystemTime::now`.
+fn write_later(path: &std::path::Path, contents: &str) {
+    std::fs::write(path, contents).unwrap();
+    let file = std::fs::File::options().write(true).open(path).unwrap();
+    file.set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(1)).unwrap();
+}
+
+#[tokio::test]
+async fn edits_to_the_prompts_file_apply_from_the_next_message() {
+    let path = std::env::temp_dir().join(format!("dynamic-agent-prompts-{}.json", std::process::id()));
+    let original = std::fs::read_to_string("json/prompts.json").unwrap();
+    std::fs::write(&path, &original).unwrap();
+    let args = args(&["--prompts-path", path.to_str().unwrap()]);
+    let t = agent_with(args.clone(), profile_store(), &[
+        PROFILE_INFO, "experience", "Initech and Globex.",
+        PROFILE_INFO, "experience", "Initech and Globex.",
+        PROFILE_INFO, "experience", "Initech and Globex.",
+    ]);
+    let agent = t.agent.with_prompts_file(&args);
+
+    agent.process_message("conv-1", "where did I work?").await.unwrap();
+    let edited = original
+        .replace("Classify the user message", "Sort the user message")
+        .replace("Identify the single most relevant index", "Pick the one index");
+    write_later(&path, &edited);
+    agent.process_message("conv-1", "which companies did I work at?").await.unwrap();
+    // A broken edit is logged and the last good prompts stay in use.
+    write_later(&path, "{");
+    agent.process_message("conv-1", "who employed me?").await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let prompts = t.chat.prompts();
+    assert!(prompts[0].contains("Classify the user message"));
+    assert!(prompts[1].contains("Identify the single most relevant index"));
+    for turn in [3, 6] {
+        assert!(prompts[turn].contains("Sort the user message"), "intent prompt of turn {}", turn);
+        // The topic prompt comes from the RAG state, which is swapped with the prompts.
+        assert!(prompts[turn + 1].contains("Pick the one index"), "topic prompt of turn {}", turn);
+    }
+}
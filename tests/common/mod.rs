@@ -0,0 +1,151 @@
+//! Shared setup for the integration tests: agents built from the `testing` mocks
+//! and the repository's own prompt config.
+
+#![allow(dead_code)]
+
+pub mod provider;
+pub mod redis;
+
+use async_trait::async_trait;
+use clap::Parser;
+use dynamic_agent::agent::AIAgent;
+use dynamic_agent::cli::Args;
+use dynamic_agent::config::prompt::{ load_prompts_from_str, PromptConfig };
+use dynamic_agent::history::InMemoryHistoryStore;
+use dynamic_agent::llm::chat::{ ChatClient, CompletionResponse };
+use dynamic_agent::testing::{ MockChatClient, MockEmbeddingClient, MockVectorStore };
+use rllm::builder::LLMBackend;
+use serde_json::json;
+use std::error::Error;
+use std::sync::Arc;
+use tokio::sync::{ Notify, Semaphore };
+use vector_nexus::schema::IndexSchema;
+
+pub const PROFILE_INFO: &str = "PROFILE_INFO";
+pub const GENERAL_CHAT: &str = "GENERAL_CHAT";
+
+/// Args as parsed from the command line, after the program name.
+pub fn args(flags: &[&str]) -> Args {
+    Args::parse_from(std::iter::once("dynamic-agent").chain(flags.iter().copied()))
+}
+
+pub fn prompts() -> PromptConfig {
+    let raw = std::fs::read_to_string("json/prompts.json").expect("json/prompts.json");
+    load_prompts_from_str(&raw).expect("valid prompts").as_ref().clone()
+}
+
+pub fn schema(name: &str, fields: &[&str]) -> IndexSchema {
+    IndexSchema {
+        name: name.to_string(),
+        fields: fields.iter().map(|f| f.to_string()).collect(),
+        prefix: format!("qdrant:{}", name),
+    }
+}
+
+/// The indexes of a profile: `experience` and `skills`.
+pub fn profile_schemas() -> Vec<IndexSchema> {
+    vec![
+        schema("experience", &["company", "title", "start_date", "end_date"]),
+        schema("skills", &["name", "level"])
+    ]
+}
+
+/// Two jobs, the older one first so recency sorting has something to do.
+pub fn profile_store() -> MockVectorStore {
+    let store = MockVectorStore::new(profile_schemas());
+    store.add_document(
+        "experience",
+        "exp-1",
+        json!({ "company": "Initech", "title": "Engineer", "start_date": "2018-01-01", "end_date": "2020-06-30" })
+    );
+    store.add_document(
+        "experience",
+        "exp-2",
+        json!({ "company": "Globex", "title": "Lead", "start_date": "2020-07-01", "end_date": "2024-03-31" })
+    );
+    store.add_document("skills", "skill-1", json!({ "name": "Rust", "level": "expert" }));
+    store
+}
+
+pub struct TestAgent {
+    pub agent: AIAgent,
+    pub chat: Arc<MockChatClient>,
+    pub history: Arc<InMemoryHistoryStore>,
+}
+
+/// Agent over `store` answering with `responses` in order, one per LLM call.
+pub fn agent_with(args: Args, store: MockVectorStore, responses: &[&str]) -> TestAgent {
+    let chat = Arc::new(MockChatClient::new(responses.iter().copied()));
+    let (agent, history) = agent_with_chat(args, store, chat.clone());
+    TestAgent { agent, chat, history }
+}
+
+/// Agent over `store` with any chat client.
+pub fn agent_with_chat(
+    args: Args,
+    store: MockVectorStore,
+    chat: Arc<dyn ChatClient>
+) -> (AIAgent, Arc<InMemoryHistoryStore>) {
+    let history = Arc::new(InMemoryHistoryStore::new(100));
+    let agent = AIAgent::for_test(
+        args,
+        prompts(),
+        chat,
+        Arc::new(MockEmbeddingClient::new(8)),
+        Arc::new(store),
+        history.clone(),
+        profile_schemas()
+    ).expect("test agent");
+    (agent, history)
+}
+
+pub fn agent(responses: &[&str]) -> TestAgent {
+    agent_with(args(&[]), profile_store(), responses)
+}
+
+/// Chat client that answers like `MockChatClient` but holds each call until the
+/// test releases it with `release`.
+pub struct GatedChatClient {
+    inner: MockChatClient,
+    gate: Semaphore,
+    pub started: Notify,
+}
+
+impl GatedChatClient {
+    pub fn new(responses: &[&str]) -> Self {
+        Self {
+            inner: MockChatClient::new(responses.iter().copied()),
+            gate: Semaphore::new(0),
+            started: Notify::new(),
+        }
+    }
+
+    pub fn release(&self, calls: usize) {
+        self.gate.add_permits(calls);
+    }
+}
+
+#[async_trait]
+impl ChatClient for GatedChatClient {
+    async fn complete(&self, prompt: &str) -> Result<CompletionResponse, Box<dyn Error + Send + Sync>> {
+        self.started.notify_one();
+        self.gate.acquire().await?.forget();
+        self.inner.complete(prompt).await
+    }
+
+    fn get_api_key(&self) -> String {
+        String::new()
+    }
+
+    fn get_model(&self) -> String {
+        self.inner.get_model()
+    }
+
+    fn get_base_url(&self) -> Option<String> {
+        None
+    }
+
+    fn get_llm_backend(&self) -> LLMBackend {
+        self.inner.get_llm_backend()
+    }
+}
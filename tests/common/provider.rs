@@ -0,0 +1,105 @@
+//! A local HTTP server standing in for an LLM provider API. It records every
+//! request body and answers in a shape every chat client can parse.
+
+use axum::body::{ Body, Bytes };
+use axum::http::{ StatusCode, Uri };
+use axum::http::header;
+use axum::response::{ IntoResponse, Response };
+use serde_json::{ json, Value };
+use futures::StreamExt;
+use std::sync::{ Arc, Mutex };
+use std::time::Duration;
+
+type Reply = dyn Fn(usize) -> Response + Send + Sync;
+
+#[derive(Clone)]
+pub struct MockProvider {
+    url: String,
+    requests: Arc<Mutex<Vec<(String, Value)>>>,
+}
+
+/// A completion carrying `text` in the OpenAI-compatible, Anthropic, Gemini and
+/// Ollama response formats at once.
+pub fn completion(text: &str) -> Value {
+    json!({
+        "response": text,
+        "choices": [{ "message": { "role": "assistant", "content": text } }],
+        "content": [{ "type": "text", "text": text }],
+        "candidates": [{ "content": { "parts": [{ "text": text }] } }],
+    })
+}
+
+impl MockProvider {
+    /// Answers every request with `completion("ok")`.
+    pub async fn start() -> Self {
+        Self::with_replies(|_| (StatusCode::OK, completion("ok"))).await
+    }
+
+    /// Answers the n-th request (from 0) with `reply(n)`.
+    pub async fn with_replies(reply: impl Fn(usize) -> (StatusCode, Value) + Send + Sync + 'static) -> Self {
+        Self::serve(Arc::new(move |attempt| {
+            let (status, body) = reply(attempt);
+            (status, axum::Json(body)).into_response()
+        })).await
+    }
+
+    /// Answers every request with `events` as a server-sent event stream.
+    pub async fn streaming(events: &'static str) -> Self {
+        Self::streaming_chunks(vec![events.as_bytes()]).await
+    }
+
+    /// Like `streaming`, but writes each chunk separately, so lines (and UTF-8
+    /// characters) can be split across network reads.
+    pub async fn streaming_chunks(chunks: Vec<&'static [u8]>) -> Self {
+        Self::serve(Arc::new(move |_| {
+            let body = futures::stream::iter(chunks.clone()).then(|chunk| async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                Ok::<_, std::convert::Infallible>(chunk)
+            });
+            ([(header::CONTENT_TYPE, "text/event-stream")], Body::from_stream(body)).into_response()
+        })).await
+    }
+
+    async fn serve(reply: Arc<Reply>) -> Self {
+        let requests: Arc<Mutex<Vec<(String, Value)>>> = Arc::default();
+        let recorded = requests.clone();
+        let app = axum::Router::new().fallback(move |uri: Uri, body: Bytes| {
+            let (recorded, reply) = (recorded.clone(), reply.clone());
+            async move {
+                let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+                let attempt = {
+                    let mut recorded = recorded.lock().unwrap();
+                    recorded.push((uri.path().to_string(), body));
+                    recorded.len() - 1
+                };
+                reply(attempt)
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Self { url, requests }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Path and JSON body of each request received, oldest first.
+    pub fn requests(&self) -> Vec<(String, Value)> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// URL of a server that accepts connections but never answers.
+pub async fn unresponsive() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let mut connections = Vec::new();
+        while let Ok((socket, _)) = listener.accept().await {
+            connections.push(socket);
+        }
+    });
+    url
+}
@@ -0,0 +1,230 @@
+//! Minimal in-process Redis speaking RESP2, enough for the cache, history and job
+//! stores. Keys never expire; `ttl` reports the last EXPIRE/SETEX seconds so tests
+//! can check what was requested.
+
+use std::collections::{ HashMap, VecDeque };
+use std::sync::{ Arc, Mutex };
+use tokio::io::{ AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader };
+use tokio::net::{ TcpListener, TcpStream };
+
+#[derive(Default)]
+struct State {
+    strings: HashMap<String, String>,
+    lists: HashMap<String, VecDeque<String>>,
+    hashes: HashMap<String, HashMap<String, String>>,
+    ttls: HashMap<String, i64>,
+}
+
+enum Reply {
+    Ok,
+    Int(i64),
+    Bulk(Option<String>),
+    Array(Vec<String>),
+}
+
+impl Reply {
+    fn encode(&self) -> Vec<u8> {
+        fn bulk(out: &mut String, value: &str) {
+            out.push_str(&format!("${}\r\n{}\r\n", value.len(), value));
+        }
+        let mut out = String::new();
+        match self {
+            Reply::Ok => out.push_str("+OK\r\n"),
+            Reply::Int(n) => out.push_str(&format!(":{}\r\n", n)),
+            Reply::Bulk(None) => out.push_str("$-1\r\n"),
+            Reply::Bulk(Some(value)) => bulk(&mut out, value),
+            Reply::Array(values) => {
+                out.push_str(&format!("*{}\r\n", values.len()));
+                values.iter().for_each(|value| bulk(&mut out, value));
+            }
+        }
+        out.into_bytes()
+    }
+}
+
+#[derive(Clone)]
+pub struct FakeRedis {
+    url: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeRedis {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(State::default()));
+        let shared = Arc::clone(&state);
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(serve(socket, Arc::clone(&shared)));
+            }
+        });
+        Self { url, state }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Seconds of the last EXPIRE or SETEX on `key`.
+    pub fn ttl(&self, key: &str) -> Option<i64> {
+        self.state.lock().unwrap().ttls.get(key).copied()
+    }
+
+    /// Overwrites the recorded TTL of `key`, as if time had passed since the last EXPIRE.
+    pub fn set_ttl(&self, key: &str, seconds: i64) {
+        self.state.lock().unwrap().ttls.insert(key.to_string(), seconds);
+    }
+
+    pub fn list(&self, key: &str) -> Vec<String> {
+        self.state.lock().unwrap().lists.get(key).map(|l| l.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        let mut keys: Vec<String> = state.strings
+            .keys()
+            .chain(state.lists.keys())
+            .chain(state.hashes.keys())
+            .cloned()
+            .collect();
+        keys.sort();
+        keys
+    }
+}
+
+async fn serve(socket: TcpStream, state: Arc<Mutex<State>>) {
+    let (read, mut write) = socket.into_split();
+    let mut reader = BufReader::new(read);
+    while let Some(command) = read_command(&mut reader).await {
+        let reply = execute(&mut state.lock().unwrap(), &command);
+        if write.write_all(&reply.encode()).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn read_line(reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>) -> Option<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await.ok()? == 0 {
+        return None;
+    }
+    Some(line.trim_end().to_string())
+}
+
+async fn read_command(reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>) -> Option<Vec<String>> {
+    let header = read_line(reader).await?;
+    let count: usize = header.strip_prefix('*')?.parse().ok()?;
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        let len: usize = read_line(reader).await?.strip_prefix('$')?.parse().ok()?;
+        let mut buf = vec![0u8; len + 2];
+        reader.read_exact(&mut buf).await.ok()?;
+        buf.truncate(len);
+        args.push(String::from_utf8(buf).ok()?);
+    }
+    Some(args)
+}
+
+/// Redis list range semantics: negative indexes count from the end, bounds are inclusive.
+fn range(len: usize, start: &str, stop: &str) -> std::ops::Range<usize> {
+    let resolve = |index: &str| {
+        let index: i64 = index.parse().unwrap_or(0);
+        if index < 0 { (len as i64 + index).max(0) } else { index }
+    };
+    let start = resolve(start).min(len as i64) as usize;
+    let stop = (resolve(stop) + 1).min(len as i64).max(start as i64) as usize;
+    start..stop
+}
+
+fn execute(state: &mut State, command: &[String]) -> Reply {
+    let name = command[0].to_uppercase();
+    let args = &command[1..];
+    match name.as_str() {
+        "GET" => Reply::Bulk(state.strings.get(&args[0]).cloned()),
+        "SET" => {
+            state.strings.insert(args[0].clone(), args[1].clone());
+            if let Some(pos) = args.iter().position(|a| a.eq_ignore_ascii_case("EX")) {
+                state.ttls.insert(args[0].clone(), args[pos + 1].parse().unwrap_or(0));
+            }
+            Reply::Ok
+        }
+        "SETEX" => {
+            state.strings.insert(args[0].clone(), args[2].clone());
+            state.ttls.insert(args[0].clone(), args[1].parse().unwrap_or(0));
+            Reply::Ok
+        }
+        "DEL" => {
+            let removed = args
+                .iter()
+                .filter(|key| {
+                    let string = state.strings.remove(*key).is_some();
+                    let list = state.lists.remove(*key).is_some();
+                    let hash = state.hashes.remove(*key).is_some();
+                    string || list || hash
+                })
+                .count();
+            Reply::Int(removed as i64)
+        }
+        "EXPIRE" => {
+            state.ttls.insert(args[0].clone(), args[1].parse().unwrap_or(0));
+            Reply::Int(1)
+        }
+        "TTL" => Reply::Int(state.ttls.get(&args[0]).copied().unwrap_or(-1)),
+        "LPUSH" => {
+            let list = state.lists.entry(args[0].clone()).or_default();
+            args[1..].iter().for_each(|value| list.push_front(value.clone()));
+            Reply::Int(list.len() as i64)
+        }
+        "LTRIM" => {
+            if let Some(list) = state.lists.get_mut(&args[0]) {
+                let kept = range(list.len(), &args[1], &args[2]);
+                *list = list.drain(..).skip(kept.start).take(kept.len()).collect();
+            }
+            Reply::Ok
+        }
+        "LRANGE" => {
+            let list = state.lists.get(&args[0]).cloned().unwrap_or_default();
+            let range = range(list.len(), &args[1], &args[2]);
+            Reply::Array(list.into_iter().skip(range.start).take(range.len()).collect())
+        }
+        "LREM" => {
+            let Some(list) = state.lists.get_mut(&args[0]) else {
+                return Reply::Int(0);
+            };
+            let Some(pos) = list.iter().position(|value| value == &args[2]) else {
+                return Reply::Int(0);
+            };
+            list.remove(pos);
+            Reply::Int(1)
+        }
+        "RPOPLPUSH" | "BRPOPLPUSH" => {
+            let Some(value) = state.lists.get_mut(&args[0]).and_then(|list| list.pop_back()) else {
+                return Reply::Bulk(None);
+            };
+            state.lists.entry(args[1].clone()).or_default().push_front(value.clone());
+            Reply::Bulk(Some(value))
+        }
+        "HSET" => {
+            let hash = state.hashes.entry(args[0].clone()).or_default();
+            let added = args[1..]
+                .chunks(2)
+                .filter(|pair| hash.insert(pair[0].clone(), pair[1].clone()).is_none())
+                .count();
+            Reply::Int(added as i64)
+        }
+        "HGET" => Reply::Bulk(state.hashes.get(&args[0]).and_then(|hash| hash.get(&args[1]).cloned())),
+        "HGETALL" => {
+            let hash = state.hashes.get(&args[0]).cloned().unwrap_or_default();
+            Reply::Array(hash.into_iter().flat_map(|(field, value)| [field, value]).collect())
+        }
+        "HDEL" => {
+            let Some(hash) = state.hashes.get_mut(&args[0]) else {
+                return Reply::Int(0);
+            };
+            Reply::Int(args[1..].iter().filter(|field| hash.remove(*field).is_some()).count() as i64)
+        }
+        // Handshake and anything else the tests don't inspect.
+        _ => Reply::Ok,
+    }
+}
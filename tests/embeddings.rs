@@ -0,0 +1,73 @@
+//! Batch embedding through the provider clients and the wrappers around them.
+
+mod common;
+
+use axum::http::StatusCode;
+use common::provider::MockProvider;
+use dynamic_agent::llm::embedding::fallback::FallbackEmbeddingClient;
+use dynamic_agent::llm::embedding::{ new_client, EmbeddingClient };
+use dynamic_agent::llm::{ LlmConfig, LlmType };
+use dynamic_agent::testing::MockEmbeddingClient;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn ollama(provider: &MockProvider) -> Arc<dyn EmbeddingClient> {
+    new_client(&LlmConfig {
+        llm_type: LlmType::Ollama,
+        embedding_model: Some("test-embed".into()),
+        base_url: Some(provider.url().to_string()),
+        ..Default::default()
+    }).unwrap()
+}
+
+fn texts(items: &[&str]) -> Vec<String> {
+    items.iter().map(|s| s.to_string()).collect()
+}
+
+#[tokio::test]
+async fn ollama_sends_a_batch_in_one_request() {
+    let provider = MockProvider::with_replies(|_| {
+        (StatusCode::OK, json!({ "embeddings": [[1.0, 0.0], [0.0, 1.0], [0.5, 0.5]] }))
+    }).await;
+    let embeddings = ollama(&provider).embed_batch(&texts(&["a", "b", "c"])).await.unwrap();
+    assert_eq!(embeddings, vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![0.5, 0.5]]);
+
+    let requests = provider.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].0, "/api/embed");
+    assert_eq!(requests[0].1["input"], json!(["a", "b", "c"]));
+}
+
+#[tokio::test]
+async fn ollama_rejects_a_batch_with_the_wrong_count() {
+    let provider = MockProvider::with_replies(|_| (StatusCode::OK, json!({ "embeddings": [[1.0, 0.0]] }))).await;
+    let err = ollama(&provider).embed_batch(&texts(&["a", "b"])).await.unwrap_err();
+    assert!(err.to_string().contains("1 embeddings for 2 inputs"), "{}", err);
+}
+
+#[tokio::test]
+async fn default_batch_embeds_in_input_order() {
+    let client = MockEmbeddingClient::new(8);
+    let embeddings = client.embed_batch(&texts(&["first", "second"])).await.unwrap();
+    assert_eq!(embeddings, vec![
+        client.embed("first").await.unwrap().embedding,
+        client.embed("second").await.unwrap().embedding,
+    ]);
+    assert!(client.embed_batch(&[]).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn fallback_fails_over_a_whole_batch() {
+    let failing = MockProvider::with_replies(|_| (StatusCode::SERVICE_UNAVAILABLE, json!({ "error": "overloaded" }))).await;
+    let backup: Arc<dyn EmbeddingClient> = Arc::new(MockEmbeddingClient::new(8));
+    let client = FallbackEmbeddingClient::new(
+        vec![("batch-primary".into(), ollama(&failing)), ("batch-backup".into(), backup.clone())],
+        5,
+        Duration::from_secs(30)
+    );
+
+    let batch = texts(&["a", "b"]);
+    assert_eq!(client.embed_batch(&batch).await.unwrap(), backup.embed_batch(&batch).await.unwrap());
+    assert_eq!(failing.requests().len(), 1);
+}
@@ -0,0 +1,85 @@
+//! The history stores: Redis against the in-process fake Redis, and the memory store.
+
+mod common;
+
+use common::args;
+use common::redis::FakeRedis;
+use dynamic_agent::history::{ create_history_store, HistoryStore };
+
+fn redis_store(redis: &FakeRedis, flags: &[&str]) -> std::sync::Arc<dyn HistoryStore> {
+    let mut all = vec!["--history-type", "redis", "--history-host", redis.url()];
+    all.extend_from_slice(flags);
+    create_history_store(&args(&all)).unwrap()
+}
+
+#[tokio::test]
+async fn every_write_refreshes_the_conversation_ttl() {
+    let redis = FakeRedis::start().await;
+    let store = redis_store(&redis, &["--history-ttl-secs", "120"]);
+
+    store.add_message("conv-1", "user", "hello").await.unwrap();
+    assert_eq!(redis.ttl("history:conv-1"), Some(120));
+
+    // Most of the TTL has run out by the next message, which resets it.
+    redis.set_ttl("history:conv-1", 5);
+    store.add_message("conv-1", "assistant", "hi there").await.unwrap();
+    assert_eq!(redis.ttl("history:conv-1"), Some(120));
+    assert_eq!(redis.list("history:conv-1").len(), 2);
+}
+
+#[tokio::test]
+async fn zero_ttl_never_expires_history() {
+    let redis = FakeRedis::start().await;
+    let store = redis_store(&redis, &[]);
+
+    store.add_message("conv-1", "user", "hello").await.unwrap();
+
+    assert_eq!(redis.list("history:conv-1").len(), 1);
+    assert_eq!(redis.ttl("history:conv-1"), None);
+}
+
+/// Message contents of a page, oldest first.
+async fn page(store: &dyn HistoryStore, offset: usize, limit: usize) -> Vec<String> {
+    let conversation = store.get_conversation_range("conv-1", offset, limit).await.unwrap();
+    conversation.messages.into_iter().map(|m| m.content).collect()
+}
+
+async fn add_messages(store: &dyn HistoryStore, count: usize) {
+    for i in 1..=count {
+        store.add_message("conv-1", "user", &format!("m{}", i)).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn redis_pages_back_from_the_newest_message() {
+    let redis = FakeRedis::start().await;
+    let store = redis_store(&redis, &[]);
+    add_messages(store.as_ref(), 5).await;
+
+    assert_eq!(page(store.as_ref(), 0, 2).await, ["m4", "m5"]);
+    assert_eq!(page(store.as_ref(), 2, 2).await, ["m2", "m3"]);
+    assert_eq!(page(store.as_ref(), 4, 2).await, ["m1"]);
+    assert!(page(store.as_ref(), 5, 2).await.is_empty());
+    assert!(page(store.as_ref(), 0, 0).await.is_empty());
+    assert_eq!(store.get_conversation("conv-1", 3).await.unwrap().messages.len(), 3);
+}
+
+#[tokio::test]
+async fn redis_trims_to_history_max_messages() {
+    let redis = FakeRedis::start().await;
+    let store = redis_store(&redis, &["--history-max-messages", "3"]);
+    add_messages(store.as_ref(), 5).await;
+
+    assert_eq!(redis.list("history:conv-1").len(), 3);
+    assert_eq!(page(store.as_ref(), 0, 10).await, ["m3", "m4", "m5"]);
+}
+
+#[tokio::test]
+async fn memory_store_pages_like_redis() {
+    let store = create_history_store(&args(&["--history-type", "memory", "--history-max-messages", "4"])).unwrap();
+    add_messages(store.as_ref(), 5).await;
+
+    assert_eq!(page(store.as_ref(), 0, 2).await, ["m4", "m5"]);
+    assert_eq!(page(store.as_ref(), 2, 5).await, ["m2", "m3"]);
+    assert!(page(store.as_ref(), 4, 2).await.is_empty());
+}
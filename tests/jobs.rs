@@ -0,0 +1,116 @@
+//! The Redis job store against the in-process fake Redis, and how jobs share
+//! conversations with streamed replies.
+
+mod common;
+
+use common::redis::FakeRedis;
+use common::{ agent_with, agent_with_chat, args, profile_store, GatedChatClient, GENERAL_CHAT };
+use dynamic_agent::agent::MessageOptions;
+use dynamic_agent::history::HistoryStore;
+use dynamic_agent::jobs::JobStore;
+use dynamic_agent::models::job::JobStatus;
+use dynamic_agent::server::streams::{ ConversationStreams, StreamPolicy };
+use futures::TryStreamExt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+#[tokio::test]
+async fn worker_runs_queued_jobs_under_the_namespace() {
+    let redis = FakeRedis::start().await;
+    let args = args(&["--job-redis-url", redis.url(), "--redis-namespace", "acme", "--job-result-ttl", "60"]);
+    let store = JobStore::new(&args).unwrap();
+    let t = agent_with(args, profile_store(), &[GENERAL_CHAT, "Done and dusted."]);
+    let agent = Arc::new(Mutex::new(t.agent));
+
+    let job = store.enqueue("conv-1".into(), "finish it".into()).await.unwrap();
+    assert_eq!(redis.keys(), [format!("acme:job:{}", job.id), "acme:jobs:queue".to_string()]);
+
+    store.process_next(&agent, &ConversationStreams::new(StreamPolicy::Queue)).await.unwrap();
+
+    let job = store.get(&job.id).await.unwrap().expect("job record");
+    assert_eq!(job.status, JobStatus::Done);
+    assert_eq!(job.result.as_deref(), Some("Done and dusted."));
+    assert_eq!(redis.ttl(&format!("acme:job:{}", job.id)), Some(60));
+    assert!(redis.list("acme:jobs:queue").is_empty());
+    assert!(redis.list("acme:jobs:processing").is_empty());
+}
+
+#[tokio::test]
+async fn running_job_does_not_hold_the_agent_lock() {
+    let redis = FakeRedis::start().await;
+    let args = args(&["--job-redis-url", redis.url()]);
+    let store = Arc::new(JobStore::new(&args).unwrap());
+    let chat = Arc::new(GatedChatClient::new(&[GENERAL_CHAT, "Eventually."]));
+    let (agent, _history) = agent_with_chat(args, profile_store(), chat.clone());
+    let agent = Arc::new(Mutex::new(agent));
+    let job = store.enqueue("conv-1".into(), "slow question".into()).await.unwrap();
+
+    let worker = tokio::spawn({
+        let (store, agent) = (store.clone(), agent.clone());
+        async move { store.process_next(&agent, &ConversationStreams::new(StreamPolicy::Queue)).await }
+    });
+    chat.started.notified().await;
+    // The job is waiting on the LLM; other clients can still use the agent.
+    drop(tokio::time::timeout(Duration::from_secs(1), agent.lock()).await.expect("agent lock is free"));
+
+    chat.release(2);
+    worker.await.unwrap().unwrap();
+    let job = store.get(&job.id).await.unwrap().unwrap();
+    assert_eq!(job.result.as_deref(), Some("Eventually."));
+}
+
+#[tokio::test]
+async fn jobs_wait_for_a_reply_streaming_on_their_conversation() {
+    let redis = FakeRedis::start().await;
+    let args = args(&["--job-redis-url", redis.url()]);
+    let store = Arc::new(JobStore::new(&args).unwrap());
+    let t = agent_with(args, profile_store(), &[GENERAL_CHAT, "Streamed reply.", GENERAL_CHAT, "Job reply."]);
+    let history = t.history.clone();
+    let agent = Arc::new(Mutex::new(t.agent));
+    let streams = Arc::new(ConversationStreams::new(StreamPolicy::Queue));
+
+    // A streamed turn holds the conversation, as the WebSocket and SSE routes do.
+    let guard = streams.acquire("conv-1").await.unwrap();
+    let job = store.enqueue("conv-1".into(), "queued question".into()).await.unwrap();
+    let worker = tokio::spawn({
+        let (store, agent, streams) = (store.clone(), agent.clone(), streams.clone());
+        async move { store.process_next(&agent, &streams).await }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!worker.is_finished());
+    assert!(t.chat.prompts().is_empty(), "the job must not start while the stream runs");
+
+    let outcome = agent.lock().await
+        .process_message_stream("conv-1", "streamed question", &MessageOptions::default()).await
+        .unwrap();
+    outcome.stream.try_collect::<Vec<_>>().await.unwrap();
+    drop(guard);
+    worker.await.unwrap().unwrap();
+
+    assert_eq!(store.get(&job.id).await.unwrap().unwrap().status, JobStatus::Done);
+    let turns: Vec<String> = history.get_conversation("conv-1", 10).await.unwrap().messages
+        .into_iter()
+        .map(|m| m.content)
+        .collect();
+    assert_eq!(turns, ["streamed question", "Streamed reply.", "queued question", "Job reply."]);
+}
+
+#[tokio::test]
+async fn jobs_on_a_busy_conversation_fail_under_the_reject_policy() {
+    let redis = FakeRedis::start().await;
+    let args = args(&["--job-redis-url", redis.url()]);
+    let store = JobStore::new(&args).unwrap();
+    let t = agent_with(args, profile_store(), &[]);
+    let agent = Arc::new(Mutex::new(t.agent));
+    let streams = ConversationStreams::new(StreamPolicy::Reject);
+
+    let _streaming = streams.acquire("conv-1").await.unwrap();
+    let job = store.enqueue("conv-1".into(), "queued question".into()).await.unwrap();
+    store.process_next(&agent, &streams).await.unwrap();
+
+    let job = store.get(&job.id).await.unwrap().unwrap();
+    assert_eq!(job.status, JobStatus::Error);
+    assert_eq!(job.error.as_deref(), Some("conversation busy"));
+    assert!(t.chat.prompts().is_empty());
+}
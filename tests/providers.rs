@@ -0,0 +1,243 @@
+//! Request payloads and transport behaviour of the chat clients, checked against a
+//! local mock of the provider API.
+
+mod common;
+
+use axum::http::StatusCode;
+use common::provider::{ completion, MockProvider };
+use dynamic_agent::llm::chat::{ new_client, ChatClient };
+use dynamic_agent::llm::{ GenerationParams, LlmConfig, LlmType };
+use serde_json::{ json, Value };
+use futures::StreamExt;
+use std::sync::Arc;
+use std::time::{ Duration, Instant };
+
+fn client(llm_type: LlmType, provider: &MockProvider, config: LlmConfig) -> Arc<dyn ChatClient> {
+    new_client(&LlmConfig {
+        llm_type,
+        api_key: Some("test-key".into()),
+        completion_model: Some("test-model".into()),
+        base_url: Some(provider.url().to_string()),
+        ..config
+    }).unwrap()
+}
+
+/// Body of the single request `provider` received.
+fn only_request(provider: &MockProvider) -> Value {
+    let requests = provider.requests();
+    assert_eq!(requests.len(), 1, "requests: {:?}", requests);
+    requests[0].1.clone()
+}
+
+#[tokio::test]
+async fn seed_is_sent_by_providers_that_support_it() {
+    let openai = MockProvider::start().await;
+    client(LlmType::OpenAI, &openai, LlmConfig { seed: Some(42), ..Default::default() }).complete("hi").await.unwrap();
+    assert_eq!(only_request(&openai)["seed"], 42);
+
+    let ollama = MockProvider::start().await;
+    client(LlmType::Ollama, &ollama, LlmConfig { seed: Some(42), ..Default::default() }).complete("hi").await.unwrap();
+    assert_eq!(only_request(&ollama)["options"]["seed"], 42);
+}
+
+#[tokio::test]
+async fn seed_is_omitted_when_unset() {
+    let openai = MockProvider::start().await;
+    client(LlmType::OpenAI, &openai, LlmConfig::default()).complete("hi").await.unwrap();
+    assert!(only_request(&openai).get("seed").is_none());
+
+    let ollama = MockProvider::start().await;
+    client(LlmType::Ollama, &ollama, LlmConfig::default()).complete("hi").await.unwrap();
+    assert!(only_request(&ollama)["options"].get("seed").is_none());
+}
+
+#[tokio::test]
+async fn chat_timeout_bounds_requests_and_streams() {
+    let url = common::provider::unresponsive().await;
+    let client = new_client(&LlmConfig {
+        llm_type: LlmType::OpenAI,
+        api_key: Some("test-key".into()),
+        completion_model: Some("test-model".into()),
+        base_url: Some(url),
+        request_timeout_secs: 1,
+        // Timeouts are not retried, so this must not stretch the wait.
+        max_retries: 3,
+        ..Default::default()
+    }).unwrap();
+
+    let started = Instant::now();
+    assert!(client.complete("hi").await.is_err());
+    assert!(started.elapsed() < Duration::from_secs(3), "took {:?}", started.elapsed());
+
+    let started = Instant::now();
+    let failed = match client.stream_completion("hi").await {
+        Err(_) => true,
+        Ok(mut stream) => matches!(stream.next().await, Some(Err(_))),
+    };
+    assert!(failed);
+    assert!(started.elapsed() < Duration::from_secs(3), "took {:?}", started.elapsed());
+}
+
+fn retrying_client(provider: &MockProvider, max_retries: u32) -> Arc<dyn ChatClient> {
+    client(LlmType::OpenAI, provider, LlmConfig { max_retries, retry_base_ms: 1, ..Default::default() })
+}
+
+#[tokio::test]
+async fn transient_failures_are_retried() {
+    let provider = MockProvider::with_replies(|attempt| match attempt {
+        0 | 1 => (StatusCode::SERVICE_UNAVAILABLE, json!({ "error": "overloaded" })),
+        _ => (StatusCode::OK, completion("recovered")),
+    }).await;
+    let reply = retrying_client(&provider, 2).complete("hi").await.unwrap();
+    assert_eq!(reply.response, "recovered");
+    assert_eq!(provider.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn retries_stop_after_max_retries() {
+    let provider = MockProvider::with_replies(|_| (StatusCode::TOO_MANY_REQUESTS, json!({ "error": "slow down" }))).await;
+    assert!(retrying_client(&provider, 1).complete("hi").await.is_err());
+    assert_eq!(provider.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn client_errors_are_not_retried() {
+    let provider = MockProvider::with_replies(|_| (StatusCode::BAD_REQUEST, json!({ "error": "bad request" }))).await;
+    assert!(retrying_client(&provider, 3).complete("hi").await.is_err());
+    assert_eq!(provider.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn anthropic_streams_text_deltas_split_across_reads() {
+    // "Hé" is split inside its delta line and inside the two bytes of "é".
+    let provider = MockProvider::streaming_chunks(vec![
+        b"event: message_start\ndata: {\"type\":\"message_start\",\"message\":{}}\n\nevent: content_block_delta\ndata: {\"type\":\"content_bl",
+        b"ock_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"H\xC3",
+        b"\xA9\"}}\n\nevent: ping\ndata: {\"type\":\"ping\"}\n",
+        b"\nevent: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"llo\"}}\r\n\r\n",
+        b"event: message_stop\ndata: {\"type\":\"message_stop\"}",
+    ]).await;
+    let client = client(LlmType::Anthropic, &provider, LlmConfig::default());
+    assert!(client.supports_native_streaming());
+
+    let mut stream = client.stream_completion("hi").await.unwrap();
+    let mut text = String::new();
+    while let Some(chunk) = stream.next().await {
+        text.push_str(&chunk.unwrap());
+    }
+    assert_eq!(text, "Héllo");
+
+    let (path, body) = provider.requests().remove(0);
+    assert_eq!(path, "/v1/messages");
+    assert_eq!(body["stream"], true);
+}
+
+#[tokio::test]
+async fn anthropic_stream_error_events_surface_as_errors() {
+    let provider = MockProvider::streaming_chunks(vec![
+        b"event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hel\"}}\n\n",
+        b"event: error\ndata: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n",
+        b"event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"lo\"}}\n\n",
+    ]).await;
+    let client = client(LlmType::Anthropic, &provider, LlmConfig::default());
+
+    let mut stream = client.stream_completion("hi").await.unwrap();
+    assert_eq!(stream.next().await.unwrap().unwrap(), "Hel");
+    let error = stream.next().await.unwrap().unwrap_err();
+    assert!(error.to_string().contains("overloaded_error"), "{}", error);
+    assert!(stream.next().await.is_none(), "the stream ends at the error");
+}
+
+#[tokio::test]
+async fn deepseek_streams_deltas_split_across_reads() {
+    let provider = MockProvider::streaming_chunks(vec![
+        b"data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\ndata: {\"choices\":[{\"del",
+        b"ta\":{\"content\":\"Hel\"}}]}\n",
+        b"\ndata: {\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}\r\n\r\n",
+        b"data: [DONE]\n\n",
+    ]).await;
+    let client = client(LlmType::DeepSeek, &provider, LlmConfig {
+        system_prompt: Some("Be brief.".into()),
+        ..Default::default()
+    });
+    assert!(client.supports_native_streaming());
+
+    let mut stream = client.stream_completion("hi").await.unwrap();
+    let mut text = String::new();
+    while let Some(chunk) = stream.next().await {
+        text.push_str(&chunk.unwrap());
+    }
+    assert_eq!(text, "Hello");
+
+    let (path, body) = provider.requests().remove(0);
+    assert_eq!(path, "/chat/completions");
+    assert_eq!(body["stream"], true);
+    assert_eq!(body["messages"], json!([
+        { "role": "system", "content": "Be brief." },
+        { "role": "user", "content": "hi" },
+    ]));
+}
+
+const SAMPLING: GenerationParams = GenerationParams {
+    temperature: Some(0.25),
+    max_tokens: Some(256),
+    top_p: Some(0.5),
+    frequency_penalty: Some(0.75),
+    presence_penalty: Some(0.125),
+};
+
+#[tokio::test]
+async fn sampling_params_reach_complete_requests() {
+    let openai = MockProvider::start().await;
+    client(LlmType::OpenAI, &openai, LlmConfig { generation: SAMPLING, ..Default::default() }).complete("hi").await.unwrap();
+    let body = only_request(&openai);
+    assert_eq!(body["temperature"], 0.25);
+    assert_eq!(body["top_p"], 0.5);
+    assert_eq!(body["frequency_penalty"], 0.75);
+    assert_eq!(body["presence_penalty"], 0.125);
+    assert_eq!(body["max_completion_tokens"], 256);
+
+    let ollama = MockProvider::start().await;
+    client(LlmType::Ollama, &ollama, LlmConfig { generation: SAMPLING, ..Default::default() }).complete("hi").await.unwrap();
+    let options = &only_request(&ollama)["options"];
+    assert_eq!(options["temperature"], 0.25);
+    assert_eq!(options["num_predict"], 256);
+
+    let anthropic = MockProvider::start().await;
+    client(LlmType::Anthropic, &anthropic, LlmConfig { generation: SAMPLING, ..Default::default() }).complete("hi").await.unwrap();
+    let body = only_request(&anthropic);
+    assert_eq!(body["temperature"], 0.25);
+    assert_eq!(body["max_tokens"], 256);
+    assert!(body.get("frequency_penalty").is_none());
+}
+
+#[tokio::test]
+async fn sampling_params_reach_streaming_requests() {
+    let provider = MockProvider::streaming("data: {\"choices\":[{\"delta\":{\"content\":\"ok\"}}]}\n\ndata: [DONE]\n\n").await;
+    let client = client(LlmType::OpenAI, &provider, LlmConfig { generation: SAMPLING, ..Default::default() });
+
+    let mut stream = client.stream_completion("hi").await.unwrap();
+    while let Some(chunk) = stream.next().await {
+        chunk.unwrap();
+    }
+
+    let body = only_request(&provider);
+    assert_eq!(body["stream"], true);
+    assert_eq!(body["temperature"], 0.25);
+    assert_eq!(body["top_p"], 0.5);
+    assert_eq!(body["max_tokens"], 256);
+}
+
+#[tokio::test]
+async fn unset_sampling_params_keep_the_client_defaults() {
+    let groq = MockProvider::start().await;
+    client(LlmType::Groq, &groq, LlmConfig::default()).complete("hi").await.unwrap();
+    let body = only_request(&groq);
+    assert_eq!(body["max_tokens"], 1024);
+    assert!(body.get("top_p").is_none());
+
+    let ollama = MockProvider::start().await;
+    client(LlmType::Ollama, &ollama, LlmConfig::default()).complete("hi").await.unwrap();
+    let options = &only_request(&ollama)["options"];
+    assert!(options.get("temperature").is_none() && options.get("num_predict").is_none());
+}
@@ -0,0 +1,185 @@
+//! WebSocket turns over an in-memory socket: retrieval metadata and progress events
+//! for clients that opt in through `capabilities`, per-message `rag_limit`, and
+//! `response_postprocess` rules on streamed answers.
+
+mod common;
+
+use common::{ agent, args, profile_schemas, profile_store, prompts, GENERAL_CHAT, PROFILE_INFO };
+use dynamic_agent::agent::AIAgent;
+use dynamic_agent::models::websocket::ProtocolVersion;
+use dynamic_agent::server::rate_limit::ClientIdentity;
+use dynamic_agent::server::shutdown::Shutdown;
+use dynamic_agent::server::streams::{ ConversationStreams, StreamPolicy };
+use dynamic_agent::server::websocket::{ handle_connection, ConnectionLimits };
+use dynamic_agent::testing::{ InMemoryHistoryStore, MockChatClient, MockEmbeddingClient };
+use futures::{ SinkExt, StreamExt };
+use serde_json::{ json, Value };
+use std::net::{ IpAddr, Ipv4Addr, SocketAddr };
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::DuplexStream;
+use tokio::sync::Mutex;
+use tokio_tungstenite::tungstenite::protocol::{ Message, Role };
+use tokio_tungstenite::WebSocketStream;
+
+const LIMITS: ConnectionLimits = ConnectionLimits {
+    max_message_size: 64 * 1024,
+    first_token_timeout: None,
+    inter_token_timeout: None,
+    flush_bytes: 20,
+    flush_interval: None,
+    max_thinking_tokens: None,
+};
+
+/// Serves one connection for `agent` and returns the client end.
+async fn connect(agent: AIAgent) -> WebSocketStream<DuplexStream> {
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    let peer = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 40000);
+    tokio::spawn(async move {
+        let websocket = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+        handle_connection(
+            peer,
+            websocket,
+            Arc::new(Mutex::new(agent)),
+            LIMITS,
+            ProtocolVersion::V1,
+            ClientIdentity::Anonymous(peer.ip()),
+            None,
+            Arc::new(ConversationStreams::new(StreamPolicy::Queue)),
+            Shutdown::new(Duration::from_secs(1))
+        ).await;
+    });
+    WebSocketStream::from_raw_socket(client, Role::Client, None).await
+}
+
+/// Sends `message` and collects the server messages up to the turn's `done`.
+async fn turn(ws: &mut WebSocketStream<DuplexStream>, message: Value) -> Vec<Value> {
+    ws.send(Message::Text(message.to_string())).await.unwrap();
+    let mut received = Vec::new();
+    while let Some(frame) = tokio::time::timeout(Duration::from_secs(5), ws.next()).await.expect("server reply") {
+        let Message::Text(text) = frame.unwrap() else {
+            continue;
+        };
+        let value: Value = serde_json::from_str(&text).unwrap();
+        let done = matches!(value["type"].as_str(), Some("done" | "error"));
+        received.push(value);
+        if done {
+            break;
+        }
+    }
+    received
+}
+
+fn of_type<'a>(messages: &'a [Value], kind: &str) -> Vec<&'a Value> {
+    messages.iter().filter(|m| m["type"] == kind).collect()
+}
+
+fn answer(messages: &[Value]) -> String {
+    of_type(messages, "partial")
+        .iter()
+        .chain(of_type(messages, "response").iter())
+        .filter_map(|m| m["content"].as_str())
+        .collect()
+}
+
+#[tokio::test]
+async fn metadata_precedes_retrieval_answers_when_requested() {
+    let t = agent(&[PROFILE_INFO, "experience", "You worked at Initech and Globex."]);
+    let mut ws = connect(t.agent).await;
+
+    let messages = turn(&mut ws, json!({
+        "type": "chat",
+        "content": "where did I work?",
+        "capabilities": { "supports_metadata": true, "wants_status_events": false }
+    })).await;
+
+    assert_eq!(messages[0], json!({
+        "type": "metadata",
+        "topic": "experience",
+        "fields": ["company", "title", "start_date", "end_date"],
+        "hit_count": 2
+    }));
+    assert_eq!(answer(&messages), "You worked at Initech and Globex.");
+}
+
+#[tokio::test]
+async fn metadata_is_opt_in_and_skipped_without_retrieval() {
+    let t = agent(&[PROFILE_INFO, "experience", "Initech and Globex.", GENERAL_CHAT, "Hello!"]);
+    let mut ws = connect(t.agent).await;
+
+    let messages = turn(&mut ws, json!({ "type": "chat", "content": "where did I work?" })).await;
+    assert!(of_type(&messages, "metadata").is_empty());
+    assert_eq!(answer(&messages), "Initech and Globex.");
+
+    let messages = turn(&mut ws, json!({
+        "type": "chat",
+        "content": "hi",
+        "capabilities": { "supports_metadata": true }
+    })).await;
+    assert!(of_type(&messages, "metadata").is_empty());
+    assert_eq!(answer(&messages), "Hello!");
+}
+
+#[tokio::test]
+async fn progress_follows_the_pipeline_for_clients_that_opt_in() {
+    let t = agent(&[PROFILE_INFO, "experience", "Initech and Globex.", GENERAL_CHAT, "Hello!"]);
+    let mut ws = connect(t.agent).await;
+
+    let messages = turn(&mut ws, json!({
+        "type": "chat",
+        "content": "where did I work?",
+        "capabilities": { "supports_progress": true }
+    })).await;
+    let stages: Vec<&str> = of_type(&messages, "progress").iter().filter_map(|m| m["stage"].as_str()).collect();
+    assert_eq!(stages, ["classifying", "retrieving", "generating"]);
+    let first_answer = messages.iter().position(|m| m["type"] == "partial" || m["type"] == "response").unwrap();
+    let last_progress = messages.iter().rposition(|m| m["type"] == "progress").unwrap();
+    assert!(last_progress < first_answer);
+
+    let messages = turn(&mut ws, json!({ "type": "chat", "content": "hi" })).await;
+    assert!(of_type(&messages, "progress").is_empty());
+}
+
+#[tokio::test]
+async fn rag_limit_bounds_streamed_retrieval() {
+    let t = agent(&[PROFILE_INFO, "experience", "You worked at Initech."]);
+    let mut ws = connect(t.agent).await;
+
+    let messages = turn(&mut ws, json!({
+        "type": "chat",
+        "content": "where did I work?",
+        "rag_limit": 1,
+        "capabilities": { "supports_metadata": true }
+    })).await;
+
+    assert_eq!(of_type(&messages, "metadata")[0]["hit_count"], 1);
+    let prompts = t.chat.prompts();
+    assert!(prompts[2].contains("Initech") && !prompts[2].contains("Globex"));
+}
+
+#[tokio::test]
+async fn postprocess_rules_match_across_streamed_fragments() {
+    let mut prompts = prompts();
+    prompts.response_postprocess = serde_json::from_value(json!({
+        "replace": [{ "pattern": "\\\\boxed\\{([^}]*)\\}", "replacement": "$1" }],
+        "strip_prefixes": ["The user's input is[\\s\\S]*?\\n\\n"],
+        "max_match_chars": 60,
+    })).unwrap();
+    let reply = "The user's input is a greeting, so reply in kind.\n\nHello! The first answer is \\boxed{42}, the second is \\boxed{7} and the last one is \\boxed{1234567}, as you asked.";
+    // Three-character fragments with 20-byte flushes put both patterns across fragment boundaries.
+    let chat = MockChatClient::new([GENERAL_CHAT, reply]).fragmented(3);
+    let agent = AIAgent::for_test(
+        args(&[]),
+        prompts,
+        Arc::new(chat),
+        Arc::new(MockEmbeddingClient::new(8)),
+        Arc::new(profile_store()),
+        Arc::new(InMemoryHistoryStore::new(100)),
+        profile_schemas()
+    ).unwrap();
+    let mut ws = connect(agent).await;
+
+    let messages = turn(&mut ws, json!({ "type": "chat", "content": "hi" })).await;
+    assert!(of_type(&messages, "partial").len() > 1);
+    assert_eq!(answer(&messages), "Hello! The first answer is 42, the second is 7 and the last one is 1234567, as you asked.");
+}